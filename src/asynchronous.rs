@@ -0,0 +1,359 @@
+use crate::decoder::{ExtractStats, Extracted};
+use crate::driver::{
+    ByteProgress, CancellationToken, Driver, Phase, ProgressEvent, ProgressReporter, UpdateStatus,
+    DEFAULT_BUFFER_SIZE,
+};
+use crate::encoder::{Digested, Entry};
+use crate::{paths, Error};
+use anyhow::Context;
+use anyhow_source_location::format_context;
+use futures::StreamExt;
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+type AsyncSink = Box<dyn tokio::io::AsyncWrite + Unpin + Send>;
+type AsyncSource = Box<dyn tokio::io::AsyncRead + Unpin + Send>;
+
+fn unsupported(driver: Driver) -> anyhow::Error {
+    Error::UnsupportedFormat {
+        driver,
+        reason: format!(
+            "{} archives aren't supported by the async API; use the blocking API instead",
+            driver.extension()
+        ),
+    }
+    .into()
+}
+
+async fn digest_file(
+    file_path: &str,
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<String> {
+    use sha2::Digest;
+    progress.on_event(ProgressEvent::PhaseChanged {
+        phase: Phase::Digesting,
+    });
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .context(format_context!("{file_path}"))?;
+    let bytes_total = file.metadata().await.ok().map(|metadata| metadata.len());
+    let mut byte_progress = ByteProgress::new(bytes_total);
+
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = vec![0; DEFAULT_BUFFER_SIZE];
+    loop {
+        cancellation.check()?;
+        let bytes_read = file
+            .read(buffer.as_mut_slice())
+            .await
+            .context(format_context!("{file_path}"))?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        byte_progress.add(bytes_read as u64);
+        progress.update_status(UpdateStatus {
+            detail: Some("Digesting...".to_string()),
+            ..byte_progress.update_status()
+        });
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Async counterpart of `Encoder` for tar based archives. Files are read with
+/// `tokio::fs` and compressed as they are appended, so nothing blocks the
+/// runtime. Zip and 7z archives aren't supported.
+pub struct AsyncEncoder<Progress: ProgressReporter> {
+    archiver: tokio_tar::Builder<AsyncSink>,
+    output_file_path: String,
+    driver: Driver,
+    cancellation: CancellationToken,
+    progress: Progress,
+}
+
+impl<Progress: ProgressReporter> AsyncEncoder<Progress> {
+    pub async fn new(
+        output_directory: &str,
+        output_filename: &str,
+        progress: Progress,
+    ) -> anyhow::Result<Self> {
+        use async_compression::tokio::write::GzipEncoder;
+        let driver = Driver::from_filename(output_filename)
+            .ok_or_else(|| Error::unknown_format(output_filename))?;
+        driver.check_supported()?;
+
+        let output_file_path = format!("{output_directory}/{output_filename}");
+        let file = tokio::fs::File::create(output_file_path.as_str())
+            .await
+            .context(format_context!("{output_file_path}"))?;
+        let file = tokio::io::BufWriter::new(file);
+        let sink: AsyncSink = match driver {
+            Driver::Gzip => Box::new(GzipEncoder::new(file)),
+            #[cfg(feature = "bzip2")]
+            Driver::Bzip2 => Box::new(async_compression::tokio::write::BzEncoder::new(file)),
+            #[cfg(feature = "xz")]
+            Driver::Xz => Box::new(async_compression::tokio::write::XzEncoder::new(file)),
+            _ => {
+                drop(file);
+                let _ = tokio::fs::remove_file(output_file_path.as_str()).await;
+                return Err(unsupported(driver));
+            }
+        };
+
+        Ok(Self {
+            archiver: tokio_tar::Builder::new(sink),
+            output_file_path,
+            driver,
+            cancellation: CancellationToken::default(),
+            progress,
+        })
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub async fn add_entries(&mut self, entries: &[Entry]) -> anyhow::Result<()> {
+        self.progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Archiving,
+        });
+        self.progress.update_status(UpdateStatus {
+            detail: Some(format!("Archiving... ({})", self.driver.extension())),
+            ..Default::default()
+        });
+
+        let mut bytes_total = 0;
+        for entry in entries.iter() {
+            if let Ok(metadata) = tokio::fs::symlink_metadata(entry.file_path.as_path()).await {
+                bytes_total += metadata.len();
+            }
+        }
+        let mut byte_progress = ByteProgress::new(Some(bytes_total));
+
+        for entry in entries.iter() {
+            self.cancellation.check()?;
+            let archive_path = entry.archive_path_lossy();
+            self.progress.update_status(UpdateStatus {
+                detail: Some(archive_path.clone()),
+                increment: Some(1),
+                total: Some(entries.len() as u64),
+                ..byte_progress.update_status()
+            });
+
+            let size = self
+                .add_file(entry.archive_path.as_path(), entry.file_path.as_path())
+                .await
+                .context(format_context!("{archive_path}"))?;
+            byte_progress.add(size);
+        }
+        Ok(())
+    }
+
+    /// Appends `file_path` as `archive_path` and returns its size.
+    pub async fn add_file(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        file_path: impl AsRef<Path>,
+    ) -> anyhow::Result<u64> {
+        let (archive_path, file_path) = (archive_path.as_ref(), file_path.as_ref());
+        let start = std::time::Instant::now();
+        self.progress.on_event(ProgressEvent::EntryStarted {
+            archive_path: paths::to_archive_path(archive_path),
+        });
+
+        self.archiver
+            .append_path_with_name(file_path, archive_path)
+            .await
+            .context(format_context!("failed to add {file_path:?} to archive"))?;
+
+        let size = tokio::fs::symlink_metadata(file_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        self.progress.on_event(ProgressEvent::EntryFinished {
+            archive_path: paths::to_archive_path(archive_path),
+            size,
+            elapsed: start.elapsed(),
+        });
+        Ok(size)
+    }
+
+    /// Finishes the archive, flushes it to disk and returns its sha256.
+    pub async fn compress(self) -> anyhow::Result<Digested<Progress>> {
+        let output_file_path = self.output_file_path;
+        let mut progress = self.progress;
+        progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Compressing,
+        });
+
+        let mut sink = self
+            .archiver
+            .into_inner()
+            .await
+            .context(format_context!("failed to finish {output_file_path}"))?;
+        sink.shutdown()
+            .await
+            .context(format_context!("failed to flush {output_file_path}"))?;
+
+        let sha256 = digest_file(output_file_path.as_str(), &mut progress, &self.cancellation)
+            .await
+            .context(format_context!("{output_file_path}"))?;
+        Ok(Digested {
+            sha256,
+            progress_bar: progress,
+        })
+    }
+}
+
+/// Async counterpart of `Decoder` for tar based archives.
+pub struct AsyncDecoder<Progress: ProgressReporter> {
+    input_file_path: String,
+    driver: Driver,
+    sha256: Option<String>,
+    output_directory: String,
+    cancellation: CancellationToken,
+    progress_bar: Progress,
+}
+
+impl<Progress: ProgressReporter> AsyncDecoder<Progress> {
+    pub fn new(
+        input_file_path: &str,
+        sha256: Option<String>,
+        output_directory: &str,
+        progress_bar: Progress,
+    ) -> anyhow::Result<Self> {
+        let driver = Driver::from_filename(input_file_path)
+            .ok_or_else(|| Error::unknown_format(input_file_path))?;
+        driver.check_supported()?;
+        if matches!(
+            driver,
+            Driver::Zip | Driver::SevenZ | Driver::Deb | Driver::Rpm
+        ) {
+            return Err(unsupported(driver));
+        }
+        Ok(Self {
+            input_file_path: input_file_path.to_string(),
+            driver,
+            sha256,
+            output_directory: output_directory.to_string(),
+            cancellation: CancellationToken::default(),
+            progress_bar,
+        })
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub async fn extract(self) -> anyhow::Result<Extracted<Progress>> {
+        use async_compression::tokio::bufread::GzipDecoder;
+        let start = std::time::Instant::now();
+        let input_file_path = self.input_file_path;
+        let output_directory = self.output_directory;
+        let mut progress_bar = self.progress_bar;
+
+        if let Some(digest) = self.sha256.as_ref() {
+            let actual_digest = digest_file(
+                input_file_path.as_str(),
+                &mut progress_bar,
+                &self.cancellation,
+            )
+            .await
+            .context(format_context!("{input_file_path}"))?;
+            if actual_digest != *digest {
+                return Err(Error::DigestMismatch {
+                    name: input_file_path.clone(),
+                    expected: digest.clone(),
+                    actual: actual_digest,
+                }
+                .into());
+            }
+        }
+
+        progress_bar.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Extracting,
+        });
+        let file = tokio::fs::File::open(input_file_path.as_str())
+            .await
+            .context(format_context!("{input_file_path}"))?;
+        let file = tokio::io::BufReader::new(file);
+        let source: AsyncSource = match self.driver {
+            Driver::Gzip => {
+                let mut decoder = GzipDecoder::new(file);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            #[cfg(feature = "bzip2")]
+            Driver::Bzip2 => Box::new(async_compression::tokio::bufread::BzDecoder::new(file)),
+            #[cfg(feature = "xz")]
+            Driver::Xz => Box::new(async_compression::tokio::bufread::XzDecoder::new(file)),
+            _ => return Err(unsupported(self.driver)),
+        };
+
+        tokio::fs::create_dir_all(output_directory.as_str())
+            .await
+            .context(format_context!("{output_directory}"))?;
+
+        let mut archive = tokio_tar::Archive::new(source);
+        let mut entries = archive
+            .entries()
+            .context(format_context!("{input_file_path}"))?;
+        let mut files = HashSet::new();
+        let mut stats = ExtractStats::default();
+        while let Some(entry) = entries.next().await {
+            self.cancellation.check()?;
+            let mut entry = entry.context(format_context!("{input_file_path}"))?;
+            let path = entry
+                .path()
+                .context(format_context!("{input_file_path}"))?
+                .to_string_lossy()
+                .to_string();
+            let entry_type = entry.header().entry_type();
+            let size = entry.header().size().unwrap_or(0);
+
+            progress_bar.update_status(UpdateStatus {
+                detail: Some(path.clone()),
+                increment: Some(1),
+                ..Default::default()
+            });
+
+            // `unpack_in` skips entries that would land outside the output directory
+            let unpacked = entry
+                .unpack_in(output_directory.as_str())
+                .await
+                .context(format_context!("failed to extract {path}"))?;
+            if unpacked.is_none() {
+                continue;
+            }
+
+            if entry_type.is_dir() {
+                stats.directories += 1;
+                continue;
+            }
+            if entry_type.is_symlink() {
+                stats.symlinks += 1;
+            } else {
+                stats.files += 1;
+                stats.bytes_written += size;
+            }
+            files.insert(path.trim_start_matches("./").to_string());
+        }
+
+        Ok(Extracted {
+            progress_bar,
+            files,
+            warnings: Vec::new(),
+            failures: Vec::new(),
+            conflicts: Vec::new(),
+            digests: std::collections::BTreeMap::new(),
+            stats: ExtractStats {
+                elapsed: start.elapsed(),
+                ..stats
+            },
+        })
+    }
+}
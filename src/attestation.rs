@@ -0,0 +1,112 @@
+use crate::driver::ProgressReporter;
+use crate::encoder::Digested;
+use anyhow::Context;
+use anyhow_source_location::format_context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+pub const BUILD_TYPE: &str = "https://github.com/work-spaces/easy-archiver/create-archive/v1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subject {
+    pub name: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildDefinition {
+    pub build_type: String,
+    pub external_parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Builder {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunDetails {
+    pub builder: Builder,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub build_definition: BuildDefinition,
+    pub run_details: RunDetails,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    pub predicate_type: String,
+    pub predicate: Provenance,
+}
+
+impl Statement {
+    pub fn new<Progress: ProgressReporter>(
+        archive_path: &str,
+        digested: &Digested<Progress>,
+        builder_id: &str,
+    ) -> Self {
+        Self::from_sha256(archive_path, digested.sha256.as_str(), builder_id)
+    }
+
+    /// Like `new` for an archive whose sha256 is already known.
+    pub fn from_sha256(archive_path: &str, sha256: &str, builder_id: &str) -> Self {
+        let name = std::path::Path::new(archive_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| archive_path.to_string());
+
+        let mut digest = BTreeMap::new();
+        digest.insert("sha256".to_string(), sha256.to_string());
+
+        Self {
+            statement_type: STATEMENT_TYPE.to_string(),
+            subject: vec![Subject { name, digest }],
+            predicate_type: PREDICATE_TYPE.to_string(),
+            predicate: Provenance {
+                build_definition: BuildDefinition {
+                    build_type: BUILD_TYPE.to_string(),
+                    external_parameters: serde_json::Value::Object(Default::default()),
+                },
+                run_details: RunDetails {
+                    builder: Builder {
+                        id: builder_id.to_string(),
+                    },
+                },
+            },
+        }
+    }
+
+    pub fn with_external_parameters(mut self, external_parameters: serde_json::Value) -> Self {
+        self.predicate.build_definition.external_parameters = external_parameters;
+        self
+    }
+
+    pub fn get_output_file(archive_path: &str) -> String {
+        format!("{archive_path}.intoto.json")
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string(self).context(format_context!("failed to serialize statement"))
+    }
+
+    /// Writes the statement as a single-line JSON document next to the archive
+    /// and returns the path of the written file.
+    pub fn write(&self, archive_path: &str) -> anyhow::Result<String> {
+        let output_path = Self::get_output_file(archive_path);
+        let mut contents = self.to_json().context(format_context!("{output_path}"))?;
+        contents.push('\n');
+        std::fs::write(output_path.as_str(), contents)
+            .context(format_context!("failed to write {output_path}"))?;
+        Ok(output_path)
+    }
+}
@@ -0,0 +1,129 @@
+use crate::decoder::Decoder;
+use crate::driver::{Driver, NoProgress};
+use crate::encoder::{Encoder, Entry};
+use anyhow::Context;
+use anyhow_source_location::format_context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub driver: Driver,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// `output_bytes / input_bytes`; lower is better.
+    pub ratio: f64,
+    pub compress_time: std::time::Duration,
+    pub extract_time: std::time::Duration,
+}
+
+fn sample_entries(input_dir: &str, sample_limit: u64) -> anyhow::Result<(Vec<Entry>, u64)> {
+    let mut walk_dir: Vec<_> = walkdir::WalkDir::new(input_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .collect();
+    walk_dir.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let mut entries = Vec::new();
+    let mut input_bytes = 0;
+    for item in walk_dir {
+        if input_bytes >= sample_limit {
+            break;
+        }
+        let size = item
+            .metadata()
+            .context(format_context!("{:?}", item.path()))?
+            .len();
+        let archive_path = item
+            .path()
+            .strip_prefix(input_dir)
+            .context(format_context!("{:?}", item.path()))?;
+        entries.push(Entry::new(archive_path, item.path()));
+        input_bytes += size;
+    }
+    Ok((entries, input_bytes))
+}
+
+fn benchmark_driver(
+    driver: Driver,
+    entries: &[Entry],
+    input_bytes: u64,
+    work_directory: &str,
+) -> anyhow::Result<BenchmarkResult> {
+    let output_filename = format!("benchmark.{}", driver.extension());
+    let output_path = format!("{work_directory}/{output_filename}");
+
+    let start = std::time::Instant::now();
+    let mut encoder = Encoder::new(work_directory, output_filename.as_str(), NoProgress)
+        .context(format_context!("{output_path}"))?;
+    encoder
+        .add_entries(entries)
+        .context(format_context!("{output_path}"))?;
+    encoder
+        .compress()
+        .context(format_context!("{output_path}"))?;
+    let compress_time = start.elapsed();
+
+    let output_bytes = std::fs::metadata(output_path.as_str())
+        .context(format_context!("{output_path}"))?
+        .len();
+
+    let extract_directory = format!("{work_directory}/extract.{}", driver.extension());
+    let start = std::time::Instant::now();
+    Decoder::new(
+        output_path.as_str(),
+        None,
+        extract_directory.as_str(),
+        NoProgress,
+    )
+    .context(format_context!("{output_path}"))?
+    .extract()
+    .context(format_context!("{output_path}"))?;
+    let extract_time = start.elapsed();
+
+    Ok(BenchmarkResult {
+        driver,
+        input_bytes,
+        output_bytes,
+        ratio: if input_bytes > 0 {
+            output_bytes as f64 / input_bytes as f64
+        } else {
+            1.0
+        },
+        compress_time,
+        extract_time,
+    })
+}
+
+/// Compresses and extracts up to `sample_limit` bytes of `input_dir` with each
+/// of `drivers` and reports the timings and compression ratio of each.
+pub fn benchmark(
+    input_dir: &str,
+    drivers: &[Driver],
+    sample_limit: u64,
+) -> anyhow::Result<Vec<BenchmarkResult>> {
+    let (entries, input_bytes) =
+        sample_entries(input_dir, sample_limit).context(format_context!("{input_dir}"))?;
+
+    let work_directory = std::env::temp_dir()
+        .join(format!("easy-archiver-benchmark-{}", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    std::fs::create_dir_all(work_directory.as_str())
+        .context(format_context!("{work_directory}"))?;
+
+    let mut results = Vec::with_capacity(drivers.len());
+    let mut outcome = Ok(());
+    for driver in drivers {
+        match benchmark_driver(*driver, &entries, input_bytes, work_directory.as_str()) {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                outcome = Err(err).context(format_context!("{driver:?}"));
+                break;
+            }
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(work_directory.as_str());
+    outcome.map(|_| results)
+}
@@ -0,0 +1,323 @@
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use clap::{Args, Parser, Subcommand};
+use easy_archiver::driver::{self, Driver};
+use easy_archiver::{CancellationToken, CreateArchive, Decoder, VerifyMismatch, VerifyReport};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Create, extract, list and verify archives")]
+struct Arguments {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Creates an archive from a spec file or from the given options and
+    /// prints its sha256.
+    Create(CreateArguments),
+    /// Extracts an archive, optionally checking its sha256 first.
+    Extract {
+        archive: String,
+        #[arg(long, default_value = ".")]
+        output_directory: String,
+        #[arg(long)]
+        sha256: Option<String>,
+    },
+    /// Prints the files `create` would archive without writing anything.
+    Plan {
+        #[command(flatten)]
+        create: CreateArguments,
+        /// Print the plan as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Lists the entries of an archive.
+    List {
+        archive: String,
+        /// Also print entry sizes.
+        #[arg(long)]
+        long: bool,
+        /// Print the listing as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Prints the sha256 of an archive after checking that its entries can
+    /// be read. Fails on any mismatch with `expected`.
+    Verify {
+        archive: String,
+        /// A sha256 digest of the archive, or a `sha256sum` style checksums
+        /// file listing the archive and/or the files inside it.
+        expected: Option<String>,
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct CreateArguments {
+    /// A `.toml` or `.json` archive spec. Overrides the options below.
+    #[arg(long)]
+    spec: Option<String>,
+    #[arg(long)]
+    input: Option<String>,
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long)]
+    version: Option<String>,
+    /// Archive extension such as `tar.gz`, `tar.xz` or `zip`.
+    #[arg(long, value_parser = parse_driver, default_value = "tar.gz")]
+    driver: Driver,
+    #[arg(long)]
+    platform: Option<String>,
+    #[arg(long)]
+    include: Vec<String>,
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Leave out files smaller than this many bytes.
+    #[arg(long)]
+    min_file_size: Option<u64>,
+    /// Leave out files larger than this many bytes.
+    #[arg(long)]
+    max_file_size: Option<u64>,
+    #[arg(long, default_value = ".")]
+    output_directory: String,
+}
+
+fn parse_driver(extension: &str) -> anyhow::Result<Driver> {
+    Driver::from_extension(extension).ok_or(format_error!(
+        "unknown archive extension {extension}; expected one of {}",
+        Driver::supported_extensions().join(", ")
+    ))
+}
+
+fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(value).context(format_context!(""))?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Returns the archive spec and the output directory.
+fn build_create_archive(arguments: CreateArguments) -> anyhow::Result<(CreateArchive, String)> {
+    let CreateArguments {
+        spec,
+        input,
+        name,
+        version,
+        driver,
+        platform,
+        include,
+        exclude,
+        min_file_size,
+        max_file_size,
+        output_directory,
+    } = arguments;
+
+    let create_archive = if let Some(spec) = spec {
+        CreateArchive::from_file(spec.as_str()).context(format_context!("{spec}"))?
+    } else {
+        let mut builder = CreateArchive::builder()
+            .input(input.unwrap_or_default().as_str())
+            .name(name.unwrap_or_default().as_str())
+            .version(version.unwrap_or_default().as_str())
+            .driver(driver);
+        if let Some(platform) = platform {
+            builder = builder.platform(platform.as_str());
+        }
+        for pattern in include {
+            builder = builder.include(pattern.as_str());
+        }
+        for pattern in exclude {
+            builder = builder.exclude(pattern.as_str());
+        }
+        if let Some(min_file_size) = min_file_size {
+            builder = builder.min_file_size(min_file_size);
+        }
+        if let Some(max_file_size) = max_file_size {
+            builder = builder.max_file_size(max_file_size);
+        }
+        builder
+            .build()
+            .context(format_context!("invalid archive options"))?
+    };
+    Ok((create_archive, output_directory))
+}
+
+fn create(arguments: CreateArguments, printer: &mut printer::Printer) -> anyhow::Result<()> {
+    let (create_archive, output_directory) = build_create_archive(arguments)?;
+    let mut multi_progress = printer::MultiProgress::new(printer);
+    let progress_bar =
+        multi_progress.add_progress(create_archive.get_output_file().as_str(), None, None);
+    let (output_file_path, sha256) = create_archive
+        .create(output_directory.as_str(), progress_bar)
+        .context(format_context!("{output_directory}"))?;
+    println!("{sha256}  {output_file_path}");
+    Ok(())
+}
+
+fn extract(
+    archive: &str,
+    output_directory: &str,
+    sha256: Option<String>,
+    printer: &mut printer::Printer,
+) -> anyhow::Result<()> {
+    let mut multi_progress = printer::MultiProgress::new(printer);
+    let progress_bar = multi_progress.add_progress(archive, None, None);
+    let extracted = Decoder::new(archive, sha256, output_directory, progress_bar)
+        .context(format_context!("{archive}"))?
+        .extract()
+        .context(format_context!("{archive}"))?;
+    for warning in extracted.warnings.iter() {
+        eprintln!("warning: {warning:?}");
+    }
+    println!(
+        "extracted {} files ({} bytes) to {output_directory}",
+        extracted.stats.files, extracted.stats.bytes_written
+    );
+    Ok(())
+}
+
+fn plan(arguments: CreateArguments, json: bool) -> anyhow::Result<()> {
+    let (create_archive, _) = build_create_archive(arguments)?;
+    let plan = create_archive
+        .plan()
+        .context(format_context!("failed to plan"))?;
+    if json {
+        return print_json(&plan);
+    }
+    for entry in plan.entries.iter() {
+        println!("{:>12} {}", entry.size, entry.archive_path);
+    }
+    println!(
+        "{}: {} files, {} bytes, about {} bytes compressed",
+        plan.output_file,
+        plan.entries.len(),
+        plan.total_size,
+        plan.estimated_compressed_size
+    );
+    Ok(())
+}
+
+fn list(archive: &str, long: bool, json: bool) -> anyhow::Result<()> {
+    let listing = Decoder::new(archive, None, ".", easy_archiver::NoProgress)
+        .context(format_context!("{archive}"))?
+        .list()
+        .context(format_context!("{archive}"))?;
+    if json {
+        return print_json(&listing);
+    }
+    for entry in listing.entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        if long {
+            println!("{:>12} {}{suffix}", entry.size, entry.archive_path);
+        } else {
+            println!("{}{suffix}", entry.archive_path);
+        }
+    }
+    Ok(())
+}
+
+fn verify(
+    archive: &str,
+    expected: Option<String>,
+    json: bool,
+    printer: &mut printer::Printer,
+) -> anyhow::Result<()> {
+    Decoder::new(archive, None, ".", easy_archiver::NoProgress)
+        .context(format_context!("{archive}"))?
+        .list()
+        .context(format_context!("{archive} is not readable"))?;
+
+    let Some(expected) = expected else {
+        let mut multi_progress = printer::MultiProgress::new(printer);
+        let mut progress_bar = multi_progress.add_progress(archive, None, None);
+        let digest = driver::digest_file(archive, &mut progress_bar, &CancellationToken::default())
+            .context(format_context!("{archive}"))?;
+        if json {
+            return print_json(&VerifyReport {
+                sha256: digest,
+                ..Default::default()
+            });
+        }
+        println!("{digest}  {archive}");
+        return Ok(());
+    };
+
+    let report =
+        easy_archiver::verify(archive, expected.as_str()).context(format_context!("{archive}"))?;
+    if json {
+        print_json(&report)?;
+    } else {
+        println!("{}  {archive}", report.sha256);
+    }
+    for mismatch in report.mismatches.iter().filter(|_| !json) {
+        match mismatch {
+            VerifyMismatch::Archive { expected, actual } => {
+                eprintln!("{archive}: FAILED (expected {expected}, got {actual})")
+            }
+            VerifyMismatch::Entry {
+                archive_path,
+                expected,
+                actual,
+            } => eprintln!("{archive_path}: FAILED (expected {expected}, got {actual})"),
+            VerifyMismatch::Missing { archive_path } => {
+                eprintln!("{archive_path}: FAILED (not in the archive)")
+            }
+            VerifyMismatch::Extra { archive_path } => {
+                eprintln!("{archive_path}: FAILED (not in the checksums file)")
+            }
+        }
+    }
+    if !report.is_ok() {
+        return Err(format_error!(
+            "{archive}: {} of {} digests did not match",
+            report.mismatches.len(),
+            report.checked
+        ));
+    }
+    if !json {
+        println!("{archive}: OK ({} digests checked)", report.checked);
+    }
+    Ok(())
+}
+
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(error) => {
+            // the code first so scripts can branch on it without parsing the message
+            eprintln!("error[{}]: {error:?}", easy_archiver::ErrorKind::of(&error));
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let arguments = Arguments::parse();
+    let mut printer = printer::Printer::new_stdout();
+    match arguments.command {
+        Command::Create(arguments) => create(arguments, &mut printer),
+        Command::Extract {
+            archive,
+            output_directory,
+            sha256,
+        } => extract(
+            archive.as_str(),
+            output_directory.as_str(),
+            sha256,
+            &mut printer,
+        ),
+        Command::Plan { create, json } => plan(create, json),
+        Command::List {
+            archive,
+            long,
+            json,
+        } => list(archive.as_str(), long, json),
+        Command::Verify {
+            archive,
+            expected,
+            json,
+        } => verify(archive.as_str(), expected, json, &mut printer),
+    }
+}
@@ -0,0 +1,262 @@
+use crate::driver::{Driver, RetryPolicy, TarFormat};
+use crate::sfx::SfxStub;
+use crate::{CreateArchive, ExcludeSyntax, FileList, GlobOptions};
+use anyhow_source_location::format_error;
+
+#[derive(Debug, Clone, Default)]
+pub struct CreateArchiveBuilder {
+    input: Option<String>,
+    inputs: Vec<(String, Option<String>)>,
+    name: Option<String>,
+    version: Option<String>,
+    driver: Option<Driver>,
+    platform: Option<String>,
+    includes: Option<Vec<String>>,
+    excludes: Option<Vec<String>>,
+    file_list: Option<FileList>,
+    exclude_syntax: ExcludeSyntax,
+    respect_ignore_files: bool,
+    glob_options: GlobOptions,
+    follow_symlinks: bool,
+    exclude_hidden: bool,
+    max_depth: Option<usize>,
+    min_file_size: Option<u64>,
+    max_file_size: Option<u64>,
+    modified_since: Option<std::time::SystemTime>,
+    embed_manifest: bool,
+    tar_format: TarFormat,
+    skip_unreadable: bool,
+    retry: RetryPolicy,
+    allow_empty: bool,
+    archive_prefix: Option<String>,
+    deduplicate: bool,
+    self_extracting: Option<SfxStub>,
+    threads: Option<usize>,
+    digest_cache: Option<String>,
+    segment_size: Option<u64>,
+    attestation_builder_id: Option<String>,
+}
+
+impl CreateArchiveBuilder {
+    pub fn input(mut self, input: &str) -> Self {
+        self.input = Some(input.to_string());
+        self
+    }
+
+    pub fn add_input(mut self, input: &str, prefix: Option<&str>) -> Self {
+        self.inputs
+            .push((input.to_string(), prefix.map(|prefix| prefix.to_string())));
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    pub fn driver(mut self, driver: Driver) -> Self {
+        self.driver = Some(driver);
+        self
+    }
+
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.platform = Some(platform.to_string());
+        self
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.includes
+            .get_or_insert_with(Vec::new)
+            .push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.excludes
+            .get_or_insert_with(Vec::new)
+            .push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude_syntax(mut self, exclude_syntax: ExcludeSyntax) -> Self {
+        self.exclude_syntax = exclude_syntax;
+        self
+    }
+
+    pub fn respect_ignore_files(mut self, respect_ignore_files: bool) -> Self {
+        self.respect_ignore_files = respect_ignore_files;
+        self
+    }
+
+    pub fn glob_options(mut self, glob_options: GlobOptions) -> Self {
+        self.glob_options = glob_options;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn exclude_hidden(mut self, exclude_hidden: bool) -> Self {
+        self.exclude_hidden = exclude_hidden;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn min_file_size(mut self, min_file_size: u64) -> Self {
+        self.min_file_size = Some(min_file_size);
+        self
+    }
+
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    pub fn modified_since(mut self, modified_since: std::time::SystemTime) -> Self {
+        self.modified_since = Some(modified_since);
+        self
+    }
+
+    pub fn embed_manifest(mut self, embed_manifest: bool) -> Self {
+        self.embed_manifest = embed_manifest;
+        self
+    }
+
+    pub fn tar_format(mut self, tar_format: TarFormat) -> Self {
+        self.tar_format = tar_format;
+        self
+    }
+
+    pub fn skip_unreadable(mut self, skip_unreadable: bool) -> Self {
+        self.skip_unreadable = skip_unreadable;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.allow_empty = allow_empty;
+        self
+    }
+
+    pub fn archive_prefix(mut self, archive_prefix: &str) -> Self {
+        self.archive_prefix = Some(archive_prefix.to_string());
+        self
+    }
+
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn digest_cache(mut self, path: &str) -> Self {
+        self.digest_cache = Some(path.to_string());
+        self
+    }
+
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = Some(segment_size);
+        self
+    }
+
+    pub fn attestation_builder_id(mut self, builder_id: &str) -> Self {
+        self.attestation_builder_id = Some(builder_id.to_string());
+        self
+    }
+
+    pub fn self_extracting(mut self, stub: SfxStub) -> Self {
+        self.self_extracting = Some(stub);
+        self
+    }
+
+    pub fn file_list(mut self, file_list: FileList) -> Self {
+        self.file_list = Some(file_list);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<CreateArchive> {
+        let input = self.input.unwrap_or_default();
+        if input.is_empty() && self.inputs.is_empty() && self.file_list.is_none() {
+            return Err(format_error!("archive input is required"));
+        }
+        let roots = std::iter::once(input.as_str())
+            .filter(|input| !input.is_empty())
+            .chain(self.inputs.iter().map(|(input, _)| input.as_str()));
+        for root in roots {
+            if !std::path::Path::new(root).exists() {
+                return Err(format_error!("archive input {root} does not exist"));
+            }
+        }
+
+        let name = self.name.unwrap_or_default();
+        if name.is_empty() {
+            return Err(format_error!("archive name must not be empty"));
+        }
+
+        let version = self.version.unwrap_or_default();
+        if !is_valid_version(version.as_str()) {
+            return Err(format_error!("invalid archive version {version:?}"));
+        }
+
+        Ok(CreateArchive {
+            input,
+            inputs: self.inputs,
+            name,
+            version,
+            driver: self.driver.unwrap_or(Driver::Gzip),
+            platform: self.platform,
+            includes: self.includes,
+            excludes: self.excludes,
+            file_list: self.file_list,
+            exclude_syntax: self.exclude_syntax,
+            respect_ignore_files: self.respect_ignore_files,
+            glob_options: self.glob_options,
+            follow_symlinks: self.follow_symlinks,
+            exclude_hidden: self.exclude_hidden,
+            max_depth: self.max_depth,
+            min_file_size: self.min_file_size,
+            max_file_size: self.max_file_size,
+            modified_since: self.modified_since,
+            embed_manifest: self.embed_manifest,
+            tar_format: self.tar_format,
+            skip_unreadable: self.skip_unreadable,
+            retry: self.retry,
+            allow_empty: self.allow_empty,
+            archive_prefix: self.archive_prefix,
+            deduplicate: self.deduplicate,
+            self_extracting: self.self_extracting,
+            threads: self.threads,
+            digest_cache: self.digest_cache,
+            segment_size: self.segment_size,
+            attestation_builder_id: self.attestation_builder_id,
+        })
+    }
+}
+
+// The version becomes part of the output file name as `-v{version}`.
+fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && !version.starts_with('v')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'))
+}
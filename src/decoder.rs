@@ -2,306 +2,2658 @@ use anyhow_source_location::{format_context, format_error};
 use std::collections::HashSet;
 use std::io::Read;
 
-use crate::driver::{self, Driver, UpdateStatus, SEVEN_Z_TAR_FILENAME};
+use crate::driver::{
+    self, ByteProgress, CancellationToken, DigestAlgorithm, Driver, DriverOptions, Phase,
+    ProgressEvent, ProgressReporter, UpdateStatus, DEFAULT_BUFFER_SIZE,
+};
+use crate::{paths, Error};
 
 use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    #[default]
+    Abort,
+    Continue,
+}
+
+/// Which regular files `Decoder::extract` rewrites when they already exist.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum UpdatePolicy {
+    #[default]
+    Always,
+    /// Skip entries whose file on disk is at least as new, by mtime. Skipped
+    /// entries aren't read.
+    Newer,
+    /// Skip entries whose file on disk has the same size and contents. A tar
+    /// entry the same size as the file is compared while it is read and the
+    /// file is rewritten in place from the first difference, keeping its
+    /// permissions unless `mode_policy` sets them.
+    Changed,
+}
+
+impl UpdatePolicy {
+    /// Whether an entry can be skipped without reading it, given the size and
+    /// mtime of the file already at its destination.
+    fn is_up_to_date(&self, existing: Option<(u64, Option<u64>)>, mtime: Option<u64>) -> bool {
+        match (self, existing, mtime) {
+            (UpdatePolicy::Newer, Some((_, Some(existing_mtime))), Some(mtime)) => {
+                existing_mtime >= mtime
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether an entry of `size` is compared with the existing file.
+    fn compares(&self, existing: Option<(u64, Option<u64>)>, size: u64) -> bool {
+        *self == UpdatePolicy::Changed
+            && existing.is_some_and(|(existing_size, _)| existing_size == size)
+    }
+}
+
+/// Permissions given to extracted files and directories on unix.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModePolicy {
+    /// Use the modes stored in the archive.
+    #[default]
+    Archive,
+    /// Clear these bits from the archive modes, e.g. `0o022`.
+    Umask(u32),
+    /// Ignore the archive and use these modes, e.g. `0o644` and `0o755`.
+    Fixed { file: u32, directory: u32 },
+}
+
+impl ModePolicy {
+    fn mode(&self, archive_mode: Option<u32>, is_dir: bool) -> Option<u32> {
+        let default_mode = if is_dir { 0o755 } else { 0o644 };
+        match *self {
+            ModePolicy::Archive => None,
+            ModePolicy::Umask(umask) => {
+                Some(archive_mode.unwrap_or(default_mode) & 0o7777 & !umask)
+            }
+            ModePolicy::Fixed { file, directory } => Some(if is_dir { directory } else { file }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractOptions {
+    /// What to do when a zip entry fails its CRC32 check. It is consulted
+    /// before `entry_policy`: `Continue` skips the entry with a `CrcMismatch`
+    /// warning and `entry_policy` is never involved. `Abort` turns the
+    /// mismatch into an entry failure, which `entry_policy` then either
+    /// returns or records in `Extracted::failures`.
+    pub crc_policy: ErrorPolicy,
+    /// What to do when a single entry can't be extracted, e.g. permission
+    /// denied or a CRC mismatch under `ErrorPolicy::Abort`. `Continue` records
+    /// the failure in `Extracted::failures` and moves on to the next entry.
+    pub entry_policy: ErrorPolicy,
+    /// Applies the unix mode stored with zip entries. Tar entries always keep
+    /// their permission bits.
+    pub restore_permissions: bool,
+    /// Sets the modification time of extracted files from the archive.
+    pub restore_mtime: bool,
+    /// Sets the modification time of directory entries once everything has
+    /// been extracted, deepest first, so later writes don't bump them.
+    pub restore_directory_mtimes: bool,
+    /// Applied after everything has been extracted so restrictive directory
+    /// modes don't block writing their contents.
+    pub mode_policy: ModePolicy,
+    /// Fails before writing anything when the destination filesystem has
+    /// less free space than the extracted files need. Not checked for tar
+    /// based archives decoded with more than one thread.
+    pub check_free_space: bool,
+    /// When extraction fails, removes the files and directories this run
+    /// created. Files that already existed and were overwritten keep their new
+    /// contents.
+    pub rollback_on_failure: bool,
+    /// Fills `Extracted::digests` with the sha256 of each regular file,
+    /// hashed as it is written.
+    pub compute_digests: bool,
+    /// Sends entries under a prefix to another directory instead of the
+    /// output directory. The longest matching prefix wins. Routed files
+    /// aren't listed in `Extracted::files`. The free space check still
+    /// counts them, against the filesystem of the output directory.
+    pub routes: Vec<ExtractRoute>,
+    pub update: UpdatePolicy,
+    /// Never replaces anything already in the destination: entries whose path
+    /// exists, other than directories, are skipped.
+    pub merge: bool,
+    /// With `merge`, compares the skipped entries with what is on disk and
+    /// lists the ones that differ in `Extracted::conflicts`.
+    pub report_conflicts: bool,
+    /// How many levels of archives found among the extracted files to
+    /// extract as well, each into a directory named after it without the
+    /// extension. The nested archives are kept and extracted with these same
+    /// options, except `routes`.
+    pub nested_depth: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractRoute {
+    /// Archive path prefix, matched by whole path components, e.g. `bin`.
+    pub prefix: String,
+    /// Directory the entries under `prefix` are extracted into, with
+    /// `prefix` removed from their paths.
+    pub destination: String,
+}
+
+impl ExtractRoute {
+    pub fn new(prefix: &str, destination: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            destination: destination.to_string(),
+        }
+    }
+
+    /// The rest of `archive_path` after the prefix, if it matches.
+    fn strip<'path>(&self, archive_path: &'path str) -> Option<&'path str> {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            return Some(archive_path);
+        }
+        let rest = archive_path.strip_prefix(prefix)?;
+        if rest.is_empty() {
+            return Some(rest);
+        }
+        rest.strip_prefix('/')
+    }
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            crc_policy: ErrorPolicy::default(),
+            entry_policy: ErrorPolicy::default(),
+            restore_permissions: true,
+            restore_mtime: true,
+            restore_directory_mtimes: false,
+            check_free_space: true,
+            rollback_on_failure: false,
+            compute_digests: false,
+            routes: Vec::new(),
+            update: UpdatePolicy::default(),
+            merge: false,
+            report_conflicts: false,
+            nested_depth: 0,
+            mode_policy: ModePolicy::default(),
+        }
+    }
+}
+
+trait SeekableRead: std::io::Read + std::io::Seek + Send {}
+
+impl<Reader: std::io::Read + std::io::Seek + Send> SeekableRead for Reader {}
+
+type StreamSource = Box<dyn std::io::Read + Send>;
+type SeekableSource = Box<dyn SeekableRead>;
 
 enum DecoderDriver {
-    Gzip(flate2::read::GzDecoder<std::fs::File>),
-    Bzip2(bzip2::read::BzDecoder<std::fs::File>),
-    Xz(xz2::read::XzDecoder<std::fs::File>),
-    Zip(zip::ZipArchive<std::fs::File>),
-    SevenZ,
+    /// Reads every member, as written by pigz or by concatenating files.
+    Gzip(flate2::read::MultiGzDecoder<StreamSource>),
+    #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+    Bzip2(bzip2::read::BzDecoder<StreamSource>),
+    #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+    Xz(xz2::read::XzDecoder<StreamSource>),
+    #[cfg(feature = "zip")]
+    Zip(zip::ZipArchive<SeekableSource>),
+    SevenZ(SeekableSource),
+}
+
+impl DecoderDriver {
+    fn from_stream(driver: Driver, source: StreamSource) -> anyhow::Result<Self> {
+        driver.check_extractable()?;
+        match driver {
+            Driver::Gzip => Ok(DecoderDriver::Gzip(flate2::read::MultiGzDecoder::new(
+                source,
+            ))),
+            #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+            Driver::Bzip2 => Ok(DecoderDriver::Bzip2(bzip2::read::BzDecoder::new(source))),
+            #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+            Driver::Xz => Ok(DecoderDriver::Xz(xz2::read::XzDecoder::new(source))),
+            #[cfg(not(all(feature = "bzip2", not(target_family = "wasm"))))]
+            Driver::Bzip2 => unreachable!("rejected by check_supported"),
+            #[cfg(not(all(feature = "xz", not(target_family = "wasm"))))]
+            Driver::Xz => unreachable!("rejected by check_supported"),
+            Driver::Deb | Driver::Rpm => unreachable!("rejected by check_extractable"),
+            Driver::Zip | Driver::SevenZ => Err(Error::UnsupportedFormat {
+                driver,
+                reason: format!(
+                    "{} archives need random access and can't be decoded from a stream",
+                    driver.extension()
+                ),
+            }
+            .into()),
+        }
+    }
+
+    #[cfg_attr(not(feature = "zip"), allow(unused_variables))]
+    fn from_seekable(driver: Driver, source: SeekableSource, name: &str) -> anyhow::Result<Self> {
+        match driver {
+            #[cfg(feature = "zip")]
+            Driver::Zip => Ok(DecoderDriver::Zip(
+                zip::ZipArchive::new(source).context(format_context!("open zip failed: {name}"))?,
+            )),
+            Driver::SevenZ => Ok(DecoderDriver::SevenZ(source)),
+            _ => Self::from_stream(driver, Box::new(source)),
+        }
+    }
+}
+
+/// Entries of a tar based archive, returned by `Decoder::entries`.
+pub struct EntryStream {
+    archive: tar::Archive<StreamSource>,
+    input_file_name: String,
+    cancellation: CancellationToken,
+}
+
+impl EntryStream {
+    /// Yields each entry in archive order with a reader over its contents.
+    /// A reader is only valid until the next entry is requested.
+    pub fn iter(
+        &mut self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(EntryInfo, impl Read + '_)>> + '_>
+    {
+        let input_file = self.input_file_name.clone();
+        let cancellation = self.cancellation.clone();
+        let entries = self
+            .archive
+            .entries()
+            .context(format_context!("{input_file}"))?;
+        Ok(entries.filter_map(move |entry| {
+            let next = || -> anyhow::Result<Option<(EntryInfo, tar::Entry<'_, StreamSource>)>> {
+                cancellation.check()?;
+                let entry = entry.context(format_context!("{input_file}"))?;
+                if entry.header().entry_type().is_pax_global_extensions() {
+                    return Ok(None);
+                }
+                let archive_path = entry
+                    .path()
+                    .context(format_context!("{input_file}"))?
+                    .to_string_lossy()
+                    .to_string();
+                Ok(Some((tar_entry_info(&entry, archive_path), entry)))
+            };
+            next().transpose()
+        }))
+    }
+}
+
+fn tar_entry_info<Reader: Read>(entry: &tar::Entry<'_, Reader>, archive_path: String) -> EntryInfo {
+    let entry_type = entry.header().entry_type();
+    EntryInfo {
+        archive_path,
+        size: entry.size(),
+        is_dir: entry_type.is_dir(),
+        is_symlink: entry_type.is_symlink(),
+        mtime: entry.header().mtime().ok(),
+    }
+}
+
+/// Expected digest of a source that is hashed while it is decoded.
+#[cfg(any(feature = "http", feature = "object-store"))]
+struct StreamDigest {
+    expected: String,
+    hasher: std::sync::Arc<std::sync::Mutex<sha2::Sha256>>,
+}
+
+#[cfg(any(feature = "http", feature = "object-store"))]
+impl StreamDigest {
+    fn actual(&self) -> String {
+        use sha2::Digest;
+        let hasher = self
+            .hasher
+            .lock()
+            .map(|hasher| hasher.clone())
+            .unwrap_or_default();
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[cfg(any(feature = "http", feature = "object-store"))]
+struct HashingReader<Reader: std::io::Read> {
+    inner: Reader,
+    hasher: std::sync::Arc<std::sync::Mutex<sha2::Sha256>>,
+}
+
+#[cfg(any(feature = "http", feature = "object-store"))]
+impl<Reader: std::io::Read> std::io::Read for HashingReader<Reader> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let bytes_read = self.inner.read(buffer)?;
+        if let Ok(mut hasher) = self.hasher.lock() {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Hashes the contents of the current tar entry while it is unpacked.
+/// `unpack_tar` sets a hasher before an entry's data is read and takes it after.
+struct EntryHashingReader<Reader: std::io::Read> {
+    inner: Reader,
+    hasher: std::rc::Rc<std::cell::RefCell<Option<sha2::Sha256>>>,
+}
+
+impl<Reader: std::io::Read> std::io::Read for EntryHashingReader<Reader> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let bytes_read = self.inner.read(buffer)?;
+        if let Some(hasher) = self.hasher.borrow_mut().as_mut() {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        Ok(bytes_read)
+    }
 }
 
-pub struct Decoder {
+pub struct Decoder<Progress: ProgressReporter> {
     decoder: DecoderDriver,
-    output_directory: String,
+    pub(crate) output_directory: String,
     input_file_name: String,
     reader_size: u64,
     driver: Driver,
     sha256: Option<String>,
-    #[cfg(feature = "printer")]
-    progress_bar: printer::MultiProgressBar,
+    digest_algorithm: DigestAlgorithm,
+    options: ExtractOptions,
+    driver_options: DriverOptions,
+    cancellation: CancellationToken,
+    threads: usize,
+    buffer_size: usize,
+    #[cfg(any(feature = "http", feature = "object-store"))]
+    stream_digest: Option<StreamDigest>,
+    progress_bar: Progress,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtractStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub bytes_written: u64,
+    /// Files left alone because `ExtractOptions::update` found them up to date.
+    pub up_to_date: u64,
+    pub elapsed: std::time::Duration,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryIssueKind {
+    CrcMismatch,
+    UnsafePath,
+    UnsupportedType,
+    WriteFailed,
+    /// The entry differs from a file that was kept by `ExtractOptions::merge`.
+    Conflict,
+}
+
+#[derive(Debug, Clone)]
+pub struct EntryIssue {
+    pub path: String,
+    pub kind: EntryIssueKind,
+    pub message: String,
 }
 
-pub struct Extracted {
-    #[cfg(feature = "printer")]
-    pub progress_bar: printer::MultiProgressBar,
+/// An entry passed to the `Decoder::extract_with` filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub archive_path: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Seconds since the unix epoch.
+    pub mtime: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractAction {
+    Extract,
+    Skip,
+    /// Extracts the entry to this path relative to the output directory.
+    ExtractAs(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListedEntry {
+    pub archive_path: String,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Listing {
+    pub entries: Vec<ListedEntry>,
+    /// The zip archive comment, or the PAX `comment` global of tar based archives.
+    pub comment: Option<String>,
+    /// Records from PAX global extended headers, in archive order.
+    pub pax_globals: Vec<(String, String)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum VisitedEntryKind {
+    File,
+    Directory,
+    /// Symlinks, hard links and special files.
+    Other,
+}
+
+/// An entry passed to `Decoder::visit_entries`.
+#[derive(Debug, Clone)]
+pub(crate) struct VisitedEntry {
+    pub archive_path: String,
+    pub kind: VisitedEntryKind,
+    pub size: u64,
+    pub mode: Option<u32>,
+    pub mtime: Option<u64>,
+}
+
+/// Format level information returned by `Decoder::metadata`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMetadata {
+    pub driver: Driver,
+    /// Stream compression for tar based archives, or the distinct per entry
+    /// methods (`deflated`, `stored`, ...) used by zip archives.
+    pub compression_methods: Vec<String>,
+    pub entry_count: u64,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    pub comment: Option<String>,
+    pub pax_globals: Vec<(String, String)>,
+    pub is_zip64: bool,
+    pub is_encrypted: bool,
+}
+
+pub struct Extracted<Progress: ProgressReporter> {
+    pub progress_bar: Progress,
     pub files: HashSet<String>,
+    pub warnings: Vec<EntryIssue>,
+    /// Entries skipped under `ExtractOptions::entry_policy` `Continue`.
+    pub failures: Vec<EntryIssue>,
+    /// Entries not extracted because `ExtractOptions::merge` kept a different
+    /// file, when `ExtractOptions::report_conflicts` is set.
+    pub conflicts: Vec<EntryIssue>,
+    /// sha256 of each extracted regular file keyed by the path it was
+    /// extracted to, when `ExtractOptions::compute_digests` is set. The path
+    /// is relative to the output directory, or the full path for entries sent
+    /// elsewhere by `ExtractOptions::routes`.
+    pub digests: std::collections::BTreeMap<String, String>,
+    pub stats: ExtractStats,
 }
 
-impl Decoder {
+impl<Progress: ProgressReporter> Decoder<Progress> {
+    /// The format is taken from the magic number at the start of the file,
+    /// falling back to the extension when it isn't recognized, so archives
+    /// without an extension or with a misleading one are still decoded.
     pub fn new(
         input_file_path: &str,
         sha256: Option<String>,
         destination_directory: &str,
-        #[cfg(feature = "printer")] progress_bar: printer::MultiProgressBar,
+        progress_bar: Progress,
     ) -> anyhow::Result<Self> {
-        let driver =
-            Driver::from_filename(input_file_path).context(format_context!("{input_file_path}"))?;
-
         let reader_size = std::path::Path::new(input_file_path)
             .metadata()
+            .map_err(|err| Error::io(input_file_path, err))
             .context(format_context!("{input_file_path}"))?
             .len();
 
-        let input_file =
-            std::fs::File::open(input_file_path).context(format_context!("{input_file_path}"))?;
+        let mut input_file = std::fs::File::open(input_file_path)
+            .map_err(|err| Error::io(input_file_path, err))
+            .context(format_context!("{input_file_path}"))?;
+        let sniffed =
+            Driver::from_reader(&mut input_file).context(format_context!("{input_file_path}"))?;
+        std::io::Seek::rewind(&mut input_file)
+            .map_err(|err| Error::io(input_file_path, err))
+            .context(format_context!("{input_file_path}"))?;
+        let driver = sniffed
+            .or_else(|| Driver::from_filename(input_file_path))
+            .ok_or_else(|| Error::unknown_format(input_file_path))
+            .context(format_context!("{input_file_path}"))?;
 
-        let decoder = match driver {
-            Driver::Gzip => DecoderDriver::Gzip(flate2::read::GzDecoder::new(input_file)),
-            Driver::Zip => DecoderDriver::Zip(
-                zip::ZipArchive::new(input_file)
-                    .context(format_context!("open zip failed: {input_file_path}"))?,
-            ),
-            Driver::Bzip2 => DecoderDriver::Bzip2(bzip2::read::BzDecoder::new(input_file)),
-            Driver::Xz => DecoderDriver::Xz(xz2::read::XzDecoder::new(input_file)),
-            Driver::SevenZ => DecoderDriver::SevenZ,
-        };
+        let decoder = DecoderDriver::from_seekable(driver, Box::new(input_file), input_file_path)?;
+
+        Ok(Self::from_decoder_driver(
+            decoder,
+            driver,
+            input_file_path,
+            reader_size,
+            sha256,
+            destination_directory,
+            progress_bar,
+        ))
+    }
+
+    /// Extracts an archive that is already in memory, such as one downloaded
+    /// into a buffer or embedded with `include_bytes!`. `sha256` is checked
+    /// before anything is decoded. `entries`, `metadata` and other methods
+    /// that reopen the input aren't available.
+    pub fn from_bytes(
+        contents: impl AsRef<[u8]> + Send + 'static,
+        driver: Driver,
+        sha256: Option<String>,
+        destination_directory: &str,
+        progress_bar: Progress,
+    ) -> anyhow::Result<Self> {
+        let reader_size = contents.as_ref().len() as u64;
+        let name = format!("<{reader_size} bytes>.{}", driver.extension());
+        if let Some(expected) = sha256 {
+            use sha2::Digest;
+            let actual = format!("{:x}", sha2::Sha256::digest(contents.as_ref()));
+            if actual != expected {
+                return Err(Error::DigestMismatch {
+                    name,
+                    expected,
+                    actual,
+                }
+                .into());
+            }
+        }
 
-        let output_directory = destination_directory.to_string();
+        let source = Box::new(std::io::Cursor::new(contents));
+        let decoder = DecoderDriver::from_seekable(driver, source, name.as_str())?;
+        Ok(Self::from_decoder_driver(
+            decoder,
+            driver,
+            name.as_str(),
+            reader_size,
+            None,
+            destination_directory,
+            progress_bar,
+        ))
+    }
 
-        Ok(Self {
+    fn from_decoder_driver(
+        decoder: DecoderDriver,
+        driver: Driver,
+        input_file_name: &str,
+        reader_size: u64,
+        sha256: Option<String>,
+        destination_directory: &str,
+        progress_bar: Progress,
+    ) -> Self {
+        Self {
             decoder,
-            output_directory,
+            output_directory: destination_directory.to_string(),
+            reader_size,
+            input_file_name: input_file_name.to_string(),
+            driver,
+            sha256,
+            digest_algorithm: DigestAlgorithm::Sha256,
+            options: ExtractOptions::default(),
+            driver_options: DriverOptions::default(),
+            cancellation: CancellationToken::default(),
+            threads: 1,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            #[cfg(any(feature = "http", feature = "object-store"))]
+            stream_digest: None,
+            progress_bar,
+        }
+    }
+
+    /// Downloads `url` and extracts it into `destination_directory`. Tar based
+    /// archives are decoded as they download and `sha256` is checked once the
+    /// whole stream has been read (extracted files are removed on a mismatch).
+    /// Zip and 7z archives need random access, so they are downloaded into
+    /// memory and checked before anything is extracted.
+    #[cfg(feature = "http")]
+    pub fn from_url(
+        url: &str,
+        sha256: Option<String>,
+        destination_directory: &str,
+        progress_bar: Progress,
+    ) -> anyhow::Result<Self> {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let driver = Driver::from_filename(path)
+            .ok_or_else(|| Error::unknown_format(path))
+            .context(format_context!("{url}"))?;
+
+        let response = ureq::get(url)
+            .call()
+            .context(format_context!("failed to download {url}"))?;
+        let reader_size = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default();
+
+        Self::from_remote_reader(
+            url,
+            driver,
+            Box::new(response.into_reader()),
             reader_size,
-            input_file_name: input_file_path.to_string(),
+            sha256,
+            destination_directory,
+            progress_bar,
+        )
+    }
+
+    /// Extracts an object such as `s3://bucket/key.tar.gz` or
+    /// `gs://bucket/key.zip`, reading credentials from the environment. The
+    /// digest is verified the same way as `from_url`.
+    #[cfg(feature = "object-store")]
+    pub fn from_object_store(
+        uri: &str,
+        sha256: Option<String>,
+        destination_directory: &str,
+        progress_bar: Progress,
+    ) -> anyhow::Result<Self> {
+        let driver = Driver::from_filename(uri)
+            .ok_or_else(|| Error::unknown_format(uri))
+            .context(format_context!("{uri}"))?;
+        let reader = crate::object_storage::ObjectLocation::parse(uri)
+            .context(format_context!("{uri}"))?
+            .reader()
+            .context(format_context!("{uri}"))?;
+        let reader_size = reader.size();
+        Self::from_remote_reader(
+            uri,
             driver,
+            Box::new(reader),
+            reader_size,
             sha256,
-            #[cfg(feature = "printer")]
+            destination_directory,
+            progress_bar,
+        )
+    }
+
+    #[cfg(any(feature = "http", feature = "object-store"))]
+    fn from_remote_reader(
+        name: &str,
+        driver: Driver,
+        reader: StreamSource,
+        reader_size: u64,
+        sha256: Option<String>,
+        destination_directory: &str,
+        progress_bar: Progress,
+    ) -> anyhow::Result<Self> {
+        let hasher =
+            std::sync::Arc::new(std::sync::Mutex::new(<sha2::Sha256 as sha2::Digest>::new()));
+        let mut reader = HashingReader {
+            inner: reader,
+            hasher: hasher.clone(),
+        };
+
+        let stream_digest = sha256.map(|expected| StreamDigest { expected, hasher });
+        let (decoder, stream_digest) = match driver {
+            Driver::Zip | Driver::SevenZ => {
+                // the size comes from the server, so it only sizes the first allocation
+                let mut contents =
+                    Vec::with_capacity(reader_size.min(driver::MAX_ENTRY_PREALLOCATION) as usize);
+                reader
+                    .read_to_end(&mut contents)
+                    .context(format_context!("failed to download {name}"))?;
+                if let Some(stream_digest) = stream_digest {
+                    let actual_digest = stream_digest.actual();
+                    if actual_digest != stream_digest.expected {
+                        return Err(Error::DigestMismatch {
+                            name: format!("{name} ({} bytes)", contents.len()),
+                            expected: stream_digest.expected.clone(),
+                            actual: actual_digest,
+                        }
+                        .into());
+                    }
+                }
+                let source = Box::new(std::io::Cursor::new(contents));
+                (DecoderDriver::from_seekable(driver, source, name)?, None)
+            }
+            _ => (
+                DecoderDriver::from_stream(driver, Box::new(reader))?,
+                stream_digest,
+            ),
+        };
+
+        let mut decoder = Self::from_decoder_driver(
+            decoder,
+            driver,
+            name,
+            reader_size,
+            None,
+            destination_directory,
             progress_bar,
+        );
+        decoder.stream_digest = stream_digest;
+        Ok(decoder)
+    }
+
+    /// Reads the `artifact.json` manifest embedded by `CreateArchive` without
+    /// extracting anything. Returns `None` if the archive doesn't start with one.
+    /// Tar based archives only decode up to the first entry.
+    #[cfg(feature = "manifest")]
+    pub fn read_manifest(&self) -> anyhow::Result<Option<crate::manifest::ArtifactManifest>> {
+        use crate::manifest::{ArtifactManifest, MANIFEST_FILE_NAME};
+        let input_file = self.input_file_name.as_str();
+        let file = std::fs::File::open(input_file).context(format_context!("{input_file}"))?;
+
+        let contents = match self.driver {
+            #[cfg(feature = "zip")]
+            Driver::Zip => {
+                let mut archive = zip::ZipArchive::new(file)
+                    .context(format_context!("open zip failed: {input_file}"))?;
+                let result = match archive.by_name(MANIFEST_FILE_NAME) {
+                    Ok(mut zip_file) => {
+                        let mut contents = Vec::new();
+                        zip_file
+                            .read_to_end(&mut contents)
+                            .context(format_context!("{input_file}"))?;
+                        Some(contents)
+                    }
+                    Err(zip::result::ZipError::FileNotFound) => None,
+                    Err(err) => return Err(err).context(format_context!("{input_file}")),
+                };
+                result
+            }
+            _ => {
+                let reader = self
+                    .open_tar_stream(file)
+                    .context(format_context!("{input_file}"))?;
+                Self::read_first_tar_entry(reader, MANIFEST_FILE_NAME)?
+            }
+        };
+
+        contents
+            .map(|contents| ArtifactManifest::from_json(contents.as_slice()))
+            .transpose()
+            .context(format_context!("{input_file}"))
+    }
+
+    /// Lists the entries along with the archive comment and PAX global headers
+    /// without extracting anything.
+    pub fn list(&self) -> anyhow::Result<Listing> {
+        let input_file = self.input_file_name.as_str();
+        let file = std::fs::File::open(input_file).context(format_context!("{input_file}"))?;
+        let mut listing = Listing::default();
+
+        #[cfg(feature = "zip")]
+        if self.driver == Driver::Zip {
+            let mut archive = zip::ZipArchive::new(file)
+                .context(format_context!("open zip failed: {input_file}"))?;
+            let comment = String::from_utf8_lossy(archive.comment()).to_string();
+            listing.comment = (!comment.is_empty()).then_some(comment);
+            for index in 0..archive.len() {
+                let zip_file = archive
+                    .by_index_raw(index)
+                    .context(format_context!("{input_file}"))?;
+                listing.entries.push(ListedEntry {
+                    archive_path: zip_file.name().to_string(),
+                    size: zip_file.size(),
+                    is_dir: zip_file.is_dir(),
+                });
+            }
+            return Ok(listing);
+        }
+
+        let reader = self
+            .open_tar_stream(file)
+            .context(format_context!("{input_file}"))?;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context(format_context!("{input_file}"))? {
+            let mut entry = entry.context(format_context!("{input_file}"))?;
+            let entry_type = entry.header().entry_type();
+            if entry_type == tar::EntryType::XGlobalHeader {
+                let Some(extensions) = entry
+                    .pax_extensions()
+                    .context(format_context!("{input_file}"))?
+                else {
+                    continue;
+                };
+                for extension in extensions {
+                    let extension = extension.context(format_context!("{input_file}"))?;
+                    let key = extension
+                        .key()
+                        .context(format_context!("{input_file}"))?
+                        .to_string();
+                    let value = String::from_utf8_lossy(extension.value_bytes()).to_string();
+                    if key == "comment" {
+                        listing.comment = Some(value.clone());
+                    }
+                    listing.pax_globals.push((key, value));
+                }
+                continue;
+            }
+            listing.entries.push(ListedEntry {
+                archive_path: entry
+                    .path()
+                    .context(format_context!("{input_file}"))?
+                    .to_string_lossy()
+                    .to_string(),
+                size: entry.size(),
+                is_dir: entry_type.is_dir(),
+            });
+        }
+        Ok(listing)
+    }
+
+    /// Summarizes the archive without extracting it. Tar based archives are
+    /// decompressed once to count their entries.
+    pub fn metadata(&self) -> anyhow::Result<ArchiveMetadata> {
+        let input_file = self.input_file_name.as_str();
+        let listing = self.list().context(format_context!("{input_file}"))?;
+        let mut metadata = ArchiveMetadata {
+            driver: self.driver,
+            compression_methods: Vec::new(),
+            entry_count: listing.entries.len() as u64,
+            uncompressed_size: listing.entries.iter().map(|entry| entry.size).sum(),
+            compressed_size: self.reader_size,
+            comment: listing.comment,
+            pax_globals: listing.pax_globals,
+            is_zip64: false,
+            is_encrypted: false,
+        };
+
+        if self.driver != Driver::Zip {
+            let method = match self.driver {
+                Driver::Bzip2 => "bzip2",
+                Driver::Xz => "xz",
+                Driver::SevenZ => "7z",
+                _ => "gzip",
+            };
+            metadata.compression_methods.push(method.to_string());
+            return Ok(metadata);
+        }
+
+        #[cfg(feature = "zip")]
+        {
+            let mut file =
+                std::fs::File::open(input_file).context(format_context!("{input_file}"))?;
+            metadata.is_zip64 =
+                has_zip64_locator(&mut file).context(format_context!("{input_file}"))?;
+            let mut archive = zip::ZipArchive::new(file)
+                .context(format_context!("open zip failed: {input_file}"))?;
+            let mut methods = std::collections::BTreeSet::new();
+            for index in 0..archive.len() {
+                let zip_file = archive
+                    .by_index_raw(index)
+                    .context(format_context!("{input_file}"))?;
+                methods.insert(format!("{:?}", zip_file.compression()).to_lowercase());
+                metadata.is_encrypted |= zip_file.encrypted();
+            }
+            metadata.compression_methods = methods.into_iter().collect();
+        }
+        Ok(metadata)
+    }
+
+    /// sha256 of every regular file entry keyed by archive path, computed
+    /// from the decompressed stream without extracting anything.
+    pub fn entry_digests(&self) -> anyhow::Result<std::collections::BTreeMap<String, String>> {
+        let mut digests = std::collections::BTreeMap::new();
+        self.visit_entries(|entry, reader| {
+            if entry.kind == VisitedEntryKind::File {
+                let digest = driver::sha256_reader(reader, &self.cancellation)
+                    .context(format_context!("{}", entry.archive_path))?;
+                digests.insert(entry.archive_path, digest);
+            }
+            Ok(())
+        })
+        .context(format_context!("{}", self.input_file_name))?;
+        Ok(digests)
+    }
+
+    /// Streams the entries of a tar based archive without writing anything to
+    /// disk. Only the current entry is held in memory, except for tar.7z
+    /// which is decompressed up front.
+    pub fn entries(&self) -> anyhow::Result<EntryStream> {
+        let input_file = self.input_file_name.as_str();
+        if self.driver == Driver::Zip {
+            return Err(Error::UnsupportedFormat {
+                driver: self.driver,
+                reason: "zip archives need random access and can't be streamed".to_string(),
+            })
+            .context(format_context!("{input_file}"));
+        }
+        let file = std::fs::File::open(input_file)
+            .map_err(|err| Error::io(input_file, err))
+            .context(format_context!("{input_file}"))?;
+        let reader = self
+            .open_tar_stream(file)
+            .context(format_context!("{input_file}"))?;
+        Ok(EntryStream {
+            archive: tar::Archive::new(reader),
+            input_file_name: self.input_file_name.clone(),
+            cancellation: self.cancellation.clone(),
         })
     }
 
+    /// Calls `visit` with each entry in archive order and a reader over its
+    /// decompressed contents, without writing anything to disk.
+    pub(crate) fn visit_entries(
+        &self,
+        mut visit: impl FnMut(VisitedEntry, &mut dyn Read) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let input_file = self.input_file_name.as_str();
+        let file = std::fs::File::open(input_file).context(format_context!("{input_file}"))?;
+
+        #[cfg(feature = "zip")]
+        if self.driver == Driver::Zip {
+            let mut archive = zip::ZipArchive::new(file)
+                .context(format_context!("open zip failed: {input_file}"))?;
+            for index in 0..archive.len() {
+                self.cancellation.check()?;
+                let mut zip_file = archive
+                    .by_index(index)
+                    .context(format_context!("{input_file}"))?;
+                let kind = if zip_file.is_dir() {
+                    VisitedEntryKind::Directory
+                } else if zip_file.is_symlink() {
+                    VisitedEntryKind::Other
+                } else {
+                    VisitedEntryKind::File
+                };
+                let entry = VisitedEntry {
+                    archive_path: zip_file.name().to_string(),
+                    kind,
+                    size: zip_file.size(),
+                    mode: zip_file.unix_mode(),
+                    mtime: zip_mtime(&zip_file)
+                        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_secs()),
+                };
+                visit(entry, &mut zip_file)?;
+            }
+            return Ok(());
+        }
+
+        let reader = self
+            .open_tar_stream(file)
+            .context(format_context!("{input_file}"))?;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context(format_context!("{input_file}"))? {
+            self.cancellation.check()?;
+            let mut entry = entry.context(format_context!("{input_file}"))?;
+            let entry_type = entry.header().entry_type();
+            let kind = match entry_type {
+                tar::EntryType::XGlobalHeader => continue,
+                entry_type if entry_type.is_file() => VisitedEntryKind::File,
+                entry_type if entry_type.is_dir() => VisitedEntryKind::Directory,
+                _ => VisitedEntryKind::Other,
+            };
+            let visited = VisitedEntry {
+                archive_path: entry
+                    .path()
+                    .context(format_context!("{input_file}"))?
+                    .to_string_lossy()
+                    .to_string(),
+                kind,
+                size: entry.size(),
+                mode: entry.header().mode().ok(),
+                mtime: entry.header().mtime().ok(),
+            };
+            visit(visited, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    /// Opens the tar stream of a tar based or 7z archive.
+    pub(crate) fn open_tar_stream(&self, file: std::fs::File) -> anyhow::Result<StreamSource> {
+        if self.driver == Driver::SevenZ {
+            let contents =
+                crate::memory::seven_z_tar_contents(file).context(format_context!(""))?;
+            return Ok(Box::new(std::io::Cursor::new(contents)));
+        }
+        let reader: StreamSource = Box::new(std::io::BufReader::new(file));
+        match DecoderDriver::from_stream(self.driver, reader)? {
+            DecoderDriver::Gzip(decoder) => Ok(Box::new(decoder)),
+            #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+            DecoderDriver::Bzip2(decoder) => Ok(Box::new(decoder)),
+            #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+            DecoderDriver::Xz(decoder) => Ok(Box::new(decoder)),
+            #[cfg(feature = "zip")]
+            DecoderDriver::Zip(_) => unreachable!("from_stream only returns stream decoders"),
+            DecoderDriver::SevenZ(_) => unreachable!("from_stream only returns stream decoders"),
+        }
+    }
+
+    #[cfg(feature = "manifest")]
+    fn read_first_tar_entry<Reader: Read>(
+        reader: Reader,
+        archive_path: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut archive = tar::Archive::new(reader);
+        let mut entries = archive.entries().context(format_context!(""))?;
+        let Some(entry) = entries.next() else {
+            return Ok(None);
+        };
+        let mut entry = entry.context(format_context!(""))?;
+        if entry.path().context(format_context!(""))? != std::path::Path::new(archive_path) {
+            return Ok(None);
+        }
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .context(format_context!("{archive_path}"))?;
+        Ok(Some(contents))
+    }
+
+    pub fn with_options(mut self, options: ExtractOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets the thread count when `threads` is given. Options that only
+    /// apply to compression are ignored.
+    pub fn with_driver_options(mut self, driver_options: DriverOptions) -> Self {
+        if let Some(threads) = driver_options.threads {
+            self = self.with_threads(threads);
+        }
+        self.driver_options = driver_options;
+        self
+    }
+
+    /// How the digest passed to `new` was computed. Archives read from
+    /// memory or a stream are always checked with sha256.
+    pub fn with_digest_algorithm(mut self, digest_algorithm: DigestAlgorithm) -> Self {
+        self.digest_algorithm = digest_algorithm;
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Size of the buffer reused for reading decompressed data and zip entries.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// With more than one thread, gzip, bzip2 and xz streams are decompressed on
+    /// a worker thread while entries are unpacked, and `threads` bounds the
+    /// number of decoded chunks buffered between the two. The bundled liblzma
+    /// has no block-parallel decoder, so a single stream never uses more than
+    /// one decompression thread. Defaults to `1`, which does all the work,
+    /// including 7z decoding, on the calling thread and keeps
+    /// `ExtractOptions::check_free_space` in effect for tar based archives.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        // wasm targets have no threads to pipeline with
+        self.threads = if cfg!(target_family = "wasm") {
+            1
+        } else {
+            threads.max(1)
+        };
+        self
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(decoder, state, progress_bar, cancellation))
+    )]
+    fn unpack_pipelined<Decoder: std::io::Read + Send + 'static>(
+        decoder: Decoder,
+        driver: Driver,
+        threads: usize,
+        buffer_size: usize,
+        state: &mut ExtractState,
+        progress_bar: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        progress_bar.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Decoding,
+        });
+        let reader =
+            PipelinedReader::spawn(decoder, driver, threads, buffer_size, cancellation.clone());
+        let result = Self::unpack_tar(reader, state, progress_bar, cancellation);
+        state.check_cancelled(cancellation)?;
+        result
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(decoder, progress_bar, cancellation))
+    )]
     fn extract_to_tar_bytes<Decoder: std::io::Read>(
         mut decoder: Decoder,
         reader_size: u64,
         driver: Driver,
-        #[cfg(feature = "printer")] progress_bar: &mut printer::MultiProgressBar,
+        buffer_size: usize,
+        progress_bar: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
     ) -> anyhow::Result<Vec<u8>> {
-        let mut result = Vec::with_capacity(reader_size as usize);
-        let mut buffer = [0; 8192];
+        // remote archives report a size the server chose
+        let mut result =
+            Vec::with_capacity(reader_size.min(driver::MAX_ENTRY_PREALLOCATION) as usize);
+        let mut buffer = vec![0; buffer_size];
+        let mut byte_progress = ByteProgress::new(None);
 
-        #[cfg(feature = "printer")]
-        driver::update_status(
-            progress_bar,
-            UpdateStatus {
-                detail: Some(format!("creating {} as binary blob", driver.extension())),
-                total: Some(200),
-                ..Default::default()
-            },
-        );
+        progress_bar.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Decoding,
+        });
+        progress_bar.update_status(UpdateStatus {
+            detail: Some(format!("creating {} as binary blob", driver.extension())),
+            total: Some(200),
+            ..Default::default()
+        });
 
-        while let Ok(bytes_read) = decoder.read(&mut buffer) {
+        loop {
+            cancellation.check()?;
+            let bytes_read = match decoder.read(buffer.as_mut_slice()) {
+                Ok(bytes_read) => bytes_read,
+                Err(err) => {
+                    let last_entry = last_tar_entry(result.as_slice());
+                    return Err(format_error!(
+                        "failed to decode {} at offset {} ({}): {err}",
+                        driver.extension(),
+                        result.len(),
+                        describe_last_entry(last_entry.as_deref())
+                    ));
+                }
+            };
             if bytes_read == 0 {
                 break;
             }
             result.extend_from_slice(&buffer[..bytes_read]);
+            byte_progress.add(bytes_read as u64);
 
-            #[cfg(feature = "printer")]
-            driver::update_status(
-                progress_bar,
-                UpdateStatus {
-                    increment: Some(1),
-                    ..Default::default()
-                },
-            );
+            progress_bar.update_status(UpdateStatus {
+                increment: Some(1),
+                ..byte_progress.update_status()
+            });
         }
 
+        trace_event!(bytes = result.len(), "decoded tar stream");
+
         Ok(result)
     }
 
-    pub fn extract(self) -> anyhow::Result<Extracted> {
+    pub fn extract(self) -> anyhow::Result<Extracted<Progress>> {
+        self.extract_with(|_| ExtractAction::Extract)
+    }
+
+    /// Like `extract` but calls `filter` for each entry, in archive order, to
+    /// decide whether to skip it or where to put it. Directories that are
+    /// skipped are still created when an entry inside them is extracted.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(input = %self.input_file_name, output = %self.output_directory)
+        )
+    )]
+    pub fn extract_with(
+        self,
+        mut filter: impl FnMut(&EntryInfo) -> ExtractAction,
+    ) -> anyhow::Result<Extracted<Progress>> {
+        let start = std::time::Instant::now();
         let reader_size = self.reader_size;
         let driver = self.driver;
+        self.driver_options
+            .check(driver)
+            .context(format_context!("{}", self.input_file_name))?;
         let input_file: String = self.input_file_name.clone();
-        let output_directory = self.output_directory.clone();
-
-        #[cfg(feature = "printer")]
+        let cancellation = self.cancellation.clone();
+        let threads = self.threads;
+        let buffer_size = self.buffer_size;
         let mut progress_bar = self.progress_bar;
 
         if let Some(digest) = self.sha256.as_ref() {
-            let actual_digest = driver::digest_file(
+            let actual_digest = driver::digest_file_with(
                 input_file.as_str(),
-                #[cfg(feature = "printer")]
+                self.digest_algorithm,
                 &mut progress_bar,
+                &cancellation,
             )?;
+            trace_event!(digest = %actual_digest, "verifying digest");
             if actual_digest != *digest {
-                return Err(format_error!(
-                    "digest mismatch: expected: {} actual: {}",
-                    digest,
-                    actual_digest
-                ));
+                return Err(Error::DigestMismatch {
+                    name: format!("{input_file} ({reader_size} bytes)"),
+                    expected: digest.clone(),
+                    actual: actual_digest,
+                }
+                .into());
             }
         }
 
-        let tar_bytes = match self.decoder {
-            DecoderDriver::Gzip(decoder) => Some(Self::extract_to_tar_bytes(
-                decoder,
-                reader_size,
-                driver,
-                #[cfg(feature = "printer")]
-                &mut progress_bar,
-            )?),
-            DecoderDriver::Zip(mut decoder) => {
-                let file_names: Vec<String> = decoder.file_names().map(|e| e.to_string()).collect();
-
-                #[cfg(feature = "printer")]
-                driver::update_status(
-                    &mut progress_bar,
-                    UpdateStatus {
-                        detail: Some("Extracting (zip)".to_string()),
-                        total: Some(file_names.len() as u64),
-                        ..Default::default()
-                    },
-                );
-
-                for file in file_names {
-                    let mut zip_file = decoder
-                        .by_name(file.as_str())
-                        .context(format_context!("{file:?}"))?;
+        let mut state = ExtractState {
+            output_directory: self.output_directory.clone(),
+            options: self.options,
+            stats: ExtractStats::default(),
+            warnings: Vec::new(),
+            failures: Vec::new(),
+            conflicts: Vec::new(),
+            written: Vec::new(),
+            created: Vec::new(),
+            digests: std::collections::BTreeMap::new(),
+            filter: &mut filter,
+            last_entry: None,
+            #[cfg(feature = "zip")]
+            buffer: Vec::with_capacity(buffer_size),
+            directory_mtimes: Vec::new(),
+            modes: Vec::new(),
+        };
 
-                    #[cfg(feature = "printer")]
-                    driver::update_status(
+        let result = (|| -> anyhow::Result<()> {
+            let tar_bytes = match self.decoder {
+                DecoderDriver::Gzip(decoder) if threads > 1 => {
+                    Self::unpack_pipelined(
+                        decoder,
+                        driver,
+                        threads,
+                        buffer_size,
+                        &mut state,
                         &mut progress_bar,
-                        UpdateStatus {
-                            detail: Some(file.clone()),
-                            increment: Some(1),
-                            ..Default::default()
-                        },
-                    );
-
-                    let mut buffer = Vec::new();
-                    let destination_path = format!("{}/{}", self.output_directory, zip_file.name());
-                    if zip_file.is_file() {
-                        let dest_parent = std::path::Path::new(destination_path.as_str())
-                            .parent()
-                            .context(format_context!("{destination_path}"))?;
-
-                        std::fs::create_dir_all(dest_parent)
-                            .context(format_context!("failed to create {dest_parent:?}"))?;
-
-                        let mut file = std::fs::File::create(destination_path.as_str())
-                            .context(format_context!("failed to create {destination_path}"))?;
-                        use std::io::Write;
-                        zip_file.read_to_end(&mut buffer).context(format_context!(
-                            "failed to read zip for {destination_path}"
-                        ))?;
-                        file.write(buffer.as_slice())
-                            .context(format_context!("failed to write {destination_path}"))?;
-                    }
+                        &cancellation,
+                    )?;
+                    None
                 }
-
-                decoder
-                    .extract(self.output_directory.as_str())
-                    .context(format_context!("{output_directory}"))?;
-
-                None
-            }
-            DecoderDriver::Bzip2(decoder) => Some(Self::extract_to_tar_bytes(
-                decoder,
-                reader_size,
-                driver,
-                #[cfg(feature = "printer")]
-                &mut progress_bar,
-            )?),
-            DecoderDriver::Xz(decoder) => Some(Self::extract_to_tar_bytes(
-                decoder,
-                reader_size,
-                driver,
-                #[cfg(feature = "printer")]
-                &mut progress_bar,
-            )?),
-            DecoderDriver::SevenZ => {
-                #[cfg(feature = "printer")]
-                driver::update_status(
+                #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+                DecoderDriver::Bzip2(decoder) if threads > 1 => {
+                    Self::unpack_pipelined(
+                        decoder,
+                        driver,
+                        threads,
+                        buffer_size,
+                        &mut state,
+                        &mut progress_bar,
+                        &cancellation,
+                    )?;
+                    None
+                }
+                #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+                DecoderDriver::Xz(decoder) if threads > 1 => {
+                    Self::unpack_pipelined(
+                        decoder,
+                        driver,
+                        threads,
+                        buffer_size,
+                        &mut state,
+                        &mut progress_bar,
+                        &cancellation,
+                    )?;
+                    None
+                }
+                DecoderDriver::Gzip(decoder) => Some(Self::extract_to_tar_bytes(
+                    decoder,
+                    reader_size,
+                    driver,
+                    buffer_size,
+                    &mut progress_bar,
+                    &cancellation,
+                )?),
+                #[cfg(feature = "zip")]
+                DecoderDriver::Zip(mut decoder) => {
+                    Self::extract_zip(
+                        &mut decoder,
+                        self.driver_options.password.as_deref(),
+                        &mut state,
+                        &mut progress_bar,
+                        &cancellation,
+                    )?;
+                    None
+                }
+                #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+                DecoderDriver::Bzip2(decoder) => Some(Self::extract_to_tar_bytes(
+                    decoder,
+                    reader_size,
+                    driver,
+                    buffer_size,
+                    &mut progress_bar,
+                    &cancellation,
+                )?),
+                #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+                DecoderDriver::Xz(decoder) => Some(Self::extract_to_tar_bytes(
+                    decoder,
+                    reader_size,
+                    driver,
+                    buffer_size,
                     &mut progress_bar,
-                    UpdateStatus {
+                    &cancellation,
+                )?),
+                DecoderDriver::SevenZ(source) => {
+                    progress_bar.on_event(ProgressEvent::PhaseChanged {
+                        phase: Phase::Decoding,
+                    });
+                    progress_bar.update_status(UpdateStatus {
                         detail: Some("creating tar as binary blob".to_string()),
                         total: Some(200),
                         ..Default::default()
-                    },
-                );
+                    });
 
-                let handle = std::thread::spawn(move || -> anyhow::Result<Vec<u8>> {
-                    let temporary_file_path =
-                        format!("{output_directory}/{}", SEVEN_Z_TAR_FILENAME);
-                    let input_file = std::fs::File::open(input_file.as_str())
-                        .context(format_context!("{input_file}"))?;
-                    sevenz_rust::decompress(input_file, output_directory.as_str()).context(
-                        format_context!("{temporary_file_path} -> {output_directory}"),
-                    )?;
-                    let result = std::fs::read(temporary_file_path.as_str())
-                        .context(format_context!("{temporary_file_path}"));
+                    let work = move || -> anyhow::Result<Vec<u8>> {
+                        crate::memory::seven_z_tar_contents(source)
+                            .context(format_context!("{input_file}"))
+                    };
 
-                    std::fs::remove_file(temporary_file_path.as_str())
-                        .context(format_context!("{temporary_file_path}"))?;
+                    let tar_contents =
+                        driver::run_worker(work, threads, &mut progress_bar, &cancellation)
+                            .context(format_context!(""))?;
 
-                    result
-                });
+                    Some(tar_contents)
+                }
+            };
 
-                let tar_contents = driver::wait_handle(
-                    handle,
-                    #[cfg(feature = "printer")]
+            if let Some(tar_bytes) = tar_bytes {
+                if state.options.check_free_space {
+                    check_free_space(
+                        state.output_directory.as_str(),
+                        tar_file_bytes(tar_bytes.as_slice()),
+                    )?;
+                }
+                Self::unpack_tar(
+                    tar_bytes.as_slice(),
+                    &mut state,
                     &mut progress_bar,
-                )
-                .context(format_context!(""))?;
-
-                Some(tar_contents)
+                    &cancellation,
+                )?;
             }
-        };
-
-        let output_directory = self.output_directory.clone();
 
-        if let Some(tar_bytes) = tar_bytes {
-            let handle = std::thread::spawn(move || -> anyhow::Result<()> {
-                let mut archive = tar::Archive::new(tar_bytes.as_slice());
-                archive
-                    .unpack(output_directory.as_str())
-                    .context(format_context!("{output_directory}"))?;
-
-                Ok(())
-            });
+            #[cfg(any(feature = "http", feature = "object-store"))]
+            if let Some(stream_digest) = self.stream_digest.as_ref() {
+                let actual_digest = stream_digest.actual();
+                if actual_digest != stream_digest.expected {
+                    remove_written(&state.written);
+                    return Err(Error::DigestMismatch {
+                        name: format!("{} ({} bytes)", self.input_file_name, self.reader_size),
+                        expected: stream_digest.expected.clone(),
+                        actual: actual_digest,
+                    }
+                    .into());
+                }
+            }
 
-            #[cfg(feature = "printer")]
-            driver::update_status(
-                &mut progress_bar,
-                UpdateStatus {
-                    detail: Some("Unpacking (tar)".to_string()),
-                    ..Default::default()
-                },
-            );
+            if state.options.nested_depth > 0 {
+                Self::extract_nested(&mut state, &mut progress_bar, &cancellation)?;
+            }
 
-            driver::wait_handle(
-                handle,
-                #[cfg(feature = "printer")]
-                &mut progress_bar,
-            )
-            .context(format_context!(""))?;
+            state
+                .finish()
+                .context(format_context!("{}", self.output_directory))?;
+            Ok(())
+        })();
+        if let Err(error) = result {
+            if state.options.rollback_on_failure {
+                state.rollback();
+            }
+            return Err(error);
         }
 
-        let walk_dir: Vec<_> = walkdir::WalkDir::new(self.output_directory.as_str())
+        let output_directory = paths::long_path(self.output_directory.as_str());
+        let walk_dir: Vec<_> = walkdir::WalkDir::new(output_directory.as_path())
             .into_iter()
             .filter_map(|entry| entry.ok())
             .collect();
 
-        let prefix = format!("{}/", self.output_directory);
         let mut files = HashSet::new();
         for entry in walk_dir {
             if entry.file_type().is_dir() {
                 continue;
             }
-            let full_path = entry.path().to_string_lossy().to_string();
-            if let Some(relative_path) = full_path.strip_prefix(prefix.as_str()) {
-                files.insert(relative_path.to_string());
+            if let Ok(relative_path) = entry.path().strip_prefix(output_directory.as_path()) {
+                files.insert(paths::to_archive_path(relative_path));
             }
         }
 
+        trace_event!(files = files.len(), "extracted");
+
         Ok(Extracted {
-            #[cfg(feature = "printer")]
             progress_bar,
             files,
+            warnings: state.warnings,
+            failures: state.failures,
+            conflicts: state.conflicts,
+            digests: state.digests,
+            stats: ExtractStats {
+                elapsed: start.elapsed(),
+                ..state.stats
+            },
         })
     }
+
+    /// Extracts the archives written so far, for `ExtractOptions::nested_depth`.
+    fn extract_nested(
+        state: &mut ExtractState,
+        progress_bar: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let archives: Vec<(String, String)> = state
+            .written
+            .iter()
+            .filter(|path| {
+                std::fs::symlink_metadata(paths::long_path(path.as_path()))
+                    .is_ok_and(|metadata| metadata.is_file())
+            })
+            .filter_map(|path| {
+                let path = path.to_string_lossy().to_string();
+                let (directory, driver) = Driver::split_filename(path.as_str())?;
+                let directory = directory.to_string();
+                (!driver.is_package()).then_some((path, directory))
+            })
+            .collect();
+
+        for (archive, directory) in archives {
+            cancellation.check()?;
+            let directory = directory.as_str();
+            let prefix = match std::path::Path::new(directory)
+                .strip_prefix(state.output_directory.as_str())
+            {
+                Ok(relative_path) => paths::to_archive_path(relative_path),
+                Err(_) => directory.to_string(),
+            };
+            let options = ExtractOptions {
+                nested_depth: state.options.nested_depth - 1,
+                routes: Vec::new(),
+                ..state.options.clone()
+            };
+            let extracted = Decoder::new(archive.as_str(), None, directory, &mut *progress_bar)
+                .and_then(|decoder| {
+                    decoder
+                        .with_options(options)
+                        .with_cancellation(cancellation.clone())
+                        .extract()
+                })
+                .context(format_context!(
+                    "failed to extract nested archive {archive}"
+                ));
+            let extracted = match extracted {
+                Ok(extracted) => extracted,
+                Err(error) => {
+                    state.fail(prefix.as_str(), error)?;
+                    continue;
+                }
+            };
+
+            let nested_path = |path: &str| format!("{prefix}/{path}");
+            for (issues, nested_issues) in [
+                (&mut state.warnings, extracted.warnings),
+                (&mut state.failures, extracted.failures),
+                (&mut state.conflicts, extracted.conflicts),
+            ] {
+                issues.extend(nested_issues.into_iter().map(|issue| EntryIssue {
+                    path: nested_path(issue.path.as_str()),
+                    ..issue
+                }));
+            }
+            state.digests.extend(
+                extracted
+                    .digests
+                    .into_iter()
+                    .map(|(path, digest)| (nested_path(path.as_str()), digest)),
+            );
+            state.stats.files += extracted.stats.files;
+            state.stats.directories += extracted.stats.directories;
+            state.stats.symlinks += extracted.stats.symlinks;
+            state.stats.bytes_written += extracted.stats.bytes_written;
+            state.stats.up_to_date += extracted.stats.up_to_date;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "zip")]
+    fn extract_zip(
+        decoder: &mut zip::ZipArchive<SeekableSource>,
+        password: Option<&str>,
+        state: &mut ExtractState,
+        progress_bar: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        trace_event!(entries = decoder.len(), "extracting zip");
+        let file_names: Vec<String> = decoder.file_names().map(|e| e.to_string()).collect();
+
+        progress_bar.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Extracting,
+        });
+        progress_bar.update_status(UpdateStatus {
+            detail: Some("Extracting (zip)".to_string()),
+            total: Some(file_names.len() as u64),
+            ..Default::default()
+        });
+
+        let bytes_total = (0..decoder.len())
+            .filter_map(|index| decoder.by_index_raw(index).ok().map(|file| file.size()))
+            .sum();
+        if state.options.check_free_space {
+            check_free_space(state.output_directory.as_str(), bytes_total)?;
+        }
+        let mut byte_progress = ByteProgress::new(Some(bytes_total));
+
+        for file in file_names {
+            state.check_cancelled(cancellation)?;
+
+            let zip_file = match password {
+                Some(password) => decoder.by_name_decrypt(file.as_str(), password.as_bytes()),
+                None => decoder.by_name(file.as_str()),
+            };
+            let mut zip_file = zip_file.context(format_context!(
+                "{file:?} ({})",
+                state.describe_last_entry()
+            ))?;
+
+            byte_progress.add(zip_file.size());
+            progress_bar.update_status(UpdateStatus {
+                detail: Some(file.clone()),
+                increment: Some(1),
+                ..byte_progress.update_status()
+            });
+
+            if let Err(error) =
+                Self::extract_zip_entry(file.as_str(), &mut zip_file, state, progress_bar)
+            {
+                state.fail(file.as_str(), error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "zip")]
+    fn extract_zip_entry(
+        file: &str,
+        zip_file: &mut zip::read::ZipFile<'_>,
+        state: &mut ExtractState,
+        progress_bar: &mut dyn ProgressReporter,
+    ) -> anyhow::Result<()> {
+        let Some(enclosed_name) = zip_file.enclosed_name() else {
+            state.warn(
+                file,
+                EntryIssueKind::UnsafePath,
+                "entry path escapes the output directory".to_string(),
+            );
+            return Ok(());
+        };
+        let entry_info = EntryInfo {
+            archive_path: file.to_string(),
+            size: zip_file.size(),
+            is_dir: zip_file.is_dir(),
+            is_symlink: zip_file.is_symlink(),
+            mtime: zip_mtime(zip_file)
+                .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs()),
+        };
+        let Some((base_directory, enclosed_name)) =
+            state.route(&entry_info, enclosed_name.as_path())?
+        else {
+            return Ok(());
+        };
+        let destination = paths::join_path(base_directory.as_str(), &enclosed_name);
+        let destination_path = destination.to_string_lossy().to_string();
+
+        if zip_file.is_dir() {
+            state.record_created(destination.as_path());
+            std::fs::create_dir_all(paths::long_path(destination.as_path()))
+                .context(format_context!("failed to create {destination_path}"))?;
+            if let Some(modified) = zip_mtime(zip_file) {
+                state.record_directory_mtime(destination.as_path(), modified);
+            }
+            state.record_mode(destination.as_path(), zip_file.unix_mode(), true);
+            state.stats.directories += 1;
+            return Ok(());
+        }
+
+        // zip entries are written directly rather than through `unpack_in`,
+        // so this also keeps the write below inside the output
+        if !state.is_contained(file, base_directory.as_str(), destination.as_path())? {
+            return Ok(());
+        }
+
+        let is_symlink = zip_file.is_symlink();
+        let size = zip_file.size();
+        let keeps_existing = state.keeps_existing(file, destination.as_path(), || {
+            if is_symlink {
+                let mut target = String::new();
+                zip_file.read_to_string(&mut target)?;
+                Ok(symlink_equals(
+                    destination.as_path(),
+                    std::path::Path::new(target.as_str()),
+                ))
+            } else {
+                file_equals(destination.as_path(), size, zip_file)
+            }
+        })?;
+        if keeps_existing {
+            return Ok(());
+        }
+
+        let existing = if is_symlink {
+            None
+        } else {
+            existing_file(destination.as_path())
+        };
+        if state
+            .options
+            .update
+            .is_up_to_date(existing, entry_info.mtime)
+        {
+            state.stats.up_to_date += 1;
+            return Ok(());
+        }
+
+        let entry_start = std::time::Instant::now();
+        progress_bar.on_event(ProgressEvent::EntryStarted {
+            archive_path: file.to_string(),
+        });
+
+        // reuse the allocation from earlier entries
+        let mut buffer = std::mem::take(&mut state.buffer);
+        buffer.clear();
+        let expected_crc32 = zip_file.crc32();
+        // AES entries written as AE-2 store no CRC; the zip reader checks
+        // their authentication code instead and fails the read on mismatch
+        let has_crc32 = !(zip_file.encrypted() && expected_crc32 == 0);
+        let read_result = zip_file.read_to_end(&mut buffer);
+        let actual_crc32 = crc32fast::hash(buffer.as_slice());
+        if read_result.is_err() || (has_crc32 && actual_crc32 != expected_crc32) {
+            let crc_message = format!("expected: {expected_crc32:08x} actual: {actual_crc32:08x}");
+            match state.options.crc_policy {
+                ErrorPolicy::Abort => {
+                    return Err(Error::CorruptEntry {
+                        name: file.to_string(),
+                        reason: format!(
+                            "CRC32 validation failed at offset {}: {crc_message} {:?} ({})",
+                            zip_file.header_start(),
+                            read_result.err(),
+                            state.describe_last_entry()
+                        ),
+                    }
+                    .into());
+                }
+                ErrorPolicy::Continue => {
+                    state.warn(file, EntryIssueKind::CrcMismatch, crc_message);
+                    state.buffer = buffer;
+                    return Ok(());
+                }
+            }
+        }
+
+        if state.options.update.compares(existing, buffer.len() as u64)
+            && file_equals(
+                destination.as_path(),
+                buffer.len() as u64,
+                &mut buffer.as_slice(),
+            )
+            .context(format_context!("failed to compare {destination_path}"))?
+        {
+            if state.options.compute_digests {
+                use sha2::Digest;
+                state.digests.insert(
+                    state.digest_key(base_directory.as_str(), enclosed_name.as_path()),
+                    format!("{:x}", sha2::Sha256::digest(buffer.as_slice())),
+                );
+            }
+            state.stats.up_to_date += 1;
+            state.buffer = buffer;
+            return Ok(());
+        }
+
+        let long_destination_path = paths::long_path(destination.as_path());
+        let dest_parent = long_destination_path
+            .parent()
+            .context(format_context!("{destination_path}"))?;
+
+        state.record_created(destination.as_path());
+        std::fs::create_dir_all(dest_parent)
+            .context(format_context!("failed to create {dest_parent:?}"))?;
+
+        state.written.push(destination.clone());
+        if std::fs::symlink_metadata(long_destination_path.as_path())
+            .is_ok_and(|metadata| metadata.is_symlink())
+        {
+            // replace the link rather than writing to its target
+            std::fs::remove_file(long_destination_path.as_path())
+                .context(format_context!("failed to replace {destination_path}"))?;
+        }
+        if zip_file.is_symlink() {
+            let target = String::from_utf8_lossy(buffer.as_slice()).to_string();
+            create_symlink(target.as_str(), destination_path.as_str())?;
+            state.stats.symlinks += 1;
+        } else {
+            let mut output_file = std::fs::File::create(long_destination_path.as_path())
+                .context(format_context!("failed to create {destination_path}"))?;
+            use std::io::Write;
+            output_file
+                .write_all(buffer.as_slice())
+                .context(format_context!("failed to write {destination_path}"))?;
+            if state.options.compute_digests {
+                use sha2::Digest;
+                state.digests.insert(
+                    state.digest_key(base_directory.as_str(), enclosed_name.as_path()),
+                    format!("{:x}", sha2::Sha256::digest(buffer.as_slice())),
+                );
+            }
+
+            if state.options.restore_mtime {
+                if let Some(modified) = zip_mtime(zip_file) {
+                    output_file
+                        .set_modified(modified)
+                        .context(format_context!("failed to set mtime {destination_path}"))?;
+                }
+            }
+
+            state.record_mode(destination.as_path(), zip_file.unix_mode(), false);
+
+            #[cfg(unix)]
+            if let Some(mode) = zip_file
+                .unix_mode()
+                .filter(|_| state.options.restore_permissions)
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(
+                    destination_path.as_str(),
+                    std::fs::Permissions::from_mode(mode),
+                )
+                .context(format_context!(
+                    "failed to set permissions {destination_path}"
+                ))?;
+            }
+
+            state.stats.files += 1;
+            state.stats.bytes_written += buffer.len() as u64;
+        }
+
+        progress_bar.on_event(ProgressEvent::EntryFinished {
+            archive_path: file.to_string(),
+            size: buffer.len() as u64,
+            elapsed: entry_start.elapsed(),
+        });
+        state.last_entry = Some(file.to_string());
+        state.buffer = buffer;
+
+        Ok(())
+    }
+
+    fn unpack_tar<Reader: std::io::Read>(
+        tar_reader: Reader,
+        state: &mut ExtractState,
+        progress_bar: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let output_directory = state.output_directory.clone();
+
+        progress_bar.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Extracting,
+        });
+        progress_bar.update_status(UpdateStatus {
+            detail: Some("Unpacking (tar)".to_string()),
+            ..Default::default()
+        });
+
+        let long_output_directory = paths::long_path(output_directory.as_str());
+        state.record_created(std::path::Path::new(output_directory.as_str()));
+        std::fs::create_dir_all(long_output_directory.as_path())
+            .context(format_context!("failed to create {output_directory}"))?;
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let mut batch_writer = crate::uring::BatchWriter::new();
+
+        let entry_hasher = std::rc::Rc::new(std::cell::RefCell::new(None));
+        let mut archive = tar::Archive::new(EntryHashingReader {
+            inner: tar_reader,
+            hasher: entry_hasher.clone(),
+        });
+        archive.set_preserve_mtime(state.options.restore_mtime);
+        let entries = archive
+            .entries()
+            .context(format_context!("{output_directory}"))?;
+
+        // where the header after the last good entry should start
+        let mut next_offset = 0;
+        for entry in entries {
+            state.check_cancelled(cancellation)?;
+
+            let mut entry = entry.context(format_context!(
+                "corrupt tar entry at offset {next_offset} ({})",
+                state.describe_last_entry()
+            ))?;
+            let offset = entry.raw_header_position();
+            next_offset = entry.raw_file_position() + entry.size().next_multiple_of(512);
+            let entry_path = entry
+                .path()
+                .context(format_context!("bad tar entry path at offset {offset}"))?
+                .into_owned();
+            let path = entry_path.to_string_lossy().to_string();
+
+            progress_bar.update_status(UpdateStatus {
+                increment: Some(1),
+                ..Default::default()
+            });
+
+            let entry_type = entry.header().entry_type();
+            if !(entry_type.is_file()
+                || entry_type.is_dir()
+                || entry_type.is_symlink()
+                || entry_type.is_hard_link())
+            {
+                if !entry_type.is_pax_global_extensions() {
+                    state.warn(
+                        path.as_str(),
+                        EntryIssueKind::UnsupportedType,
+                        format!("unsupported entry type {entry_type:?}"),
+                    );
+                }
+                continue;
+            }
+
+            let entry_info = tar_entry_info(&entry, path.clone());
+            let (base_directory, relative_path) = match state.route(&entry_info, &entry_path) {
+                Ok(Some(route)) => route,
+                Ok(None) => continue,
+                Err(error) => {
+                    state.fail(path.as_str(), error)?;
+                    continue;
+                }
+            };
+            let is_renamed = base_directory != output_directory || relative_path != entry_path;
+            let destination_path = paths::join_path(base_directory.as_str(), &relative_path);
+
+            let link_name = entry
+                .link_name()
+                .ok()
+                .flatten()
+                .map(|link| link.into_owned());
+            // merging and updating look at what is already at the destination
+            if state.options.merge || state.options.update != UpdatePolicy::Always {
+                match state.is_contained(
+                    path.as_str(),
+                    base_directory.as_str(),
+                    destination_path.as_path(),
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(error) => {
+                        state.fail(path.as_str(), error)?;
+                        continue;
+                    }
+                }
+            }
+            let keeps_existing = state.keeps_existing(path.as_str(), &destination_path, || {
+                if entry_type.is_file() {
+                    file_equals(destination_path.as_path(), entry.size(), &mut entry)
+                } else if entry_type.is_symlink() {
+                    Ok(link_name.is_some_and(|target| {
+                        symlink_equals(destination_path.as_path(), target.as_path())
+                    }))
+                } else {
+                    Ok(false)
+                }
+            });
+            match keeps_existing {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(error) => {
+                    state.fail(path.as_str(), error)?;
+                    continue;
+                }
+            }
+
+            let existing = if entry_type.is_file() {
+                existing_file(destination_path.as_path())
+            } else {
+                None
+            };
+            if state
+                .options
+                .update
+                .is_up_to_date(existing, entry_info.mtime)
+            {
+                state.stats.up_to_date += 1;
+                continue;
+            }
+
+            let entry_start = std::time::Instant::now();
+            progress_bar.on_event(ProgressEvent::EntryStarted {
+                archive_path: path.clone(),
+            });
+
+            state.record_created(destination_path.as_path());
+            state.written.push(destination_path.clone());
+            if state.options.compute_digests && entry_type.is_file() {
+                entry_hasher.replace(Some(sha2::Sha256::default()));
+            }
+            let mut is_unchanged = false;
+            let is_unpacked = if state.options.update.compares(existing, entry.size()) {
+                let mtime = entry_info.mtime.filter(|_| state.options.restore_mtime);
+                update_in_place(&mut entry, destination_path.as_path(), mtime).map(|is_changed| {
+                    is_unchanged = !is_changed;
+                    true
+                })
+            } else if is_renamed {
+                unpack_entry_as(&mut entry, base_directory.as_str(), &relative_path)
+            } else {
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                let is_unpacked = unpack_entry_batched(
+                    &mut entry,
+                    output_directory.as_str(),
+                    state.options.restore_mtime,
+                    batch_writer.as_mut(),
+                );
+                #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+                let is_unpacked = entry
+                    .unpack_in(long_output_directory.as_path())
+                    .map_err(anyhow::Error::from);
+                is_unpacked
+            };
+            let hasher = entry_hasher.take();
+            let is_unpacked = match is_unpacked.context(format_context!(
+                "failed to unpack {path} at offset {offset} ({})",
+                state.describe_last_entry()
+            )) {
+                Ok(is_unpacked) => is_unpacked,
+                Err(error) => {
+                    state.written.pop();
+                    state.fail(path.as_str(), error)?;
+                    continue;
+                }
+            };
+            if !is_unpacked {
+                state.written.pop();
+                state.warn(
+                    path.as_str(),
+                    EntryIssueKind::UnsafePath,
+                    "entry path escapes the output directory".to_string(),
+                );
+                continue;
+            }
+            if is_unchanged {
+                // nothing was written, so there is nothing to clean up
+                state.written.pop();
+                state.stats.up_to_date += 1;
+            }
+
+            if entry_type.is_dir() || entry_type.is_file() {
+                state.record_mode(
+                    destination_path.as_path(),
+                    entry.header().mode().ok(),
+                    entry_type.is_dir(),
+                );
+            }
+            if entry_type.is_dir() {
+                if let Ok(mtime) = entry.header().mtime() {
+                    state.record_directory_mtime(
+                        destination_path.as_path(),
+                        std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime),
+                    );
+                }
+                state.stats.directories += 1;
+            } else if entry_type.is_symlink() {
+                state.stats.symlinks += 1;
+            } else {
+                if !is_unchanged {
+                    state.stats.files += 1;
+                    state.stats.bytes_written += entry.size();
+                }
+                if let Some(hasher) = hasher {
+                    use sha2::Digest;
+                    state.digests.insert(
+                        state.digest_key(base_directory.as_str(), relative_path.as_path()),
+                        format!("{:x}", hasher.finalize()),
+                    );
+                }
+            }
+
+            progress_bar.on_event(ProgressEvent::EntryFinished {
+                archive_path: path.clone(),
+                size: entry.size(),
+                elapsed: entry_start.elapsed(),
+            });
+            trace_event!(path = %path, offset, "unpacked tar entry");
+            state.last_entry = Some(path);
+        }
+
+        // read past the end-of-archive blocks so a streamed digest covers the whole input
+        std::io::copy(&mut archive.into_inner(), &mut std::io::sink())
+            .context(format_context!("failed to read trailing tar padding"))?;
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(batch_writer) = batch_writer.as_mut() {
+            batch_writer
+                .flush()
+                .context(format_context!("{output_directory}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads decompressed chunks produced by a worker thread.
+struct PipelinedReader {
+    receiver: std::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl PipelinedReader {
+    fn spawn<Decoder: std::io::Read + Send + 'static>(
+        mut decoder: Decoder,
+        driver: Driver,
+        threads: usize,
+        buffer_size: usize,
+        cancellation: CancellationToken,
+    ) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(threads);
+        std::thread::spawn(move || {
+            let mut offset = 0_u64;
+            loop {
+                if cancellation.is_cancelled() {
+                    let _ = sender.send(Err(std::io::Error::other("operation cancelled")));
+                    return;
+                }
+                let mut chunk = vec![0; buffer_size];
+                let bytes_read = match decoder.read(chunk.as_mut_slice()) {
+                    Ok(bytes_read) => bytes_read,
+                    Err(err) => {
+                        let _ = sender.send(Err(std::io::Error::other(format!(
+                            "failed to decode {} at offset {offset}: {err}",
+                            driver.extension()
+                        ))));
+                        return;
+                    }
+                };
+                if bytes_read == 0 {
+                    return;
+                }
+                chunk.truncate(bytes_read);
+                offset += bytes_read as u64;
+                // the receiver is gone if unpacking failed
+                if sender.send(Ok(chunk)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl std::io::Read for PipelinedReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+struct ExtractState<'filter> {
+    output_directory: String,
+    options: ExtractOptions,
+    stats: ExtractStats,
+    warnings: Vec<EntryIssue>,
+    failures: Vec<EntryIssue>,
+    conflicts: Vec<EntryIssue>,
+    written: Vec<std::path::PathBuf>,
+    /// Paths that didn't exist before this run, parents first.
+    created: Vec<std::path::PathBuf>,
+    digests: std::collections::BTreeMap<String, String>,
+    filter: &'filter mut dyn FnMut(&EntryInfo) -> ExtractAction,
+    last_entry: Option<String>,
+    /// Reused for the contents of each zip entry.
+    #[cfg(feature = "zip")]
+    buffer: Vec<u8>,
+    directory_mtimes: Vec<(std::path::PathBuf, u64)>,
+    modes: Vec<(std::path::PathBuf, u32)>,
+}
+
+impl ExtractState<'_> {
+    fn describe_last_entry(&self) -> String {
+        describe_last_entry(self.last_entry.as_deref())
+    }
+
+    fn record_directory_mtime(&mut self, path: &std::path::Path, modified: std::time::SystemTime) {
+        if !self.options.restore_directory_mtimes {
+            return;
+        }
+        let seconds = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        self.directory_mtimes.push((path.to_path_buf(), seconds));
+    }
+
+    fn record_mode(&mut self, path: &std::path::Path, archive_mode: Option<u32>, is_dir: bool) {
+        if let Some(mode) = self.options.mode_policy.mode(archive_mode, is_dir) {
+            self.modes.push((path.to_path_buf(), mode));
+        }
+    }
+
+    /// Applies the mode policy and directory mtimes once every entry is on disk.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.restore_directory_mtimes()?;
+        #[cfg(unix)]
+        for (path, mode) in self.modes.drain(..) {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path.as_path(), std::fs::Permissions::from_mode(mode))
+                .context(format_context!("failed to set permissions {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn restore_directory_mtimes(&mut self) -> anyhow::Result<()> {
+        self.directory_mtimes
+            .sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+        for (path, seconds) in self.directory_mtimes.drain(..) {
+            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            std::fs::File::open(paths::long_path(path.as_path()))
+                .and_then(|directory| directory.set_modified(modified))
+                .context(format_context!("failed to set mtime {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Where `entry` goes: the directory to extract it under and its path
+    /// relative to that, or `None` to skip it.
+    fn route(
+        &mut self,
+        entry: &EntryInfo,
+        path: &std::path::Path,
+    ) -> anyhow::Result<Option<(String, std::path::PathBuf)>> {
+        let Some(relative_path) = self.filter_entry(entry, path)? else {
+            return Ok(None);
+        };
+        let archive_path = paths::to_archive_path(relative_path.as_path());
+        let route = self
+            .options
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, route.strip(archive_path.as_str())?)))
+            .max_by_key(|(route, _)| route.prefix.trim_matches('/').len());
+        let Some((route, rest)) = route else {
+            return Ok(Some((self.output_directory.clone(), relative_path)));
+        };
+        let destination = route.destination.clone();
+        if rest.is_empty() {
+            // the prefix directory itself
+            self.record_created(std::path::Path::new(destination.as_str()));
+            std::fs::create_dir_all(paths::long_path(destination.as_str()))
+                .context(format_context!("failed to create {destination}"))?;
+            return Ok(None);
+        }
+        Ok(Some((destination, std::path::PathBuf::from(rest))))
+    }
+
+    fn filter_entry(
+        &mut self,
+        entry: &EntryInfo,
+        path: &std::path::Path,
+    ) -> anyhow::Result<Option<std::path::PathBuf>> {
+        match (self.filter)(entry) {
+            ExtractAction::Extract => Ok(Some(path.to_path_buf())),
+            ExtractAction::Skip => Ok(None),
+            ExtractAction::ExtractAs(new_path) => {
+                let relative_path = std::path::Path::new(new_path.as_str());
+                let is_enclosed = relative_path.components().all(|component| {
+                    matches!(
+                        component,
+                        std::path::Component::Normal(_) | std::path::Component::CurDir
+                    )
+                });
+                if !is_enclosed || relative_path.as_os_str().is_empty() {
+                    return Err(Error::PathEscape { name: new_path }.into());
+                }
+                Ok(Some(relative_path.to_path_buf()))
+            }
+        }
+    }
+
+    fn digest_key(&self, base_directory: &str, relative_path: &std::path::Path) -> String {
+        if base_directory == self.output_directory {
+            paths::to_archive_path(relative_path)
+        } else {
+            paths::join_path(base_directory, relative_path)
+                .to_string_lossy()
+                .to_string()
+        }
+    }
+
+    /// Records `path` and its missing ancestors before they are written so
+    /// `rollback` can remove them.
+    fn record_created(&mut self, path: &std::path::Path) {
+        if !self.options.rollback_on_failure {
+            return;
+        }
+        let missing: Vec<_> = path
+            .ancestors()
+            .take_while(|ancestor| {
+                !ancestor.as_os_str().is_empty() && ancestor.symlink_metadata().is_err()
+            })
+            .map(std::path::Path::to_path_buf)
+            .collect();
+        self.created.extend(missing.into_iter().rev());
+    }
+
+    /// Removes everything recorded by `record_created`, children first.
+    fn rollback(&mut self) {
+        for path in self.created.drain(..).rev() {
+            let path = paths::long_path(path.as_path());
+            let _ = if path.is_dir() && !path.is_symlink() {
+                std::fs::remove_dir(path.as_path())
+            } else {
+                std::fs::remove_file(path.as_path())
+            };
+        }
+    }
+
+    fn warn(&mut self, path: &str, kind: EntryIssueKind, message: String) {
+        self.warnings.push(EntryIssue {
+            path: path.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    /// Whether what is at `destination` can be inspected or rewritten in
+    /// place, which needs its parent to resolve inside `base_directory`. A
+    /// symlink extracted earlier could otherwise point it anywhere. Warns
+    /// about entries that escape.
+    fn is_contained(
+        &mut self,
+        path: &str,
+        base_directory: &str,
+        destination: &std::path::Path,
+    ) -> anyhow::Result<bool> {
+        if parent_is_inside(base_directory, destination)
+            .context(format_context!("failed to resolve {destination:?}"))?
+        {
+            return Ok(true);
+        }
+        self.warn(
+            path,
+            EntryIssueKind::UnsafePath,
+            "entry path escapes the output directory".to_string(),
+        );
+        Ok(false)
+    }
+
+    /// Whether `merge` keeps what is at `destination` instead of extracting
+    /// over it. `matches` compares the entry with it for `report_conflicts`.
+    fn keeps_existing(
+        &mut self,
+        path: &str,
+        destination: &std::path::Path,
+        matches: impl FnOnce() -> std::io::Result<bool>,
+    ) -> anyhow::Result<bool> {
+        if !self.options.merge {
+            return Ok(false);
+        }
+        let Ok(metadata) = std::fs::symlink_metadata(paths::long_path(destination)) else {
+            return Ok(false);
+        };
+        if metadata.is_dir() && !metadata.is_symlink() {
+            // merged into rather than replaced
+            return Ok(false);
+        }
+        if self.options.report_conflicts
+            && !matches().context(format_context!("failed to compare {destination:?}"))?
+        {
+            self.conflicts.push(EntryIssue {
+                path: path.to_string(),
+                kind: EntryIssueKind::Conflict,
+                message: format!("{destination:?} exists with different contents"),
+            });
+        }
+        Ok(true)
+    }
+
+    /// Returns `error` under `ErrorPolicy::Abort`, otherwise records it.
+    fn fail(&mut self, path: &str, error: anyhow::Error) -> anyhow::Result<()> {
+        if self.options.entry_policy == ErrorPolicy::Abort {
+            return Err(error);
+        }
+        let kind = match Error::find(&error) {
+            Some(Error::CorruptEntry { .. }) => EntryIssueKind::CrcMismatch,
+            _ => EntryIssueKind::WriteFailed,
+        };
+        self.failures.push(EntryIssue {
+            path: path.to_string(),
+            kind,
+            message: format!("{error:#}"),
+        });
+        Ok(())
+    }
+
+    fn check_cancelled(&self, cancellation: &CancellationToken) -> anyhow::Result<()> {
+        if cancellation.is_cancelled() {
+            remove_written(&self.written);
+            cancellation.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// Total size of the regular files in an in memory tar.
+fn tar_file_bytes(tar_bytes: &[u8]) -> u64 {
+    let mut archive = tar::Archive::new(tar_bytes);
+    let Ok(entries) = archive.entries() else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.header().entry_type().is_file())
+        .map(|entry| entry.size())
+        .sum()
+}
+
+/// Fails if the filesystem that will hold `output_directory` has less than
+/// `needed` bytes available.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn check_free_space(output_directory: &str, needed: u64) -> anyhow::Result<()> {
+    // the output directory may not exist yet
+    let existing = std::path::Path::new(output_directory)
+        .ancestors()
+        .find(|path| path.exists())
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    let available = fs4::available_space(existing)
+        .map_err(|err| Error::io(existing, err))
+        .context(format_context!("{output_directory}"))?;
+    if needed > available {
+        return Err(Error::InsufficientSpace {
+            path: output_directory.into(),
+            needed,
+            available,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(target_family = "wasm")]
+pub(crate) fn check_free_space(_output_directory: &str, _needed: u64) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "zip"))]
+fn create_symlink(target: &str, destination_path: &str) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(target, destination_path).context(format_context!(
+        "failed to create symlink {destination_path} -> {target}"
+    ))
+}
+
+#[cfg(all(not(unix), feature = "zip"))]
+fn create_symlink(target: &str, destination_path: &str) -> anyhow::Result<()> {
+    std::fs::write(destination_path, target)
+        .context(format_context!("failed to write {destination_path}"))
+}
+
+/// Queues regular files on `batch_writer` and falls back to `unpack_in` for
+/// everything else (or when io_uring isn't available).
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn unpack_entry_batched<Reader: std::io::Read>(
+    entry: &mut tar::Entry<'_, Reader>,
+    output_directory: &str,
+    restore_mtime: bool,
+    batch_writer: Option<&mut crate::uring::BatchWriter>,
+) -> anyhow::Result<bool> {
+    let Some(batch_writer) = batch_writer else {
+        return Ok(entry.unpack_in(output_directory)?);
+    };
+    if !entry.header().entry_type().is_file() {
+        return Ok(entry.unpack_in(output_directory)?);
+    }
+
+    let path = entry.path()?.into_owned();
+    if path.components().any(|component| {
+        !matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    }) {
+        return Ok(false);
+    }
+
+    let destination = std::path::Path::new(output_directory).join(path);
+    // a symlink unpacked earlier must not redirect the write outside the output
+    if !parent_is_inside(output_directory, destination.as_path())? {
+        return Ok(false);
+    }
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent).context(format_context!("{parent:?}"))?;
+    }
+
+    let mode = entry.header().mode().unwrap_or(0o644) & 0o777;
+    let mtime = entry.header().mtime().ok().filter(|_| restore_mtime);
+    let mut contents =
+        Vec::with_capacity(entry.size().min(driver::MAX_ENTRY_PREALLOCATION) as usize);
+    entry.read_to_end(&mut contents)?;
+    batch_writer
+        .push(destination.as_path(), mode, mtime, contents)
+        .context(format_context!("{destination:?}"))?;
+    Ok(true)
+}
+
+/// Whether the parent directory of `path` resolves inside `base_directory`
+/// after following symlinks extracted earlier. Only the deepest ancestor that
+/// exists is resolved, so this can be checked before creating directories.
+pub(crate) fn parent_is_inside(
+    base_directory: &str,
+    path: &std::path::Path,
+) -> std::io::Result<bool> {
+    let canonical_base = match std::fs::canonicalize(paths::long_path(base_directory)) {
+        Ok(canonical_base) => canonical_base,
+        // nothing has been extracted under a base that doesn't exist yet
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(error) => return Err(error),
+    };
+    let mut ancestor = path.parent();
+    while let Some(directory) = ancestor {
+        match std::fs::canonicalize(paths::long_path(directory)) {
+            Ok(canonical) => return Ok(canonical.starts_with(canonical_base.as_path())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                // a dangling symlink would be followed when it's created
+                if std::fs::symlink_metadata(paths::long_path(directory)).is_ok() {
+                    return Ok(false);
+                }
+                ancestor = directory.parent();
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(false)
+}
+
+/// The size and mtime in seconds of the regular file at `path`, if there is one.
+fn existing_file(path: &std::path::Path) -> Option<(u64, Option<u64>)> {
+    let metadata = std::fs::symlink_metadata(paths::long_path(path)).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs());
+    Some((metadata.len(), mtime))
+}
+
+/// Whether the regular file at `path` has exactly the bytes of `contents`.
+fn file_equals(
+    path: &std::path::Path,
+    size: u64,
+    contents: &mut dyn Read,
+) -> std::io::Result<bool> {
+    if existing_file(path).map(|(existing_size, _)| existing_size) != Some(size) {
+        return Ok(false);
+    }
+    let mut file = std::fs::File::open(paths::long_path(path))?;
+    let mut contents_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+    let mut file_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+    loop {
+        let bytes_read = contents.read(contents_buffer.as_mut_slice())?;
+        if bytes_read == 0 {
+            return Ok(true);
+        }
+        let file_buffer = &mut file_buffer[..bytes_read];
+        if file.read_exact(file_buffer).is_err() || *file_buffer != contents_buffer[..bytes_read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Whether `path` is a symlink to `target`.
+fn symlink_equals(path: &std::path::Path, target: &std::path::Path) -> bool {
+    std::fs::read_link(paths::long_path(path)).is_ok_and(|existing| existing == target)
+}
+
+/// Compares `reader` with the file at `path`, which is the same size, and
+/// rewrites the file from the first chunk that differs. Returns whether
+/// anything was written.
+fn update_in_place(
+    reader: &mut dyn Read,
+    path: &std::path::Path,
+    mtime: Option<u64>,
+) -> anyhow::Result<bool> {
+    use std::io::{Seek, Write};
+    let long_path = paths::long_path(path);
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(long_path.as_path())
+        .context(format_context!("failed to open {path:?}"))?;
+    let mut entry_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+    let mut file_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+    let mut offset = 0;
+    let bytes_read = loop {
+        let bytes_read = reader
+            .read(entry_buffer.as_mut_slice())
+            .context(format_context!("{path:?}"))?;
+        if bytes_read == 0 {
+            return Ok(false);
+        }
+        file.read_exact(&mut file_buffer[..bytes_read])
+            .context(format_context!("failed to read {path:?}"))?;
+        if entry_buffer[..bytes_read] != file_buffer[..bytes_read] {
+            break bytes_read;
+        }
+        offset += bytes_read as u64;
+    };
+
+    file.seek(std::io::SeekFrom::Start(offset))
+        .and_then(|_| file.write_all(&entry_buffer[..bytes_read]))
+        .and_then(|_| std::io::copy(reader, &mut file))
+        .context(format_context!("failed to write {path:?}"))?;
+    if let Some(mtime) = mtime {
+        file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+            .context(format_context!("failed to set mtime {path:?}"))?;
+    }
+    Ok(true)
+}
+
+/// Unpacks `entry` to `relative_path` under `output_directory` instead of the
+/// path stored in the archive.
+fn unpack_entry_as<Reader: std::io::Read>(
+    entry: &mut tar::Entry<'_, Reader>,
+    output_directory: &str,
+    relative_path: &std::path::Path,
+) -> anyhow::Result<bool> {
+    let destination = paths::join_path(output_directory, relative_path);
+    // a symlink unpacked earlier must not redirect the write outside the output
+    if !parent_is_inside(output_directory, destination.as_path())? {
+        return Ok(false);
+    }
+    if let Some(parent) = destination.parent() {
+        let parent = paths::long_path(parent);
+        std::fs::create_dir_all(parent.as_path()).context(format_context!("{parent:?}"))?;
+    }
+    entry
+        .unpack(paths::long_path(destination.as_path()))
+        .context(format_context!("{destination:?}"))?;
+    Ok(true)
+}
+
+fn remove_written(written: &[std::path::PathBuf]) {
+    for path in written.iter().rev() {
+        if path.is_symlink() || path.is_file() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn describe_last_entry(last_entry: Option<&str>) -> String {
+    match last_entry {
+        Some(entry) => format!("last good entry: {entry}"),
+        None => "no entries processed".to_string(),
+    }
+}
+
+fn last_tar_entry(tar_bytes: &[u8]) -> Option<String> {
+    let mut archive = tar::Archive::new(tar_bytes);
+    let entries = archive.entries().ok()?;
+    let mut last_entry = None;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            break;
+        };
+        if entry.raw_file_position() + entry.size() > tar_bytes.len() as u64 {
+            break;
+        }
+        if let Ok(path) = entry.path() {
+            last_entry = Some(path.to_string_lossy().to_string());
+        }
+    }
+    last_entry
+}
+
+/// Zip64 archives have a locator just before the end of central directory
+/// record, which is followed by at most a 64 KiB comment.
+#[cfg(feature = "zip")]
+fn has_zip64_locator<Reader: std::io::Read + std::io::Seek>(
+    reader: &mut Reader,
+) -> anyhow::Result<bool> {
+    const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+    const ZIP64_LOCATOR: &[u8] = b"PK\x06\x07";
+    const ZIP64_LOCATOR_SIZE: usize = 20;
+
+    let size = reader
+        .seek(std::io::SeekFrom::End(0))
+        .context(format_context!(""))?;
+    let tail_size = size.min(22 + 0xFFFF + ZIP64_LOCATOR_SIZE as u64);
+    reader
+        .seek(std::io::SeekFrom::End(-(tail_size as i64)))
+        .context(format_context!(""))?;
+    let mut tail = Vec::with_capacity(tail_size as usize);
+    reader.read_to_end(&mut tail).context(format_context!(""))?;
+
+    let Some(end) = tail
+        .windows(END_OF_CENTRAL_DIRECTORY.len())
+        .rposition(|window| window == END_OF_CENTRAL_DIRECTORY)
+    else {
+        return Ok(false);
+    };
+    Ok(end >= ZIP64_LOCATOR_SIZE && tail[end - ZIP64_LOCATOR_SIZE..].starts_with(ZIP64_LOCATOR))
+}
+
+/// Days between 1970-01-01 and the given date in the proleptic Gregorian calendar.
+#[cfg(feature = "zip")]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Prefers the extended timestamp field and falls back to the MS-DOS time,
+/// which has no time zone and is read as UTC.
+#[cfg(feature = "zip")]
+fn zip_mtime(zip_file: &zip::read::ZipFile<'_>) -> Option<std::time::SystemTime> {
+    let extended = zip_file.extra_data_fields().find_map(|field| match field {
+        zip::extra_fields::ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+        _ => None,
+    });
+    let seconds = match extended {
+        Some(seconds) => seconds as i64,
+        None => {
+            let modified = zip_file.last_modified()?;
+            let days = days_from_civil(
+                modified.year() as i64,
+                modified.month() as i64,
+                modified.day() as i64,
+            );
+            days * 86400
+                + modified.hour() as i64 * 3600
+                + modified.minute() as i64 * 60
+                + modified.second() as i64
+        }
+    };
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
 }
@@ -0,0 +1,154 @@
+use crate::decoder::{Decoder, VisitedEntryKind};
+use crate::driver::{self, CancellationToken, NoProgress};
+use crate::paths;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Differences between the files in an archive and a directory, as sorted
+/// archive paths. Directory entries and symlinks aren't compared.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveDiff {
+    /// Files in the directory that aren't in the archive.
+    pub added: Vec<String>,
+    /// Files in the archive that aren't in the directory.
+    pub removed: Vec<String>,
+    /// Files whose size or sha256 differs.
+    pub changed: Vec<String>,
+}
+
+impl ArchiveDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// An entry as seen by `compare`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ComparedEntry {
+    #[serde(rename = "file")]
+    File { size: u64, sha256: String },
+    /// Symlinks, hard links and special files, compared by presence only.
+    #[serde(rename = "other")]
+    Other,
+}
+
+/// An entry that differs between the two archives given to `compare`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryDifference {
+    pub archive_path: String,
+    /// `None` if the entry is only in the second archive.
+    pub first: Option<ComparedEntry>,
+    /// `None` if the entry is only in the first archive.
+    pub second: Option<ComparedEntry>,
+}
+
+fn summarize(archive_path: &str) -> anyhow::Result<BTreeMap<String, ComparedEntry>> {
+    let cancellation = CancellationToken::default();
+    let mut entries = BTreeMap::new();
+    Decoder::new(archive_path, None, ".", NoProgress)
+        .context(format_context!("{archive_path}"))?
+        .visit_entries(|entry, reader| {
+            let compared = match entry.kind {
+                VisitedEntryKind::File => ComparedEntry::File {
+                    size: entry.size,
+                    sha256: driver::sha256_reader(reader, &cancellation)
+                        .context(format_context!("{}", entry.archive_path))?,
+                },
+                VisitedEntryKind::Directory => return Ok(()),
+                VisitedEntryKind::Other => ComparedEntry::Other,
+            };
+            let path = paths::to_archive_path(std::path::Path::new(entry.archive_path.as_str()));
+            entries.insert(path, compared);
+            Ok(())
+        })
+        .context(format_context!("{archive_path}"))?;
+    Ok(entries)
+}
+
+/// Compares the entries of two archives, of any supported formats, by
+/// hashing their decompressed contents without extracting either. Directory
+/// entries are skipped since only some formats store them. Returns the
+/// differences sorted by archive path; an empty result means the archives
+/// hold the same files.
+pub fn compare(first_archive: &str, second_archive: &str) -> anyhow::Result<Vec<EntryDifference>> {
+    let mut first = summarize(first_archive).context(format_context!("{first_archive}"))?;
+    let mut second = summarize(second_archive).context(format_context!("{second_archive}"))?;
+
+    let archive_paths: std::collections::BTreeSet<String> =
+        first.keys().chain(second.keys()).cloned().collect();
+    let mut differences = Vec::new();
+    for archive_path in archive_paths {
+        let first = first.remove(archive_path.as_str());
+        let second = second.remove(archive_path.as_str());
+        if first != second {
+            differences.push(EntryDifference {
+                archive_path,
+                first,
+                second,
+            });
+        }
+    }
+    Ok(differences)
+}
+
+/// Compares the files in `archive_path` with the files under `directory`.
+/// Archive entries are hashed from the decompressed stream, so nothing is
+/// extracted, and files on disk are only hashed when their size matches.
+pub fn diff(archive_path: &str, directory: &str) -> anyhow::Result<ArchiveDiff> {
+    if !std::path::Path::new(directory).is_dir() {
+        return Err(format_error!("{directory} is not a directory"));
+    }
+    let cancellation = CancellationToken::default();
+    let archived: BTreeMap<String, (u64, String)> = summarize(archive_path)
+        .context(format_context!("{archive_path}"))?
+        .into_iter()
+        .filter_map(|(archive_path, entry)| match entry {
+            ComparedEntry::File { size, sha256 } => Some((archive_path, (size, sha256))),
+            ComparedEntry::Other => None,
+        })
+        .collect();
+
+    let mut result = ArchiveDiff::default();
+    let mut found = std::collections::HashSet::new();
+    for item in walkdir::WalkDir::new(directory).sort_by_file_name() {
+        let item = item.context(format_context!("{directory}"))?;
+        if !item.file_type().is_file() {
+            continue;
+        }
+        let relative_path = item
+            .path()
+            .strip_prefix(directory)
+            .context(format_context!("{:?}", item.path()))?;
+        let relative_path = paths::to_archive_path(relative_path);
+        let Some((size, digest)) = archived.get(relative_path.as_str()) else {
+            result.added.push(relative_path);
+            continue;
+        };
+        found.insert(relative_path.clone());
+        let metadata = item
+            .metadata()
+            .context(format_context!("{:?}", item.path()))?;
+        if metadata.len() != *size {
+            result.changed.push(relative_path);
+            continue;
+        }
+        let file = std::fs::File::open(paths::long_path(item.path()))
+            .context(format_context!("{:?}", item.path()))?;
+        let file_digest = driver::sha256_reader(std::io::BufReader::new(file), &cancellation)
+            .context(format_context!("{:?}", item.path()))?;
+        if file_digest != *digest {
+            result.changed.push(relative_path);
+        }
+    }
+
+    result.removed = archived
+        .into_keys()
+        .filter(|archive_path| !found.contains(archive_path))
+        .collect();
+    result.added.sort();
+    result.changed.sort();
+    Ok(result)
+}
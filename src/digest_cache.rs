@@ -0,0 +1,139 @@
+use crate::driver::{self, CancellationToken};
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Files modified this recently when they are hashed aren't cached, since a
+/// later write within the same mtime tick would go unnoticed. Two seconds
+/// covers filesystems with coarse timestamps like FAT.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedDigest {
+    size: u64,
+    modified: SystemTime,
+    sha256: String,
+}
+
+/// sha256 digests of source files keyed by absolute path and reused while
+/// the size and modification time are unchanged. Stored as JSON at the path
+/// set with `CreateArchive::digest_cache` (needs the `digest-cache` feature).
+#[derive(Debug, Clone, Default)]
+pub struct DigestCache {
+    path: String,
+    entries: BTreeMap<String, CachedDigest>,
+    hits: usize,
+    is_modified: bool,
+}
+
+impl DigestCache {
+    /// Loads the cache at `path`. A missing or unreadable cache file starts
+    /// an empty cache rather than failing.
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        #[cfg(feature = "digest-cache")]
+        {
+            let entries = std::fs::read(path)
+                .ok()
+                .and_then(|contents| serde_json::from_slice(contents.as_slice()).ok())
+                .unwrap_or_default();
+            Ok(Self {
+                path: path.to_string(),
+                entries,
+                ..Default::default()
+            })
+        }
+        #[cfg(not(feature = "digest-cache"))]
+        Err(format_error!(
+            "{path}: digest caches require the digest-cache feature"
+        ))
+    }
+
+    /// Number of digests served from the cache since it was opened.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The sha256 of the regular file at `file_path`, hashing it only when
+    /// `metadata` doesn't match the cached size and modification time.
+    pub fn digest(
+        &mut self,
+        file_path: &Path,
+        metadata: &std::fs::Metadata,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<String> {
+        if !metadata.is_file() {
+            return Err(format_error!("{file_path:?} is not a regular file"));
+        }
+        let key = std::path::absolute(file_path)
+            .context(format_context!("{file_path:?}"))?
+            .to_string_lossy()
+            .to_string();
+        let modified = metadata
+            .modified()
+            .context(format_context!("{file_path:?}"))?;
+        if let Some(cached) = self.entries.get(key.as_str()) {
+            if cached.size == metadata.len() && cached.modified == modified {
+                self.hits += 1;
+                return Ok(cached.sha256.clone());
+            }
+        }
+
+        let started = SystemTime::now();
+        let file = std::fs::File::open(file_path).context(format_context!("{file_path:?}"))?;
+        let sha256 =
+            driver::sha256_reader(file, cancellation).context(format_context!("{file_path:?}"))?;
+        if modified + RACY_WINDOW < started {
+            self.entries.insert(
+                key,
+                CachedDigest {
+                    size: metadata.len(),
+                    modified,
+                    sha256: sha256.clone(),
+                },
+            );
+            self.is_modified = true;
+        } else if self.entries.remove(key.as_str()).is_some() {
+            self.is_modified = true;
+        }
+        Ok(sha256)
+    }
+
+    /// Writes the cache back to its file if any digest was added. The file is
+    /// replaced atomically so an interrupted run leaves the previous cache.
+    pub fn save(&self) -> anyhow::Result<()> {
+        if !self.is_modified {
+            return Ok(());
+        }
+        #[cfg(feature = "digest-cache")]
+        {
+            let path = self.path.as_str();
+            if let Some(parent) = Path::new(path).parent() {
+                if !parent.as_os_str().is_empty() {
+                    std::fs::create_dir_all(parent).context(format_context!("{parent:?}"))?;
+                }
+            }
+            let contents = serde_json::to_vec(&self.entries).context(format_context!("{path}"))?;
+            let temporary_path = format!("{path}.tmp");
+            std::fs::write(temporary_path.as_str(), contents)
+                .context(format_context!("{temporary_path}"))?;
+            std::fs::rename(temporary_path.as_str(), path).context(format_context!("{path}"))?;
+            Ok(())
+        }
+        #[cfg(not(feature = "digest-cache"))]
+        Err(format_error!(
+            "{}: digest caches require the digest-cache feature",
+            self.path
+        ))
+    }
+}
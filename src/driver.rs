@@ -1,7 +1,11 @@
+use crate::external::CommandDriver;
+use crate::Error;
 use anyhow::Context;
 use anyhow_source_location::{format_context, format_error};
 use serde::{Deserialize, Serialize};
 
+/// Built-in archive formats. There is no zstd driver; `tar.zst` archives are
+/// extracted with `ExternalTool::tar`.
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Driver {
     #[serde(rename = "tar.gz")]
@@ -14,8 +18,112 @@ pub enum Driver {
     SevenZ,
     #[serde(rename = "tar.xz")]
     Xz,
+    /// Debian package; can be created but not extracted.
+    #[serde(rename = "deb")]
+    Deb,
+    /// RPM package (needs the `rpm` feature); can be created but not extracted.
+    #[serde(rename = "rpm")]
+    Rpm,
 }
 
+/// Header layout used by the tar based drivers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum TarFormat {
+    /// POSIX ustar headers. Paths that don't fit the name and prefix fields
+    /// fall back to GNU long name entries.
+    #[serde(rename = "ustar")]
+    Ustar,
+    /// Plain ustar for old readers such as busybox tar. Entries that need an
+    /// extension (long paths, sizes over 8 GiB, large ids) are rejected.
+    #[serde(rename = "ustar-strict")]
+    UstarStrict,
+    /// GNU headers with long name entries and base-256 sizes.
+    #[default]
+    #[serde(rename = "gnu")]
+    Gnu,
+    /// ustar headers with a PAX extended header for long paths, long link
+    /// targets and sizes over 8 GiB.
+    #[serde(rename = "pax")]
+    Pax,
+}
+
+/// Compression method for zip entries.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ZipMethod {
+    #[default]
+    #[serde(rename = "deflated")]
+    Deflated,
+    /// No compression, for contents that are already compressed.
+    #[serde(rename = "stored")]
+    Stored,
+}
+
+/// Settings for the compression backends, passed to
+/// `Encoder::with_driver_options` and `Decoder::with_driver_options`. Fields
+/// left at `None` keep each driver's default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriverOptions {
+    /// 0 (fastest) to 9 (smallest) for gzip, bzip2, xz and deflated zip
+    /// entries. tar.7z ignores it. Without a level, mostly incompressible
+    /// inputs are stored with the fastest setting.
+    #[serde(default)]
+    pub level: Option<u32>,
+    /// Threads used to compress and extract; see `Encoder::with_threads` and
+    /// `Decoder::with_threads`. The encoder defaults to `default_threads` and
+    /// the decoder to `1`.
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// Dictionary size in bytes for xz.
+    #[serde(default)]
+    pub dictionary_size: Option<u32>,
+    #[serde(default)]
+    pub zip_method: ZipMethod,
+    #[serde(default)]
+    pub tar_format: TarFormat,
+    /// Rejected by drivers without `Driver::supports_encryption`.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl DriverOptions {
+    pub(crate) fn check(&self, driver: Driver) -> anyhow::Result<()> {
+        if let Some(level) = self.level {
+            if level > 9 {
+                return Err(format_error!("compression level {level} is not in 0..=9"));
+            }
+        }
+        if self.password.is_some() && !driver.supports_encryption() {
+            return Err(Error::UnsupportedFormat {
+                driver,
+                reason: format!(
+                    "{} archives can't be encrypted or decrypted",
+                    driver.extension()
+                ),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Default size of the read/write buffers used while encoding, decoding and digesting.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Most bytes pre-allocated for an entry from the size in its header, which a
+/// crafted archive can set to anything.
+pub(crate) const MAX_ENTRY_PREALLOCATION: u64 = 1024 * 1024;
+
+/// Extensions added with `Driver::register_alias`, checked before the
+/// built-in aliases.
+static REGISTERED_ALIASES: std::sync::RwLock<Vec<(String, Driver)>> =
+    std::sync::RwLock::new(Vec::new());
+
+/// Formats added with `Driver::register_command`.
+static REGISTERED_COMMANDS: std::sync::RwLock<Vec<CommandDriver>> =
+    std::sync::RwLock::new(Vec::new());
+
+#[cfg(feature = "sevenz")]
 pub(crate) const SEVEN_Z_TAR_FILENAME: &str = "swiss_army_archive_seven7_temp.tar";
 
 impl Driver {
@@ -26,36 +134,327 @@ impl Driver {
             Driver::Zip => "zip".to_string(),
             Driver::SevenZ => "tar.7z".to_string(),
             Driver::Xz => "tar.xz".to_string(),
+            Driver::Deb => "deb".to_string(),
+            Driver::Rpm => "rpm".to_string(),
         }
     }
 
-    pub fn from_extension(extension: &str) -> Option<Self> {
-        match extension {
-            "tar.gz" => Some(Driver::Gzip),
-            "tar.tgz" => Some(Driver::Gzip),
-            "tar.bz2" => Some(Driver::Bzip2),
-            "zip" => Some(Driver::Zip),
-            "tar.7z" => Some(Driver::SevenZ),
-            "tar.xz" => Some(Driver::Xz),
-            _ => None,
+    /// Rough compressed/uncompressed ratio for typical source and binary trees.
+    pub fn estimated_compression_ratio(&self) -> f64 {
+        match &self {
+            Driver::Gzip => 0.35,
+            Driver::Bzip2 => 0.30,
+            Driver::Zip => 0.37,
+            Driver::SevenZ => 0.25,
+            Driver::Xz => 0.25,
+            Driver::Deb => 0.35,
+            Driver::Rpm => 0.35,
         }
     }
 
-    pub fn from_filename(filename: &str) -> Option<Self> {
-        if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
-            Some(Driver::Gzip)
-        } else if filename.ends_with(".tar.bz") || filename.ends_with(".tar.bz2") {
-            Some(Driver::Bzip2)
-        } else if filename.ends_with(".zip") {
-            Some(Driver::Zip)
-        } else if filename.ends_with(".tar.7z") {
-            Some(Driver::SevenZ)
-        } else if filename.ends_with(".tar.xz") {
-            Some(Driver::Xz)
+    /// Each backend except gzip has a cargo feature of the same name (`sevenz`
+    /// for tar.7z). bzip2 and xz wrap C libraries that aren't built for wasm
+    /// targets.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Driver::Bzip2 => cfg!(all(feature = "bzip2", not(target_family = "wasm"))),
+            Driver::Xz => cfg!(all(feature = "xz", not(target_family = "wasm"))),
+            Driver::Zip => cfg!(feature = "zip"),
+            Driver::SevenZ => cfg!(feature = "sevenz"),
+            Driver::Rpm => cfg!(feature = "rpm"),
+            Driver::Gzip | Driver::Deb => true,
+        }
+    }
+
+    /// The cargo feature that enables this driver, if it has one.
+    pub fn feature(&self) -> Option<&'static str> {
+        match self {
+            Driver::Bzip2 => Some("bzip2"),
+            Driver::Xz => Some("xz"),
+            Driver::Zip => Some("zip"),
+            Driver::SevenZ => Some("sevenz"),
+            Driver::Rpm => Some("rpm"),
+            Driver::Gzip | Driver::Deb => None,
+        }
+    }
+
+    /// Package formats are only written, never extracted.
+    pub fn is_package(&self) -> bool {
+        matches!(self, Driver::Deb | Driver::Rpm)
+    }
+
+    /// Tar based archives store symlinks as links. Zip archives and packages
+    /// store the contents of the target instead.
+    pub fn supports_symlinks(&self) -> bool {
+        !matches!(self, Driver::Zip | Driver::Deb | Driver::Rpm)
+    }
+
+    /// Whether `DriverOptions::password` is accepted. Zip entries are written
+    /// with AES-256 and read with AES or ZipCrypto (needs the `encryption`
+    /// feature).
+    pub fn supports_encryption(&self) -> bool {
+        *self == Driver::Zip && cfg!(feature = "encryption")
+    }
+
+    /// Whether `Encoder::reopen` can add entries to an existing archive.
+    pub fn supports_append(&self) -> bool {
+        !matches!(self, Driver::Zip | Driver::Deb | Driver::Rpm)
+    }
+
+    /// Whether the permission bits of files on disk are stored. Zip archives
+    /// created by `Encoder` mark every file `0o755`.
+    pub fn preserves_permissions(&self) -> bool {
+        *self != Driver::Zip
+    }
+
+    pub(crate) fn check_supported(&self) -> anyhow::Result<()> {
+        let is_wasm_only =
+            matches!(self, Driver::Bzip2 | Driver::Xz) && cfg!(target_family = "wasm");
+        if self.is_supported() {
+            Ok(())
+        } else if let Some(feature) = self.feature().filter(|_| !is_wasm_only) {
+            Err(Error::UnsupportedFormat {
+                driver: *self,
+                reason: format!("{} needs the {feature} feature", self.extension()),
+            }
+            .into())
         } else {
-            None
+            Err(Error::UnsupportedFormat {
+                driver: *self,
+                reason: format!(
+                    "{} archives aren't supported on this target",
+                    self.extension()
+                ),
+            }
+            .into())
         }
     }
+
+    pub(crate) fn check_extractable(&self) -> anyhow::Result<()> {
+        if self.is_package() {
+            Err(Error::UnsupportedFormat {
+                driver: *self,
+                reason: format!(
+                    "{} packages can be created but not extracted",
+                    self.extension()
+                ),
+            }
+            .into())
+        } else {
+            self.check_supported()
+        }
+    }
+
+    /// The driver for an extension such as `tar.gz` or `tgz`, without the
+    /// leading dot. Accepts the same aliases as `from_filename`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::find_alias(|alias| alias == extension).map(|(driver, _)| driver)
+    }
+
+    const ALL: [Driver; 7] = [
+        Driver::Gzip,
+        Driver::Bzip2,
+        Driver::Zip,
+        Driver::SevenZ,
+        Driver::Xz,
+        Driver::Deb,
+        Driver::Rpm,
+    ];
+
+    /// Every driver, including ones this build doesn't support; filter with
+    /// `is_supported`.
+    pub fn all() -> &'static [Driver] {
+        &Self::ALL
+    }
+
+    /// Built-in extensions recognized by `from_extension` and `from_filename`,
+    /// without the leading dot. The first one is `extension()`.
+    pub fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Driver::Gzip => &["tar.gz", "tgz"],
+            Driver::Bzip2 => &["tar.bz2", "tbz2", "tar.bz", "tbz"],
+            Driver::Zip => &["zip"],
+            Driver::SevenZ => &["tar.7z"],
+            Driver::Xz => &["tar.xz", "txz"],
+            Driver::Deb => &["deb"],
+            Driver::Rpm => &["rpm"],
+        }
+    }
+
+    /// Extensions of the drivers available in this build, such as `tar.gz`.
+    pub fn supported_extensions() -> Vec<String> {
+        Self::ALL
+            .iter()
+            .filter(|driver| driver.is_supported())
+            .map(|driver| driver.extension())
+            .collect()
+    }
+
+    /// Supported extensions that `filename` probably meant, e.g. `tar.7z`
+    /// for `.7z` or every tar based extension for `.tar`.
+    pub(crate) fn suggest_extensions(filename: &str) -> Vec<String> {
+        let filename = filename.to_lowercase();
+        if let Some(driver) = Self::from_filename(filename.as_str()) {
+            return vec![driver.extension()];
+        }
+        let Some((_, suffix)) = filename.rsplit_once('.') else {
+            return Vec::new();
+        };
+        Self::supported_extensions()
+            .into_iter()
+            .filter(|extension| extension.split('.').any(|part| part == suffix))
+            .collect()
+    }
+
+    /// Identifies the format from the magic number at the start of `reader`.
+    /// Reads up to 16 bytes; callers rewind or chain them back as needed.
+    /// Returns `None` for unrecognized data, including plain tar streams.
+    pub fn from_reader(reader: &mut impl std::io::Read) -> anyhow::Result<Option<Self>> {
+        let mut header = [0; 16];
+        let mut length = 0;
+        while length < header.len() {
+            let bytes_read = reader
+                .read(&mut header[length..])
+                .context(format_context!("reading the archive header"))?;
+            if bytes_read == 0 {
+                break;
+            }
+            length += bytes_read;
+        }
+        Ok(Self::from_magic_bytes(&header[..length]))
+    }
+
+    /// Like `from_reader` for a header that is already in memory.
+    pub fn from_magic_bytes(header: &[u8]) -> Option<Self> {
+        const MAGIC_NUMBERS: &[(&[u8], Driver)] = &[
+            (&[0x1f, 0x8b], Driver::Gzip),
+            (b"BZh", Driver::Bzip2),
+            (b"PK\x03\x04", Driver::Zip),
+            (b"PK\x05\x06", Driver::Zip),
+            (b"PK\x07\x08", Driver::Zip),
+            (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Driver::Xz),
+            (&[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c], Driver::SevenZ),
+            (b"!<arch>\ndebian", Driver::Deb),
+            (&[0xed, 0xab, 0xee, 0xdb], Driver::Rpm),
+        ];
+        MAGIC_NUMBERS
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, driver)| *driver)
+    }
+
+    /// Makes `from_filename` recognize `extension`, such as `nupkg` or
+    /// `crate`, as `driver` for the rest of the process. Registering an
+    /// extension again replaces its driver. Registered extensions are checked
+    /// before the built-in aliases.
+    pub fn register_alias(extension: &str, driver: Driver) {
+        let extension = extension.trim_start_matches('.').to_string();
+        let mut registered = REGISTERED_ALIASES
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        registered.retain(|(registered, _)| *registered != extension);
+        registered.push((extension, driver));
+    }
+
+    /// Removes an extension added with `register_alias`, returning its driver.
+    pub fn unregister_alias(extension: &str) -> Option<Driver> {
+        let extension = extension.trim_start_matches('.');
+        let mut registered = REGISTERED_ALIASES
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = registered
+            .iter()
+            .position(|(registered, _)| registered == extension)?;
+        Some(registered.remove(index).1)
+    }
+
+    /// Extensions added with `register_alias`, in the order they are checked.
+    pub fn registered_aliases() -> Vec<(String, Driver)> {
+        REGISTERED_ALIASES
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Hands archives ending in `command.extension` to an external program
+    /// for the rest of the process, through `CommandDriver::create`,
+    /// `CommandDriver::extract` and `extract_with_fallback`. Registering an
+    /// extension again replaces its command. Built-in drivers still read the
+    /// archives they recognize.
+    pub fn register_command(command: CommandDriver) -> anyhow::Result<()> {
+        command.check()?;
+        let mut registered = REGISTERED_COMMANDS
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        registered.retain(|registered| registered.extension != command.extension);
+        registered.push(command);
+        Ok(())
+    }
+
+    /// Removes a command added with `register_command`.
+    pub fn unregister_command(extension: &str) -> Option<CommandDriver> {
+        let extension = extension.trim_start_matches('.');
+        let mut registered = REGISTERED_COMMANDS
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = registered
+            .iter()
+            .position(|registered| registered.extension == extension)?;
+        Some(registered.remove(index))
+    }
+
+    pub fn registered_commands() -> Vec<CommandDriver> {
+        REGISTERED_COMMANDS
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// The registered command with the longest extension ending `filename`.
+    pub fn command_for_filename(filename: &str) -> Option<CommandDriver> {
+        REGISTERED_COMMANDS
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|command| command.handles(filename))
+            .max_by_key(|command| command.extension.len())
+            .cloned()
+    }
+
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        Self::split_filename(filename).map(|(_, driver)| driver)
+    }
+
+    /// `filename` without the extension `from_filename` matched and its dot,
+    /// e.g. `layer` for `layer.tgz`, along with the driver.
+    pub fn split_filename(filename: &str) -> Option<(&str, Self)> {
+        let (driver, alias_length) = Self::find_alias(|alias| {
+            filename
+                .strip_suffix(alias)
+                .is_some_and(|stem| stem.ends_with('.'))
+        })?;
+        Some((&filename[..filename.len() - alias_length - 1], driver))
+    }
+
+    /// The driver and length of the first registered or built-in alias
+    /// `matches` accepts.
+    fn find_alias(matches: impl Fn(&str) -> bool) -> Option<(Self, usize)> {
+        let registered = REGISTERED_ALIASES
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .find(|(alias, _)| matches(alias.as_str()))
+            .map(|(alias, driver)| (*driver, alias.len()));
+        registered.or_else(|| {
+            Self::ALL.iter().find_map(|driver| {
+                driver
+                    .aliases()
+                    .iter()
+                    .find(|alias| matches(alias))
+                    .map(|alias| (*driver, alias.len()))
+            })
+        })
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -64,73 +463,584 @@ pub struct UpdateStatus {
     pub detail: Option<String>,
     pub increment: Option<u64>,
     pub total: Option<u64>,
+    pub bytes_processed: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub bytes_per_second: Option<f64>,
+    pub eta: Option<std::time::Duration>,
+}
+
+impl UpdateStatus {
+    /// Formats the byte fields as "312.0 MB / 1.2 GB, 45.0 MB/s, ~20s left".
+    pub fn describe_bytes(&self) -> Option<String> {
+        let bytes_processed = self.bytes_processed?;
+        let mut result = format_bytes(bytes_processed);
+        if let Some(bytes_total) = self.bytes_total {
+            result.push_str(format!(" / {}", format_bytes(bytes_total)).as_str());
+        }
+        if let Some(bytes_per_second) = self.bytes_per_second {
+            result.push_str(format!(", {}/s", format_bytes(bytes_per_second as u64)).as_str());
+        }
+        if let Some(eta) = self.eta {
+            result.push_str(format!(", ~{}s left", eta.as_secs()).as_str());
+        }
+        Some(result)
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS {
+        if value < 1000.0 {
+            break;
+        }
+        value /= 1000.0;
+        unit = next_unit;
+    }
+    format!("{value:.1} {unit}")
+}
+
+#[derive(Debug, Clone)]
+pub struct ByteProgress {
+    start: std::time::Instant,
+    bytes_processed: u64,
+    bytes_total: Option<u64>,
+}
+
+impl ByteProgress {
+    pub fn new(bytes_total: Option<u64>) -> Self {
+        Self {
+            start: std::time::Instant::now(),
+            bytes_processed: 0,
+            bytes_total,
+        }
+    }
+
+    pub fn add(&mut self, bytes: u64) {
+        self.bytes_processed += bytes;
+    }
+
+    pub fn bytes_processed(&self) -> u64 {
+        self.bytes_processed
+    }
+
+    pub fn bytes_per_second(&self) -> Option<f64> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            Some(self.bytes_processed as f64 / elapsed)
+        } else {
+            None
+        }
+    }
+
+    pub fn eta(&self) -> Option<std::time::Duration> {
+        let bytes_total = self.bytes_total?;
+        let bytes_per_second = self.bytes_per_second()?;
+        if bytes_per_second <= 0.0 {
+            return None;
+        }
+        let remaining = bytes_total.saturating_sub(self.bytes_processed) as f64;
+        Some(std::time::Duration::from_secs_f64(
+            remaining / bytes_per_second,
+        ))
+    }
+
+    /// Returns an `UpdateStatus` with only the byte fields populated, suitable
+    /// for use as the base of a struct update expression.
+    pub fn update_status(&self) -> UpdateStatus {
+        UpdateStatus {
+            bytes_processed: Some(self.bytes_processed),
+            bytes_total: self.bytes_total,
+            bytes_per_second: self.bytes_per_second(),
+            eta: self.eta(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Retries transient I/O errors such as `EIO` from network filesystems,
+/// doubling the delay after each attempt.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total tries including the first; `1` disables retrying.
+    pub attempts: u32,
+    pub initial_backoff: std::time::Duration,
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            initial_backoff: std::time::Duration::from_millis(100),
+            max_backoff: std::time::Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, initial_backoff: std::time::Duration) -> Self {
+        Self {
+            attempts,
+            initial_backoff,
+            ..Default::default()
+        }
+    }
+
+    fn is_transient(error: &anyhow::Error) -> bool {
+        // EIO is 5 on every unix
+        const EIO: i32 = 5;
+        error
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .any(|error| {
+                matches!(
+                    error.kind(),
+                    std::io::ErrorKind::Interrupted
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::WouldBlock
+                ) || (cfg!(unix) && error.raw_os_error() == Some(EIO))
+            })
+    }
+
+    /// Calls `operation` until it succeeds, fails with a non transient error
+    /// or runs out of attempts.
+    pub(crate) fn run<T>(
+        &self,
+        mut operation: impl FnMut() -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(error) if attempt < self.attempts && Self::is_transient(&error) => {
+                    trace_event!(attempt, error = %error, "retrying transient error");
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Phase {
+    Archiving,
+    Compressing,
+    Digesting,
+    Decoding,
+    Extracting,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    PhaseChanged {
+        phase: Phase,
+    },
+    EntryStarted {
+        archive_path: String,
+    },
+    EntryFinished {
+        archive_path: String,
+        size: u64,
+        elapsed: std::time::Duration,
+    },
+    /// An input left out of the archive because it couldn't be read.
+    EntrySkipped {
+        archive_path: String,
+        message: String,
+    },
+}
+
+pub trait ProgressReporter {
+    fn update_status(&mut self, _update_status: UpdateStatus) {}
+    fn on_event(&mut self, _event: ProgressEvent) {}
+}
+
+impl ProgressReporter for () {}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {}
+
+impl<Progress: ProgressReporter> ProgressReporter for Option<Progress> {
+    fn update_status(&mut self, update_status: UpdateStatus) {
+        if let Some(progress) = self.as_mut() {
+            progress.update_status(update_status);
+        }
+    }
+
+    fn on_event(&mut self, event: ProgressEvent) {
+        if let Some(progress) = self.as_mut() {
+            progress.on_event(event);
+        }
+    }
+}
+
+impl<Progress: ProgressReporter + ?Sized> ProgressReporter for Box<Progress> {
+    fn update_status(&mut self, update_status: UpdateStatus) {
+        self.as_mut().update_status(update_status);
+    }
+
+    fn on_event(&mut self, event: ProgressEvent) {
+        self.as_mut().on_event(event);
+    }
+}
+
+impl<Progress: ProgressReporter + ?Sized> ProgressReporter for &mut Progress {
+    fn update_status(&mut self, update_status: UpdateStatus) {
+        (**self).update_status(update_status);
+    }
+
+    fn on_event(&mut self, event: ProgressEvent) {
+        (**self).on_event(event);
+    }
+}
+
+impl ProgressReporter for std::sync::mpsc::Sender<ProgressEvent> {
+    fn on_event(&mut self, event: ProgressEvent) {
+        // a dropped receiver just means nobody is listening anymore
+        let _ = self.send(event);
+    }
 }
 
 #[cfg(feature = "printer")]
-pub(crate) fn update_status(progress: &mut printer::MultiProgressBar, update_status: UpdateStatus) {
-    if let Some(brief) = update_status.brief {
-        progress.set_prefix(brief.as_str());
+impl ProgressReporter for printer::MultiProgressBar {
+    fn update_status(&mut self, update_status: UpdateStatus) {
+        if let Some(brief) = update_status.brief.as_ref() {
+            self.set_prefix(brief.as_str());
+        }
+
+        if let Some(detail) = update_status.detail.as_ref() {
+            self.set_message(detail.as_str());
+        } else if let Some(bytes) = update_status.describe_bytes() {
+            self.set_message(bytes.as_str());
+        }
+
+        if let Some(total) = update_status.total {
+            self.set_total(total);
+            if let Some(increment) = update_status.increment {
+                self.increment_with_overflow(increment);
+            }
+        } else {
+            self.set_total(100_u64);
+            self.increment_with_overflow(1);
+        }
     }
+}
+
+/// How often `digest_file` reports progress, in bytes hashed.
+const DIGEST_PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Hash used by `digest_file_with`, `Digestable::digest_with` and
+/// `Decoder::with_digest_algorithm`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DigestAlgorithm {
+    #[default]
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "blake3")]
+    Blake3,
+    /// 64 bit XXH3, many times faster than sha256 but not cryptographic. Fine
+    /// for validating caches, not for anything an attacker can write to.
+    #[serde(rename = "xxh3")]
+    Xxh3,
+}
+
+/// Digests of one file computed in a single read by `digest_file_all`. Only
+/// the requested algorithms are filled in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Digests {
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    pub xxh3: Option<String>,
+}
 
-    if let Some(detail) = update_status.detail {
-        progress.set_message(detail.as_str());
+impl Digests {
+    pub fn get(&self, algorithm: DigestAlgorithm) -> Option<&str> {
+        match algorithm {
+            DigestAlgorithm::Sha256 => self.sha256.as_deref(),
+            DigestAlgorithm::Blake3 => self.blake3.as_deref(),
+            DigestAlgorithm::Xxh3 => self.xxh3.as_deref(),
+        }
+    }
+
+    fn set(&mut self, algorithm: DigestAlgorithm, digest: String) {
+        let slot = match algorithm {
+            DigestAlgorithm::Sha256 => &mut self.sha256,
+            DigestAlgorithm::Blake3 => &mut self.blake3,
+            DigestAlgorithm::Xxh3 => &mut self.xxh3,
+        };
+        *slot = Some(digest);
     }
+}
 
-    if let Some(total) = update_status.total {
-        progress.set_total(total);
-        if let Some(increment) = update_status.increment {
-            progress.increment_with_overflow(increment);
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    fn new(algorithm: DigestAlgorithm) -> Self {
+        use sha2::Digest;
+        match algorithm {
+            DigestAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            DigestAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+            DigestAlgorithm::Xxh3 => Hasher::Xxh3(Box::default()),
+        }
+    }
+
+    fn algorithm(&self) -> DigestAlgorithm {
+        match self {
+            Hasher::Sha256(_) => DigestAlgorithm::Sha256,
+            Hasher::Blake3(_) => DigestAlgorithm::Blake3,
+            Hasher::Xxh3(_) => DigestAlgorithm::Xxh3,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        use sha2::Digest;
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Lowercase hex.
+    fn finish(self) -> String {
+        use sha2::Digest;
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
         }
-    } else {
-        progress.set_total(100_u64);
-        progress.increment_with_overflow(1);
     }
 }
 
-pub(crate) fn digest_file(
+/// Hex encoded sha256 of the file at `file_path`, hashed on the calling
+/// thread with constant memory. Progress counts the bytes hashed out of the
+/// file size.
+pub fn digest_file(
     file_path: &str,
-    #[cfg(feature = "printer")] progress: &mut printer::MultiProgressBar,
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
 ) -> anyhow::Result<String> {
-    #[cfg(feature = "printer")]
-    update_status(
-        progress,
-        UpdateStatus {
-            brief: None,
-            detail: Some("Digesting...".to_string()),
-            total: Some(200),
-            ..Default::default()
-        },
-    );
+    digest_file_with(file_path, DigestAlgorithm::Sha256, progress, cancellation)
+}
 
-    let file_path = file_path.to_owned();
+/// Like `digest_file` with another hash.
+pub fn digest_file_with(
+    file_path: &str,
+    algorithm: DigestAlgorithm,
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<String> {
+    let digests = digest_file_all(file_path, &[algorithm], progress, cancellation)?;
+    Ok(digests.get(algorithm).unwrap_or_default().to_string())
+}
 
-    let handle = std::thread::spawn(move || -> anyhow::Result<String> {
-        let file_contents = std::fs::read(&file_path).context(format_context!("{file_path}"))?;
-        let digest = sha256::digest(file_contents);
-        Ok(digest)
+/// Computes every algorithm in `algorithms`, plus the size, from one read of
+/// the file at `file_path`.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(progress, cancellation)))]
+pub fn digest_file_all(
+    file_path: &str,
+    algorithms: &[DigestAlgorithm],
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Digests> {
+    progress.on_event(ProgressEvent::PhaseChanged {
+        phase: Phase::Digesting,
+    });
+    let file = std::fs::File::open(file_path)
+        .map_err(|err| Error::io(file_path, err))
+        .context(format_context!("{file_path}"))?;
+    let bytes_total = file
+        .metadata()
+        .map_err(|err| Error::io(file_path, err))
+        .context(format_context!("{file_path}"))?
+        .len();
+    progress.update_status(UpdateStatus {
+        brief: None,
+        detail: Some("Digesting...".to_string()),
+        total: Some(bytes_total),
+        bytes_total: Some(bytes_total),
+        ..Default::default()
     });
 
-    wait_handle(
-        handle,
-        #[cfg(feature = "printer")]
-        progress,
+    let mut hashers: Vec<Hasher> = Vec::with_capacity(algorithms.len());
+    for algorithm in algorithms {
+        if !hashers
+            .iter()
+            .any(|hasher| hasher.algorithm() == *algorithm)
+        {
+            hashers.push(Hasher::new(*algorithm));
+        }
+    }
+    let mut byte_progress = ByteProgress::new(Some(bytes_total));
+    let size = hash_buf_reader(
+        std::io::BufReader::with_capacity(DEFAULT_BUFFER_SIZE, file),
+        hashers.as_mut_slice(),
+        cancellation,
+        |bytes_hashed| {
+            let increment = bytes_hashed - byte_progress.bytes_processed();
+            if increment >= DIGEST_PROGRESS_INTERVAL || bytes_hashed >= bytes_total {
+                byte_progress.add(increment);
+                progress.update_status(UpdateStatus {
+                    total: Some(bytes_total),
+                    increment: Some(increment),
+                    ..byte_progress.update_status()
+                });
+            }
+        },
     )
-    .context(format_context!(""))
+    .context(format_context!("{file_path}"))?;
+
+    let mut digests = Digests {
+        size,
+        ..Default::default()
+    };
+    for hasher in hashers {
+        digests.set(hasher.algorithm(), hasher.finish());
+    }
+    Ok(digests)
+}
+
+/// Hashes `reader` in fixed-size chunks so memory use doesn't grow with the input.
+pub(crate) fn sha256_reader<Reader: std::io::Read>(
+    reader: Reader,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<String> {
+    let mut hasher = Hasher::new(DigestAlgorithm::Sha256);
+    hash_buf_reader(
+        std::io::BufReader::with_capacity(DEFAULT_BUFFER_SIZE, reader),
+        std::slice::from_mut(&mut hasher),
+        cancellation,
+        |_| {},
+    )?;
+    Ok(hasher.finish())
 }
 
-pub(crate) fn wait_handle<OkType>(
+/// Feeds each chunk `reader` buffers to every hasher, calling `on_progress`
+/// with the number of bytes hashed so far. Returns the total.
+fn hash_buf_reader<Reader: std::io::BufRead>(
+    mut reader: Reader,
+    hashers: &mut [Hasher],
+    cancellation: &CancellationToken,
+    mut on_progress: impl FnMut(u64),
+) -> anyhow::Result<u64> {
+    let mut bytes_hashed = 0;
+    loop {
+        cancellation.check()?;
+        let chunk = match reader.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(format_error!("failed to read for digest: {err}")),
+        };
+        if chunk.is_empty() {
+            break;
+        }
+        for hasher in hashers.iter_mut() {
+            hasher.update(chunk);
+        }
+        let chunk_size = chunk.len();
+        reader.consume(chunk_size);
+        bytes_hashed += chunk_size as u64;
+        on_progress(bytes_hashed);
+    }
+    Ok(bytes_hashed)
+}
+
+/// Thread count used when none is configured: the available parallelism, or
+/// `1` on wasm targets, which have no threads.
+pub fn default_threads() -> usize {
+    if cfg!(target_family = "wasm") {
+        1
+    } else {
+        std::thread::available_parallelism()
+            .map(std::num::NonZeroUsize::get)
+            .unwrap_or(1)
+    }
+}
+
+/// Runs `work` on a worker thread while reporting progress. With one thread,
+/// and on wasm targets, `work` runs inline instead.
+pub(crate) fn run_worker<OkType: Send + 'static>(
+    work: impl FnOnce() -> anyhow::Result<OkType> + Send + 'static,
+    threads: usize,
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<OkType> {
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (threads, progress);
+        cancellation.check()?;
+        work()
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    if threads > 1 {
+        wait_handle(std::thread::spawn(work), progress, cancellation)
+    } else {
+        cancellation.check()?;
+        work()
+    }
+}
+
+/// Polls `handle` until it finishes. If `cancellation` fires first, the worker
+/// thread is detached and an error is returned immediately.
+#[cfg(not(target_family = "wasm"))]
+fn wait_handle<OkType>(
     handle: std::thread::JoinHandle<Result<OkType, anyhow::Error>>,
-    #[cfg(feature = "printer")] progress: &mut printer::MultiProgressBar,
+    progress: &mut dyn ProgressReporter,
+    cancellation: &CancellationToken,
 ) -> anyhow::Result<OkType> {
     while !handle.is_finished() {
-        #[cfg(feature = "printer")]
-        update_status(
-            progress,
-            UpdateStatus {
-                increment: Some(1),
-                ..Default::default()
-            },
-        );
+        cancellation.check()?;
+        progress.update_status(UpdateStatus {
+            increment: Some(1),
+            ..Default::default()
+        });
         std::thread::sleep(std::time::Duration::from_millis(50));
     }
 
@@ -1,63 +1,530 @@
-use crate::driver::{self, Driver, UpdateStatus, SEVEN_Z_TAR_FILENAME};
-use anyhow_source_location::format_context;
-use std::io::Write;
+use crate::driver::{
+    self, ByteProgress, CancellationToken, DigestAlgorithm, Digests, Driver, DriverOptions, Phase,
+    ProgressEvent, ProgressReporter, RetryPolicy, TarFormat, UpdateStatus, DEFAULT_BUFFER_SIZE,
+};
+use crate::package::{PackageEntry, PackageInfo, PackageSource};
+use crate::{paths, Error};
 use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+#[cfg(feature = "zip")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+/// Paths are kept as `PathBuf` so tar based archives store names that aren't
+/// valid UTF-8 byte for byte. Zip archives and packages need UTF-8 names and
+/// get a lossy conversion.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Entry {
-    pub archive_path: String,
-    pub file_path: String,
+    pub archive_path: PathBuf,
+    pub file_path: PathBuf,
+}
+
+impl Entry {
+    pub fn new(archive_path: impl Into<PathBuf>, file_path: impl Into<PathBuf>) -> Self {
+        Self {
+            archive_path: archive_path.into(),
+            file_path: file_path.into(),
+        }
+    }
+
+    /// `/` separated archive path, with invalid UTF-8 replaced.
+    pub fn archive_path_lossy(&self) -> String {
+        paths::to_archive_path(self.archive_path.as_path())
+    }
+
+    pub fn archive_path_str(&self) -> Option<&str> {
+        self.archive_path.to_str()
+    }
+
+    pub fn file_path_str(&self) -> Option<&str> {
+        self.file_path.to_str()
+    }
 }
 
 enum EncoderDriver {
     Gzip(tar::Builder<Vec<u8>>),
     Bzip2(tar::Builder<Vec<u8>>),
     Xz(tar::Builder<Vec<u8>>),
+    #[cfg(feature = "zip")]
     Zip(Box<zip::ZipWriter<std::fs::File>>),
     SevenZ(tar::Builder<Vec<u8>>),
+    Deb(Vec<PackageEntry>),
+    Rpm(Vec<PackageEntry>),
 }
 
-pub struct Digestable {
+pub struct Digestable<Progress: ProgressReporter> {
     path: String,
-    #[cfg(feature = "printer")]
-    progress_bar: printer::MultiProgressBar,
+    cancellation: CancellationToken,
+    progress_bar: Progress,
 }
 
-pub struct Digested {
+pub struct Digested<Progress: ProgressReporter> {
     pub sha256: String,
-    #[cfg(feature = "printer")]
-    pub progress_bar: printer::MultiProgressBar,
+    pub progress_bar: Progress,
+}
+
+pub struct Checksummed<Progress: ProgressReporter> {
+    pub algorithm: DigestAlgorithm,
+    /// Lowercase hex.
+    pub checksum: String,
+    pub progress_bar: Progress,
 }
 
-impl Digestable {
-    pub fn digest(self) -> anyhow::Result<Digested> {
+pub struct MultiDigested<Progress: ProgressReporter> {
+    pub digests: Digests,
+    pub progress_bar: Progress,
+}
+
+impl<Progress: ProgressReporter> Digestable<Progress> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(path = %self.path)))]
+    pub fn digest(self) -> anyhow::Result<Digested<Progress>> {
         let mut progress_bar = self.progress_bar;
 
-        let digest = driver::digest_file(
-            self.path.as_str(),
-            #[cfg(feature = "printer")]
-            &mut progress_bar,
-        );
+        let digest = driver::digest_file(self.path.as_str(), &mut progress_bar, &self.cancellation);
 
         Ok(Digested {
             sha256: digest?,
-            #[cfg(feature = "printer")]
+            progress_bar,
+        })
+    }
+
+    /// Like `digest` with another hash, such as `DigestAlgorithm::Xxh3` to
+    /// validate a cache quickly.
+    pub fn digest_with(self, algorithm: DigestAlgorithm) -> anyhow::Result<Checksummed<Progress>> {
+        let mut progress_bar = self.progress_bar;
+        let checksum = driver::digest_file_with(
+            self.path.as_str(),
+            algorithm,
+            &mut progress_bar,
+            &self.cancellation,
+        )?;
+        Ok(Checksummed {
+            algorithm,
+            checksum,
+            progress_bar,
+        })
+    }
+
+    /// Computes each of `algorithms` and the size from a single read of the
+    /// archive, for publishing with both a sha256 and a blake3.
+    pub fn digest_all(
+        self,
+        algorithms: &[DigestAlgorithm],
+    ) -> anyhow::Result<MultiDigested<Progress>> {
+        let mut progress_bar = self.progress_bar;
+        let digests = driver::digest_file_all(
+            self.path.as_str(),
+            algorithms,
+            &mut progress_bar,
+            &self.cancellation,
+        )?;
+        Ok(MultiDigested {
+            digests,
             progress_bar,
         })
     }
 }
 
-pub struct Encoder {
+pub struct Encoder<Progress: ProgressReporter> {
     encoder: EncoderDriver,
     driver: Driver,
     output_directory: String,
     output_filename: String,
-    #[cfg(feature = "printer")]
-    progress: printer::MultiProgressBar,
+    cancellation: CancellationToken,
+    buffer_size: usize,
+    buffer: Vec<u8>,
+    store_incompressible: bool,
+    incompressible_bytes: u64,
+    total_bytes: u64,
+    package_info: Option<PackageInfo>,
+    pax_globals: Vec<(String, String)>,
+    pax_globals_written: bool,
+    tar_format: TarFormat,
+    skip_unreadable: bool,
+    skipped: Vec<SkippedFile>,
+    retry: RetryPolicy,
+    deduplicate: bool,
+    stored_contents: std::collections::HashMap<(u64, Vec<u8>), PathBuf>,
+    driver_options: DriverOptions,
+    threads: usize,
+    progress: Progress,
+}
+
+/// State restored before an entry is retried.
+struct Checkpoint {
+    tar_len: Option<usize>,
+    total_bytes: u64,
+    incompressible_bytes: u64,
+}
+
+fn create_output_file(output_path: &str, retry: &RetryPolicy) -> anyhow::Result<std::fs::File> {
+    retry.run(|| {
+        std::fs::File::create(paths::long_path(output_path))
+            .context(format_context!("cannot create {output_path}"))
+    })
+}
+
+/// Offset just past the data of the last entry in a tar stream.
+fn tar_entries_end(tar_contents: &[u8]) -> anyhow::Result<usize> {
+    let mut archive = tar::Archive::new(tar_contents);
+    let mut end = 0;
+    for entry in archive.entries().context(format_context!(""))? {
+        let entry = entry.context(format_context!("corrupt tar entry"))?;
+        end = entry.raw_file_position() + entry.size().div_ceil(512) * 512;
+    }
+    Ok(end as usize)
+}
+
+/// A file left out by `Encoder::add_entries` under `with_skip_unreadable`.
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    pub archive_path: String,
+    pub file_path: PathBuf,
+    pub message: String,
+}
+
+/// Fails if `file_path` disappeared or can't be opened, before anything about
+/// it is written to the archive.
+fn check_readable(file_path: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(paths::long_path(file_path))?;
+    if metadata.is_file() {
+        std::fs::File::open(paths::long_path(file_path))?;
+    }
+    Ok(())
+}
+
+const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "bz2", "deb", "flac", "gif", "gz", "jar", "jpeg", "jpg", "mkv", "mov",
+    "mp3", "mp4", "ogg", "png", "rpm", "tgz", "webm", "webp", "whl", "woff", "woff2", "xz", "zip",
+    "zst",
+];
+const ENTROPY_SAMPLE_SIZE: usize = 64 * 1024;
+const MIN_ENTROPY_SAMPLE_SIZE: usize = 4096;
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+const INCOMPRESSIBLE_ARCHIVE_FRACTION: f64 = 0.8;
+/// Largest size an octal ustar size field can hold (8 GiB - 1).
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+/// Largest uid or gid an octal ustar id field can hold.
+const USTAR_MAX_ID: u64 = 0o7777777;
+
+/// Bits of entropy per byte, from 0 (constant) to 8 (random).
+fn shannon_entropy(sample: &[u8]) -> f64 {
+    let mut counts = [0_u64; 256];
+    for byte in sample {
+        counts[*byte as usize] += 1;
+    }
+    let length = sample.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / length;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+/// Each record is `<length> <key>=<value>\n` where the length counts itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body_length = key.len() + value.len() + 3;
+    let mut length = body_length + 1;
+    while body_length + length.to_string().len() != length {
+        length = body_length + length.to_string().len();
+    }
+    let mut record = format!("{length} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+fn append_pax_globals(
+    archiver: &mut tar::Builder<Vec<u8>>,
+    records: &[(String, String)],
+) -> anyhow::Result<()> {
+    let contents: Vec<u8> = records
+        .iter()
+        .flat_map(|(key, value)| pax_record(key, value.as_bytes()))
+        .collect();
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XGlobalHeader);
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    archiver
+        .append_data(&mut header, "pax_global_header", contents.as_slice())
+        .context(format_context!("appending pax global header"))
+}
+
+/// Appends an entry using `header` for its metadata. `Gnu` and `Ustar` let
+/// the tar crate add GNU long name entries when needed. `Pax` writes
+/// the values that don't fit the ustar fields to an extended header instead.
+fn append_tar_entry(
+    archiver: &mut tar::Builder<Vec<u8>>,
+    format: TarFormat,
+    header: &mut tar::Header,
+    archive_path: &Path,
+    link_name: Option<&Path>,
+    data: impl std::io::Read,
+) -> anyhow::Result<()> {
+    if format == TarFormat::UstarStrict {
+        check_strict_ustar(header, archive_path, link_name)?;
+        header.set_cksum();
+        archiver
+            .append(header, data)
+            .context(format_context!("appending {archive_path:?}"))?;
+        return Ok(());
+    }
+    if format != TarFormat::Pax {
+        match link_name {
+            Some(link_name) => archiver.append_link(header, archive_path, link_name),
+            None => archiver.append_data(header, archive_path, data),
+        }
+        .context(format_context!("appending {archive_path:?}"))?;
+        return Ok(());
+    }
+
+    let mut records = Vec::new();
+    if header.set_path(archive_path).is_err() {
+        let path = paths::to_bytes(archive_path);
+        records.extend(pax_record("path", path.as_slice()));
+        // readers without PAX support still see a recognizable name
+        if let Some(ustar) = header.as_ustar_mut() {
+            ustar.prefix.fill(0);
+        }
+        let name = &mut header.as_old_mut().name;
+        let length = path.len().min(name.len() - 1);
+        name.fill(0);
+        name[..length].copy_from_slice(&path[..length]);
+    }
+    if let Some(link_name) = link_name {
+        if header.set_link_name(link_name).is_err() {
+            records.extend(pax_record(
+                "linkpath",
+                paths::to_bytes(link_name).as_slice(),
+            ));
+        }
+    }
+    let size = header
+        .entry_size()
+        .context(format_context!("{archive_path:?}"))?;
+    if size > USTAR_MAX_SIZE {
+        records.extend(pax_record("size", size.to_string().as_bytes()));
+    }
+
+    if !records.is_empty() {
+        let mut pax_header = tar::Header::new_ustar();
+        pax_header.set_entry_type(tar::EntryType::XHeader);
+        pax_header
+            .set_path("PaxHeader")
+            .context(format_context!("{archive_path:?}"))?;
+        pax_header.set_size(records.len() as u64);
+        pax_header.set_mode(0o644);
+        pax_header.set_cksum();
+        archiver
+            .append(&pax_header, records.as_slice())
+            .context(format_context!("appending pax header for {archive_path:?}"))?;
+    }
+    header.set_cksum();
+    archiver
+        .append(header, data)
+        .context(format_context!("appending {archive_path:?}"))
+}
+
+fn new_tar_header(format: TarFormat) -> tar::Header {
+    match format {
+        TarFormat::Gnu => tar::Header::new_gnu(),
+        TarFormat::Ustar | TarFormat::UstarStrict | TarFormat::Pax => {
+            // strict readers reject the empty numeric fields `new_ustar` leaves
+            let mut header = tar::Header::new_ustar();
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header
+        }
+    }
+}
+
+/// Sets the path and link name on `header`, failing instead of falling back
+/// to an extension for values a plain ustar reader can't handle.
+fn check_strict_ustar(
+    header: &mut tar::Header,
+    archive_path: &Path,
+    link_name: Option<&Path>,
+) -> anyhow::Result<()> {
+    header.set_path(archive_path).map_err(|_| {
+        format_error!(
+            "{archive_path:?} can't be stored in strict ustar: paths are limited to a 100 byte name and a 155 byte directory prefix"
+        )
+    })?;
+    if let Some(link_name) = link_name {
+        header.set_link_name(link_name).map_err(|_| {
+            format_error!(
+                "{archive_path:?} can't be stored in strict ustar: the link target {link_name:?} is longer than 100 bytes"
+            )
+        })?;
+    }
+    let size = header
+        .entry_size()
+        .context(format_context!("{archive_path:?}"))?;
+    if size > USTAR_MAX_SIZE {
+        return Err(format_error!(
+            "{archive_path:?} can't be stored in strict ustar: {size} bytes is over the 8 GiB limit"
+        ));
+    }
+    let mtime = header
+        .mtime()
+        .context(format_context!("{archive_path:?}"))?;
+    if mtime > USTAR_MAX_SIZE {
+        return Err(format_error!(
+            "{archive_path:?} can't be stored in strict ustar: mtime {mtime} is out of range"
+        ));
+    }
+    for (name, id) in [("uid", header.uid()), ("gid", header.gid())] {
+        let id = id.context(format_context!("{archive_path:?}"))?;
+        if id > USTAR_MAX_ID {
+            return Err(format_error!(
+                "{archive_path:?} can't be stored in strict ustar: {name} {id} is over {USTAR_MAX_ID}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Inverse of `days_from_civil`: the (year, month, day) of a day count
+/// relative to 1970-01-01.
+#[cfg(feature = "zip")]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// MS-DOS timestamp (read as UTC) for `mtime`. Zip can't store times before
+/// 1980, so those become 1980-01-01.
+#[cfg(feature = "zip")]
+fn zip_datetime(mtime: u64) -> zip::DateTime {
+    let (year, month, day) = civil_from_days((mtime / 86400) as i64);
+    let seconds = mtime % 86400;
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                month as u8,
+                day as u8,
+                (seconds / 3600) as u8,
+                (seconds % 3600 / 60) as u8,
+                (seconds % 60) as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Method, level and encryption for a zip entry; `store` forces `Stored`.
+#[cfg(feature = "zip")]
+fn zip_file_options(
+    driver_options: &DriverOptions,
+    store: bool,
+) -> zip::write::FileOptions<'_, ()> {
+    let options = zip::write::SimpleFileOptions::default();
+    let options = if store || driver_options.zip_method == driver::ZipMethod::Stored {
+        options.compression_method(zip::CompressionMethod::Stored)
+    } else {
+        options
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(driver_options.level.map(i64::from))
+    };
+    #[cfg(feature = "encryption")]
+    if let Some(password) = driver_options.password.as_deref() {
+        return options.with_aes_encryption(zip::AesMode::Aes256, password);
+    }
+    options
+}
+
+/// Size and sha256 of the rest of `file`, which is rewound afterwards.
+fn content_key(
+    file: &mut std::fs::File,
+    buffer: &mut Vec<u8>,
+    buffer_size: usize,
+) -> std::io::Result<(u64, Vec<u8>)> {
+    use sha2::Digest;
+    use std::io::{Read, Seek};
+    buffer.resize(buffer_size, 0);
+    let mut hasher = sha2::Sha256::new();
+    let mut size = 0;
+    loop {
+        let bytes_read = file.read(buffer.as_mut_slice())?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        size += bytes_read as u64;
+    }
+    file.rewind()?;
+    Ok((size, hasher.finalize().to_vec()))
+}
+
+/// Counts the bytes read from a generated entry.
+struct CountingReader<Reader: std::io::Read> {
+    inner: Reader,
+    size: u64,
+}
+
+impl<Reader: std::io::Read> std::io::Read for CountingReader<Reader> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buffer)?;
+        self.size += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Hashes and counts the bytes of a download as it is archived.
+#[cfg(feature = "http")]
+struct HashingReader<Reader: std::io::Read> {
+    inner: Reader,
+    hasher: sha2::Sha256,
+    size: u64,
+}
+
+#[cfg(feature = "http")]
+impl<Reader: std::io::Read> std::io::Read for HashingReader<Reader> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let bytes_read = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..bytes_read]);
+        self.size += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Checks the extension first and falls back to the entropy of the first block.
+fn is_incompressible(file_path: &Path, sample: &[u8]) -> bool {
+    let extension = file_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    if let Some(extension) = extension {
+        if INCOMPRESSIBLE_EXTENSIONS.contains(&extension.as_str()) {
+            return true;
+        }
+    }
+    sample.len() >= MIN_ENTROPY_SAMPLE_SIZE && shannon_entropy(sample) > INCOMPRESSIBLE_ENTROPY
 }
 
-impl Encoder {
+impl<Progress: ProgressReporter> Encoder<Progress> {
     fn get_output_file_path(output_directory: &str, output_filename: &str) -> String {
-        format!("{output_directory}/{output_filename}")
+        paths::join(output_directory, output_filename)
     }
 
     fn get_encoder_output_file_path(&self) -> String {
@@ -67,33 +534,34 @@ impl Encoder {
         )
     }
 
-    #[allow(unused)]
     fn update_status(&mut self, update_status: UpdateStatus) {
-        #[cfg(feature = "printer")]
-        driver::update_status(&mut self.progress, update_status);
+        self.progress.update_status(update_status);
     }
 
     pub fn new(
         output_directory: &str,
         output_filename: &str,
-        #[cfg(feature = "printer")] progress: printer::MultiProgressBar,
+        progress: Progress,
     ) -> anyhow::Result<Self> {
-        let driver = Driver::from_filename(output_filename).ok_or(anyhow::anyhow!(
-            "could not determine compression type from {output_filename} suffix"
-        ))?;
+        let driver = Driver::from_filename(output_filename)
+            .ok_or_else(|| Error::unknown_format(output_filename))?;
+        driver.check_supported()?;
 
         let encoder = match driver {
             Driver::Gzip => {
                 let archiver = tar::Builder::new(Vec::new());
                 EncoderDriver::Gzip(archiver)
             }
+            #[cfg(feature = "zip")]
             Driver::Zip => {
                 let file_path = Self::get_output_file_path(output_directory, output_filename);
-                let file = std::fs::File::create(file_path.as_str())
+                let file = std::fs::File::create(paths::long_path(file_path.as_str()))
                     .context(format_context!("{file_path}"))?;
                 let encoder = zip::ZipWriter::new(file);
                 EncoderDriver::Zip(Box::new(encoder))
             }
+            #[cfg(not(feature = "zip"))]
+            Driver::Zip => unreachable!("rejected by check_supported"),
             Driver::Bzip2 => {
                 let archiver = tar::Builder::new(Vec::new());
                 EncoderDriver::Bzip2(archiver)
@@ -106,6 +574,8 @@ impl Encoder {
                 let archiver = tar::Builder::new(Vec::new());
                 EncoderDriver::SevenZ(archiver)
             }
+            Driver::Deb => EncoderDriver::Deb(Vec::new()),
+            Driver::Rpm => EncoderDriver::Rpm(Vec::new()),
         };
 
         Ok(Self {
@@ -113,27 +583,302 @@ impl Encoder {
             driver,
             output_directory: output_directory.to_string(),
             output_filename: output_filename.to_string(),
-            #[cfg(feature = "printer")]
+            cancellation: CancellationToken::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            buffer: Vec::new(),
+            store_incompressible: true,
+            incompressible_bytes: 0,
+            total_bytes: 0,
+            package_info: None,
+            pax_globals: Vec::new(),
+            pax_globals_written: false,
+            tar_format: TarFormat::default(),
+            skip_unreadable: false,
+            skipped: Vec::new(),
+            retry: RetryPolicy::default(),
+            deduplicate: false,
+            stored_contents: std::collections::HashMap::new(),
+            driver_options: DriverOptions::default(),
+            threads: driver::default_threads(),
             progress,
         })
     }
 
+    /// Opens an existing tar based archive so more entries can be added. The
+    /// archive is decompressed into memory and written back, recompressed,
+    /// by `compress`. Entries added with a path that is already in the
+    /// archive are appended after it, so they win when extracted.
+    pub fn reopen(archive_path: &str, progress: Progress) -> anyhow::Result<Self> {
+        let path = Path::new(archive_path);
+        let output_filename = path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .context(format_context!("{archive_path} has no file name"))?;
+        let output_directory = path
+            .parent()
+            .map(|parent| parent.to_string_lossy().to_string())
+            .filter(|parent| !parent.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+        let driver = Driver::from_filename(output_filename.as_str())
+            .ok_or_else(|| Error::unknown_format(output_filename.as_str()))?;
+        if !driver.supports_append() {
+            return Err(Error::UnsupportedFormat {
+                driver,
+                reason: format!("{} archives can't be reopened", driver.extension()),
+            })
+            .context(format_context!("{archive_path}"));
+        }
+
+        let contents = std::fs::read(paths::long_path(archive_path))
+            .map_err(|err| Error::io(archive_path, err))
+            .context(format_context!("{archive_path}"))?;
+        let mut tar_contents = crate::memory::tar_contents(driver, contents.as_slice())
+            .context(format_context!("{archive_path}"))?;
+        // drop the end-of-archive blocks so the builder appends after the last entry
+        let end =
+            tar_entries_end(tar_contents.as_slice()).context(format_context!("{archive_path}"))?;
+        tar_contents.truncate(end);
+
+        let mut encoder = Self::new(
+            output_directory.as_str(),
+            output_filename.as_str(),
+            progress,
+        )?;
+        let archiver = tar::Builder::new(tar_contents);
+        encoder.encoder = match driver {
+            Driver::Gzip => EncoderDriver::Gzip(archiver),
+            Driver::Bzip2 => EncoderDriver::Bzip2(archiver),
+            Driver::Xz => EncoderDriver::Xz(archiver),
+            Driver::SevenZ => EncoderDriver::SevenZ(archiver),
+            Driver::Zip | Driver::Deb | Driver::Rpm => unreachable!("not tar based"),
+        };
+        Ok(encoder)
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Metadata for the `deb` and `rpm` drivers. Without it the package is
+    /// named after the output file with version `0`.
+    pub fn with_package_info(mut self, package_info: PackageInfo) -> Self {
+        self.package_info = Some(package_info);
+        self
+    }
+
+    /// Sets the zip archive comment. Tar based archives store it as the PAX
+    /// `comment` global header instead.
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        match &mut self.encoder {
+            #[cfg(feature = "zip")]
+            EncoderDriver::Zip(encoder) => encoder.set_comment(comment),
+            _ => self
+                .pax_globals
+                .push(("comment".to_string(), comment.to_string())),
+        }
+        self
+    }
+
+    /// Adds a record to the PAX global extended header written before the
+    /// first entry of tar based archives. Readers apply standard keywords such
+    /// as `path` to every entry, so use a vendor prefix (`BUILD.id`) for
+    /// custom values. Zip has no equivalent; use `with_comment` instead.
+    pub fn with_pax_global(mut self, key: &str, value: &str) -> Self {
+        self.pax_globals.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Header layout for tar based archives. Defaults to `TarFormat::Gnu`;
+    /// ignored by zip and packages.
+    pub fn with_tar_format(mut self, tar_format: TarFormat) -> Self {
+        self.tar_format = tar_format;
+        self
+    }
+
+    fn write_pax_globals(&mut self) -> anyhow::Result<()> {
+        if self.pax_globals_written || self.pax_globals.is_empty() {
+            return Ok(());
+        }
+        self.pax_globals_written = true;
+        match &mut self.encoder {
+            EncoderDriver::Gzip(_)
+            | EncoderDriver::Bzip2(_)
+            | EncoderDriver::Xz(_)
+            | EncoderDriver::SevenZ(_)
+                if self.tar_format == TarFormat::UstarStrict =>
+            {
+                Err(format_error!(
+                    "PAX global headers (comments) can't be stored in strict ustar"
+                ))
+            }
+            EncoderDriver::Gzip(archiver)
+            | EncoderDriver::Bzip2(archiver)
+            | EncoderDriver::Xz(archiver)
+            | EncoderDriver::SevenZ(archiver) => {
+                append_pax_globals(archiver, self.pax_globals.as_slice())
+            }
+            #[cfg(feature = "zip")]
+            EncoderDriver::Zip(_) => Ok(()),
+            EncoderDriver::Deb(_) | EncoderDriver::Rpm(_) => Ok(()),
+        }
+    }
+
+    /// Already-compressed files (by extension or entropy) are stored in zip
+    /// archives instead of deflated. Tar based archives switch to the fastest
+    /// compression level when most of their bytes are incompressible. Enabled
+    /// by default.
+    pub fn with_store_incompressible(mut self, store_incompressible: bool) -> Self {
+        self.store_incompressible = store_incompressible;
+        self
+    }
+
+    /// Makes `add_entries` leave out files that are missing or can't be
+    /// opened instead of failing. They are reported with
+    /// `ProgressEvent::EntrySkipped` and returned by `skipped`.
+    pub fn with_skip_unreadable(mut self, skip_unreadable: bool) -> Self {
+        self.skip_unreadable = skip_unreadable;
+        self
+    }
+
+    /// Stores files whose contents were already added as hard links to the
+    /// first copy in tar based archives. Zip archives and packages store
+    /// every copy.
+    pub fn with_deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Compression level, xz dictionary size and zip method of the output,
+    /// the tar format and the thread count. Invalid options are reported by
+    /// `compress`.
+    pub fn with_driver_options(mut self, driver_options: DriverOptions) -> Self {
+        self.tar_format = driver_options.tar_format;
+        if let Some(threads) = driver_options.threads {
+            self = self.with_threads(threads);
+        }
+        self.driver_options = driver_options;
+        self
+    }
+
+    /// Threads available to compress the output. Only tar.7z compresses on a
+    /// worker thread today. Defaults to `driver::default_threads`; `1` does all
+    /// the work on the calling thread.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn skipped(&self) -> &[SkippedFile] {
+        &self.skipped
+    }
+
+    /// Reports files left out before `add_entries` was called.
+    pub(crate) fn record_skipped(&mut self, skipped: Vec<SkippedFile>) {
+        for skipped_file in skipped {
+            self.progress.on_event(ProgressEvent::EntrySkipped {
+                archive_path: skipped_file.archive_path.clone(),
+                message: skipped_file.message.clone(),
+            });
+            self.skipped.push(skipped_file);
+        }
+    }
+
+    /// Retries transient errors while adding files and when `compress`
+    /// creates the output file. Zip output files are created by `new` and
+    /// aren't retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        let tar_len = match &self.encoder {
+            EncoderDriver::Gzip(archiver)
+            | EncoderDriver::Bzip2(archiver)
+            | EncoderDriver::Xz(archiver)
+            | EncoderDriver::SevenZ(archiver) => Some(archiver.get_ref().len()),
+            _ => None,
+        };
+        Checkpoint {
+            tar_len,
+            total_bytes: self.total_bytes,
+            incompressible_bytes: self.incompressible_bytes,
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: &Checkpoint) {
+        if let Some(tar_len) = checkpoint.tar_len {
+            match &mut self.encoder {
+                EncoderDriver::Gzip(archiver)
+                | EncoderDriver::Bzip2(archiver)
+                | EncoderDriver::Xz(archiver)
+                | EncoderDriver::SevenZ(archiver) => archiver.get_mut().truncate(tar_len),
+                _ => {}
+            }
+        }
+        self.total_bytes = checkpoint.total_bytes;
+        self.incompressible_bytes = checkpoint.incompressible_bytes;
+    }
+
+    /// Size of the buffer reused for reading input files and feeding the compressor.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(count = entries.len()))
+    )]
     pub fn add_entries(&mut self, entries: &[Entry]) -> anyhow::Result<()> {
+        self.progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Archiving,
+        });
         self.update_status(UpdateStatus {
             detail: Some(format!("Archiving... ({})", self.driver.extension())),
             ..Default::default()
         });
 
+        let bytes_total = entries
+            .iter()
+            .filter_map(|entry| std::fs::symlink_metadata(entry.file_path.as_path()).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let mut byte_progress = ByteProgress::new(Some(bytes_total));
+
         for entry in entries.iter() {
+            self.cancellation.check()?;
+            let archive_path = entry.archive_path_lossy();
             self.update_status(UpdateStatus {
-                detail: Some(entry.archive_path.clone()),
+                detail: Some(archive_path.clone()),
                 increment: Some(1),
                 total: Some(entries.len() as u64),
-                ..Default::default()
+                ..byte_progress.update_status()
             });
 
-            self.add_file(&entry.archive_path, &entry.file_path)
-                .context(format_context!("{}", entry.archive_path))?;
+            if self.skip_unreadable {
+                if let Err(err) = check_readable(entry.file_path.as_path()) {
+                    let message = err.to_string();
+                    self.progress.on_event(ProgressEvent::EntrySkipped {
+                        archive_path: archive_path.clone(),
+                        message: message.clone(),
+                    });
+                    self.skipped.push(SkippedFile {
+                        archive_path,
+                        file_path: entry.file_path.clone(),
+                        message,
+                    });
+                    continue;
+                }
+            }
+
+            self.add_file(entry.archive_path.as_path(), entry.file_path.as_path())
+                .context(format_context!("{archive_path}"))?;
+
+            if let Ok(metadata) = std::fs::symlink_metadata(entry.file_path.as_path()) {
+                byte_progress.add(metadata.len());
+            }
         }
 
         self.update_status(UpdateStatus {
@@ -143,171 +888,721 @@ impl Encoder {
 
         Ok(())
     }
-    
-    pub fn add_file(&mut self, archive_path: &str, file_path: &str) -> anyhow::Result<()> {
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(archive_path = ?archive_path.as_ref(), file_path = ?file_path.as_ref())
+        )
+    )]
+    pub fn add_file(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        file_path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let (archive_path, file_path) = (archive_path.as_ref(), file_path.as_ref());
+        let start = std::time::Instant::now();
+        self.progress.on_event(ProgressEvent::EntryStarted {
+            archive_path: paths::to_archive_path(archive_path),
+        });
+
+        // written outside the retried part so a rollback doesn't drop them
+        self.write_pax_globals()?;
+        let checkpoint = self.checkpoint();
+        let retry = self.retry;
+        retry.run(|| {
+            self.rollback(&checkpoint);
+            self.add_file_to_archive(archive_path, file_path)
+        })?;
+
+        let size = std::fs::symlink_metadata(file_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        self.progress.on_event(ProgressEvent::EntryFinished {
+            archive_path: paths::to_archive_path(archive_path),
+            size,
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    }
+
+    /// Adds a regular file with `contents` that doesn't exist on disk.
+    pub fn add_data(&mut self, archive_path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        self.append_data(archive_path, contents, 0o644, None)
+    }
+
+    /// Like `add_data` with the permission bits and modification time (seconds
+    /// since the epoch) to store. Packages ignore both.
+    pub fn add_data_with_metadata(
+        &mut self,
+        archive_path: &str,
+        contents: &[u8],
+        mode: u32,
+        mtime: u64,
+    ) -> anyhow::Result<()> {
+        self.append_data(archive_path, contents, mode, Some(mtime))
+    }
+
+    /// Downloads `url` into the archive at `archive_path` without a staging
+    /// file. The download is streamed when the server sends its length and
+    /// held in memory otherwise. With `sha256`, a download that doesn't match
+    /// is left out of the archive and `Error::DigestMismatch` is returned.
+    #[cfg(feature = "http")]
+    pub fn add_url(
+        &mut self,
+        archive_path: &str,
+        url: &str,
+        sha256: Option<&str>,
+    ) -> anyhow::Result<()> {
+        use sha2::Digest;
+        use std::io::Read;
+        let start = std::time::Instant::now();
+        self.progress.on_event(ProgressEvent::EntryStarted {
+            archive_path: archive_path.to_string(),
+        });
+
+        let response = ureq::get(url)
+            .call()
+            .context(format_context!("failed to download {url}"))?;
+        let length: Option<u64> = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok());
+        let mut reader = HashingReader {
+            inner: response.into_reader(),
+            hasher: sha2::Sha256::new(),
+            size: 0,
+        };
+
+        self.write_pax_globals()?;
+        let checkpoint = self.checkpoint();
+        match length {
+            Some(length) => self.append_reader(
+                archive_path,
+                length,
+                (&mut reader).take(length),
+                0o644,
+                None,
+            )?,
+            None => {
+                let mut contents = Vec::new();
+                reader
+                    .read_to_end(&mut contents)
+                    .context(format_context!("failed to download {url}"))?;
+                self.append_reader(
+                    archive_path,
+                    contents.len() as u64,
+                    contents.as_slice(),
+                    0o644,
+                    None,
+                )?;
+            }
+        }
+
+        let actual = format!("{:x}", reader.hasher.finalize());
+        let error = match (length, sha256) {
+            (Some(length), _) if reader.size != length => Some(Error::CorruptEntry {
+                name: url.to_string(),
+                reason: format!("expected {length} bytes, downloaded {}", reader.size),
+            }),
+            (_, Some(expected)) if !expected.eq_ignore_ascii_case(actual.as_str()) => {
+                Some(Error::DigestMismatch {
+                    name: url.to_string(),
+                    expected: expected.to_string(),
+                    actual,
+                })
+            }
+            _ => None,
+        };
+        if let Some(error) = error {
+            self.discard_entry(&checkpoint)
+                .context(format_context!("{archive_path}"))?;
+            return Err(error).context(format_context!("{archive_path}"));
+        }
+
+        self.progress.on_event(ProgressEvent::EntryFinished {
+            archive_path: archive_path.to_string(),
+            size: reader.size,
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    }
+
+    /// Adds a regular file read from `reader`, such as a database dump or the
+    /// output of a process, without a temporary file. With `size_hint`, tar
+    /// based archives stream the contents and fail if `reader` doesn't produce
+    /// exactly that many bytes. Without it they hold the contents in memory.
+    /// Zip archives always stream.
+    pub fn add_from_reader(
+        &mut self,
+        archive_path: &str,
+        size_hint: Option<u64>,
+        reader: impl std::io::Read,
+    ) -> anyhow::Result<()> {
+        use std::io::Read;
+        let start = std::time::Instant::now();
+        self.progress.on_event(ProgressEvent::EntryStarted {
+            archive_path: archive_path.to_string(),
+        });
+        let mut reader = CountingReader {
+            inner: reader,
+            size: 0,
+        };
+
+        self.write_pax_globals()?;
+        let checkpoint = self.checkpoint();
+        let is_zip = self.driver == Driver::Zip;
+        match size_hint {
+            _ if is_zip => self.append_reader(archive_path, 0, &mut reader, 0o644, None)?,
+            Some(size) => {
+                self.append_reader(archive_path, size, (&mut reader).take(size), 0o644, None)?;
+                let mut extra = [0; 1];
+                let has_extra = reader
+                    .inner
+                    .read(&mut extra)
+                    .context(format_context!("{archive_path}"))?
+                    > 0;
+                if has_extra || reader.size != size {
+                    self.discard_entry(&checkpoint)
+                        .context(format_context!("{archive_path}"))?;
+                    return Err(format_error!(
+                        "{archive_path}: expected {size} bytes, read {}{}",
+                        reader.size,
+                        if has_extra { " or more" } else { "" }
+                    ));
+                }
+            }
+            None => {
+                let mut contents = Vec::new();
+                reader
+                    .read_to_end(&mut contents)
+                    .context(format_context!("{archive_path}"))?;
+                self.append_data(archive_path, contents.as_slice(), 0o644, None)?;
+            }
+        }
+
+        self.progress.on_event(ProgressEvent::EntryFinished {
+            archive_path: archive_path.to_string(),
+            size: reader.size,
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    }
+
+    /// Copies the entries of `source_archive` whose paths `select` accepts,
+    /// without decompressing and recompressing their contents one by one.
+    /// Zip entries are copied raw into a zip output. Tar based entries are
+    /// streamed with their headers, links and modes into a tar based output.
+    /// Other combinations need `transcode`. Returns the number of entries copied.
+    pub fn copy_entries_from(
+        &mut self,
+        source_archive: &str,
+        mut select: impl FnMut(&str) -> bool,
+    ) -> anyhow::Result<usize> {
+        let source_driver = Driver::from_filename(source_archive)
+            .ok_or_else(|| Error::unknown_format(source_archive))
+            .context(format_context!("{source_archive}"))?;
+        source_driver
+            .check_extractable()
+            .context(format_context!("{source_archive}"))?;
+        let file = std::fs::File::open(source_archive)
+            .map_err(|err| Error::io(source_archive, err))
+            .context(format_context!("{source_archive}"))?;
+        self.write_pax_globals()?;
+
+        let mut copied = 0;
+        match &mut self.encoder {
+            #[cfg(feature = "zip")]
+            EncoderDriver::Zip(encoder) if source_driver == Driver::Zip => {
+                let mut archive = zip::ZipArchive::new(file)
+                    .context(format_context!("open zip failed: {source_archive}"))?;
+                for index in 0..archive.len() {
+                    self.cancellation.check()?;
+                    let zip_file = archive
+                        .by_index_raw(index)
+                        .context(format_context!("{source_archive}"))?;
+                    if !select(zip_file.name()) {
+                        continue;
+                    }
+                    let archive_path = zip_file.name().to_string();
+                    encoder
+                        .raw_copy_file(zip_file)
+                        .context(format_context!("{source_archive}: {archive_path}"))?;
+                    copied += 1;
+                }
+            }
+            EncoderDriver::Gzip(archiver)
+            | EncoderDriver::Bzip2(archiver)
+            | EncoderDriver::Xz(archiver)
+            | EncoderDriver::SevenZ(archiver)
+                if source_driver != Driver::Zip =>
+            {
+                let decoder = crate::Decoder::new(source_archive, None, "", driver::NoProgress)
+                    .context(format_context!("{source_archive}"))?;
+                let reader = decoder
+                    .open_tar_stream(file)
+                    .context(format_context!("{source_archive}"))?;
+                let mut archive = tar::Archive::new(reader);
+                for entry in archive
+                    .entries()
+                    .context(format_context!("{source_archive}"))?
+                {
+                    self.cancellation.check()?;
+                    let mut entry = entry.context(format_context!("{source_archive}"))?;
+                    if entry.header().entry_type().is_pax_global_extensions() {
+                        continue;
+                    }
+                    let archive_path = entry
+                        .path()
+                        .context(format_context!("{source_archive}"))?
+                        .into_owned();
+                    if !select(paths::to_archive_path(archive_path.as_path()).as_str()) {
+                        continue;
+                    }
+                    let link_name = entry
+                        .link_name()
+                        .context(format_context!("{source_archive}: {archive_path:?}"))?
+                        .map(|link_name| link_name.into_owned());
+                    let mut header = new_tar_header(self.tar_format);
+                    header.set_entry_type(entry.header().entry_type());
+                    header.set_size(entry.size());
+                    header.set_mode(entry.header().mode().unwrap_or(0o644));
+                    header.set_mtime(entry.header().mtime().unwrap_or_default());
+                    append_tar_entry(
+                        archiver,
+                        self.tar_format,
+                        &mut header,
+                        archive_path.as_path(),
+                        link_name.as_deref(),
+                        &mut entry,
+                    )
+                    .context(format_context!("{source_archive}"))?;
+                    copied += 1;
+                }
+            }
+            _ => {
+                return Err(Error::UnsupportedFormat {
+                    driver: source_driver,
+                    reason: format!(
+                        "entries of {} archives can't be copied into {} archives without \
+                        recompressing, use transcode instead",
+                        source_driver.extension(),
+                        self.driver.extension()
+                    ),
+                })
+                .context(format_context!("{source_archive}"));
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Removes the entry added since `checkpoint`.
+    fn discard_entry(&mut self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        self.rollback(checkpoint);
+        match &mut self.encoder {
+            #[cfg(feature = "zip")]
+            EncoderDriver::Zip(encoder) => {
+                encoder.abort_file().context(format_context!(""))?;
+            }
+            EncoderDriver::Deb(entries) | EncoderDriver::Rpm(entries) => {
+                entries.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn append_data(
+        &mut self,
+        archive_path: &str,
+        contents: &[u8],
+        mode: u32,
+        mtime: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.append_reader(archive_path, contents.len() as u64, contents, mode, mtime)
+    }
+
+    /// Adds a regular file of `size` bytes read from `reader`.
+    fn append_reader(
+        &mut self,
+        archive_path: &str,
+        size: u64,
+        mut reader: impl std::io::Read,
+        mode: u32,
+        mtime: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.write_pax_globals()?;
+        match &mut self.encoder {
+            EncoderDriver::Gzip(archiver)
+            | EncoderDriver::Bzip2(archiver)
+            | EncoderDriver::Xz(archiver)
+            | EncoderDriver::SevenZ(archiver) => {
+                let mut header = new_tar_header(self.tar_format);
+                header.set_size(size);
+                header.set_mode(mode);
+                header.set_mtime(mtime.unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|duration| duration.as_secs())
+                        .unwrap_or_default()
+                }));
+                append_tar_entry(
+                    archiver,
+                    self.tar_format,
+                    &mut header,
+                    Path::new(archive_path),
+                    None,
+                    reader,
+                )?;
+            }
+            #[cfg(feature = "zip")]
+            EncoderDriver::Zip(encoder) => {
+                let mut options =
+                    zip_file_options(&self.driver_options, false).unix_permissions(mode);
+                if let Some(mtime) = mtime {
+                    options = options.last_modified_time(zip_datetime(mtime));
+                }
+                encoder
+                    .start_file(archive_path, options)
+                    .context(format_context!("{archive_path}"))?;
+                std::io::copy(&mut reader, &mut **encoder)
+                    .context(format_context!("{archive_path}"))?;
+            }
+            EncoderDriver::Deb(entries) | EncoderDriver::Rpm(entries) => {
+                let mut contents = Vec::new();
+                reader
+                    .read_to_end(&mut contents)
+                    .context(format_context!("{archive_path}"))?;
+                entries.push(PackageEntry {
+                    archive_path: archive_path.to_string(),
+                    source: PackageSource::Data(contents),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file_to_archive(&mut self, archive_path: &Path, file_path: &Path) -> anyhow::Result<()> {
+        self.write_pax_globals()?;
         match &mut self.encoder {
             EncoderDriver::Gzip(archiver)
             | EncoderDriver::Bzip2(archiver)
             | EncoderDriver::Xz(archiver)
             | EncoderDriver::SevenZ(archiver) => {
-                let path = std::path::Path::new(file_path);
-                if path.is_symlink() {
-                    let target = path
+                if file_path.is_symlink() {
+                    let target = file_path
                         .read_link()
-                        .context(format_context!("failed to read symlink {file_path}"))?;
-                    let mut header = tar::Header::new_gnu();
+                        .context(format_context!("failed to read symlink {file_path:?}"))?;
+                    let mut header = new_tar_header(self.tar_format);
                     header.set_entry_type(tar::EntryType::Symlink);
                     header.set_size(0);
-                    let metadata = std::fs::metadata(file_path)
-                    .context(format_context!("{file_path}"))?;
                     #[cfg(unix)]
                     {
                         use std::os::unix::fs::{MetadataExt, PermissionsExt};
+                        let metadata = std::fs::metadata(file_path)
+                            .context(format_context!("{file_path:?}"))?;
                         header.set_mode(metadata.permissions().mode());
                         header.set_mtime(metadata.mtime() as u64);
                     }
 
-                    archiver
-                        .append_link(&mut header, archive_path, target)
-                        .context(format_context!("Failed to append symlink {file_path}"))?;
-
+                    append_tar_entry(
+                        archiver,
+                        self.tar_format,
+                        &mut header,
+                        archive_path,
+                        Some(target.as_path()),
+                        std::io::empty(),
+                    )
+                    .context(format_context!("Failed to append symlink {file_path:?}"))?;
                 } else {
-                    let mut file =
-                        std::fs::File::open(file_path).context(format_context!("{file_path}"))?;
-                    archiver
-                        .append_file(archive_path, &mut file)
-                        .context(format_context!("appending {archive_path}"))?;
+                    let mut file = std::fs::File::open(paths::long_path(file_path))
+                        .context(format_context!("{file_path:?}"))?;
+                    let content_key = if self.deduplicate {
+                        let key = content_key(&mut file, &mut self.buffer, self.buffer_size)
+                            .context(format_context!("{file_path:?}"))?;
+                        if let Some(original) = self.stored_contents.get(&key) {
+                            let mut header = new_tar_header(self.tar_format);
+                            header.set_metadata(
+                                &file.metadata().context(format_context!("{file_path:?}"))?,
+                            );
+                            header.set_entry_type(tar::EntryType::Link);
+                            header.set_size(0);
+                            append_tar_entry(
+                                archiver,
+                                self.tar_format,
+                                &mut header,
+                                archive_path,
+                                Some(original.as_path()),
+                                std::io::empty(),
+                            )?;
+                            return Ok(());
+                        }
+                        Some(key)
+                    } else {
+                        None
+                    };
+                    if self.store_incompressible {
+                        use std::io::{Read, Seek};
+                        self.buffer.clear();
+                        (&mut file)
+                            .take(ENTROPY_SAMPLE_SIZE as u64)
+                            .read_to_end(&mut self.buffer)
+                            .context(format_context!("{file_path:?}"))?;
+                        file.rewind().context(format_context!("{file_path:?}"))?;
+                        let size = file
+                            .metadata()
+                            .context(format_context!("{file_path:?}"))?
+                            .len();
+                        self.total_bytes += size;
+                        if is_incompressible(file_path, self.buffer.as_slice()) {
+                            self.incompressible_bytes += size;
+                        }
+                    }
+                    if self.tar_format == TarFormat::Gnu {
+                        archiver
+                            .append_file(archive_path, &mut file)
+                            .context(format_context!("appending {archive_path:?}"))?;
+                    } else {
+                        let mut header = new_tar_header(self.tar_format);
+                        header.set_metadata(
+                            &file.metadata().context(format_context!("{file_path:?}"))?,
+                        );
+                        append_tar_entry(
+                            archiver,
+                            self.tar_format,
+                            &mut header,
+                            archive_path,
+                            None,
+                            &mut file,
+                        )?;
+                    }
+                    if let Some(key) = content_key {
+                        self.stored_contents.insert(key, archive_path.to_path_buf());
+                    }
                 }
             }
+            EncoderDriver::Deb(entries) | EncoderDriver::Rpm(entries) => {
+                entries.push(PackageEntry {
+                    archive_path: paths::to_archive_path(archive_path),
+                    source: PackageSource::File(file_path.to_path_buf()),
+                });
+            }
+            #[cfg(feature = "zip")]
             EncoderDriver::Zip(encoder) => {
-                let options = zip::write::SimpleFileOptions::default()
-                    .compression_method(zip::CompressionMethod::Deflated)
-                    .unix_permissions(0o755);
+                let mut file = std::fs::File::open(paths::long_path(file_path)).context(
+                    format_context!("Failed to read file for zip archive {file_path:?}"),
+                )?;
+                self.buffer.resize(self.buffer_size, 0);
+                let mut bytes_read = std::io::Read::read(&mut file, self.buffer.as_mut_slice())
+                    .context(format_context!("{file_path:?}"))?;
+
+                let store = self.store_incompressible
+                    && is_incompressible(file_path, &self.buffer[..bytes_read]);
+                let options = zip_file_options(&self.driver_options, store).unix_permissions(0o755);
 
-                let contents = std::fs::read(file_path).context(format_context!(
-                    "Failed to read file for zip archive {file_path}"
-                ))?;
-                encoder
-                    .start_file(archive_path, options)
-                    .context(format_context!("{file_path}"))?;
                 encoder
-                    .write_all(contents.as_slice())
-                    .context(format_context!("{file_path}"))?;
+                    .start_file(paths::to_archive_path(archive_path), options)
+                    .context(format_context!("{file_path:?}"))?;
+                let mut copy = || -> anyhow::Result<()> {
+                    while bytes_read > 0 {
+                        encoder
+                            .write_all(&self.buffer[..bytes_read])
+                            .context(format_context!("{file_path:?}"))?;
+                        bytes_read = std::io::Read::read(&mut file, self.buffer.as_mut_slice())
+                            .context(format_context!("{file_path:?}"))?;
+                    }
+                    Ok(())
+                };
+                if let Err(error) = copy() {
+                    // drop the partial entry so the archive stays valid
+                    let _ = encoder.abort_file();
+                    return Err(error);
+                }
             }
         }
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(archiver, encoder, progress, cancellation))
+    )]
     fn encode_in_chunks<Encoder: std::io::Write>(
         archiver: tar::Builder<Vec<u8>>,
         mut encoder: Encoder,
         driver: Driver,
-        #[cfg(feature = "printer")] progress: &mut printer::MultiProgressBar,
+        buffer_size: usize,
+        progress: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
     ) -> anyhow::Result<()> {
         let contents = archiver
             .into_inner()
             .context(format_context!("{driver:?}"))?;
 
-        let total_chunks = contents.len() / 4096;
+        let total_chunks = contents.len().div_ceil(buffer_size);
 
-        #[cfg(feature = "printer")]
-        driver::update_status(
-            progress,
-            UpdateStatus {
-                detail: Some(format!("Compressing ({})", driver.extension())),
-                ..Default::default()
-            },
-        );
+        progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Compressing,
+        });
+        progress.update_status(UpdateStatus {
+            detail: Some(format!("Compressing ({})", driver.extension())),
+            ..Default::default()
+        });
 
-        for chunk in contents.as_slice().chunks(total_chunks) {
-            #[cfg(feature = "printer")]
-            driver::update_status(
-                progress,
-                UpdateStatus {
-                    increment: Some(1),
-                    total: Some((contents.len() / total_chunks) as u64),
-                    ..Default::default()
-                },
-            );
+        trace_event!(bytes = contents.len(), "compressing tar stream");
 
-            if !chunk.is_empty() {
-                encoder
-                    .write_all(chunk)
-                    .context(format_context!("encoder with driver {driver:?} failed"))?;
-            } else {
-                break;
-            }
+        let mut byte_progress = ByteProgress::new(Some(contents.len() as u64));
+
+        for chunk in contents.as_slice().chunks(buffer_size) {
+            cancellation.check()?;
+            byte_progress.add(chunk.len() as u64);
+            progress.update_status(UpdateStatus {
+                increment: Some(1),
+                total: Some(total_chunks as u64),
+                ..byte_progress.update_status()
+            });
+
+            encoder
+                .write_all(chunk)
+                .context(format_context!("encoder with driver {driver:?} failed"))?;
         }
         Ok(())
     }
 
-    pub fn compress(self) -> anyhow::Result<Digestable> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(driver = ?self.driver, output = %self.output_filename))
+    )]
+    pub fn compress(self) -> anyhow::Result<Digestable<Progress>> {
+        let output_path = self.get_encoder_output_file_path();
+        let cancellation = self.cancellation.clone();
+        let result = self.compress_output();
+        if result.is_err() && cancellation.is_cancelled() {
+            let _ = std::fs::remove_file(output_path.as_str());
+        }
+        result
+    }
+
+    fn compress_output(mut self) -> anyhow::Result<Digestable<Progress>> {
+        self.driver_options.check(self.driver)?;
+        self.write_pax_globals()?;
         let driver = self.driver;
-        let output_directory = self.output_directory.clone();
+        let level = self.driver_options.level;
+        #[cfg(feature = "sevenz")]
+        let threads = self.threads;
         let output_path = self.get_encoder_output_file_path();
         let output_path_result = output_path.clone();
+        let cancellation = self.cancellation;
+        let buffer_size = self.buffer_size;
+        let is_mostly_incompressible = self.total_bytes > 0
+            && self.incompressible_bytes as f64 / self.total_bytes as f64
+                > INCOMPRESSIBLE_ARCHIVE_FRACTION;
+        trace_event!(
+            incompressible_bytes = self.incompressible_bytes,
+            total_bytes = self.total_bytes,
+            "checked compressibility"
+        );
         let mut progress_bar = self.progress;
+        let retry = self.retry;
+        let package_info = self
+            .package_info
+            .unwrap_or_else(|| PackageInfo::from_file_name(self.output_filename.as_str()));
 
         match self.encoder {
             EncoderDriver::Gzip(archiver) => {
-                let output_file = std::fs::File::create(output_path.as_str())
-                    .context(format_context!("cannot create {output_path}"))?;
-                let encoder =
-                    flate2::write::GzEncoder::new(output_file, flate2::Compression::default());
+                let output_file = create_output_file(output_path.as_str(), &retry)?;
+                let compression = match level {
+                    Some(level) => flate2::Compression::new(level),
+                    None if is_mostly_incompressible => flate2::Compression::none(),
+                    None => flate2::Compression::default(),
+                };
+                let encoder = flate2::write::GzEncoder::new(output_file, compression);
                 Self::encode_in_chunks(
                     archiver,
                     encoder,
                     driver,
-                    #[cfg(feature = "printer")]
+                    buffer_size,
                     &mut progress_bar,
+                    &cancellation,
                 )?;
             }
+            #[cfg(feature = "zip")]
             EncoderDriver::Zip(encoder) => {
                 encoder.finish().context(format_context!("{output_path}"))?;
             }
+            #[cfg(all(feature = "xz", not(target_family = "wasm")))]
             EncoderDriver::Xz(archiver) => {
-                let output_file = std::fs::File::create(output_path.as_str())
-                    .context(format_context!("{output_path}"))?;
-                let encoder = xz2::write::XzEncoder::new(output_file, 9);
+                let output_file = create_output_file(output_path.as_str(), &retry)?;
+                let level = level.unwrap_or(if is_mostly_incompressible { 0 } else { 9 });
+                let encoder = match self.driver_options.dictionary_size {
+                    Some(dictionary_size) => {
+                        let mut lzma = xz2::stream::LzmaOptions::new_preset(level)
+                            .context(format_context!("xz level {level}"))?;
+                        lzma.dict_size(dictionary_size);
+                        let mut filters = xz2::stream::Filters::new();
+                        filters.lzma2(&lzma);
+                        let stream = xz2::stream::Stream::new_stream_encoder(
+                            &filters,
+                            xz2::stream::Check::Crc64,
+                        )
+                        .context(format_context!("xz dictionary size {dictionary_size}"))?;
+                        xz2::write::XzEncoder::new_stream(output_file, stream)
+                    }
+                    None => xz2::write::XzEncoder::new(output_file, level),
+                };
                 Self::encode_in_chunks(
                     archiver,
                     encoder,
                     driver,
-                    #[cfg(feature = "printer")]
+                    buffer_size,
                     &mut progress_bar,
+                    &cancellation,
                 )?;
             }
+            #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
             EncoderDriver::Bzip2(archiver) => {
-                let output_file = std::fs::File::create(output_path.as_str())
-                    .context(format_context!("{output_path}"))?;
-                let encoder =
-                    bzip2::write::BzEncoder::new(output_file, bzip2::Compression::default());
+                let output_file = create_output_file(output_path.as_str(), &retry)?;
+                let compression = match level {
+                    // bzip2 has no level 0
+                    Some(level) => bzip2::Compression::new(level.max(1)),
+                    None if is_mostly_incompressible => bzip2::Compression::fast(),
+                    None => bzip2::Compression::default(),
+                };
+                let encoder = bzip2::write::BzEncoder::new(output_file, compression);
                 Self::encode_in_chunks(
                     archiver,
                     encoder,
                     driver,
-                    #[cfg(feature = "printer")]
+                    buffer_size,
                     &mut progress_bar,
+                    &cancellation,
                 )?;
             }
+            #[cfg(not(all(feature = "xz", not(target_family = "wasm"))))]
+            EncoderDriver::Xz(_) => driver.check_supported()?,
+            #[cfg(not(all(feature = "bzip2", not(target_family = "wasm"))))]
+            EncoderDriver::Bzip2(_) => driver.check_supported()?,
+            #[cfg(feature = "sevenz")]
             EncoderDriver::SevenZ(archiver) => {
                 let contents = archiver.into_inner().context("tar.7z")?;
 
-                #[cfg(feature = "printer")]
-                driver::update_status(
-                    &mut progress_bar,
-                    UpdateStatus {
-                        detail: Some(format!("Compressing ({})", driver.extension())),
-                        total: Some(200),
-                        ..Default::default()
-                    },
-                );
-
-                let handle = std::thread::spawn(move || -> anyhow::Result<()> {
-                    let output_file = std::fs::File::create(output_path.as_str())
-                        .context(format_context!("{output_path}"))?;
-
-                    let temporary_tar_path = format!("{output_directory}/{}", SEVEN_Z_TAR_FILENAME);
+                progress_bar.on_event(ProgressEvent::PhaseChanged {
+                    phase: Phase::Compressing,
+                });
+                progress_bar.update_status(UpdateStatus {
+                    detail: Some(format!("Compressing ({})", driver.extension())),
+                    total: Some(200),
+                    bytes_total: Some(contents.len() as u64),
+                    ..Default::default()
+                });
+
+                let output_directory = self.output_directory.clone();
+                let work = move || -> anyhow::Result<()> {
+                    let output_file = create_output_file(output_path.as_str(), &retry)?;
+
+                    let temporary_tar_path =
+                        paths::join(output_directory.as_str(), driver::SEVEN_Z_TAR_FILENAME);
                     // create a temporary tar file
                     std::fs::write(temporary_tar_path.as_str(), contents)
                         .context(format_context!("{temporary_tar_path}"))?;
@@ -318,18 +1613,34 @@ impl Encoder {
                     //std::fs::remove_file(temporary_tar_path.as_str()).context(format_context!(""))?;
 
                     Ok(())
-                });
+                };
 
-                driver::wait_handle(
-                    handle,
-                    #[cfg(feature = "printer")]
-                    &mut progress_bar,
-                )
-                .context(format_context!(""))?;
+                driver::run_worker(work, threads, &mut progress_bar, &cancellation)
+                    .context(format_context!(""))?;
+            }
+            #[cfg(not(feature = "sevenz"))]
+            EncoderDriver::SevenZ(_) => driver.check_supported()?,
+            EncoderDriver::Deb(entries) => {
+                progress_bar.on_event(ProgressEvent::PhaseChanged {
+                    phase: Phase::Compressing,
+                });
+                crate::package::write_deb(&entries, &package_info, output_path.as_str())
+                    .context(format_context!("{output_path}"))?;
+            }
+            #[cfg(feature = "rpm")]
+            EncoderDriver::Rpm(entries) => {
+                progress_bar.on_event(ProgressEvent::PhaseChanged {
+                    phase: Phase::Compressing,
+                });
+                crate::package::write_rpm(&entries, &package_info, output_path.as_str())
+                    .context(format_context!("{output_path}"))?;
             }
+            #[cfg(not(feature = "rpm"))]
+            EncoderDriver::Rpm(_) => driver.check_supported()?,
         }
         Ok(Digestable {
             path: output_path_result,
+            cancellation,
             progress_bar,
         })
     }
@@ -0,0 +1,165 @@
+use crate::driver::{self, Driver};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Failures callers may want to tell apart. Functions still return
+/// `anyhow::Result`; these are the root cause underneath any added context and
+/// can be recovered with `Error::find` or `anyhow::Error::downcast_ref`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{}", describe_unknown_format(file_name, suggestions))]
+    UnknownFormat {
+        file_name: String,
+        /// Supported extensions that look like what was meant.
+        suggestions: Vec<String>,
+    },
+    #[error("{reason}")]
+    UnsupportedFormat { driver: Driver, reason: String },
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("digest mismatch for {name}: expected: {expected} actual: {actual}")]
+    DigestMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("{name} escapes the output directory")]
+    PathEscape { name: String },
+    #[error("symlink loop: {path:?} points back to its ancestor {ancestor:?}")]
+    SymlinkLoop { path: PathBuf, ancestor: PathBuf },
+    #[error("{name} is corrupt: {reason}")]
+    CorruptEntry { name: String, reason: String },
+    #[error(
+        "not enough space for {path:?}: need {}, have {}",
+        driver::format_bytes(*needed),
+        driver::format_bytes(*available)
+    )]
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    #[error("operation cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Stable identifier for a failure, for callers that branch on the cause
+/// without parsing messages. `code` values never change once released.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    UnknownFormat,
+    UnsupportedFormat,
+    Io,
+    DigestMismatch,
+    PathEscape,
+    SymlinkLoop,
+    CorruptEntry,
+    InsufficientSpace,
+    Cancelled,
+    /// Anything without a more specific kind, such as invalid options.
+    Other,
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnknownFormat => "unknown_format",
+            ErrorKind::UnsupportedFormat => "unsupported_format",
+            ErrorKind::Io => "io",
+            ErrorKind::DigestMismatch => "digest_mismatch",
+            ErrorKind::PathEscape => "path_escape",
+            ErrorKind::SymlinkLoop => "symlink_loop",
+            ErrorKind::CorruptEntry => "corrupt_entry",
+            ErrorKind::InsufficientSpace => "insufficient_space",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// The kind of any error returned by this crate. Without an `Error` in
+    /// the chain it is `Io` if an I/O error caused it and `Other` otherwise.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = Error::find(error) {
+            return error.kind();
+        }
+        if error
+            .chain()
+            .any(|cause| cause.downcast_ref::<std::io::Error>().is_some())
+        {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(self.code())
+    }
+}
+
+fn describe_unknown_format(file_name: &str, suggestions: &[String]) -> String {
+    let mut description = format!("could not determine the archive format of {file_name}");
+    if !suggestions.is_empty() {
+        let suggestions: Vec<_> = suggestions
+            .iter()
+            .map(|extension| format!(".{extension}"))
+            .collect();
+        description.push_str(&format!("; did you mean {}?", suggestions.join(" or ")));
+    }
+    if file_name.to_lowercase().ends_with(".7z") {
+        description.push_str(" Plain 7z archives aren't supported, only tar.7z.");
+    }
+    let supported: Vec<_> = Driver::supported_extensions()
+        .into_iter()
+        .map(|extension| format!(".{extension}"))
+        .collect();
+    description.push_str(&format!(" Supported extensions: {}", supported.join(", ")));
+    description
+}
+
+impl Error {
+    pub(crate) fn unknown_format(file_name: &str) -> Self {
+        Self::UnknownFormat {
+            file_name: file_name.to_string(),
+            suggestions: Driver::suggest_extensions(file_name),
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::UnknownFormat { .. } => ErrorKind::UnknownFormat,
+            Error::UnsupportedFormat { .. } => ErrorKind::UnsupportedFormat,
+            Error::Io { .. } => ErrorKind::Io,
+            Error::DigestMismatch { .. } => ErrorKind::DigestMismatch,
+            Error::PathEscape { .. } => ErrorKind::PathEscape,
+            Error::SymlinkLoop { .. } => ErrorKind::SymlinkLoop,
+            Error::CorruptEntry { .. } => ErrorKind::CorruptEntry,
+            Error::InsufficientSpace { .. } => ErrorKind::InsufficientSpace,
+            Error::Cancelled => ErrorKind::Cancelled,
+            Error::Other(error) => ErrorKind::of(error),
+        }
+    }
+
+    /// The `Error` that caused `error`, if there is one.
+    pub fn find(error: &anyhow::Error) -> Option<&Error> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+    }
+}
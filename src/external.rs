@@ -0,0 +1,421 @@
+use crate::decoder::{Decoder, Extracted};
+use crate::driver::{
+    CancellationToken, Driver, Phase, ProgressEvent, ProgressReporter, UpdateStatus,
+};
+use crate::Error;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Read};
+
+/// A system program that extracts archives the built-in drivers can't read,
+/// such as rar, plain tar or tar.zst.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalTool {
+    pub program: String,
+    /// `{archive}` and `{destination}` are replaced in each argument.
+    pub args: Vec<String>,
+    /// File name extensions the tool is used for, without the leading dot.
+    pub extensions: Vec<String>,
+    /// Only lines on standard output starting with this are counted as
+    /// extracted files. Every non-empty line is counted when `None`.
+    #[serde(default)]
+    pub entry_prefix: Option<String>,
+}
+
+impl ExternalTool {
+    /// The system `tar`, which reads plain tar and whatever compression it
+    /// was built with. Progress is counted from GNU tar's verbose output.
+    pub fn tar() -> Self {
+        Self {
+            program: "tar".to_string(),
+            args: ["-xvf", "{archive}", "-C", "{destination}"]
+                .map(str::to_string)
+                .to_vec(),
+            extensions: [
+                "tar", "tar.zst", "tzst", "tar.lz", "tar.lzma", "tlz", "tar.Z",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+            entry_prefix: None,
+        }
+    }
+
+    /// p7zip or 7-Zip, for 7z archives that aren't tar based and the
+    /// formats it reads that have no built-in driver.
+    pub fn seven_zip() -> Self {
+        Self {
+            program: "7z".to_string(),
+            args: ["x", "-y", "-bb1", "-o{destination}", "{archive}"]
+                .map(str::to_string)
+                .to_vec(),
+            extensions: ["7z", "rar", "cab", "iso", "arj", "lzh", "wim"]
+                .map(str::to_string)
+                .to_vec(),
+            entry_prefix: Some("- ".to_string()),
+        }
+    }
+
+    pub fn unrar() -> Self {
+        Self {
+            program: "unrar".to_string(),
+            args: ["x", "-o+", "-y", "{archive}", "{destination}/"]
+                .map(str::to_string)
+                .to_vec(),
+            extensions: vec!["rar".to_string()],
+            entry_prefix: Some("Extracting ".to_string()),
+        }
+    }
+
+    /// Whether the file name of `archive_path` ends with one of `extensions`.
+    pub fn handles(&self, archive_path: &str) -> bool {
+        let file_name = std::path::Path::new(archive_path)
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.extensions.iter().any(|extension| {
+            file_name
+                .strip_suffix(extension.to_lowercase().as_str())
+                .is_some_and(|stem| stem.ends_with('.'))
+        })
+    }
+
+    /// Runs the tool on `archive_path`, reporting each file it lists as
+    /// progress. Returns the number of files counted.
+    pub fn extract(
+        &self,
+        archive_path: &str,
+        destination: &str,
+        progress: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<u64> {
+        std::fs::create_dir_all(destination)
+            .map_err(|err| Error::io(destination, err))
+            .context(format_context!("{destination}"))?;
+        progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Extracting,
+        });
+        let command = Command {
+            program: self.program.as_str(),
+            args: expand_args(
+                self.args.as_slice(),
+                archive_path,
+                "{destination}",
+                destination,
+            ),
+            entry_prefix: self.entry_prefix.as_deref(),
+            env_allow_list: None,
+            working_directory: None,
+        };
+        command
+            .run(progress, cancellation)
+            .context(format_context!("failed to extract {archive_path}"))
+    }
+}
+
+/// A format handled entirely by an external program, registered with
+/// `Driver::register_command`. The program is run directly rather than
+/// through a shell, with an environment holding only `env_allow_list`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandDriver {
+    /// File name extension without the leading dot, e.g. `tar.zst`.
+    pub extension: String,
+    pub program: String,
+    /// Arguments to create `{archive}` from the contents of the `{source}`
+    /// directory. The program runs in `{source}`.
+    pub create_args: Vec<String>,
+    /// Arguments to extract `{archive}` into `{destination}`. The program
+    /// runs in `{destination}`.
+    pub extract_args: Vec<String>,
+    /// Variables copied from this process, such as `PATH`. Everything else
+    /// is cleared.
+    #[serde(default)]
+    pub env_allow_list: Vec<String>,
+    /// Same as `ExternalTool::entry_prefix`.
+    #[serde(default)]
+    pub entry_prefix: Option<String>,
+}
+
+impl CommandDriver {
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        let extension = self.extension.as_str();
+        if extension.is_empty() || extension.starts_with('.') {
+            return Err(format_error!(
+                "command driver extension {extension:?} must be non-empty without a leading dot"
+            ));
+        }
+        if self.program.is_empty() {
+            return Err(format_error!(
+                "command driver for {extension} has no program"
+            ));
+        }
+        for (name, args) in [
+            ("create", &self.create_args),
+            ("extract", &self.extract_args),
+        ] {
+            if !args.iter().any(|arg| arg.contains("{archive}")) {
+                return Err(format_error!(
+                    "{name} arguments of the {extension} command driver don't use {{archive}}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the file name of `archive_path` ends with `extension`.
+    pub fn handles(&self, archive_path: &str) -> bool {
+        archive_path
+            .strip_suffix(self.extension.as_str())
+            .is_some_and(|stem| stem.ends_with('.'))
+    }
+
+    /// Writes `archive_path` from the contents of `source_directory`.
+    /// Returns the number of files counted from the program's output.
+    pub fn create(
+        &self,
+        source_directory: &str,
+        archive_path: &str,
+        progress: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<u64> {
+        let archive_path = absolute(archive_path)?;
+        let source_directory = absolute(source_directory)?;
+        progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Archiving,
+        });
+        let command = Command {
+            program: self.program.as_str(),
+            args: expand_args(
+                self.create_args.as_slice(),
+                archive_path.as_str(),
+                "{source}",
+                source_directory.as_str(),
+            ),
+            entry_prefix: self.entry_prefix.as_deref(),
+            env_allow_list: Some(self.env_allow_list.as_slice()),
+            working_directory: Some(source_directory.as_str()),
+        };
+        command
+            .run(progress, cancellation)
+            .context(format_context!("failed to create {archive_path}"))
+    }
+
+    /// Extracts `archive_path` into `destination`, which is created first.
+    /// Returns the number of files counted from the program's output.
+    pub fn extract(
+        &self,
+        archive_path: &str,
+        destination: &str,
+        progress: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<u64> {
+        let archive_path = absolute(archive_path)?;
+        std::fs::create_dir_all(destination)
+            .map_err(|err| Error::io(destination, err))
+            .context(format_context!("{destination}"))?;
+        let destination = absolute(destination)?;
+        progress.on_event(ProgressEvent::PhaseChanged {
+            phase: Phase::Extracting,
+        });
+        let command = Command {
+            program: self.program.as_str(),
+            args: expand_args(
+                self.extract_args.as_slice(),
+                archive_path.as_str(),
+                "{destination}",
+                destination.as_str(),
+            ),
+            entry_prefix: self.entry_prefix.as_deref(),
+            env_allow_list: Some(self.env_allow_list.as_slice()),
+            working_directory: Some(destination.as_str()),
+        };
+        command
+            .run(progress, cancellation)
+            .context(format_context!("failed to extract {archive_path}"))
+    }
+}
+
+/// `path` as seen from a program running in another directory.
+fn absolute(path: &str) -> anyhow::Result<String> {
+    std::path::absolute(path)
+        .map(|path| path.to_string_lossy().to_string())
+        .map_err(|err| Error::io(path, err))
+        .context(format_context!("{path}"))
+}
+
+fn expand_args(args: &[String], archive_path: &str, placeholder: &str, path: &str) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            arg.replace("{archive}", archive_path)
+                .replace(placeholder, path)
+        })
+        .collect()
+}
+
+struct Command<'a> {
+    program: &'a str,
+    args: Vec<String>,
+    entry_prefix: Option<&'a str>,
+    /// Inherits the whole environment when `None`.
+    env_allow_list: Option<&'a [String]>,
+    working_directory: Option<&'a str>,
+}
+
+impl Command<'_> {
+    /// Runs the program, counting the lines it prints as entries.
+    fn run(
+        &self,
+        progress: &mut dyn ProgressReporter,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<u64> {
+        let program = self.program;
+        progress.update_status(UpdateStatus {
+            detail: Some(format!("Running {program}")),
+            ..Default::default()
+        });
+
+        let mut command = std::process::Command::new(program);
+        command
+            .args(self.args.as_slice())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        if let Some(env_allow_list) = self.env_allow_list {
+            command.env_clear().envs(
+                env_allow_list
+                    .iter()
+                    .filter_map(|name| Some((name, std::env::var_os(name)?))),
+            );
+        }
+        if let Some(working_directory) = self.working_directory {
+            command.current_dir(working_directory);
+        }
+        let mut child = command
+            .spawn()
+            .context(format_context!("failed to run {program}"))?;
+
+        // drained on its own thread so a chatty tool can't block on a full pipe
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr_thread = std::thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut files = 0;
+        for line in std::io::BufReader::new(stdout).lines() {
+            if let Err(error) = cancellation.check() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(error);
+            }
+            let line = line.context(format_context!("{program}"))?;
+            let entry = match self.entry_prefix {
+                Some(prefix) => line.strip_prefix(prefix),
+                None => Some(line.as_str()),
+            };
+            let Some(entry) = entry.map(str::trim).filter(|entry| !entry.is_empty()) else {
+                continue;
+            };
+            files += 1;
+            progress.update_status(UpdateStatus {
+                detail: Some(entry.to_string()),
+                increment: Some(1),
+                ..Default::default()
+            });
+        }
+
+        let status = child.wait().context(format_context!("{program}"))?;
+        let stderr = stderr_thread.join().unwrap_or_default();
+        if !status.success() {
+            return Err(format_error!(
+                "{program} failed ({status}): {}",
+                stderr.trim()
+            ));
+        }
+        Ok(files)
+    }
+}
+
+pub enum FallbackExtracted<Progress: ProgressReporter> {
+    Builtin(Extracted<Progress>),
+    External {
+        program: String,
+        /// Files counted from the tool's output.
+        files: u64,
+        progress_bar: Progress,
+    },
+}
+
+/// The driver `Decoder` would use for `input_file_path`, if it is built in
+/// and can be extracted.
+fn builtin_driver(input_file_path: &str) -> anyhow::Result<Option<Driver>> {
+    let mut file = std::fs::File::open(input_file_path)
+        .map_err(|err| Error::io(input_file_path, err))
+        .context(format_context!("{input_file_path}"))?;
+    let driver = Driver::from_reader(&mut file)
+        .context(format_context!("{input_file_path}"))?
+        .or_else(|| Driver::from_filename(input_file_path));
+    Ok(driver.filter(|driver| driver.is_supported() && !driver.is_package()))
+}
+
+fn extract_builtin<Progress: ProgressReporter>(
+    input_file_path: &str,
+    output_directory: &str,
+    progress: Progress,
+) -> anyhow::Result<FallbackExtracted<Progress>> {
+    let extracted = Decoder::new(input_file_path, None, output_directory, progress)
+        .context(format_context!("{input_file_path}"))?
+        .extract()
+        .context(format_context!("{input_file_path}"))?;
+    Ok(FallbackExtracted::Builtin(extracted))
+}
+
+/// Extracts `input_file_path` with `Decoder` when a built-in driver can read
+/// it, otherwise with the command driver registered for its extension, and
+/// otherwise with the first of `tools` that handles its extension. When none
+/// does, fails the way `Decoder` would.
+pub fn extract_with_fallback<Progress: ProgressReporter>(
+    input_file_path: &str,
+    output_directory: &str,
+    tools: &[ExternalTool],
+    progress: Progress,
+) -> anyhow::Result<FallbackExtracted<Progress>> {
+    if builtin_driver(input_file_path)?.is_some() {
+        return extract_builtin(input_file_path, output_directory, progress);
+    }
+
+    let mut progress_bar = progress;
+    let cancellation = CancellationToken::default();
+    let (program, files) = if let Some(command) = Driver::command_for_filename(input_file_path) {
+        let files = command
+            .extract(
+                input_file_path,
+                output_directory,
+                &mut progress_bar,
+                &cancellation,
+            )
+            .context(format_context!("{input_file_path}"))?;
+        (command.program, files)
+    } else if let Some(tool) = tools.iter().find(|tool| tool.handles(input_file_path)) {
+        let files = tool
+            .extract(
+                input_file_path,
+                output_directory,
+                &mut progress_bar,
+                &cancellation,
+            )
+            .context(format_context!("{input_file_path}"))?;
+        (tool.program.clone(), files)
+    } else {
+        return extract_builtin(input_file_path, output_directory, progress_bar);
+    };
+    Ok(FallbackExtracted::External {
+        program,
+        files,
+        progress_bar,
+    })
+}
@@ -0,0 +1,227 @@
+use crate::decoder::{self, Decoder, EntryIssue, EntryIssueKind, Extracted};
+use crate::digest_cache::DigestCache;
+use crate::driver::{self, CancellationToken, ProgressReporter};
+use crate::encoder::Entry;
+use crate::{paths, CreateArchive};
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Entry written at the root of an incremental archive listing the archive
+/// paths removed since the base, one per line.
+pub const DELETIONS_FILE_NAME: &str = ".easy-archiver-deletions";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncrementalArchive {
+    pub output_file_path: String,
+    pub sha256: String,
+    /// Archive paths written because they are new or their contents changed.
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Digests of every file in the inputs; the base for the next increment.
+    pub digests: BTreeMap<String, String>,
+}
+
+impl CreateArchive {
+    /// Like `create` but only archives files that are missing from `base` or
+    /// whose sha256 differs, plus a `DELETIONS_FILE_NAME` entry for paths in
+    /// `base` that no longer exist. `base` maps archive paths to sha256 digests,
+    /// as returned by `Decoder::entry_digests` or a previous
+    /// `IncrementalArchive::digests`. Directories and symlinks are always
+    /// included. Files skipped by `skip_unreadable` keep their `base` digest
+    /// so they aren't recorded as deleted. `embed_manifest` is ignored.
+    pub fn create_incremental<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        base: &BTreeMap<String, String>,
+        progress: Progress,
+    ) -> anyhow::Result<IncrementalArchive> {
+        if self.driver.is_package() {
+            return Err(format_error!(
+                "{:?} packages can't be created incrementally",
+                self.driver
+            ));
+        }
+
+        let (entries, skipped) = self
+            .collect_entries()
+            .context(format_error!("Failed to build file list"))?;
+
+        let cancellation = CancellationToken::default();
+        let mut digest_cache = self.open_digest_cache()?;
+        let mut digests = BTreeMap::new();
+        let mut updated = Vec::new();
+        let mut changed_entries = Vec::new();
+        for entry in entries {
+            let archive_path = entry.archive_path_lossy();
+            let digest = match self.file_digest(&entry, digest_cache.as_mut(), &cancellation) {
+                Ok(Some(digest)) => digest,
+                Ok(None) => {
+                    changed_entries.push(entry);
+                    continue;
+                }
+                Err(_) if self.skip_unreadable => {
+                    // let the encoder report the skip
+                    changed_entries.push(entry);
+                    if let Some(digest) = base.get(&archive_path) {
+                        digests.insert(archive_path, digest.clone());
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if base.get(&archive_path) != Some(&digest) {
+                updated.push(archive_path.clone());
+                changed_entries.push(entry);
+            }
+            digests.insert(archive_path, digest);
+        }
+        if let Some(digest_cache) = digest_cache {
+            digest_cache.save().context(format_context!(""))?;
+        }
+
+        updated.sort();
+        let deleted: Vec<String> = base
+            .keys()
+            .filter(|archive_path| !digests.contains_key(*archive_path))
+            .cloned()
+            .collect();
+
+        let output_file_name = self.get_output_file();
+        std::fs::create_dir_all(output_directory)
+            .context(format_context!("failed to create {output_directory}"))?;
+        let output_file_path = paths::join(output_directory, &output_file_name);
+
+        let mut encoder = self
+            .new_encoder(output_directory, output_file_name.as_str(), progress)
+            .context(format_context!("{output_file_path}"))?;
+        encoder.record_skipped(skipped);
+        let mut deletions = String::new();
+        for archive_path in deleted.iter() {
+            deletions.push_str(archive_path);
+            deletions.push('\n');
+        }
+        encoder
+            .add_data(DELETIONS_FILE_NAME, deletions.as_bytes())
+            .context(format_context!("{output_file_path}"))?;
+        encoder
+            .add_entries(&changed_entries)
+            .context(format_context!("{output_file_path}"))?;
+
+        let digest = encoder
+            .compress()
+            .context(format_context!("{output_file_path}"))?
+            .digest()
+            .context(format_context!("{output_file_path}"))?;
+
+        Ok(IncrementalArchive {
+            output_file_path,
+            sha256: digest.sha256,
+            updated,
+            deleted,
+            digests,
+        })
+    }
+
+    /// The sha256 of a regular file, or `None` for directories and symlinks.
+    fn file_digest(
+        &self,
+        entry: &Entry,
+        digest_cache: Option<&mut DigestCache>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<Option<String>> {
+        let metadata = if self.follow_symlinks {
+            std::fs::metadata(&entry.file_path)
+        } else {
+            std::fs::symlink_metadata(&entry.file_path)
+        }
+        .context(format_context!("{:?}", entry.file_path))?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        if let Some(digest_cache) = digest_cache {
+            return digest_cache
+                .digest(entry.file_path.as_path(), &metadata, cancellation)
+                .map(Some);
+        }
+
+        let file = std::fs::File::open(&entry.file_path)
+            .context(format_context!("{:?}", entry.file_path))?;
+        let digest = driver::sha256_reader(file, cancellation)
+            .context(format_context!("{:?}", entry.file_path))?;
+        Ok(Some(digest))
+    }
+}
+
+impl<Progress: ProgressReporter> Decoder<Progress> {
+    /// Extracts an archive created by `CreateArchive::create_incremental` over
+    /// an existing tree, then removes the files it lists as deleted along with
+    /// any directories left empty. Deletions that resolve outside the output
+    /// through a symlink are skipped with an `UnsafePath` warning.
+    pub fn apply_incremental(self) -> anyhow::Result<Extracted<Progress>> {
+        let output_directory = self.output_directory.clone();
+        let mut extracted = self
+            .extract()
+            .context(format_context!("{output_directory}"))?;
+
+        let deletions_path = paths::join(output_directory.as_str(), DELETIONS_FILE_NAME);
+        let deletions = std::fs::read_to_string(deletions_path.as_str()).context(
+            format_context!("{deletions_path} is missing; not an incremental archive"),
+        )?;
+        std::fs::remove_file(deletions_path.as_str())
+            .context(format_context!("{deletions_path}"))?;
+        extracted.files.remove(DELETIONS_FILE_NAME);
+
+        let mut parents = BTreeSet::new();
+        for archive_path in deletions.lines().filter(|line| !line.is_empty()) {
+            let relative_path = paths::normalize(Path::new(archive_path));
+            let file_path = paths::join_path(output_directory.as_str(), relative_path.as_path());
+            if !decoder::parent_is_inside(output_directory.as_str(), file_path.as_path())
+                .context(format_context!("{file_path:?}"))?
+            {
+                extracted.warnings.push(EntryIssue {
+                    path: archive_path.to_string(),
+                    kind: EntryIssueKind::UnsafePath,
+                    message: "deleted path escapes the output directory".to_string(),
+                });
+                continue;
+            }
+            match std::fs::remove_file(&file_path) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(error).context(format_context!("{file_path:?}"));
+                }
+                _ => {}
+            }
+            extracted
+                .files
+                .remove(paths::to_archive_path(relative_path.as_path()).as_str());
+            parents.extend(relative_path.ancestors().skip(1).map(Path::to_path_buf));
+        }
+
+        // deepest first so a parent is only checked once its children are gone
+        for parent in parents.iter().rev() {
+            if parent.as_os_str().is_empty() {
+                continue;
+            }
+            let directory = paths::join_path(output_directory.as_str(), parent);
+            let is_directory =
+                std::fs::symlink_metadata(&directory).is_ok_and(|metadata| metadata.is_dir());
+            if !is_directory
+                || !decoder::parent_is_inside(output_directory.as_str(), directory.as_path())
+                    .context(format_context!("{directory:?}"))?
+            {
+                continue;
+            }
+            let is_empty = std::fs::read_dir(&directory)
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(false);
+            if is_empty {
+                std::fs::remove_dir(&directory).context(format_context!("{directory:?}"))?;
+            }
+        }
+
+        Ok(extracted)
+    }
+}
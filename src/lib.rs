@@ -2,27 +2,267 @@ use anyhow::Context;
 use anyhow_source_location::{format_context, format_error};
 use serde::{Deserialize, Serialize};
 
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+#[cfg(feature = "attestation")]
+pub mod attestation;
+pub mod benchmark;
+pub mod builder;
 pub mod decoder;
+pub mod diff;
+pub mod digest_cache;
 pub mod driver;
 pub mod encoder;
+pub mod error;
+pub mod external;
+pub mod incremental;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod memory;
+#[cfg(feature = "object-store")]
+mod object_storage;
+pub mod package;
+mod paths;
+pub mod pattern;
+#[cfg(feature = "http")]
+pub mod remote_zip;
+pub mod segments;
+pub mod sfx;
+#[cfg(feature = "manifest")]
+pub mod split;
+pub mod transcode;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+pub mod verify;
+#[cfg(feature = "watch")]
+pub mod watch;
 
+#[cfg(feature = "tokio")]
+pub use asynchronous::{AsyncDecoder, AsyncEncoder};
+pub use benchmark::{benchmark, BenchmarkResult};
+pub use builder::CreateArchiveBuilder;
 pub use decoder::Decoder;
-pub use driver::UpdateStatus;
+pub use diff::{compare, diff, ArchiveDiff, ComparedEntry, EntryDifference};
+pub use digest_cache::DigestCache;
+pub use driver::{
+    CancellationToken, DigestAlgorithm, Digests, DriverOptions, NoProgress, Phase, ProgressEvent,
+    ProgressReporter, RetryPolicy, TarFormat, UpdateStatus, ZipMethod,
+};
 pub use encoder::Encoder;
+pub use error::{Error, ErrorKind};
+pub use external::{extract_with_fallback, CommandDriver, ExternalTool, FallbackExtracted};
+pub use incremental::IncrementalArchive;
+pub use pattern::GlobOptions;
+pub use segments::{PartialVerification, SegmentDigests};
+#[cfg(feature = "manifest")]
+pub use split::{SplitArchive, SplitIndex, SplitPart};
+pub use transcode::{transcode, TranscodeOptions, Transcoded};
+pub use verify::{verify, verify_tree, TreeReport, VerifyMismatch, VerifyReport};
+#[cfg(feature = "watch")]
+pub use watch::{watch, WatchOptions};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct CreateArchive {
+    #[serde(default)]
     pub input: String,
+    #[serde(default)]
+    pub inputs: Vec<(String, Option<String>)>,
     pub name: String,
     pub version: String,
     pub driver: driver::Driver,
     pub platform: Option<String>,
     pub includes: Option<Vec<String>>,
     pub excludes: Option<Vec<String>>,
+    #[serde(default)]
+    pub file_list: Option<FileList>,
+    #[serde(default)]
+    pub exclude_syntax: ExcludeSyntax,
+    #[serde(default)]
+    pub glob_options: GlobOptions,
+    /// Archive the targets of symlinks instead of the links themselves.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Skip dotfiles and common junk such as `.DS_Store`, `Thumbs.db` and `*.swp`.
+    #[serde(default)]
+    pub exclude_hidden: bool,
+    /// Maximum directory depth to walk; `1` only includes files directly in each input.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Leave out files smaller than this many bytes.
+    #[serde(default)]
+    pub min_file_size: Option<u64>,
+    /// Leave out files larger than this many bytes, such as core dumps or
+    /// debug artifacts.
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// Only include files modified after this time.
+    #[serde(default)]
+    pub modified_since: Option<std::time::SystemTime>,
+    /// Skip paths matched by `.gitignore` and `.archiveignore` files found in the inputs.
+    #[serde(default)]
+    pub respect_ignore_files: bool,
+    /// Write an `artifact.json` manifest as the first entry (needs the `manifest` feature).
+    #[serde(default)]
+    pub embed_manifest: bool,
+    /// Header layout for the tar based drivers.
+    #[serde(default)]
+    pub tar_format: driver::TarFormat,
+    /// Leave out files that disappear or can't be read, and symlink loops
+    /// found with `follow_symlinks`, instead of failing.
+    #[serde(default)]
+    pub skip_unreadable: bool,
+    #[serde(default)]
+    pub retry: driver::RetryPolicy,
+    /// Create the archive even when no files match the inputs.
+    #[serde(default)]
+    pub allow_empty: bool,
+    /// Directory every entry is placed under inside the archive, e.g.
+    /// `myapp-1.2.3`. Patterns still match the unprefixed paths, and an
+    /// embedded manifest stays at the archive root.
+    #[serde(default)]
+    pub archive_prefix: Option<String>,
+    /// Store files with the same contents once; tar based archives add the
+    /// other copies as hard links.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Puts an extraction stub in front of the archive so it can be unpacked
+    /// by running it. `create` writes `<archive>.sh` or `<archive>.exe` instead
+    /// of the plain archive.
+    #[serde(default)]
+    pub self_extracting: Option<sfx::SfxStub>,
+    /// Threads used to compress; see `Encoder::with_threads`. Defaults to
+    /// `driver::default_threads`; `1` keeps all the work on the calling thread.
+    /// Digests are always computed on the calling thread.
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// JSON file of source file digests keyed by path, size and modification
+    /// time, so `embed_manifest` and `create_incremental` only hash files that
+    /// changed since the last run (needs the `digest-cache` feature).
+    #[serde(default)]
+    pub digest_cache: Option<String>,
+    /// `create` also writes `<archive>.segments` with the sha256 of every
+    /// `segment_size` bytes of the archive (such as
+    /// `segments::DEFAULT_SEGMENT_SIZE`), so a partial download can be
+    /// checked with `SegmentDigests::verify_partial`.
+    #[serde(default)]
+    pub segment_size: Option<u64>,
+    /// `create` also writes `<archive>.intoto.json`, an in-toto statement with
+    /// SLSA provenance for the archive naming this builder id (needs the
+    /// `attestation` feature).
+    #[serde(default)]
+    pub attestation_builder_id: Option<String>,
+}
+
+/// How `excludes` patterns are interpreted.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ExcludeSyntax {
+    /// Each pattern is a glob matched against the full archive path. A leading
+    /// `!` re-includes paths excluded by an earlier pattern.
+    #[default]
+    #[serde(rename = "glob")]
+    Glob,
+    /// Patterns follow `.gitignore` rules: a leading `/` anchors to the archive
+    /// root, a trailing `/` matches directories and `!` re-includes a path.
+    #[serde(rename = "gitignore")]
+    Gitignore,
+}
+
+const ARCHIVE_IGNORE_FILENAME: &str = ".archiveignore";
+
+/// A symlink found while walking the inputs and the ancestor it points back to.
+type SymlinkLoop = (encoder::Entry, std::path::PathBuf);
+
+/// An explicit list of files to archive instead of walking the inputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileList {
+    /// Paths given directly.
+    #[serde(rename = "files")]
+    Files(Vec<String>),
+    /// A newline-delimited file with one path per line (like `tar -T`).
+    #[serde(rename = "path")]
+    Path(String),
+}
+
+impl FileList {
+    pub fn from_paths(paths: &[std::path::PathBuf]) -> Self {
+        FileList::Files(
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+        )
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<String>> {
+        match self {
+            FileList::Files(files) => Ok(files.clone()),
+            FileList::Path(path) => {
+                let contents =
+                    std::fs::read_to_string(path.as_str()).context(format_context!("{path}"))?;
+                Ok(contents
+                    .lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_string())
+                    .collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedEntry {
+    pub archive_path: String,
+    pub file_path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivePlan {
+    pub output_file: String,
+    pub entries: Vec<PlannedEntry>,
+    pub total_size: u64,
+    pub estimated_compressed_size: u64,
 }
 
 impl CreateArchive {
+    pub fn builder() -> CreateArchiveBuilder {
+        CreateArchiveBuilder::default()
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        toml::from_str(contents).context(format_context!("failed to parse archive spec (toml)"))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_json(contents: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(contents)
+            .context(format_context!("failed to parse archive spec (json)"))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).context(format_context!("{path}"))?;
+        if path.ends_with(".json") {
+            Self::from_json(contents.as_str()).context(format_context!("{path}"))
+        } else if path.ends_with(".toml") {
+            Self::from_toml(contents.as_str()).context(format_context!("{path}"))
+        } else {
+            Err(format_error!(
+                "archive spec {path} must have a .toml or .json extension"
+            ))
+        }
+    }
+
     pub fn get_output_file(&self) -> String {
         let mut result = format!("{}-v{}", self.name, self.version);
         if let Some(platform) = self.platform.as_ref() {
@@ -33,105 +273,635 @@ impl CreateArchive {
         result
     }
 
-    pub fn build_file_list(&self) -> anyhow::Result<Vec<(String, String)>> {
-        let input_as_path = std::path::Path::new(self.input.as_str());
+    /// All input roots to walk: `input` (if set) followed by `inputs`.
+    pub fn input_roots(&self) -> Vec<(String, Option<String>)> {
+        let mut roots = Vec::new();
+        if !self.input.is_empty() {
+            roots.push((self.input.clone(), None));
+        }
+        roots.extend(self.inputs.iter().cloned());
+        roots
+    }
+
+    /// Returns the files under `input` and the symlinks that loop back to one
+    /// of their ancestors, each with that ancestor.
+    fn walk_input(
+        &self,
+        input: &str,
+        prefix: Option<&str>,
+    ) -> anyhow::Result<(Vec<encoder::Entry>, Vec<SymlinkLoop>)> {
+        if cfg!(all(target_family = "wasm", target_os = "unknown")) {
+            return Err(format_error!(
+                "can't walk {input} without a filesystem, use the memory module instead"
+            ));
+        }
+        let input_as_path = paths::long_path(input);
+        std::fs::metadata(input_as_path.as_path())
+            .map_err(|err| Error::io(input, err))
+            .context(format_context!("archive input {input} does not exist"))?;
 
         let strip_prefix = if input_as_path.is_dir() {
-            self.input.clone()
+            input_as_path.clone()
         } else if let Some(parent) = input_as_path.parent() {
-            parent.to_string_lossy().to_string()
+            parent.to_path_buf()
         } else {
-            "".to_string()
+            std::path::PathBuf::new()
         };
 
-        let walk_dir: Vec<_> = walkdir::WalkDir::new(self.input.as_str())
-            .into_iter()
-            .filter_map(|entry| entry.ok())
-            .collect();
+        // Both walkers detect symlink loops when following links and report
+        // them as errors instead of recursing.
+        let mut loops = Vec::new();
+        let walk_dir: Vec<std::path::PathBuf> = if self.respect_ignore_files {
+            ignore::WalkBuilder::new(input_as_path.as_path())
+                .standard_filters(false)
+                .follow_links(self.follow_symlinks)
+                .max_depth(self.max_depth)
+                .git_ignore(true)
+                .require_git(false)
+                .parents(false)
+                .add_custom_ignore_filename(ARCHIVE_IGNORE_FILENAME)
+                .build()
+                .filter_map(|entry| {
+                    entry
+                        .map_err(|err| loops.extend(pattern::symlink_loop(&err)))
+                        .ok()
+                })
+                .filter(|entry| {
+                    !entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_dir())
+                })
+                .map(|entry| entry.into_path())
+                .collect()
+        } else {
+            let mut walk_dir =
+                walkdir::WalkDir::new(input_as_path.as_path()).follow_links(self.follow_symlinks);
+            if let Some(max_depth) = self.max_depth {
+                walk_dir = walk_dir.max_depth(max_depth);
+            }
+            walk_dir
+                .into_iter()
+                .filter_map(|entry| {
+                    entry
+                        .map_err(|err| {
+                            if let (Some(child), Some(ancestor)) = (err.path(), err.loop_ancestor())
+                            {
+                                loops.push((child.to_path_buf(), ancestor.to_path_buf()));
+                            }
+                        })
+                        .ok()
+                })
+                .filter(|entry| !entry.file_type().is_dir())
+                .map(|entry| entry.into_path())
+                .collect()
+        };
 
-        let mut all_files = Vec::new();
+        let archive_path_of = |item: &std::path::Path| -> anyhow::Result<std::path::PathBuf> {
+            let relative_path = paths::normalize(
+                item.strip_prefix(strip_prefix.as_path())
+                    .context(format_context!("{item:?}"))?,
+            );
+            Ok(match prefix {
+                Some(prefix) => paths::normalize(std::path::Path::new(prefix)).join(relative_path),
+                None => relative_path,
+            })
+        };
 
+        let mut files = Vec::new();
         for item in walk_dir {
-            if item.file_type().is_dir() {
-                continue;
-            }
-            let archive_path = item
-                .path()
-                .strip_prefix(strip_prefix.as_str())
-                .context(format_context!("{item:?}"))?
-                .to_string_lossy()
-                .to_string();
+            let archive_path = archive_path_of(item.as_path())?;
+            let file_path = if self.follow_symlinks && item.is_symlink() {
+                std::fs::canonicalize(item.as_path()).context(format_context!("{item:?}"))?
+            } else {
+                item
+            };
+            files.push(encoder::Entry::new(archive_path, file_path));
+        }
 
-            let file_path = item.path().to_string_lossy().to_string();
-            all_files.push((archive_path, file_path));
+        let mut looped = Vec::new();
+        for (child, ancestor) in loops {
+            let archive_path = archive_path_of(child.as_path())?;
+            looped.push((encoder::Entry::new(archive_path, child), ancestor));
         }
 
+        Ok((files, looped))
+    }
+
+    fn build_explicit_file_list(
+        &self,
+        file_list: &FileList,
+    ) -> anyhow::Result<Vec<encoder::Entry>> {
         let mut files = Vec::new();
+        for file_path in file_list.load().context(format_context!("file list"))? {
+            let path = std::path::Path::new(file_path.as_str());
+            // the encoder reports missing files when they are skipped
+            if !path.exists() && !self.skip_unreadable {
+                return Err(format_error!("{file_path} from file list does not exist"));
+            }
+            let relative_path = if self.input.is_empty() {
+                path
+            } else {
+                path.strip_prefix(self.input.as_str()).unwrap_or(path)
+            };
+            let archive_path = paths::normalize(relative_path);
+            files.push(encoder::Entry::new(archive_path, file_path));
+        }
+        Ok(files)
+    }
 
-        for (archive_path, file_path) in all_files.iter() {
-            let mut is_match = true;
-            if let Some(includes) = self.includes.as_ref() {
-                is_match = false;
-                for pattern in includes {
-                    if glob_match::glob_match(pattern, archive_path) {
-                        is_match = true;
-                        break;
-                    }
-                }
+    /// `(archive_path, file_path)` pairs from `build_entries` as UTF-8 strings.
+    /// Names that aren't valid UTF-8 are converted lossily.
+    pub fn build_file_list(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let entries = self.build_entries().context(format_context!(""))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.archive_path_lossy(),
+                    entry.file_path.to_string_lossy().to_string(),
+                )
+            })
+            .collect())
+    }
+
+    /// The files to archive, filtered by the include, exclude and size options.
+    pub fn build_entries(&self) -> anyhow::Result<Vec<encoder::Entry>> {
+        let (entries, _) = self.collect_entries().context(format_context!(""))?;
+        Ok(entries)
+    }
+
+    /// `build_entries` plus the symlink loops left out under `skip_unreadable`.
+    /// Without it, a loop that isn't excluded is an error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(input = %self.input))
+    )]
+    fn collect_entries(&self) -> anyhow::Result<(Vec<encoder::Entry>, Vec<encoder::SkippedFile>)> {
+        if let Some(file_list) = self.file_list.as_ref() {
+            let mut files = self.build_explicit_file_list(file_list)?;
+            self.apply_archive_prefix(&mut files);
+            return Ok((files, Vec::new()));
+        }
+
+        let mut all_files = Vec::new();
+        let mut ancestors = std::collections::HashMap::new();
+        let mut loops = Vec::new();
+        for (input, prefix) in self.input_roots() {
+            let (files, looped) = self
+                .walk_input(input.as_str(), prefix.as_deref())
+                .context(format_context!("{input}"))?;
+            all_files.extend(files);
+            for (entry, ancestor) in looped {
+                ancestors.insert(entry.file_path.clone(), ancestor);
+                loops.push(entry);
             }
-            if is_match {
-                files.push((archive_path.clone(), file_path.clone()));
+        }
+
+        let mut files = self.filter_entries(all_files)?;
+        self.apply_archive_prefix(&mut files);
+        let mut skipped = Vec::new();
+        let mut loops = self.filter_entries(loops)?;
+        self.apply_archive_prefix(&mut loops);
+        for entry in loops {
+            let ancestor = ancestors.remove(&entry.file_path).unwrap_or_default();
+            if !self.skip_unreadable {
+                return Err(Error::SymlinkLoop {
+                    path: entry.file_path,
+                    ancestor,
+                }
+                .into());
             }
+            skipped.push(encoder::SkippedFile {
+                archive_path: entry.archive_path_lossy(),
+                message: format!("symlink loops back to {ancestor:?}"),
+                file_path: entry.file_path,
+            });
+        }
+
+        trace_event!(count = files.len(), "built file list");
+
+        Ok((files, skipped))
+    }
+
+    fn apply_archive_prefix(&self, files: &mut [encoder::Entry]) {
+        let Some(prefix) = self.archive_prefix.as_deref() else {
+            return;
+        };
+        let prefix = paths::normalize(std::path::Path::new(prefix));
+        if prefix.as_os_str().is_empty() {
+            return;
+        }
+        for file in files.iter_mut() {
+            file.archive_path = prefix.join(file.archive_path.as_path());
+        }
+    }
+
+    fn filter_entries(
+        &self,
+        mut files: Vec<encoder::Entry>,
+    ) -> anyhow::Result<Vec<encoder::Entry>> {
+        if self.exclude_hidden {
+            files.retain(|file| !pattern::is_hidden_or_junk(file.archive_path_lossy().as_str()));
+        }
+
+        if self.min_file_size.is_some() || self.max_file_size.is_some() {
+            files.retain(|file| {
+                let Ok(metadata) = std::fs::symlink_metadata(file.file_path.as_path()) else {
+                    return false;
+                };
+                let size = metadata.len();
+                self.min_file_size
+                    .is_none_or(|min_file_size| size >= min_file_size)
+                    && self
+                        .max_file_size
+                        .is_none_or(|max_file_size| size <= max_file_size)
+            });
+        }
+
+        if let Some(modified_since) = self.modified_since {
+            files.retain(|file| {
+                std::fs::symlink_metadata(file.file_path.as_path())
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| modified > modified_since)
+            });
+        }
+
+        if let Some(includes) = self.includes.as_ref() {
+            let includes = pattern::PatternList::new(includes, self.glob_options)
+                .context(format_context!("includes"))?;
+            files.retain(|file| {
+                includes
+                    .matches(file.archive_path_lossy().as_str())
+                    .unwrap_or(false)
+            });
         }
 
         if let Some(excludes) = self.excludes.as_ref() {
-            for pattern in excludes {
-                files.retain(|file| !glob_match::glob_match(pattern, &file.0));
+            match self.exclude_syntax {
+                ExcludeSyntax::Glob => {
+                    let excludes = pattern::PatternList::new(excludes, self.glob_options)
+                        .context(format_context!("excludes"))?;
+                    files.retain(|file| {
+                        !excludes
+                            .matches(file.archive_path_lossy().as_str())
+                            .unwrap_or(false)
+                    });
+                }
+                ExcludeSyntax::Gitignore => {
+                    let gitignore = pattern::build_gitignore(excludes, self.glob_options)
+                        .context(format_context!(""))?;
+                    files.retain(|file| {
+                        !gitignore
+                            .matched_path_or_any_parents(file.archive_path.as_path(), false)
+                            .is_ignore()
+                    });
+                }
             }
         }
 
         Ok(files)
     }
 
-    pub fn create(
+    pub fn plan(&self) -> anyhow::Result<ArchivePlan> {
+        let files = self
+            .build_entries()
+            .context(format_error!("Failed to build file list"))?;
+
+        let mut entries = Vec::with_capacity(files.len());
+        for file in files {
+            let size = std::fs::symlink_metadata(file.file_path.as_path())
+                .context(format_context!("{:?}", file.file_path))?
+                .len();
+            entries.push(PlannedEntry {
+                archive_path: file.archive_path_lossy(),
+                file_path: file.file_path.to_string_lossy().to_string(),
+                size,
+            });
+        }
+
+        let total_size = entries.iter().map(|entry| entry.size).sum::<u64>();
+        let estimated_compressed_size =
+            (total_size as f64 * self.driver.estimated_compression_ratio()) as u64;
+
+        Ok(ArchivePlan {
+            output_file: self.get_output_file(),
+            entries,
+            total_size,
+            estimated_compressed_size,
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(name = %self.name, version = %self.version))
+    )]
+    pub fn create<Progress: ProgressReporter>(
         &self,
         output_directory: &str,
-        #[cfg(feature = "printer")] progress: printer::MultiProgressBar,
+        progress: Progress,
     ) -> anyhow::Result<(String, String)> {
-        let output_file_name = self.get_output_file();
+        let (encoder, output_file_path) = self
+            .encode(output_directory, progress)
+            .context(format_context!("{output_directory}"))?;
 
-        std::fs::create_dir_all(output_directory)
-            .context(format_context!("failed to create {output_directory}"))?;
+        let digestable = encoder
+            .compress()
+            .context(format_context!("{output_directory}"))?;
 
-        let output_file_path = format!("{}/{}", output_directory, output_file_name);
+        let digest = digestable
+            .digest()
+            .context(format_context!("{output_directory}"))?;
 
-        let files = self
-            .build_file_list()
-            .context(format_error!("Failed to build file list"))?;
+        let Some(stub) = self.self_extracting.as_ref() else {
+            self.write_segments(output_file_path.as_str())?;
+            self.write_attestation(output_file_path.as_str(), digest.sha256.as_str())?;
+            return Ok((output_file_path, digest.sha256));
+        };
+        let sfx_file_path = format!("{output_file_path}.{}", stub.extension());
+        sfx::write_self_extracting(output_file_path.as_str(), stub, sfx_file_path.as_str())
+            .context(format_context!("{sfx_file_path}"))?;
+        std::fs::remove_file(output_file_path.as_str())
+            .context(format_context!("{output_file_path}"))?;
+        let mut progress = digest.progress_bar;
+        let sha256 = driver::digest_file(
+            sfx_file_path.as_str(),
+            &mut progress,
+            &driver::CancellationToken::default(),
+        )
+        .context(format_context!("{sfx_file_path}"))?;
+        self.write_segments(sfx_file_path.as_str())?;
+        self.write_attestation(sfx_file_path.as_str(), sha256.as_str())?;
+        Ok((sfx_file_path, sha256))
+    }
+
+    /// The attestation for `attestation_builder_id`, with the archive options
+    /// as the external parameters.
+    #[cfg(feature = "attestation")]
+    fn write_attestation(&self, output_file_path: &str, sha256: &str) -> anyhow::Result<()> {
+        let Some(builder_id) = self.attestation_builder_id.as_deref() else {
+            return Ok(());
+        };
+        let external_parameters = serde_json::to_value(self).context(format_context!(""))?;
+        attestation::Statement::from_sha256(output_file_path, sha256, builder_id)
+            .with_external_parameters(external_parameters)
+            .write(output_file_path)
+            .context(format_context!("{output_file_path}"))?;
+        Ok(())
+    }
 
-        let mut encoder = Encoder::new(
-            output_directory,
-            output_file_name.as_str(),
-            #[cfg(feature = "printer")]
-            progress,
+    #[cfg(not(feature = "attestation"))]
+    fn write_attestation(&self, _output_file_path: &str, _sha256: &str) -> anyhow::Result<()> {
+        if self.attestation_builder_id.is_some() {
+            return Err(format_error!(
+                "attestation_builder_id requires the attestation feature"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The segment digests sidecar for `segment_size`.
+    fn write_segments(&self, output_file_path: &str) -> anyhow::Result<()> {
+        let Some(segment_size) = self.segment_size else {
+            return Ok(());
+        };
+        SegmentDigests::compute(
+            output_file_path,
+            segment_size,
+            &driver::CancellationToken::default(),
         )
+        .and_then(|segments| segments.write_sidecar(output_file_path))
         .context(format_context!("{output_file_path}"))?;
+        Ok(())
+    }
+
+    /// Like `create` but also uploads the archive to `destination_uri` (for
+    /// example `s3://bucket/releases/`) with a multipart upload. For tar based
+    /// archives the upload starts before any entries are added and follows the
+    /// file while it is compressed. The upload is only completed if the bytes
+    /// sent hash to the returned sha256. A URI ending in `/` has the archive
+    /// file name appended. `self_extracting` is ignored.
+    #[cfg(feature = "object-store")]
+    pub fn create_and_upload<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        destination_uri: &str,
+        progress: Progress,
+    ) -> anyhow::Result<(String, String)> {
+        let destination_uri = if destination_uri.ends_with('/') {
+            format!("{destination_uri}{}", self.get_output_file())
+        } else {
+            destination_uri.to_string()
+        };
+        let location = object_storage::ObjectLocation::parse(destination_uri.as_str())
+            .context(format_context!("{destination_uri}"))?;
+
+        // the upload follows the file as it grows, so a stale archive must not be read
+        let _ = std::fs::remove_file(paths::join(output_directory, &self.get_output_file()));
+        let (mut encoder, output_file_path, entries) = self
+            .start_encode(output_directory, progress)
+            .context(format_context!("{output_directory}"))?;
+
+        let is_append_only = !matches!(
+            self.driver,
+            driver::Driver::Zip
+                | driver::Driver::SevenZ
+                | driver::Driver::Deb
+                | driver::Driver::Rpm
+        );
+        let mut upload = None;
+        let mut location = Some(location);
+        if is_append_only {
+            upload = location
+                .take()
+                .map(|location| object_storage::Upload::start(output_file_path.as_str(), location));
+        }
 
-        for (archive_path, file_path) in files {
+        let sha256 = (|| -> anyhow::Result<String> {
             encoder
-                .add_file(archive_path.as_str(), file_path.as_str())
+                .add_entries(&entries)
+                .context(format_context!("{output_directory}"))?;
+            let digest = encoder
+                .compress()
+                .and_then(|digestable| digestable.digest())
                 .context(format_context!("{output_directory}"))?;
+            Ok(digest.sha256)
+        })();
+        let sha256 = match sha256 {
+            Ok(sha256) => sha256,
+            Err(error) => {
+                if let Some(upload) = upload {
+                    upload.abort();
+                }
+                return Err(error);
+            }
+        };
+
+        if let Some(location) = location.take() {
+            upload = Some(object_storage::Upload::start(
+                output_file_path.as_str(),
+                location,
+            ));
+        }
+        if let Some(upload) = upload {
+            upload
+                .finish(sha256.as_str())
+                .context(format_context!("failed to upload to {destination_uri}"))?;
         }
 
-        let digestable = encoder
-            .compress()
-            .context(format_context!("{output_directory}"))?;
+        Ok((output_file_path, sha256))
+    }
 
-        let digest = digestable
-            .digest()
+    /// An encoder for `file_name` configured with the options shared by
+    /// `create`, `create_incremental` and `create_split`.
+    pub(crate) fn new_encoder<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        file_name: &str,
+        progress: Progress,
+    ) -> anyhow::Result<Encoder<Progress>> {
+        Ok(Encoder::new(output_directory, file_name, progress)?
+            .with_tar_format(self.tar_format)
+            .with_skip_unreadable(self.skip_unreadable)
+            .with_deduplicate(self.deduplicate)
+            .with_threads(self.threads.unwrap_or_else(driver::default_threads))
+            .with_retry(self.retry))
+    }
+
+    fn encode<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        progress: Progress,
+    ) -> anyhow::Result<(Encoder<Progress>, String)> {
+        let (mut encoder, output_file_path, entries) =
+            self.start_encode(output_directory, progress)?;
+        encoder
+            .add_entries(&entries)
             .context(format_context!("{output_directory}"))?;
+        Ok((encoder, output_file_path))
+    }
+
+    /// The encoder for `encode` with the manifest added, and the entries
+    /// still to add.
+    fn start_encode<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        progress: Progress,
+    ) -> anyhow::Result<(Encoder<Progress>, String, Vec<encoder::Entry>)> {
+        let output_file_name = self.get_output_file();
+
+        std::fs::create_dir_all(output_directory)
+            .context(format_context!("failed to create {output_directory}"))?;
+
+        let output_file_path = paths::join(output_directory, &output_file_name);
+
+        let (entries, skipped) = self
+            .collect_entries()
+            .context(format_error!("Failed to build file list"))?;
+        if entries.is_empty() && !self.allow_empty {
+            return Err(format_error!(
+                "no files matched the inputs of {output_file_name}; set allow_empty to create an empty archive"
+            ));
+        }
+
+        let mut encoder = self
+            .new_encoder(output_directory, output_file_name.as_str(), progress)
+            .context(format_context!("{output_file_path}"))?;
+        encoder.record_skipped(skipped);
+        if self.driver.is_package() {
+            encoder = encoder.with_package_info(package::PackageInfo {
+                name: self.name.clone(),
+                version: self.version.clone(),
+                platform: self.platform.clone(),
+                ..Default::default()
+            });
+        }
+
+        if self.embed_manifest {
+            self.add_manifest(&mut encoder, &entries)
+                .context(format_context!("{output_file_path}"))?;
+        }
+
+        Ok((encoder, output_file_path, entries))
+    }
+
+    #[cfg(feature = "manifest")]
+    fn add_manifest<Progress: ProgressReporter>(
+        &self,
+        encoder: &mut Encoder<Progress>,
+        entries: &[encoder::Entry],
+    ) -> anyhow::Result<()> {
+        let files: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.archive_path_lossy())
+            .collect();
+        if files
+            .iter()
+            .any(|file| file == manifest::MANIFEST_FILE_NAME)
+        {
+            return Err(format_error!(
+                "inputs already contain {}",
+                manifest::MANIFEST_FILE_NAME
+            ));
+        }
+        let mut manifest = manifest::ArtifactManifest::new(
+            self.name.as_str(),
+            self.version.as_str(),
+            self.platform.as_deref(),
+            &files,
+        );
+        let cancellation = driver::CancellationToken::default();
+        let mut digest_cache = self.open_digest_cache()?;
+        for entry in entries {
+            let Ok(metadata) = std::fs::symlink_metadata(entry.file_path.as_path()) else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let sha256 = match digest_cache.as_mut() {
+                Some(digest_cache) => {
+                    digest_cache.digest(entry.file_path.as_path(), &metadata, &cancellation)
+                }
+                None => std::fs::File::open(entry.file_path.as_path())
+                    .context(format_context!("{:?}", entry.file_path))
+                    .and_then(|file| driver::sha256_reader(file, &cancellation)),
+            };
+            match sha256 {
+                Ok(sha256) => {
+                    manifest
+                        .checksums
+                        .insert(entry.archive_path_lossy(), sha256);
+                }
+                // the encoder records the file as skipped
+                Err(_) if self.skip_unreadable => {}
+                Err(error) => return Err(error.context(format_context!("{:?}", entry.file_path))),
+            }
+        }
+        if let Some(digest_cache) = digest_cache {
+            digest_cache.save().context(format_context!(""))?;
+        }
+        let contents = manifest.to_json().context(format_context!(""))?;
+        encoder.add_data(manifest::MANIFEST_FILE_NAME, contents.as_bytes())
+    }
+
+    /// The cache set with `digest_cache`, if any.
+    fn open_digest_cache(&self) -> anyhow::Result<Option<DigestCache>> {
+        self.digest_cache
+            .as_deref()
+            .map(DigestCache::open)
+            .transpose()
+            .context(format_context!("digest cache"))
+    }
 
-        Ok((output_file_path, digest.sha256))
+    #[cfg(not(feature = "manifest"))]
+    fn add_manifest<Progress: ProgressReporter>(
+        &self,
+        _encoder: &mut Encoder<Progress>,
+        _entries: &[encoder::Entry],
+    ) -> anyhow::Result<()> {
+        Err(format_error!(
+            "embed_manifest requires the manifest feature"
+        ))
     }
 }
 
@@ -168,10 +938,7 @@ mod tests {
             } else {
                 None
             };
-            result.push(encoder::Entry {
-                archive_path,
-                file_path,
-            });
+            result.push(encoder::Entry::new(archive_path, file_path));
 
             if let Some(file) = file.as_mut() {
                 for j in 0..LINE_COUNT {
@@ -191,12 +958,35 @@ mod tests {
 
         let mut create_archive = CreateArchive {
             input: "test".to_string(),
+            inputs: Vec::new(),
             name: "test-output".to_string(),
             version: "1.0".to_string(),
             driver: driver::Driver::Gzip,
             platform: None,
             includes: None,
             excludes: Some(vec!["*.txt".to_string()]),
+            file_list: None,
+            exclude_syntax: ExcludeSyntax::Glob,
+            respect_ignore_files: false,
+            embed_manifest: false,
+            tar_format: driver::TarFormat::default(),
+            skip_unreadable: false,
+            retry: driver::RetryPolicy::default(),
+            allow_empty: false,
+            archive_prefix: None,
+            deduplicate: false,
+            self_extracting: None,
+            threads: None,
+            digest_cache: None,
+            segment_size: None,
+            attestation_builder_id: None,
+            glob_options: GlobOptions::default(),
+            follow_symlinks: false,
+            exclude_hidden: false,
+            max_depth: None,
+            min_file_size: None,
+            max_file_size: None,
+            modified_since: None,
         };
 
         let files = create_archive.build_file_list().unwrap();
@@ -253,47 +1043,3712 @@ mod tests {
     }
 
     #[test]
-    fn compress_test() {
-        let entries = generate_tmp_files();
+    fn test_builder() {
+        let create_archive = CreateArchive::builder()
+            .input("test")
+            .name("test-output")
+            .version("1.0")
+            .exclude("*.txt")
+            .build()
+            .unwrap();
+        assert_eq!(create_archive.get_output_file(), "test-output-v1.0.tar.gz");
+        assert_eq!(create_archive.build_file_list().unwrap().len(), 4);
 
-        let mut printer = printer::Printer::new_stdout();
+        let create_archive = CreateArchive::builder()
+            .add_input("test/a", Some("docs"))
+            .add_input("test/b.txt", None)
+            .name("test-output")
+            .version("1.0")
+            .build()
+            .unwrap();
+        let mut files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["b.txt", "docs/a.txt", "docs/b.txt"]);
 
-        const DRIVERS: &[driver::Driver] = &[
-            driver::Driver::Gzip,
-            driver::Driver::Bzip2,
-            driver::Driver::Zip,
-            driver::Driver::SevenZ,
-            driver::Driver::Xz,
-        ];
+        let create_archive = CreateArchive::builder()
+            .input("test")
+            .name("test-output")
+            .version("1.0")
+            .file_list(FileList::Files(vec![
+                "test/a/a.txt".to_string(),
+                "test/b.txt".to_string(),
+            ]))
+            .build()
+            .unwrap();
+        let files = create_archive.build_file_list().unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("a/a.txt".to_string(), "test/a/a.txt".to_string()),
+                ("b.txt".to_string(), "test/b.txt".to_string()),
+            ]
+        );
 
-        let mut multi_progress = printer::MultiProgress::new(&mut printer);
+        let create_archive = CreateArchive::builder()
+            .input("test")
+            .name("test-output")
+            .version("1.0")
+            .exclude_syntax(ExcludeSyntax::Gitignore)
+            .exclude("b/")
+            .exclude("*.txt")
+            .exclude("!/a.txt")
+            .build()
+            .unwrap();
+        let files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        assert_eq!(files, vec!["a.txt"]);
 
-        for driver in DRIVERS {
-            let output_directory = "./tmp";
-            let output_filename = format!("test.{}", driver.extension());
+        let create_archive = CreateArchive::builder()
+            .input("test")
+            .name("test-output")
+            .version("1.0")
+            .glob_options(GlobOptions {
+                case_insensitive: true,
+            })
+            .include("{A,B}/*.TXT")
+            .include("!b/b.txt")
+            .build()
+            .unwrap();
+        let mut files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["a/a.txt", "a/b.txt", "b/a.txt"]);
 
-            let progress_bar = multi_progress.add_progress(&driver.extension(), Some(100), None);
+        let _ = std::fs::remove_dir_all("tmp/ignore_test");
+        std::fs::create_dir_all("tmp/ignore_test/target").unwrap();
+        std::fs::write("tmp/ignore_test/.gitignore", "target/\n").unwrap();
+        std::fs::write("tmp/ignore_test/.archiveignore", "*.log\n").unwrap();
+        std::fs::write("tmp/ignore_test/target/out.bin", "out").unwrap();
+        std::fs::write("tmp/ignore_test/build.log", "log").unwrap();
+        std::fs::write("tmp/ignore_test/main.rs", "fn main() {}").unwrap();
+        let create_archive = CreateArchive::builder()
+            .input("tmp/ignore_test")
+            .name("test-output")
+            .version("1.0")
+            .respect_ignore_files(true)
+            .build()
+            .unwrap();
+        let mut files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![".archiveignore", ".gitignore", "main.rs"]);
 
-            let mut encoder =
-                encoder::Encoder::new(output_directory, &output_filename, progress_bar).unwrap();
+        std::fs::write("tmp/ignore_test/Thumbs.db", "junk").unwrap();
+        std::fs::write("tmp/ignore_test/.main.rs.swp", "junk").unwrap();
+        let create_archive = CreateArchive::builder()
+            .input("tmp/ignore_test")
+            .name("test-output")
+            .version("1.0")
+            .exclude_hidden(true)
+            .build()
+            .unwrap();
+        let mut files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["build.log", "main.rs", "target/out.bin"]);
 
-            encoder.add_entries(&entries).unwrap();
+        let create_archive = CreateArchive::builder()
+            .input("tmp/ignore_test")
+            .name("test-output")
+            .version("1.0")
+            .exclude_hidden(true)
+            .max_depth(1)
+            .min_file_size(4)
+            .max_file_size(20)
+            .build()
+            .unwrap();
+        let files: Vec<String> = create_archive
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        assert_eq!(files, vec!["main.rs"]);
 
-            let _digest = encoder.compress().unwrap().digest().unwrap();
-        }
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_dir_all("tmp/symlink_test");
+            std::fs::create_dir_all("tmp/symlink_test/real").unwrap();
+            std::fs::write("tmp/symlink_test/real/file.txt", "file").unwrap();
+            std::os::unix::fs::symlink("real", "tmp/symlink_test/linked").unwrap();
+            std::os::unix::fs::symlink("..", "tmp/symlink_test/real/loop").unwrap();
+            let builder = CreateArchive::builder()
+                .input("tmp/symlink_test")
+                .name("test-output")
+                .version("1.0")
+                .follow_symlinks(true);
+            let error = builder
+                .clone()
+                .build()
+                .unwrap()
+                .build_file_list()
+                .unwrap_err();
+            assert!(matches!(
+                Error::find(&error),
+                Some(Error::SymlinkLoop { .. })
+            ));
 
-        for driver in DRIVERS {
-            let output_dir = format!("tmp/extract_test.{}", driver.extension());
-            std::fs::create_dir_all(output_dir.as_str()).unwrap();
+            let error = builder
+                .clone()
+                .respect_ignore_files(true)
+                .build()
+                .unwrap()
+                .build_file_list()
+                .unwrap_err();
+            assert!(matches!(
+                Error::find(&error),
+                Some(Error::SymlinkLoop { .. })
+            ));
 
-            let archive_path_string = format!("tmp/test.{}", driver.extension());
+            let create_archive = builder.skip_unreadable(true).build().unwrap();
+            let mut files: Vec<String> = create_archive
+                .build_file_list()
+                .unwrap()
+                .into_iter()
+                .map(|(archive_path, _)| archive_path)
+                .collect();
+            files.sort();
+            assert_eq!(files, vec!["linked/file.txt", "real/file.txt"]);
 
-            let digest = {
-                let contents = std::fs::read(archive_path_string.as_str()).unwrap();
-                sha256::digest(contents)
-            };
+            let (sender, receiver) = std::sync::mpsc::channel();
+            create_archive.create("tmp/symlink_output", sender).unwrap();
+            let mut skipped: Vec<_> = receiver
+                .try_iter()
+                .filter_map(|event| match event {
+                    ProgressEvent::EntrySkipped { archive_path, .. } => Some(archive_path),
+                    _ => None,
+                })
+                .collect();
+            skipped.sort();
+            assert_eq!(skipped, vec!["linked/loop", "real/loop"]);
+        }
 
-            let progress_bar = multi_progress.add_progress(&driver.extension(), Some(100), None);
+        assert!(CreateArchive::builder()
+            .input("test")
+            .version("1.0")
+            .build()
+            .is_err());
+        assert!(CreateArchive::builder()
+            .input("test")
+            .name("test-output")
+            .version("1 0")
+            .build()
+            .is_err());
+        assert!(CreateArchive::builder()
+            .input("does-not-exist")
+            .name("test-output")
+            .version("1.0")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn benchmark_test() {
+        std::fs::create_dir_all("tmp/benchmark_input").unwrap();
+        std::fs::write("tmp/benchmark_input/a.txt", "a".repeat(4096)).unwrap();
+        let drivers = [driver::Driver::Gzip, driver::Driver::Zip];
+        let results = benchmark("tmp/benchmark_input", &drivers, 1024).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].driver, driver::Driver::Gzip);
+        assert!(results
+            .iter()
+            .all(|result| result.input_bytes == 4096 && result.ratio < 1.0));
+    }
+
+    /// Serves `body` for every request, honoring HEAD and single `Range` requests.
+    #[cfg(feature = "http")]
+    fn spawn_http_server(body: Vec<u8>) -> u16 {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let bytes_read = stream.read(&mut buffer).unwrap();
+                    if bytes_read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..bytes_read]);
+                }
+                let request = String::from_utf8_lossy(request.as_slice()).to_lowercase();
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .map(|(start, end)| {
+                        (
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        )
+                    });
+                let (status, content) = match range {
+                    Some((start, end)) => ("206 Partial Content", &body[start..=end]),
+                    None => ("200 OK", body.as_slice()),
+                };
+                let header = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+                    content.len()
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                if !request.starts_with("head") {
+                    stream.write_all(content).unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn from_url_test() {
+        std::fs::create_dir_all("tmp/http").unwrap();
+        std::fs::write("tmp/http/file.txt", "served").unwrap();
+        let entries = vec![encoder::Entry::new("file.txt", "tmp/http/file.txt")];
+        let mut encoder = encoder::Encoder::new("tmp/http", "served.tar.gz", NoProgress).unwrap();
+        encoder.add_entries(&entries).unwrap();
+        let digest = encoder.compress().unwrap().digest().unwrap().sha256;
+
+        let port = spawn_http_server(std::fs::read("tmp/http/served.tar.gz").unwrap());
+        let url = format!("http://127.0.0.1:{port}/served.tar.gz?token=1");
+        let extracted = decoder::Decoder::from_url(
+            url.as_str(),
+            Some(digest),
+            "tmp/http/extracted",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        assert_eq!(extracted.stats.files, 1);
+
+        let result = decoder::Decoder::from_url(
+            url.as_str(),
+            Some("0".repeat(64)),
+            "tmp/http/mismatch",
+            NoProgress,
+        )
+        .unwrap()
+        .extract();
+        assert!(result.is_err());
+        assert!(!std::path::Path::new("tmp/http/mismatch/file.txt").exists());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn add_url_test() {
+        use sha2::Digest;
+        let _ = std::fs::remove_dir_all("tmp/add_url");
+        std::fs::create_dir_all("tmp/add_url").unwrap();
+        std::fs::write("tmp/add_url/local.txt", "local").unwrap();
+        let port = spawn_http_server(b"release asset".to_vec());
+        let url = format!("http://127.0.0.1:{port}/asset.bin");
+        let digest = format!("{:x}", sha2::Sha256::digest(b"release asset"));
+
+        let mut encoder = encoder::Encoder::new("tmp/add_url", "bundle.zip", NoProgress).unwrap();
+        encoder
+            .add_entries(&[encoder::Entry::new("local.txt", "tmp/add_url/local.txt")])
+            .unwrap();
+        encoder
+            .add_url("assets/asset.bin", url.as_str(), Some(digest.as_str()))
+            .unwrap();
+        let error = encoder
+            .add_url(
+                "assets/bad.bin",
+                url.as_str(),
+                Some("0".repeat(64).as_str()),
+            )
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::DigestMismatch);
+        encoder.compress().unwrap();
+
+        Decoder::new(
+            "tmp/add_url/bundle.zip",
+            None,
+            "tmp/add_url/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/add_url/output/assets/asset.bin").unwrap(),
+            "release asset"
+        );
+        assert!(!std::path::Path::new("tmp/add_url/output/assets/bad.bin").exists());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn remote_zip_test() {
+        std::fs::create_dir_all("tmp/remote_zip").unwrap();
+        std::fs::write("tmp/remote_zip/a.txt", "a").unwrap();
+        std::fs::write("tmp/remote_zip/b.txt", "b").unwrap();
+        let entries: Vec<encoder::Entry> = ["a.txt", "b.txt"]
+            .iter()
+            .map(|name| {
+                encoder::Entry::new(format!("files/{name}"), format!("tmp/remote_zip/{name}"))
+            })
+            .collect();
+        let mut encoder =
+            encoder::Encoder::new("tmp/remote_zip", "remote.zip", NoProgress).unwrap();
+        encoder.add_entries(&entries).unwrap();
+        encoder.compress().unwrap();
+
+        let port = spawn_http_server(std::fs::read("tmp/remote_zip/remote.zip").unwrap());
+        let url = format!("http://127.0.0.1:{port}/remote.zip");
+        let mut remote_zip = remote_zip::RemoteZip::open(url.as_str()).unwrap();
+        let mut names = remote_zip.list();
+        names.sort();
+        assert_eq!(names, vec!["files/a.txt", "files/b.txt"]);
+
+        remote_zip
+            .extract(&["files/b.txt"], "tmp/remote_zip/extracted")
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/remote_zip/extracted/files/b.txt").unwrap(),
+            "b"
+        );
+        assert!(!std::path::Path::new("tmp/remote_zip/extracted/files/a.txt").exists());
+    }
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn object_store_test() {
+        let _ = std::fs::remove_dir_all("tmp/object_store");
+        std::fs::create_dir_all("tmp/object_store/input").unwrap();
+        std::fs::create_dir_all("tmp/object_store/bucket").unwrap();
+        std::fs::write("tmp/object_store/input/file.txt", "uploaded").unwrap();
+        let bucket = std::fs::canonicalize("tmp/object_store/bucket").unwrap();
+        let bucket = format!("file://{}/", bucket.to_string_lossy());
+
+        for driver in [driver::Driver::Gzip, driver::Driver::Zip] {
+            let create_archive = CreateArchive::builder()
+                .input("tmp/object_store/input")
+                .name("uploaded")
+                .version("1.0")
+                .driver(driver)
+                .build()
+                .unwrap();
+            let (_, digest) = create_archive
+                .create_and_upload("tmp/object_store", bucket.as_str(), NoProgress)
+                .unwrap();
+
+            let uri = format!("{bucket}{}", create_archive.get_output_file());
+            let destination = format!("tmp/object_store/extracted.{}", driver.extension());
+            decoder::Decoder::from_object_store(
+                uri.as_str(),
+                Some(digest),
+                destination.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            let extracted = std::fs::read_to_string(format!("{destination}/file.txt")).unwrap();
+            assert_eq!(extracted, "uploaded");
+        }
+
+        // an upload whose bytes don't hash to the archive digest isn't completed
+        std::fs::write("tmp/object_store/mismatch.bin", "mismatch").unwrap();
+        let uri = format!("{bucket}mismatch.bin");
+        let upload = object_storage::Upload::start(
+            "tmp/object_store/mismatch.bin",
+            object_storage::ObjectLocation::parse(uri.as_str()).unwrap(),
+        );
+        let error = upload.finish(&"0".repeat(64)).err().unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::DigestMismatch);
+        assert!(!std::path::Path::new("tmp/object_store/bucket/mismatch.bin").exists());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_test() {
+        let _ = std::fs::remove_dir_all("tmp/async");
+        std::fs::create_dir_all("tmp/async/input").unwrap();
+        std::fs::write("tmp/async/input/a.txt", "a".repeat(1000)).unwrap();
+        std::fs::write("tmp/async/input/b.txt", "b").unwrap();
+        let entries: Vec<encoder::Entry> = ["a.txt", "b.txt"]
+            .iter()
+            .map(|name| {
+                encoder::Entry::new(format!("files/{name}"), format!("tmp/async/input/{name}"))
+            })
+            .collect();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            for name in ["async.tar.gz", "async.tar.bz2", "async.tar.xz"] {
+                let mut encoder = AsyncEncoder::new("tmp/async", name, NoProgress)
+                    .await
+                    .unwrap();
+                encoder.add_entries(&entries).await.unwrap();
+                let digest = encoder.compress().await.unwrap().sha256;
+
+                let archive = format!("tmp/async/{name}");
+                let destination = format!("tmp/async/extracted/{name}");
+                let extracted =
+                    AsyncDecoder::new(archive.as_str(), Some(digest), &destination, NoProgress)
+                        .unwrap()
+                        .extract()
+                        .await
+                        .unwrap();
+                assert_eq!(extracted.stats.files, 2);
+                assert!(extracted.files.contains("files/a.txt"));
+                assert_eq!(
+                    std::fs::read_to_string(format!("{destination}/files/b.txt")).unwrap(),
+                    "b"
+                );
+
+                // archives written by the async API are readable by the blocking one
+                let extracted = Decoder::new(
+                    archive.as_str(),
+                    None,
+                    format!("tmp/async/blocking/{name}").as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .extract()
+                .unwrap();
+                assert_eq!(extracted.files.len(), 2);
+            }
+
+            let result = AsyncEncoder::new("tmp/async", "async.zip", NoProgress).await;
+            assert!(result.is_err());
+        });
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn manifest_test() {
+        let _ = std::fs::remove_dir_all("tmp/manifest_test");
+        std::fs::create_dir_all("tmp/manifest_test/input/dir").unwrap();
+        std::fs::write("tmp/manifest_test/input/b.txt", "b").unwrap();
+        std::fs::write("tmp/manifest_test/input/dir/a.txt", "a").unwrap();
+
+        for driver in [
+            driver::Driver::Gzip,
+            driver::Driver::Zip,
+            driver::Driver::SevenZ,
+        ] {
+            for embed_manifest in [true, false] {
+                let create_archive = CreateArchive::builder()
+                    .input("tmp/manifest_test/input")
+                    .name(if embed_manifest { "with" } else { "without" })
+                    .version("1.0")
+                    .platform("linux")
+                    .driver(driver)
+                    .embed_manifest(embed_manifest)
+                    .build()
+                    .unwrap();
+                let (output_file, _) = create_archive
+                    .create("tmp/manifest_test", NoProgress)
+                    .unwrap();
+
+                let manifest = Decoder::new(
+                    output_file.as_str(),
+                    None,
+                    "tmp/manifest_test/unused",
+                    NoProgress,
+                )
+                .unwrap()
+                .read_manifest()
+                .unwrap();
+                if !embed_manifest {
+                    assert!(manifest.is_none());
+                    continue;
+                }
+                let manifest = manifest.unwrap();
+                assert_eq!(manifest.name, "with");
+                assert_eq!(manifest.platform.as_deref(), Some("linux"));
+                assert_eq!(manifest.files, vec!["b.txt", "dir/a.txt"]);
+            }
+        }
+    }
+
+    #[test]
+    fn package_test() {
+        let _ = std::fs::remove_dir_all("tmp/package_test");
+        std::fs::create_dir_all("tmp/package_test/input/bin").unwrap();
+        std::fs::write("tmp/package_test/input/bin/tool", "tool").unwrap();
+
+        let mut drivers = vec![driver::Driver::Deb];
+        if driver::Driver::Rpm.is_supported() {
+            drivers.push(driver::Driver::Rpm);
+        }
+        for driver in drivers {
+            let create_archive = CreateArchive::builder()
+                .input("tmp/package_test/input")
+                .name("tool")
+                .version("1.0-rc1")
+                .platform("x86_64-linux")
+                .driver(driver)
+                .build()
+                .unwrap();
+            let (output_file, _) = create_archive
+                .create("tmp/package_test", NoProgress)
+                .unwrap();
+            let contents = std::fs::read(output_file.as_str()).unwrap();
+            if driver == driver::Driver::Deb {
+                assert!(contents.starts_with(b"!<arch>\ndebian-binary"));
+                let contents = String::from_utf8_lossy(contents.as_slice());
+                assert!(contents.contains("control.tar.gz"));
+                assert!(contents.contains("data.tar.gz"));
+            } else {
+                assert!(contents.starts_with(&[0xed, 0xab, 0xee, 0xdb]));
+            }
+            assert!(Decoder::new(
+                output_file.as_str(),
+                None,
+                "tmp/package_test/out",
+                NoProgress
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn add_from_reader_test() {
+        let _ = std::fs::remove_dir_all("tmp/add_from_reader");
+        std::fs::create_dir_all("tmp/add_from_reader").unwrap();
+        let dump = "INSERT INTO t VALUES (1);\n".repeat(1000);
+        for driver in [driver::Driver::Gzip, driver::Driver::Zip] {
+            let output_filename = format!("dump.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/add_from_reader", output_filename.as_str(), NoProgress)
+                    .unwrap();
+            encoder
+                .add_from_reader("sized.sql", Some(dump.len() as u64), dump.as_bytes())
+                .unwrap();
+            encoder
+                .add_from_reader("unsized.sql", None, dump.as_bytes())
+                .unwrap();
+            if driver != driver::Driver::Zip {
+                assert!(encoder
+                    .add_from_reader("short.sql", Some(dump.len() as u64 + 1), dump.as_bytes())
+                    .is_err());
+            }
+            encoder.compress().unwrap();
+
+            let output_directory = format!("tmp/add_from_reader/{}", driver.extension());
+            Decoder::new(
+                format!("tmp/add_from_reader/{output_filename}").as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            for name in ["sized.sql", "unsized.sql"] {
+                let extracted =
+                    std::fs::read_to_string(format!("{output_directory}/{name}")).unwrap();
+                assert_eq!(extracted, dump);
+            }
+            assert!(!std::path::Path::new(&format!("{output_directory}/short.sql")).exists());
+        }
+    }
+
+    #[test]
+    fn copy_entries_from_test() {
+        let _ = std::fs::remove_dir_all("tmp/copy_entries");
+        std::fs::create_dir_all("tmp/copy_entries").unwrap();
+        for driver in [driver::Driver::Gzip, driver::Driver::Zip] {
+            let extension = driver.extension();
+            let source_filename = format!("component.{extension}");
+            let mut source =
+                encoder::Encoder::new("tmp/copy_entries", source_filename.as_str(), NoProgress)
+                    .unwrap();
+            source
+                .add_data_with_metadata("bin/tool", b"#!/bin/sh", 0o755, 1_700_000_000)
+                .unwrap();
+            source.add_data("docs/readme.txt", b"readme").unwrap();
+            source.compress().unwrap();
+
+            let bundle_filename = format!("bundle.{extension}");
+            let mut bundle =
+                encoder::Encoder::new("tmp/copy_entries", bundle_filename.as_str(), NoProgress)
+                    .unwrap();
+            bundle.add_data("VERSION", b"1.0").unwrap();
+            let copied = bundle
+                .copy_entries_from(
+                    format!("tmp/copy_entries/{source_filename}").as_str(),
+                    |archive_path| archive_path.starts_with("bin/"),
+                )
+                .unwrap();
+            assert_eq!(copied, 1);
+            bundle.compress().unwrap();
+
+            let mut entries = Vec::new();
+            Decoder::new(
+                format!("tmp/copy_entries/{bundle_filename}").as_str(),
+                None,
+                "tmp/copy_entries",
+                NoProgress,
+            )
+            .unwrap()
+            .visit_entries(|entry, reader| {
+                let mut contents = Vec::new();
+                reader.read_to_end(&mut contents).unwrap();
+                let mode = entry.mode.unwrap_or_default() & 0o777;
+                entries.push((entry.archive_path, mode, contents));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(
+                entries,
+                vec![
+                    ("VERSION".to_string(), 0o644, b"1.0".to_vec()),
+                    ("bin/tool".to_string(), 0o755, b"#!/bin/sh".to_vec()),
+                ]
+            );
+        }
+
+        let mut bundle =
+            encoder::Encoder::new("tmp/copy_entries", "mixed.tar.gz", NoProgress).unwrap();
+        let error = bundle
+            .copy_entries_from("tmp/copy_entries/component.zip", |_| true)
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::UnsupportedFormat);
+    }
+
+    #[test]
+    fn verify_test() {
+        let _ = std::fs::remove_dir_all("tmp/verify");
+        std::fs::create_dir_all("tmp/verify").unwrap();
+        for driver in [driver::Driver::Gzip, driver::Driver::Zip] {
+            let output_filename = format!("verify.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/verify", output_filename.as_str(), NoProgress).unwrap();
+            encoder.add_data("a.txt", b"a").unwrap();
+            encoder.add_data("dir/b.txt", b"b").unwrap();
+            let sha256 = encoder.compress().unwrap().digest().unwrap().sha256;
+            let archive_path = format!("tmp/verify/{output_filename}");
+
+            let report = verify(archive_path.as_str(), sha256.as_str()).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(report.checked, 1);
+
+            let digest = |contents: &[u8]| {
+                driver::sha256_reader(contents, &driver::CancellationToken::default()).unwrap()
+            };
+            let checksums = format!(
+                "{sha256}  {output_filename}\n{}  a.txt\n{}  dir/b.txt\n{}  c.txt\n",
+                digest(b"a"),
+                digest(b"not b"),
+                digest(b"c")
+            );
+            std::fs::write("tmp/verify/SHA256SUMS", checksums).unwrap();
+            let report = verify(archive_path.as_str(), "tmp/verify/SHA256SUMS").unwrap();
+            assert_eq!(report.checked, 4);
+            assert_eq!(
+                report.mismatches,
+                vec![
+                    VerifyMismatch::Entry {
+                        archive_path: "dir/b.txt".to_string(),
+                        expected: digest(b"not b"),
+                        actual: digest(b"b"),
+                    },
+                    VerifyMismatch::Missing {
+                        archive_path: "c.txt".to_string()
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn verify_tree_test() {
+        let _ = std::fs::remove_dir_all("tmp/verify_tree");
+        std::fs::create_dir_all("tmp/verify_tree/input/dir").unwrap();
+        std::fs::write("tmp/verify_tree/input/a.txt", "a").unwrap();
+        std::fs::write("tmp/verify_tree/input/dir/b.txt", "b").unwrap();
+
+        let digest = |contents: &[u8]| {
+            driver::sha256_reader(contents, &driver::CancellationToken::default()).unwrap()
+        };
+        let checksums = format!(
+            "{}  a.txt\n{}  ./dir/b.txt\n{}  c.txt\n",
+            digest(b"a"),
+            digest(b"not b"),
+            digest(b"c")
+        );
+        std::fs::write("tmp/verify_tree/input/SHA256SUMS", checksums).unwrap();
+        std::fs::write("tmp/verify_tree/input/d.txt", "d").unwrap();
+        let report =
+            verify_tree("tmp/verify_tree/input", "tmp/verify_tree/input/SHA256SUMS").unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(
+            report.mismatches,
+            vec![
+                VerifyMismatch::Missing {
+                    archive_path: "c.txt".to_string()
+                },
+                VerifyMismatch::Entry {
+                    archive_path: "dir/b.txt".to_string(),
+                    expected: digest(b"not b"),
+                    actual: digest(b"b"),
+                },
+                VerifyMismatch::Extra {
+                    archive_path: "d.txt".to_string()
+                },
+            ]
+        );
+        std::fs::remove_file("tmp/verify_tree/input/SHA256SUMS").unwrap();
+        std::fs::remove_file("tmp/verify_tree/input/d.txt").unwrap();
+
+        #[cfg(feature = "manifest")]
+        {
+            let create_archive = CreateArchive::builder()
+                .input("tmp/verify_tree/input")
+                .name("tree")
+                .version("1.0")
+                .embed_manifest(true)
+                .build()
+                .unwrap();
+            let (output_file, _) = create_archive
+                .create("tmp/verify_tree", NoProgress)
+                .unwrap();
+            Decoder::new(
+                output_file.as_str(),
+                None,
+                "tmp/verify_tree/output",
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            let report = verify_tree(
+                "tmp/verify_tree/output",
+                "tmp/verify_tree/output/artifact.json",
+            )
+            .unwrap();
+            assert!(report.is_ok(), "{report:?}");
+            assert_eq!(report.checked, 2);
+
+            std::fs::write("tmp/verify_tree/output/a.txt", "changed").unwrap();
+            std::fs::remove_file("tmp/verify_tree/output/dir/b.txt").unwrap();
+            let report = verify_tree(
+                "tmp/verify_tree/output",
+                "tmp/verify_tree/output/artifact.json",
+            )
+            .unwrap();
+            assert_eq!(
+                report.mismatches,
+                vec![
+                    VerifyMismatch::Entry {
+                        archive_path: "a.txt".to_string(),
+                        expected: digest(b"a"),
+                        actual: digest(b"changed"),
+                    },
+                    VerifyMismatch::Missing {
+                        archive_path: "dir/b.txt".to_string()
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn segments_test() {
+        let _ = std::fs::remove_dir_all("tmp/segments");
+        std::fs::create_dir_all("tmp/segments/input").unwrap();
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..10_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        std::fs::write("tmp/segments/input/noise.bin", noise).unwrap();
+
+        let create_archive = CreateArchive::builder()
+            .input("tmp/segments/input")
+            .name("segments")
+            .version("1.0")
+            .segment_size(1024)
+            .build()
+            .unwrap();
+        let (output_file, _) = create_archive.create("tmp/segments", NoProgress).unwrap();
+        let contents = std::fs::read(output_file.as_str()).unwrap();
+        let segments = SegmentDigests::read_sidecar(output_file.as_str()).unwrap();
+        assert_eq!(segments.size, contents.len() as u64);
+        assert_eq!(
+            segments.segments.len(),
+            contents.len().div_ceil(1024),
+            "{}",
+            contents.len()
+        );
+        assert!(segments.verify_segment(0, &contents[..1024]));
+        assert!(!segments.verify_segment(0, &contents[1..1025]));
+        assert_eq!(
+            SegmentDigests::from_text(segments.to_text().as_str()).unwrap(),
+            segments
+        );
+        assert_eq!(segments.root().len(), 64);
+
+        let cancellation = CancellationToken::default();
+        let verification = segments
+            .verify_partial(output_file.as_str(), &cancellation)
+            .unwrap();
+        assert!(verification.is_complete);
+        assert_eq!(verification.verified, segments.size);
+
+        std::fs::write("tmp/segments/partial", &contents[..2500]).unwrap();
+        let verification = segments
+            .verify_partial("tmp/segments/partial", &cancellation)
+            .unwrap();
+        assert_eq!(
+            verification,
+            PartialVerification {
+                verified: 2048,
+                mismatch: None,
+                is_complete: false,
+            }
+        );
+
+        let mut corrupted = contents.clone();
+        corrupted[1500] ^= 0xff;
+        std::fs::write("tmp/segments/corrupted", corrupted).unwrap();
+        let verification = segments
+            .verify_partial("tmp/segments/corrupted", &cancellation)
+            .unwrap();
+        assert_eq!(verification.verified, 1024);
+        assert_eq!(verification.mismatch, Some(1));
+        assert!(!verification.is_complete);
+    }
+
+    #[cfg(feature = "attestation")]
+    #[test]
+    fn attestation_test() {
+        let _ = std::fs::remove_dir_all("tmp/attestation");
+        std::fs::create_dir_all("tmp/attestation/input").unwrap();
+        std::fs::write("tmp/attestation/input/a.txt", "a").unwrap();
+
+        let create_archive = CreateArchive::builder()
+            .input("tmp/attestation/input")
+            .name("attestation")
+            .version("1.0")
+            .attestation_builder_id("https://example.com/builder")
+            .build()
+            .unwrap();
+        let (output_file, sha256) = create_archive
+            .create("tmp/attestation", NoProgress)
+            .unwrap();
+        let contents = std::fs::read_to_string(format!("{output_file}.intoto.json")).unwrap();
+        let statement: attestation::Statement = serde_json::from_str(contents.as_str()).unwrap();
+        assert_eq!(statement.subject.len(), 1);
+        assert_eq!(statement.subject[0].digest.get("sha256"), Some(&sha256));
+        assert_eq!(
+            std::path::Path::new(output_file.as_str()).file_name(),
+            Some(std::ffi::OsStr::new(statement.subject[0].name.as_str()))
+        );
+        assert_eq!(
+            statement.predicate.run_details.builder.id,
+            "https://example.com/builder"
+        );
+        assert_eq!(
+            statement.predicate.build_definition.external_parameters["name"],
+            "attestation"
+        );
+    }
+
+    #[test]
+    fn transcode_test() {
+        let _ = std::fs::remove_dir_all("tmp/transcode");
+        std::fs::create_dir_all("tmp/transcode").unwrap();
+        let mut encoder = encoder::Encoder::new("tmp/transcode", "input.zip", NoProgress).unwrap();
+        encoder
+            .add_data_with_metadata("bin/run.sh", b"#!/bin/sh", 0o755, 1_700_000_000)
+            .unwrap();
+        encoder.add_data("a.txt", b"a").unwrap();
+        encoder.compress().unwrap();
+
+        let mut digests = Vec::new();
+        for output_directory in ["tmp/transcode/first", "tmp/transcode/second"] {
+            let options = TranscodeOptions {
+                output_directory: Some(output_directory.to_string()),
+                deterministic: true,
+                ..Default::default()
+            };
+            let transcoded =
+                transcode("tmp/transcode/input.zip", driver::Driver::Xz, &options).unwrap();
+            assert_eq!(transcoded.entries, 2);
+            assert!(transcoded.output_file_path.ends_with("input.tar.xz"));
+            digests.push(transcoded.sha256);
+        }
+        assert_eq!(digests[0], digests[1]);
+
+        let entry_digests = |archive_path: &str| {
+            Decoder::new(archive_path, None, "tmp/transcode", NoProgress)
+                .unwrap()
+                .entry_digests()
+                .unwrap()
+        };
+        assert_eq!(
+            entry_digests("tmp/transcode/input.zip"),
+            entry_digests("tmp/transcode/first/input.tar.xz")
+        );
+
+        let transcoded = transcode(
+            "tmp/transcode/input.zip",
+            driver::Driver::Gzip,
+            &TranscodeOptions::default(),
+        )
+        .unwrap();
+        let extracted = Decoder::new(
+            transcoded.output_file_path.as_str(),
+            None,
+            "tmp/transcode/extracted",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        assert_eq!(extracted.stats.files, 2);
+        let run = std::fs::metadata("tmp/transcode/extracted/bin/run.sh").unwrap();
+        assert_eq!(
+            run.modified().unwrap(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn diff_test() {
+        let _ = std::fs::remove_dir_all("tmp/diff");
+        std::fs::create_dir_all("tmp/diff/tree/dir").unwrap();
+        for (name, contents) in [("same.txt", "same"), ("size.txt", "1"), ("hash.txt", "a")] {
+            std::fs::write(format!("tmp/diff/tree/dir/{name}"), contents).unwrap();
+        }
+        let mut encoder = encoder::Encoder::new("tmp/diff", "diff.tar.gz", NoProgress).unwrap();
+        encoder.add_data("dir/same.txt", b"same").unwrap();
+        encoder.add_data("dir/size.txt", b"12").unwrap();
+        encoder.add_data("dir/hash.txt", b"b").unwrap();
+        encoder.add_data("gone.txt", b"gone").unwrap();
+        encoder.compress().unwrap();
+        std::fs::write("tmp/diff/tree/new.txt", "new").unwrap();
+
+        let result = diff("tmp/diff/diff.tar.gz", "tmp/diff/tree").unwrap();
+        assert_eq!(
+            result,
+            ArchiveDiff {
+                added: vec!["new.txt".to_string()],
+                removed: vec!["gone.txt".to_string()],
+                changed: vec!["dir/hash.txt".to_string(), "dir/size.txt".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn compare_test() {
+        let _ = std::fs::remove_dir_all("tmp/compare");
+        std::fs::create_dir_all("tmp/compare").unwrap();
+        let create = |output_filename: &str, entries: &[(&str, &[u8])]| {
+            let mut encoder =
+                encoder::Encoder::new("tmp/compare", output_filename, NoProgress).unwrap();
+            for (archive_path, contents) in entries {
+                encoder.add_data(archive_path, contents).unwrap();
+            }
+            encoder.compress().unwrap();
+        };
+        create(
+            "a.zip",
+            &[
+                ("same.txt", b"same"),
+                ("changed.txt", b"a"),
+                ("a.txt", b"a"),
+            ],
+        );
+        create("b.tar.xz", &[("changed.txt", b"b"), ("same.txt", b"same")]);
+        create(
+            "c.tar.gz",
+            &[
+                ("a.txt", b"a"),
+                ("changed.txt", b"a"),
+                ("same.txt", b"same"),
+            ],
+        );
+
+        assert!(compare("tmp/compare/a.zip", "tmp/compare/c.tar.gz")
+            .unwrap()
+            .is_empty());
+        let differences = compare("tmp/compare/a.zip", "tmp/compare/b.tar.xz").unwrap();
+        let summary: Vec<_> = differences
+            .iter()
+            .map(|difference| {
+                (
+                    difference.archive_path.as_str(),
+                    difference.first.is_some(),
+                    difference.second.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("a.txt", true, false), ("changed.txt", true, true)]
+        );
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_test() {
+        let _ = std::fs::remove_dir_all("tmp/watch");
+        std::fs::create_dir_all("tmp/watch/input").unwrap();
+        std::fs::write("tmp/watch/input/a.txt", "a").unwrap();
+        let create_archive = CreateArchive::builder()
+            .input("tmp/watch/input")
+            .name("watch")
+            .version("1.0")
+            .build()
+            .unwrap();
+        let options = WatchOptions::default().with_debounce(std::time::Duration::from_millis(200));
+
+        let mut archived = Vec::new();
+        watch(
+            &create_archive,
+            "tmp/watch/output",
+            &options,
+            || NoProgress,
+            |result| {
+                let (output_file_path, _) = result.unwrap();
+                let digests = Decoder::new(
+                    output_file_path.as_str(),
+                    None,
+                    "tmp/watch/unused",
+                    NoProgress,
+                )
+                .unwrap()
+                .entry_digests()
+                .unwrap();
+                archived.push(digests.into_keys().collect::<Vec<_>>());
+                if archived.len() == 1 {
+                    std::fs::write("tmp/watch/input/b.txt", "b").unwrap();
+                    std::ops::ControlFlow::Continue(())
+                } else {
+                    std::ops::ControlFlow::Break(())
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(archived, vec![vec!["a.txt"], vec!["a.txt", "b.txt"]]);
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn create_split_test() {
+        let _ = std::fs::remove_dir_all("tmp/split");
+        std::fs::create_dir_all("tmp/split/input").unwrap();
+        for index in 0..10 {
+            std::fs::write(
+                format!("tmp/split/input/file{index}.txt"),
+                index.to_string().repeat(1000),
+            )
+            .unwrap();
+        }
+        let split = CreateArchive::builder()
+            .input("tmp/split/input")
+            .name("media")
+            .version("1.0")
+            .build()
+            .unwrap()
+            .create_split("tmp/split/output", 8000, NoProgress)
+            .unwrap();
+        assert_eq!(split.index.parts.len(), 4);
+        assert_eq!(split.index.parts[0].file_name, "media-v1.0.part001.tar.gz");
+        assert_eq!(
+            split.index_file_path,
+            paths::join("tmp/split/output", "media-v1.0.index.json")
+        );
+        let index = SplitIndex::from_json(
+            std::fs::read(split.index_file_path.as_str())
+                .unwrap()
+                .as_slice(),
+        )
+        .unwrap();
+        assert_eq!(index, split.index);
+
+        let mut files: Vec<_> = index
+            .parts
+            .iter()
+            .flat_map(|part| part.files.iter().cloned())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 10);
+        files.dedup();
+        assert_eq!(files.len(), 10);
+        for part in index.parts.iter() {
+            assert!(part.size <= 8000);
+        }
+
+        let part = index.find("file9.txt").unwrap();
+        Decoder::new(
+            paths::join("tmp/split/output", part.file_name.as_str()).as_str(),
+            Some(part.sha256.clone()),
+            "tmp/split/extracted",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/split/extracted/file9.txt").unwrap(),
+            "9".repeat(1000)
+        );
+    }
+
+    #[test]
+    fn self_extracting_test() {
+        let _ = std::fs::remove_dir_all("tmp/sfx");
+        std::fs::create_dir_all("tmp/sfx/input/dir").unwrap();
+        std::fs::write("tmp/sfx/input/dir/a.txt", "a").unwrap();
+        let (output_file_path, sha256) = CreateArchive::builder()
+            .input("tmp/sfx/input")
+            .name("sfx")
+            .version("1.0")
+            .self_extracting(sfx::SfxStub::Shell)
+            .build()
+            .unwrap()
+            .create("tmp/sfx", NoProgress)
+            .unwrap();
+        assert_eq!(
+            output_file_path,
+            paths::join("tmp/sfx", "sfx-v1.0.tar.gz.sh")
+        );
+        assert!(!std::path::Path::new("tmp/sfx/sfx-v1.0.tar.gz").exists());
+        assert_eq!(
+            sha256,
+            driver::digest_file(
+                output_file_path.as_str(),
+                &mut NoProgress,
+                &driver::CancellationToken::default()
+            )
+            .unwrap()
+        );
+
+        sfx::extract_payload(output_file_path.as_str(), "tmp/sfx/payload.tar.gz").unwrap();
+        Decoder::new(
+            "tmp/sfx/payload.tar.gz",
+            None,
+            "tmp/sfx/decoded",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/sfx/decoded/dir/a.txt").unwrap(),
+            "a"
+        );
+
+        #[cfg(unix)]
+        {
+            let status = std::process::Command::new("sh")
+                .arg(output_file_path.as_str())
+                .arg("tmp/sfx/run")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            assert_eq!(
+                std::fs::read_to_string("tmp/sfx/run/dir/a.txt").unwrap(),
+                "a"
+            );
+        }
+
+        std::fs::write("tmp/sfx/stub.exe", b"MZ stub").unwrap();
+        let offset = sfx::write_self_extracting(
+            "tmp/sfx/payload.tar.gz",
+            &sfx::SfxStub::Executable("tmp/sfx/stub.exe".into()),
+            "tmp/sfx/payload.tar.gz.exe",
+        )
+        .unwrap();
+        assert_eq!(offset, 7);
+        let payload_size = std::fs::metadata("tmp/sfx/payload.tar.gz").unwrap().len();
+        assert_eq!(
+            sfx::payload_range("tmp/sfx/payload.tar.gz.exe").unwrap(),
+            (7, payload_size)
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn external_fallback_test() {
+        let _ = std::fs::remove_dir_all("tmp/external");
+        std::fs::create_dir_all("tmp/external").unwrap();
+        let mut archiver = tar::Builder::new(Vec::new());
+        for (name, contents) in [("dir/a.txt", "a"), ("dir/b.txt", "b")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            archiver
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        std::fs::write("tmp/external/plain.tar", archiver.into_inner().unwrap()).unwrap();
+
+        let tool = ExternalTool::tar();
+        assert!(tool.handles("tmp/external/plain.tar"));
+        assert!(!tool.handles("tmp/external/plain.tar.gz"));
+        assert!(ExternalTool::seven_zip().handles("release.RAR"));
+
+        let error = extract_with_fallback(
+            "tmp/external/plain.tar",
+            "tmp/external/none",
+            &[],
+            NoProgress,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::UnknownFormat);
+
+        let extracted = extract_with_fallback(
+            "tmp/external/plain.tar",
+            "tmp/external/out",
+            &[ExternalTool::unrar(), tool],
+            NoProgress,
+        )
+        .unwrap();
+        match extracted {
+            FallbackExtracted::External { program, files, .. } => {
+                assert_eq!(program, "tar");
+                assert_eq!(files, 2);
+            }
+            FallbackExtracted::Builtin(_) => panic!("plain tar has no built-in driver"),
+        }
+        assert_eq!(
+            std::fs::read_to_string("tmp/external/out/dir/b.txt").unwrap(),
+            "b"
+        );
+
+        let encoder = encoder::Encoder::new("tmp/external", "builtin.tar.gz", NoProgress).unwrap();
+        encoder.compress().unwrap();
+        let extracted = extract_with_fallback(
+            "tmp/external/builtin.tar.gz",
+            "tmp/external/builtin",
+            &[ExternalTool::tar()],
+            NoProgress,
+        )
+        .unwrap();
+        assert!(matches!(extracted, FallbackExtracted::Builtin(_)));
+    }
+    #[test]
+    #[cfg(unix)]
+    fn command_driver_test() {
+        let _ = std::fs::remove_dir_all("tmp/command_driver");
+        std::fs::create_dir_all("tmp/command_driver/source/dir").unwrap();
+        std::fs::write("tmp/command_driver/source/dir/a.txt", "a").unwrap();
+
+        let mut command = CommandDriver {
+            extension: "site-pack".to_string(),
+            program: "sh".to_string(),
+            create_args: vec![
+                "-c".to_string(),
+                "tar -cvf \"$0\" dir".to_string(),
+                "{archive}".to_string(),
+            ],
+            extract_args: vec![
+                "-c".to_string(),
+                "test -z \"$HOME\" && tar -xvf \"$0\"".to_string(),
+                "{archive}".to_string(),
+            ],
+            env_allow_list: vec!["PATH".to_string()],
+            entry_prefix: None,
+        };
+        command.extension = ".site-pack".to_string();
+        assert!(driver::Driver::register_command(command.clone()).is_err());
+        command.extension = "site-pack".to_string();
+        driver::Driver::register_command(command.clone()).unwrap();
+        assert_eq!(
+            driver::Driver::command_for_filename("tmp/command_driver/a.site-pack"),
+            Some(command.clone())
+        );
+        assert_eq!(driver::Driver::from_filename("a.site-pack"), None);
+
+        let files = command
+            .create(
+                "tmp/command_driver/source",
+                "tmp/command_driver/a.site-pack",
+                &mut NoProgress,
+                &CancellationToken::default(),
+            )
+            .unwrap();
+        assert_eq!(files, 2);
+
+        let extracted = extract_with_fallback(
+            "tmp/command_driver/a.site-pack",
+            "tmp/command_driver/out",
+            &[],
+            NoProgress,
+        )
+        .unwrap();
+        assert!(matches!(
+            extracted,
+            FallbackExtracted::External { files: 2, .. }
+        ));
+        assert_eq!(
+            std::fs::read_to_string("tmp/command_driver/out/dir/a.txt").unwrap(),
+            "a"
+        );
+
+        assert_eq!(
+            driver::Driver::unregister_command("site-pack"),
+            Some(command)
+        );
+        assert!(driver::Driver::command_for_filename("a.site-pack").is_none());
+
+        // relative placeholder paths must not resolve against the working directory
+        let command = CommandDriver {
+            extension: "placeholder-pack".to_string(),
+            program: "tar".to_string(),
+            create_args: ["-cvf", "{archive}", "-C", "{source}", "dir"]
+                .map(str::to_string)
+                .to_vec(),
+            extract_args: ["-xvf", "{archive}", "-C", "{destination}"]
+                .map(str::to_string)
+                .to_vec(),
+            env_allow_list: vec!["PATH".to_string()],
+            entry_prefix: None,
+        };
+        command
+            .create(
+                "tmp/command_driver/source",
+                "tmp/command_driver/b.placeholder-pack",
+                &mut NoProgress,
+                &CancellationToken::default(),
+            )
+            .unwrap();
+        command
+            .extract(
+                "tmp/command_driver/b.placeholder-pack",
+                "tmp/command_driver/placeholder_out",
+                &mut NoProgress,
+                &CancellationToken::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/command_driver/placeholder_out/dir/a.txt").unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn incremental_test() {
+        let _ = std::fs::remove_dir_all("tmp/incremental");
+        std::fs::create_dir_all("tmp/incremental/input/dir").unwrap();
+        std::fs::write("tmp/incremental/input/same.txt", "same").unwrap();
+        std::fs::write("tmp/incremental/input/changed.txt", "a").unwrap();
+        std::fs::write("tmp/incremental/input/dir/deleted.txt", "deleted").unwrap();
+        let create_archive = CreateArchive::builder()
+            .input("tmp/incremental/input")
+            .name("incremental")
+            .version("1.0")
+            .build()
+            .unwrap();
+        let (full, _) = create_archive
+            .create("tmp/incremental/full", NoProgress)
+            .unwrap();
+        Decoder::new(full.as_str(), None, "tmp/incremental/output", NoProgress)
+            .unwrap()
+            .extract()
+            .unwrap();
+        let base = Decoder::new(full.as_str(), None, "tmp/incremental/unused", NoProgress)
+            .unwrap()
+            .entry_digests()
+            .unwrap();
+
+        std::fs::write("tmp/incremental/input/changed.txt", "b").unwrap();
+        std::fs::write("tmp/incremental/input/added.txt", "added").unwrap();
+        std::fs::remove_dir_all("tmp/incremental/input/dir").unwrap();
+        let incremental = create_archive
+            .create_incremental("tmp/incremental/increment", &base, NoProgress)
+            .unwrap();
+        assert_eq!(incremental.updated, vec!["added.txt", "changed.txt"]);
+        assert_eq!(incremental.deleted, vec!["dir/deleted.txt"]);
+        assert_eq!(incremental.digests.len(), 3);
+
+        let extracted = Decoder::new(
+            incremental.output_file_path.as_str(),
+            Some(incremental.sha256),
+            "tmp/incremental/output",
+            NoProgress,
+        )
+        .unwrap()
+        .apply_incremental()
+        .unwrap();
+        let mut files: Vec<_> = extracted.files.into_iter().collect();
+        files.sort();
+        assert_eq!(files, vec!["added.txt", "changed.txt", "same.txt"]);
+        assert_eq!(
+            std::fs::read_to_string("tmp/incremental/output/changed.txt").unwrap(),
+            "b"
+        );
+        assert!(!std::path::Path::new("tmp/incremental/output/dir").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn incremental_symlink_escape_test() {
+        let _ = std::fs::remove_dir_all("tmp/incremental_symlink_escape");
+        std::fs::create_dir_all("tmp/incremental_symlink_escape/outside/empty").unwrap();
+        std::fs::write("tmp/incremental_symlink_escape/outside/victim", "victim").unwrap();
+        let outside = std::fs::canonicalize("tmp/incremental_symlink_escape/outside").unwrap();
+
+        let mut archiver = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        archiver.append_link(&mut header, "d", &outside).unwrap();
+        let deletions = "d/victim\nd/empty/gone\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(deletions.len() as u64);
+        header.set_mode(0o644);
+        archiver
+            .append_data(
+                &mut header,
+                incremental::DELETIONS_FILE_NAME,
+                deletions.as_bytes(),
+            )
+            .unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, archiver.into_inner().unwrap().as_slice()).unwrap();
+        std::fs::write(
+            "tmp/incremental_symlink_escape/escape.tar.gz",
+            encoder.finish().unwrap(),
+        )
+        .unwrap();
+
+        let extracted = Decoder::new(
+            "tmp/incremental_symlink_escape/escape.tar.gz",
+            None,
+            "tmp/incremental_symlink_escape/output",
+            NoProgress,
+        )
+        .unwrap()
+        .apply_incremental()
+        .unwrap();
+        assert!(outside.join("victim").exists());
+        assert!(outside.join("empty").exists());
+        assert!(extracted
+            .warnings
+            .iter()
+            .any(|warning| warning.path == "d/victim"
+                && warning.kind == decoder::EntryIssueKind::UnsafePath));
+    }
+
+    #[cfg(feature = "digest-cache")]
+    #[test]
+    fn digest_cache_test() {
+        let _ = std::fs::remove_dir_all("tmp/digest_cache");
+        std::fs::create_dir_all("tmp/digest_cache/input").unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        let write_old = |path: &str, contents: &str| {
+            std::fs::write(path, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(an_hour_ago)
+                .unwrap();
+        };
+        write_old("tmp/digest_cache/input/a.txt", "a");
+        std::fs::write("tmp/digest_cache/input/recent.txt", "recent").unwrap();
+
+        let cancellation = CancellationToken::default();
+        let mut digest_cache = DigestCache::open("tmp/digest_cache/cache.json").unwrap();
+        for _ in 0..2 {
+            for file_path in [
+                "tmp/digest_cache/input/a.txt",
+                "tmp/digest_cache/input/recent.txt",
+            ] {
+                let file_path = std::path::Path::new(file_path);
+                let metadata = std::fs::metadata(file_path).unwrap();
+                digest_cache
+                    .digest(file_path, &metadata, &cancellation)
+                    .unwrap();
+            }
+        }
+        // files modified just now are hashed every time
+        assert_eq!(digest_cache.hits(), 1);
+        assert_eq!(digest_cache.len(), 1);
+        digest_cache.save().unwrap();
+        assert_eq!(
+            DigestCache::open("tmp/digest_cache/cache.json")
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let create_archive = CreateArchive::builder()
+            .input("tmp/digest_cache/input")
+            .name("cached")
+            .version("1.0")
+            .digest_cache("tmp/digest_cache/cache.json")
+            .build()
+            .unwrap();
+        let first = create_archive
+            .create_incremental(
+                "tmp/digest_cache/first",
+                &std::collections::BTreeMap::new(),
+                NoProgress,
+            )
+            .unwrap();
+        assert_eq!(first.updated, vec!["a.txt", "recent.txt"]);
+
+        // same size and modification time, so the cached digest is reused
+        write_old("tmp/digest_cache/input/a.txt", "b");
+        let second = create_archive
+            .create_incremental("tmp/digest_cache/second", &first.digests, NoProgress)
+            .unwrap();
+        assert!(second.updated.is_empty());
+
+        std::fs::write("tmp/digest_cache/input/a.txt", "c").unwrap();
+        let third = create_archive
+            .create_incremental("tmp/digest_cache/third", &first.digests, NoProgress)
+            .unwrap();
+        assert_eq!(third.updated, vec!["a.txt"]);
+    }
+
+    #[test]
+    fn driver_aliases_test() {
+        assert_eq!(driver::Driver::all().len(), 7);
+        for driver in driver::Driver::all() {
+            assert_eq!(driver.aliases()[0], driver.extension());
+            assert_eq!(
+                driver::Driver::from_extension(driver.extension().as_str()),
+                Some(*driver)
+            );
+            for alias in driver.aliases() {
+                assert_eq!(
+                    driver::Driver::from_filename(format!("dir/name.{alias}").as_str()),
+                    Some(*driver)
+                );
+            }
+        }
+        assert_eq!(driver::Driver::from_filename("tar.gz"), None);
+        assert_eq!(driver::Driver::from_filename("name.gz"), None);
+
+        for (extension, driver) in [
+            ("tgz", driver::Driver::Gzip),
+            ("tbz2", driver::Driver::Bzip2),
+            ("tbz", driver::Driver::Bzip2),
+            ("tar.bz", driver::Driver::Bzip2),
+            ("txz", driver::Driver::Xz),
+        ] {
+            assert_eq!(driver::Driver::from_extension(extension), Some(driver));
+            assert_eq!(
+                driver::Driver::from_filename(format!("release.{extension}").as_str()),
+                Some(driver)
+            );
+        }
+        assert_eq!(driver::Driver::from_extension("tar.tgz"), None);
+        assert_eq!(driver::Driver::from_extension(".tar.gz"), None);
+    }
+
+    #[test]
+    fn driver_options_test() {
+        let _ = std::fs::remove_dir_all("tmp/driver_options");
+        std::fs::create_dir_all("tmp/driver_options").unwrap();
+        let contents = "compressible ".repeat(10_000);
+        let mut sizes = Vec::new();
+        for (output_filename, driver_options) in [
+            ("fast.tar.gz", DriverOptions::default()),
+            (
+                "small.tar.xz",
+                DriverOptions {
+                    level: Some(6),
+                    dictionary_size: Some(1 << 20),
+                    tar_format: TarFormat::Pax,
+                    ..Default::default()
+                },
+            ),
+            (
+                "stored.zip",
+                DriverOptions {
+                    zip_method: ZipMethod::Stored,
+                    ..Default::default()
+                },
+            ),
+            (
+                "deflated.zip",
+                DriverOptions {
+                    level: Some(9),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/driver_options", output_filename, NoProgress)
+                    .unwrap()
+                    .with_driver_options(driver_options.clone());
+            encoder.add_data("a.txt", contents.as_bytes()).unwrap();
+            encoder.compress().unwrap();
+            let archive_path = format!("tmp/driver_options/{output_filename}");
+            sizes.push(std::fs::metadata(archive_path.as_str()).unwrap().len());
+
+            let output_directory = format!("tmp/driver_options/{output_filename}.out");
+            Decoder::new(
+                archive_path.as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_driver_options(driver_options)
+            .extract()
+            .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(format!("{output_directory}/a.txt")).unwrap(),
+                contents
+            );
+        }
+        assert!(sizes[2] > contents.len() as u64);
+        assert!(sizes[3] < sizes[2]);
+
+        let mut encoder = encoder::Encoder::new("tmp/driver_options", "secret.tar.gz", NoProgress)
+            .unwrap()
+            .with_driver_options(DriverOptions {
+                password: Some("secret".to_string()),
+                ..Default::default()
+            });
+        encoder.add_data("a.txt", b"a").unwrap();
+        let error = encoder.compress().err().unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::UnsupportedFormat);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn zip_password_test() {
+        let _ = std::fs::remove_dir_all("tmp/zip_password");
+        std::fs::create_dir_all("tmp/zip_password").unwrap();
+        let driver_options = DriverOptions {
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let mut encoder = encoder::Encoder::new("tmp/zip_password", "secret.zip", NoProgress)
+            .unwrap()
+            .with_driver_options(driver_options.clone());
+        encoder.add_data("a.txt", b"hidden").unwrap();
+        encoder.compress().unwrap();
+
+        let mut archive =
+            zip::ZipArchive::new(std::fs::File::open("tmp/zip_password/secret.zip").unwrap())
+                .unwrap();
+        assert!(archive.by_index_raw(0).unwrap().encrypted());
+        assert!(archive.by_index(0).is_err());
+
+        assert!(Decoder::new(
+            "tmp/zip_password/secret.zip",
+            None,
+            "tmp/zip_password/no_password",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .is_err());
+        assert!(!std::path::Path::new("tmp/zip_password/no_password/a.txt").exists());
+
+        assert!(Decoder::new(
+            "tmp/zip_password/secret.zip",
+            None,
+            "tmp/zip_password/wrong_password",
+            NoProgress,
+        )
+        .unwrap()
+        .with_driver_options(DriverOptions {
+            password: Some("wrong".to_string()),
+            ..Default::default()
+        })
+        .extract()
+        .is_err());
+
+        Decoder::new(
+            "tmp/zip_password/secret.zip",
+            None,
+            "tmp/zip_password/output",
+            NoProgress,
+        )
+        .unwrap()
+        .with_driver_options(driver_options)
+        .extract()
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/zip_password/output/a.txt").unwrap(),
+            "hidden"
+        );
+    }
+
+    #[test]
+    fn threads_test() {
+        assert!(driver::default_threads() >= 1);
+        let _ = std::fs::remove_dir_all("tmp/threads");
+        std::fs::create_dir_all("tmp/threads/input").unwrap();
+        std::fs::write("tmp/threads/input/a.txt", "a").unwrap();
+
+        for driver in [driver::Driver::Gzip, driver::Driver::SevenZ] {
+            if !driver.is_supported() {
+                continue;
+            }
+            for threads in [1, 4] {
+                let (archive_path, _) = CreateArchive::builder()
+                    .input("tmp/threads/input")
+                    .name(format!("threads{threads}").as_str())
+                    .version("1.0.0")
+                    .driver(driver)
+                    .threads(threads)
+                    .build()
+                    .unwrap()
+                    .create("tmp/threads", NoProgress)
+                    .unwrap();
+                let output_directory = format!("tmp/threads/{}-{threads}", driver.extension());
+                Decoder::new(
+                    archive_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_driver_options(DriverOptions {
+                    threads: Some(threads),
+                    ..Default::default()
+                })
+                .extract()
+                .unwrap();
+                assert_eq!(
+                    std::fs::read_to_string(format!("{output_directory}/a.txt")).unwrap(),
+                    "a"
+                );
+            }
+        }
+    }
+    #[test]
+    fn digest_file_test() {
+        use sha2::Digest;
+        let _ = std::fs::remove_dir_all("tmp/digest_file");
+        std::fs::create_dir_all("tmp/digest_file").unwrap();
+        // spans several buffer fills and progress intervals
+        let contents: Vec<u8> = (0..40 * 1024 * 1024)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        std::fs::write("tmp/digest_file/large.bin", contents.as_slice()).unwrap();
+
+        #[derive(Default)]
+        struct Recorder {
+            events: Vec<ProgressEvent>,
+            statuses: Vec<UpdateStatus>,
+        }
+        impl ProgressReporter for Recorder {
+            fn update_status(&mut self, update_status: UpdateStatus) {
+                self.statuses.push(update_status);
+            }
+            fn on_event(&mut self, event: ProgressEvent) {
+                self.events.push(event);
+            }
+        }
+
+        let mut recorder = Recorder::default();
+        let sha256 = driver::digest_file(
+            "tmp/digest_file/large.bin",
+            &mut recorder,
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            sha256,
+            format!("{:x}", sha2::Sha256::digest(contents.as_slice()))
+        );
+        assert!(recorder.events.iter().any(|event| matches!(
+            event,
+            ProgressEvent::PhaseChanged {
+                phase: Phase::Digesting
+            }
+        )));
+        let size = contents.len() as u64;
+        assert!(recorder
+            .statuses
+            .iter()
+            .all(|status| status.total == Some(size) && status.bytes_total == Some(size)));
+        let increments: u64 = recorder
+            .statuses
+            .iter()
+            .filter_map(|status| status.increment)
+            .sum();
+        assert_eq!(increments, size);
+        assert!(recorder.statuses.len() > 2);
+        assert_eq!(
+            recorder.statuses.last().unwrap().bytes_processed,
+            Some(size)
+        );
+
+        let cancellation = CancellationToken::default();
+        cancellation.cancel();
+        let error =
+            driver::digest_file("tmp/digest_file/large.bin", &mut NoProgress, &cancellation)
+                .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Cancelled);
+    }
+    #[test]
+    fn xxh3_digest_test() {
+        let _ = std::fs::remove_dir_all("tmp/xxh3");
+        std::fs::create_dir_all("tmp/xxh3").unwrap();
+        let mut encoder = encoder::Encoder::new("tmp/xxh3", "cache.tar.gz", NoProgress).unwrap();
+        encoder.add_data("a.txt", b"a").unwrap();
+        let checksummed = encoder
+            .compress()
+            .unwrap()
+            .digest_with(DigestAlgorithm::Xxh3)
+            .unwrap();
+        let contents = std::fs::read("tmp/xxh3/cache.tar.gz").unwrap();
+        assert_eq!(checksummed.algorithm, DigestAlgorithm::Xxh3);
+        assert_eq!(
+            checksummed.checksum,
+            format!("{:016x}", xxhash_rust::xxh3::xxh3_64(contents.as_slice()))
+        );
+
+        Decoder::new(
+            "tmp/xxh3/cache.tar.gz",
+            Some(checksummed.checksum.clone()),
+            "tmp/xxh3/output",
+            NoProgress,
+        )
+        .unwrap()
+        .with_digest_algorithm(DigestAlgorithm::Xxh3)
+        .extract()
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("tmp/xxh3/output/a.txt").unwrap(),
+            "a"
+        );
+
+        let error = Decoder::new(
+            "tmp/xxh3/cache.tar.gz",
+            Some(checksummed.checksum),
+            "tmp/xxh3/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::DigestMismatch);
+    }
+
+    #[test]
+    fn digest_all_test() {
+        use sha2::Digest;
+        let _ = std::fs::remove_dir_all("tmp/digest_all");
+        std::fs::create_dir_all("tmp/digest_all").unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/digest_all", "publish.tar.gz", NoProgress).unwrap();
+        encoder.add_data("a.txt", b"a").unwrap();
+        let multi_digested = encoder
+            .compress()
+            .unwrap()
+            .digest_all(&[DigestAlgorithm::Sha256, DigestAlgorithm::Blake3])
+            .unwrap();
+        let contents = std::fs::read("tmp/digest_all/publish.tar.gz").unwrap();
+        let digests = multi_digested.digests;
+        assert_eq!(digests.size, contents.len() as u64);
+        assert_eq!(
+            digests.sha256.as_deref(),
+            Some(format!("{:x}", sha2::Sha256::digest(contents.as_slice())).as_str())
+        );
+        assert_eq!(
+            digests.get(DigestAlgorithm::Blake3),
+            Some(blake3::hash(contents.as_slice()).to_hex().as_str())
+        );
+        assert_eq!(digests.xxh3, None);
+        assert_eq!(
+            driver::digest_file_with(
+                "tmp/digest_all/publish.tar.gz",
+                DigestAlgorithm::Blake3,
+                &mut NoProgress,
+                &CancellationToken::default()
+            )
+            .unwrap(),
+            digests.blake3.unwrap()
+        );
+    }
+
+    #[test]
+    fn driver_capabilities_test() {
+        use driver::Driver;
+        assert!(Driver::Gzip.supports_symlinks());
+        assert!(!Driver::Zip.supports_symlinks());
+        assert!(Driver::all()
+            .iter()
+            .all(|driver| driver.supports_encryption()
+                == (*driver == Driver::Zip && cfg!(feature = "encryption"))));
+        assert!(Driver::Xz.supports_append());
+        assert!(!Driver::Deb.supports_append());
+        assert!(Driver::Bzip2.preserves_permissions());
+        assert!(!Driver::Zip.preserves_permissions());
+
+        let _ = std::fs::remove_dir_all("tmp/capabilities");
+        std::fs::create_dir_all("tmp/capabilities").unwrap();
+        for driver in Driver::all().iter().filter(|driver| driver.is_supported()) {
+            let output_filename = format!("archive.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/capabilities", output_filename.as_str(), NoProgress)
+                    .unwrap();
+            encoder.add_data("a.txt", b"a").unwrap();
+            encoder.compress().unwrap();
+            let reopened = encoder::Encoder::reopen(
+                format!("tmp/capabilities/{output_filename}").as_str(),
+                NoProgress,
+            );
+            assert_eq!(reopened.is_ok(), driver.supports_append(), "{driver:?}");
+        }
+    }
+
+    #[test]
+    fn driver_feature_test() {
+        use driver::Driver;
+        assert_eq!(Driver::Gzip.feature(), None);
+        assert_eq!(Driver::SevenZ.feature(), Some("sevenz"));
+        assert_eq!(Driver::Zip.is_supported(), cfg!(feature = "zip"));
+        assert_eq!(Driver::SevenZ.is_supported(), cfg!(feature = "sevenz"));
+
+        for driver in Driver::all().iter().filter(|driver| !driver.is_supported()) {
+            let error = memory::encode(*driver, &[]).err().unwrap();
+            assert_eq!(ErrorKind::of(&error), ErrorKind::UnsupportedFormat);
+        }
+    }
+
+    #[test]
+    fn register_alias_test() {
+        let driver = driver::Driver::from_filename("serde-1.0.0.test-crate");
+        assert_eq!(driver, None);
+        driver::Driver::register_alias(".test-crate", driver::Driver::Zip);
+        driver::Driver::register_alias("test-crate", driver::Driver::Gzip);
+        assert_eq!(
+            driver::Driver::from_filename("serde-1.0.0.test-crate"),
+            Some(driver::Driver::Gzip)
+        );
+        assert!(driver::Driver::registered_aliases()
+            .contains(&("test-crate".to_string(), driver::Driver::Gzip)));
+        assert_eq!(
+            driver::Driver::unregister_alias("test-crate"),
+            Some(driver::Driver::Gzip)
+        );
+        assert_eq!(
+            driver::Driver::from_filename("serde-1.0.0.test-crate"),
+            None
+        );
+    }
+
+    #[test]
+    fn from_magic_bytes_test() {
+        let _ = std::fs::remove_dir_all("tmp/magic");
+        std::fs::create_dir_all("tmp/magic").unwrap();
+        for driver in [
+            driver::Driver::Gzip,
+            driver::Driver::Bzip2,
+            driver::Driver::Zip,
+            driver::Driver::SevenZ,
+            driver::Driver::Xz,
+        ] {
+            let archive = memory::encode(
+                driver,
+                &[memory::MemoryEntry {
+                    archive_path: "a.txt".to_string(),
+                    contents: b"a".to_vec(),
+                }],
+            )
+            .unwrap();
+            assert_eq!(
+                driver::Driver::from_reader(&mut archive.as_slice()).unwrap(),
+                Some(driver)
+            );
+
+            // no extension, and one that names another format
+            for file_name in ["archive", "archive.tar.gz"] {
+                let file_path = format!("tmp/magic/{file_name}");
+                std::fs::write(file_path.as_str(), archive.as_slice()).unwrap();
+                let output_directory = format!("tmp/magic/{}", driver.extension());
+                Decoder::new(
+                    file_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .extract()
+                .unwrap();
+                assert_eq!(
+                    std::fs::read_to_string(format!("{output_directory}/a.txt")).unwrap(),
+                    "a"
+                );
+            }
+        }
+        assert_eq!(driver::Driver::from_magic_bytes(b"plain text"), None);
+        assert_eq!(driver::Driver::from_magic_bytes(b""), None);
+    }
+
+    #[test]
+    fn error_test() {
+        let _ = std::fs::remove_dir_all("tmp/error");
+        std::fs::create_dir_all("tmp/error").unwrap();
+        let mut encoder = encoder::Encoder::new("tmp/error", "a.tar.gz", NoProgress).unwrap();
+        encoder.add_data("a.txt", b"a").unwrap();
+        encoder.compress().unwrap();
+        // the format is sniffed from the contents before the extension is checked
+        std::fs::write("tmp/error/a.txt", "a").unwrap();
+        std::fs::write("tmp/error/a.7z", "a").unwrap();
+
+        let error = Decoder::new("tmp/error/a.txt", None, "tmp/error/output", NoProgress)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            Error::find(&error),
+            Some(Error::UnknownFormat { .. })
+        ));
+        let error = Decoder::new("tmp/error/a.7z", None, "tmp/error/output", NoProgress)
+            .err()
+            .unwrap();
+        let Some(Error::UnknownFormat { suggestions, .. }) = Error::find(&error) else {
+            panic!("expected an unknown format: {error:?}");
+        };
+        assert_eq!(*suggestions, vec!["tar.7z"]);
+        assert!(format!("{error:#}").contains(".tar.gz, "));
+        assert!(driver::Driver::supported_extensions().contains(&"zip".to_string()));
+        assert_eq!(
+            driver::Driver::suggest_extensions("A.TAR.XZ"),
+            vec!["tar.xz"]
+        );
+
+        let error = Decoder::new(
+            "tmp/error/missing.tar.gz",
+            None,
+            "tmp/error/output",
+            NoProgress,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(Error::find(&error), Some(Error::Io { .. })));
+
+        let error = Decoder::new(
+            "tmp/error/a.tar.gz",
+            Some("0".repeat(64)),
+            "tmp/error/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .err()
+        .unwrap();
+        let Some(Error::DigestMismatch { expected, .. }) = error.downcast_ref::<Error>() else {
+            panic!("expected a digest mismatch: {error:?}");
+        };
+        assert_eq!(*expected, "0".repeat(64));
+        assert_eq!(ErrorKind::of(&error), ErrorKind::DigestMismatch);
+        assert_eq!(ErrorKind::of(&error).code(), "digest_mismatch");
+        assert_eq!(
+            ErrorKind::of(&error.context("while verifying")),
+            ErrorKind::DigestMismatch
+        );
+        assert_eq!(
+            ErrorKind::of(&format_error!("bad option")),
+            ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn entry_policy_test() {
+        let _ = std::fs::remove_dir_all("tmp/entry_policy");
+        std::fs::create_dir_all("tmp/entry_policy").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/entry_policy", output_filename, NoProgress).unwrap();
+            encoder.add_data("blocked.txt", b"blocked").unwrap();
+            encoder.add_data("ok.txt", b"ok").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/entry_policy/{output_filename}");
+            for entry_policy in [decoder::ErrorPolicy::Abort, decoder::ErrorPolicy::Continue] {
+                // a non-empty directory where a file entry belongs can't be overwritten
+                let output_directory =
+                    format!("tmp/entry_policy/{output_filename}-{entry_policy:?}");
+                std::fs::create_dir_all(format!("{output_directory}/blocked.txt/dir")).unwrap();
+                let extracted = Decoder::new(
+                    archive_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_options(decoder::ExtractOptions {
+                    entry_policy,
+                    ..Default::default()
+                })
+                .extract();
+                if entry_policy == decoder::ErrorPolicy::Abort {
+                    assert!(extracted.is_err());
+                    continue;
+                }
+                let extracted = extracted.unwrap();
+                assert_eq!(extracted.failures.len(), 1);
+                assert_eq!(extracted.failures[0].path, "blocked.txt");
+                assert_eq!(
+                    extracted.failures[0].kind,
+                    decoder::EntryIssueKind::WriteFailed
+                );
+                assert_eq!(
+                    std::fs::read_to_string(format!("{output_directory}/ok.txt")).unwrap(),
+                    "ok"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rollback_test() {
+        let _ = std::fs::remove_dir_all("tmp/rollback");
+        std::fs::create_dir_all("tmp/rollback").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/rollback", output_filename, NoProgress).unwrap();
+            encoder.add_data("new/a.txt", b"a").unwrap();
+            encoder.add_data("keep.txt", b"new").unwrap();
+            encoder.add_data("blocked.txt", b"blocked").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/rollback/{output_filename}");
+            for rollback_on_failure in [false, true] {
+                let output_directory =
+                    format!("tmp/rollback/{output_filename}-{rollback_on_failure}");
+                std::fs::create_dir_all(format!("{output_directory}/blocked.txt/dir")).unwrap();
+                std::fs::write(format!("{output_directory}/keep.txt"), "old").unwrap();
+                let extracted = Decoder::new(
+                    archive_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_options(decoder::ExtractOptions {
+                    rollback_on_failure,
+                    ..Default::default()
+                })
+                .extract();
+                assert!(extracted.is_err());
+                assert_eq!(
+                    std::path::Path::new(&format!("{output_directory}/new")).exists(),
+                    !rollback_on_failure
+                );
+                assert!(std::path::Path::new(&format!("{output_directory}/keep.txt")).exists());
+                assert!(
+                    std::path::Path::new(&format!("{output_directory}/blocked.txt/dir")).exists()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn extract_digests_test() {
+        let _ = std::fs::remove_dir_all("tmp/extract_digests");
+        std::fs::create_dir_all("tmp/extract_digests").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/extract_digests", output_filename, NoProgress).unwrap();
+            encoder.add_data("dir/a.txt", b"a").unwrap();
+            encoder.add_data("b.txt", &vec![b'b'; 100_000]).unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/extract_digests/{output_filename}");
+            for threads in [1, 2] {
+                let output_directory = format!("tmp/extract_digests/{output_filename}-{threads}");
+                let decoder = Decoder::new(
+                    archive_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_threads(threads)
+                .with_options(decoder::ExtractOptions {
+                    compute_digests: true,
+                    ..Default::default()
+                });
+                let expected = decoder.entry_digests().unwrap();
+                let extracted = decoder.extract().unwrap();
+                assert_eq!(extracted.digests.len(), 2);
+                assert_eq!(extracted.digests, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn extract_with_test() {
+        let _ = std::fs::remove_dir_all("tmp/extract_with");
+        std::fs::create_dir_all("tmp/extract_with").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/extract_with", output_filename, NoProgress).unwrap();
+            encoder.add_data("a.txt", b"a").unwrap();
+            encoder.add_data("b.txt", b"b").unwrap();
+            encoder.add_data("dir/c.txt", b"c").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/extract_with/{output_filename}");
+            let output_directory = format!("tmp/extract_with/{output_filename}-output");
+            let mut seen = Vec::new();
+            let extracted = Decoder::new(
+                archive_path.as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract_with(|entry| {
+                seen.push(entry.archive_path.clone());
+                match entry.archive_path.as_str() {
+                    "a.txt" => decoder::ExtractAction::Skip,
+                    "b.txt" => decoder::ExtractAction::ExtractAs("renamed/b.txt".to_string()),
+                    _ => decoder::ExtractAction::Extract,
+                }
+            })
+            .unwrap();
+            assert_eq!(seen, vec!["a.txt", "b.txt", "dir/c.txt"]);
+            let mut files: Vec<_> = extracted.files.into_iter().collect();
+            files.sort();
+            assert_eq!(files, vec!["dir/c.txt", "renamed/b.txt"]);
+            assert_eq!(
+                std::fs::read_to_string(format!("{output_directory}/renamed/b.txt")).unwrap(),
+                "b"
+            );
+
+            let error = Decoder::new(
+                archive_path.as_str(),
+                None,
+                format!("{output_directory}-escape").as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract_with(|_| decoder::ExtractAction::ExtractAs("../escaped.txt".to_string()))
+            .err()
+            .unwrap();
+            assert!(matches!(
+                Error::find(&error),
+                Some(Error::PathEscape { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn extract_routes_test() {
+        let _ = std::fs::remove_dir_all("tmp/extract_routes");
+        std::fs::create_dir_all("tmp/extract_routes").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/extract_routes", output_filename, NoProgress).unwrap();
+            encoder.add_data("bin/tool", b"tool").unwrap();
+            encoder.add_data("etc/app.conf", b"conf").unwrap();
+            encoder.add_data("etc/secrets/key", b"key").unwrap();
+            encoder.add_data("binary.txt", b"binary").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/extract_routes/{output_filename}");
+            let root = format!("tmp/extract_routes/{output_filename}-output");
+            let extracted = Decoder::new(
+                archive_path.as_str(),
+                None,
+                format!("{root}/staging").as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_options(decoder::ExtractOptions {
+                compute_digests: true,
+                routes: vec![
+                    decoder::ExtractRoute::new("bin", format!("{root}/usr/bin").as_str()),
+                    decoder::ExtractRoute::new("etc/", format!("{root}/etc/app").as_str()),
+                    decoder::ExtractRoute::new("etc/secrets", format!("{root}/secrets").as_str()),
+                ],
+                ..Default::default()
+            })
+            .extract()
+            .unwrap();
+
+            for (path, contents) in [
+                ("usr/bin/tool", "tool"),
+                ("etc/app/app.conf", "conf"),
+                ("secrets/key", "key"),
+                ("staging/binary.txt", "binary"),
+            ] {
+                assert_eq!(
+                    std::fs::read_to_string(format!("{root}/{path}")).unwrap(),
+                    contents
+                );
+            }
+            assert_eq!(
+                extracted.files.into_iter().collect::<Vec<_>>(),
+                vec!["binary.txt"]
+            );
+            assert!(extracted
+                .digests
+                .contains_key(format!("{root}/usr/bin/tool").as_str()));
+            assert!(extracted.digests.contains_key("binary.txt"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_routes_symlink_escape_test() {
+        let _ = std::fs::remove_dir_all("tmp/extract_routes_symlink_escape");
+        std::fs::create_dir_all("tmp/extract_routes_symlink_escape/outside").unwrap();
+        let outside = std::fs::canonicalize("tmp/extract_routes_symlink_escape/outside").unwrap();
+        write_symlink_archive(
+            "tmp/extract_routes_symlink_escape/escape.tar.gz",
+            &[
+                ("bin/d", Some(outside.to_str().unwrap())),
+                ("bin/d/new/f", None),
+            ],
+        );
+
+        let extracted = Decoder::new(
+            "tmp/extract_routes_symlink_escape/escape.tar.gz",
+            None,
+            "tmp/extract_routes_symlink_escape/staging",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            routes: vec![decoder::ExtractRoute::new(
+                "bin",
+                "tmp/extract_routes_symlink_escape/usr/bin",
+            )],
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+        assert!(!outside.join("new").exists());
+        assert!(extracted
+            .warnings
+            .iter()
+            .any(|warning| warning.path == "bin/d/new/f"
+                && warning.kind == decoder::EntryIssueKind::UnsafePath));
+    }
+
+    #[test]
+    fn entry_stream_test() {
+        let _ = std::fs::remove_dir_all("tmp/entry_stream");
+        std::fs::create_dir_all("tmp/entry_stream").unwrap();
+        for output_filename in ["a.tar.gz", "a.zip"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/entry_stream", output_filename, NoProgress).unwrap();
+            encoder.add_data("a.txt", b"a").unwrap();
+            encoder.add_data("dir/b.txt", b"bb").unwrap();
+            encoder.compress().unwrap();
+        }
+
+        let decoder = Decoder::new(
+            "tmp/entry_stream/a.tar.gz",
+            None,
+            "tmp/entry_stream/output",
+            NoProgress,
+        )
+        .unwrap();
+        let mut stream = decoder.entries().unwrap();
+        let mut contents = Vec::new();
+        for entry in stream.iter().unwrap() {
+            let (info, mut reader) = entry.unwrap();
+            if info.is_dir {
+                continue;
+            }
+            let mut data = String::new();
+            std::io::Read::read_to_string(&mut reader, &mut data).unwrap();
+            contents.push((info.archive_path, info.size, data));
+        }
+        assert_eq!(
+            contents,
+            vec![
+                ("a.txt".to_string(), 1, "a".to_string()),
+                ("dir/b.txt".to_string(), 2, "bb".to_string()),
+            ]
+        );
+        assert!(!std::path::Path::new("tmp/entry_stream/output").exists());
+
+        let decoder = Decoder::new(
+            "tmp/entry_stream/a.zip",
+            None,
+            "tmp/entry_stream/output",
+            NoProgress,
+        )
+        .unwrap();
+        let error = decoder.entries().err().unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::UnsupportedFormat);
+    }
+
+    #[test]
+    fn update_policy_test() {
+        let _ = std::fs::remove_dir_all("tmp/update_policy");
+        std::fs::create_dir_all("tmp/update_policy").unwrap();
+        let mtime = 1_000_000_000;
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/update_policy", output_filename, NoProgress).unwrap();
+            for (name, contents) in [("a.txt", "one"), ("b.txt", "two"), ("c.txt", "three")] {
+                encoder
+                    .add_data_with_metadata(name, contents.as_bytes(), 0o644, mtime)
+                    .unwrap();
+            }
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/update_policy/{output_filename}");
+            let output_directory = format!("tmp/update_policy/{output_filename}-output");
+            let extract = |update| {
+                Decoder::new(
+                    archive_path.as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_options(decoder::ExtractOptions {
+                    update,
+                    ..Default::default()
+                })
+                .extract()
+                .unwrap()
+                .stats
+            };
+            let read =
+                |name: &str| std::fs::read_to_string(format!("{output_directory}/{name}")).unwrap();
+            extract(decoder::UpdatePolicy::Always);
+
+            // same size with different contents, and a missing file
+            std::fs::write(format!("{output_directory}/b.txt"), "TWO").unwrap();
+            std::fs::remove_file(format!("{output_directory}/c.txt")).unwrap();
+            let stats = extract(decoder::UpdatePolicy::Changed);
+            assert_eq!((stats.files, stats.up_to_date), (2, 1));
+            assert_eq!(read("b.txt"), "two");
+            assert_eq!(read("c.txt"), "three");
+
+            // older than the entry
+            let a_path = format!("{output_directory}/a.txt");
+            std::fs::write(a_path.as_str(), "xyz").unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(a_path.as_str())
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH)
+                .unwrap();
+            let stats = extract(decoder::UpdatePolicy::Newer);
+            assert_eq!((stats.files, stats.up_to_date), (1, 2));
+            assert_eq!(read("a.txt"), "one");
+        }
+    }
+
+    #[test]
+    fn merge_test() {
+        let _ = std::fs::remove_dir_all("tmp/merge");
+        std::fs::create_dir_all("tmp/merge").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/merge", output_filename, NoProgress).unwrap();
+            encoder.add_data("same.txt", b"same").unwrap();
+            encoder.add_data("dir/changed.txt", b"archive").unwrap();
+            encoder.add_data("dir/new.txt", b"new").unwrap();
+            encoder.compress().unwrap();
+
+            let output_directory = format!("tmp/merge/{output_filename}-output");
+            std::fs::create_dir_all(format!("{output_directory}/dir")).unwrap();
+            std::fs::write(format!("{output_directory}/same.txt"), "same").unwrap();
+            std::fs::write(format!("{output_directory}/dir/changed.txt"), "local").unwrap();
+            std::fs::write(format!("{output_directory}/dir/unrelated.txt"), "mine").unwrap();
+
+            let extracted = Decoder::new(
+                format!("tmp/merge/{output_filename}").as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_options(decoder::ExtractOptions {
+                merge: true,
+                report_conflicts: true,
+                ..Default::default()
+            })
+            .extract()
+            .unwrap();
+
+            let conflicts: Vec<_> = extracted
+                .conflicts
+                .iter()
+                .map(|conflict| (conflict.path.as_str(), conflict.kind))
+                .collect();
+            assert_eq!(
+                conflicts,
+                vec![("dir/changed.txt", decoder::EntryIssueKind::Conflict)]
+            );
+            let read =
+                |name: &str| std::fs::read_to_string(format!("{output_directory}/{name}")).unwrap();
+            assert_eq!(read("dir/changed.txt"), "local");
+            assert_eq!(read("dir/unrelated.txt"), "mine");
+            assert_eq!(read("dir/new.txt"), "new");
+        }
+    }
+
+    #[test]
+    fn from_bytes_test() {
+        let _ = std::fs::remove_dir_all("tmp/from_bytes");
+        std::fs::create_dir_all("tmp/from_bytes").unwrap();
+        for output_filename in ["a.zip", "a.tar.gz"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/from_bytes", output_filename, NoProgress).unwrap();
+            encoder.add_data("dir/a.txt", b"a").unwrap();
+            let digest = encoder.compress().unwrap().digest().unwrap();
+
+            let contents = std::fs::read(format!("tmp/from_bytes/{output_filename}")).unwrap();
+            let driver = driver::Driver::from_filename(output_filename).unwrap();
+            let output_directory = format!("tmp/from_bytes/{output_filename}-output");
+            let extracted = Decoder::from_bytes(
+                contents.clone(),
+                driver,
+                Some(digest.sha256),
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            assert_eq!(
+                extracted.files.into_iter().collect::<Vec<_>>(),
+                vec!["dir/a.txt"]
+            );
+
+            let error = Decoder::from_bytes(
+                contents,
+                driver,
+                Some("0".repeat(64)),
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .err()
+            .unwrap();
+            assert_eq!(ErrorKind::of(&error), ErrorKind::DigestMismatch);
+        }
+    }
+
+    #[test]
+    fn nested_archive_test() {
+        let _ = std::fs::remove_dir_all("tmp/nested_archive");
+        std::fs::create_dir_all("tmp/nested_archive").unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/nested_archive", "deepest.zip", NoProgress).unwrap();
+        encoder.add_data("deep.txt", b"deep").unwrap();
+        encoder.compress().unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/nested_archive", "layer.tar.gz", NoProgress).unwrap();
+        encoder.add_data("x.txt", b"x").unwrap();
+        encoder
+            .add_data(
+                "deepest.zip",
+                &std::fs::read("tmp/nested_archive/deepest.zip").unwrap(),
+            )
+            .unwrap();
+        encoder.compress().unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/nested_archive", "outer.zip", NoProgress).unwrap();
+        encoder
+            .add_data(
+                "layers/layer.tar.gz",
+                &std::fs::read("tmp/nested_archive/layer.tar.gz").unwrap(),
+            )
+            .unwrap();
+        encoder.compress().unwrap();
+
+        let extracted = Decoder::new(
+            "tmp/nested_archive/outer.zip",
+            None,
+            "tmp/nested_archive/output",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            nested_depth: 1,
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+        let mut files: Vec<_> = extracted.files.into_iter().collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "layers/layer.tar.gz",
+                "layers/layer/deepest.zip",
+                "layers/layer/x.txt"
+            ]
+        );
+        assert_eq!(extracted.stats.files, 3);
+    }
+
+    #[test]
+    fn nested_archive_alias_test() {
+        let _ = std::fs::remove_dir_all("tmp/nested_archive_alias");
+        std::fs::create_dir_all("tmp/nested_archive_alias").unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/nested_archive_alias", "layer.tar.gz", NoProgress).unwrap();
+        encoder.add_data("x.txt", b"x").unwrap();
+        encoder.compress().unwrap();
+        let layer = std::fs::read("tmp/nested_archive_alias/layer.tar.gz").unwrap();
+        let mut encoder =
+            encoder::Encoder::new("tmp/nested_archive_alias", "outer.zip", NoProgress).unwrap();
+        encoder.add_data("layers/layer.tgz", &layer).unwrap();
+        // stripping the length of `tar.gz` would cut into the first `é`
+        encoder.add_data("layers/éé.tgz", &layer).unwrap();
+        encoder.compress().unwrap();
+
+        let extracted = Decoder::new(
+            "tmp/nested_archive_alias/outer.zip",
+            None,
+            "tmp/nested_archive_alias/output",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            nested_depth: 1,
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+        let mut files: Vec<_> = extracted.files.into_iter().collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "layers/layer.tgz",
+                "layers/layer/x.txt",
+                "layers/éé.tgz",
+                "layers/éé/x.txt"
+            ]
+        );
+        assert_eq!(
+            driver::Driver::split_filename("dir/layer.tbz"),
+            Some(("dir/layer", driver::Driver::Bzip2))
+        );
+    }
+
+    #[test]
+    fn zip_crc_test() {
+        let _ = std::fs::remove_dir_all("tmp/zip_crc");
+        std::fs::create_dir_all("tmp/zip_crc").unwrap();
+        let mut encoder = encoder::Encoder::new("tmp/zip_crc", "corrupt.zip", NoProgress)
+            .unwrap()
+            .with_driver_options(DriverOptions {
+                zip_method: ZipMethod::Stored,
+                ..Default::default()
+            });
+        encoder.add_data("a.txt", b"contents to corrupt").unwrap();
+        encoder.add_data("b.txt", b"intact").unwrap();
+        encoder.compress().unwrap();
+
+        // stored entries keep their contents as is, so flip a byte in place
+        let mut contents = std::fs::read("tmp/zip_crc/corrupt.zip").unwrap();
+        let offset = contents
+            .windows(b"contents to corrupt".len())
+            .position(|window| window == b"contents to corrupt")
+            .unwrap();
+        contents[offset] ^= 0xff;
+        std::fs::write("tmp/zip_crc/corrupt.zip", contents).unwrap();
+
+        let error = Decoder::new(
+            "tmp/zip_crc/corrupt.zip",
+            None,
+            "tmp/zip_crc/abort",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::CorruptEntry);
+        assert!(format!("{error:?}").contains("a.txt"), "{error:?}");
+
+        let extracted = Decoder::new(
+            "tmp/zip_crc/corrupt.zip",
+            None,
+            "tmp/zip_crc/continue",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            crc_policy: decoder::ErrorPolicy::Continue,
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+        let crc_failures: Vec<_> = extracted
+            .warnings
+            .iter()
+            .filter(|warning| warning.kind == decoder::EntryIssueKind::CrcMismatch)
+            .map(|warning| warning.path.as_str())
+            .collect();
+        assert_eq!(crc_failures, vec!["a.txt"]);
+        assert!(!std::path::Path::new("tmp/zip_crc/continue/a.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string("tmp/zip_crc/continue/b.txt").unwrap(),
+            "intact"
+        );
+    }
+
+    #[test]
+    fn corrupt_tar_entry_test() {
+        let _ = std::fs::remove_dir_all("tmp/corrupt_tar_entry");
+        std::fs::create_dir_all("tmp/corrupt_tar_entry").unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in [("a.txt", "a"), ("b.txt", "b")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        let mut tar_bytes = builder.into_inner().unwrap();
+        // the header of b.txt follows the header and padded data of a.txt
+        tar_bytes[1024] ^= 0xff;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(tar_bytes.as_slice()).unwrap();
+        std::fs::write(
+            "tmp/corrupt_tar_entry/corrupt.tar.gz",
+            encoder.finish().unwrap(),
+        )
+        .unwrap();
+
+        let error = Decoder::new(
+            "tmp/corrupt_tar_entry/corrupt.tar.gz",
+            None,
+            "tmp/corrupt_tar_entry/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .err()
+        .unwrap();
+        let message = format!("{error:?}");
+        assert!(message.contains("at offset 1024"), "{message}");
+        assert!(message.contains("last good entry: a.txt"), "{message}");
+    }
+
+    #[test]
+    fn multi_member_gzip_test() {
+        let _ = std::fs::remove_dir_all("tmp/multi_member_gzip");
+        std::fs::create_dir_all("tmp/multi_member_gzip").unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in [("a.txt", "a"), ("b.txt", "b")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        let tar_bytes = builder.into_inner().unwrap();
+
+        // each half in its own gzip member, the way pigz or `cat a.gz b.gz` do
+        let mut contents = Vec::new();
+        for half in tar_bytes.chunks(tar_bytes.len() / 2 + 1) {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(half).unwrap();
+            contents.extend(encoder.finish().unwrap());
+        }
+        std::fs::write("tmp/multi_member_gzip/a.tar.gz", contents.as_slice()).unwrap();
+
+        let extracted = Decoder::new(
+            "tmp/multi_member_gzip/a.tar.gz",
+            None,
+            "tmp/multi_member_gzip/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        let mut files: Vec<_> = extracted.files.into_iter().collect();
+        files.sort();
+        assert_eq!(files, vec!["a.txt", "b.txt"]);
+
+        let entries = memory::decode(driver::Driver::Gzip, contents.as_slice()).unwrap();
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn memory_decode_oversized_entry_test() {
+        let mut header = tar::Header::new_gnu();
+        header.set_path("huge.bin").unwrap();
+        header.set_size(1 << 50);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut tar_bytes = header.as_bytes().to_vec();
+        tar_bytes.extend_from_slice(b"only a few bytes");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(tar_bytes.as_slice()).unwrap();
+        let contents = encoder.finish().unwrap();
+
+        // the declared size must not be allocated up front
+        assert!(memory::decode(driver::Driver::Gzip, contents.as_slice()).is_err());
+    }
+
+    #[test]
+    fn reopen_test() {
+        let _ = std::fs::remove_dir_all("tmp/reopen");
+        std::fs::create_dir_all("tmp/reopen").unwrap();
+        for output_filename in ["a.tar.gz", "a.tar.xz", "a.zip"] {
+            let mut encoder =
+                encoder::Encoder::new("tmp/reopen", output_filename, NoProgress).unwrap();
+            encoder.add_data("a.txt", b"a").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/reopen/{output_filename}");
+            let encoder = encoder::Encoder::reopen(archive_path.as_str(), NoProgress);
+            if output_filename == "a.zip" {
+                let error = encoder.err().unwrap();
+                assert_eq!(ErrorKind::of(&error), ErrorKind::UnsupportedFormat);
+                continue;
+            }
+            let mut encoder = encoder.unwrap();
+            encoder.add_data("dir/b.txt", b"b").unwrap();
+            encoder.compress().unwrap();
+
+            let output_directory = format!("tmp/reopen/{output_filename}-output");
+            let extracted = Decoder::new(
+                archive_path.as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            let mut files: Vec<_> = extracted.files.into_iter().collect();
+            files.sort();
+            assert_eq!(files, vec!["a.txt", "dir/b.txt"]);
+        }
+    }
+
+    #[test]
+    fn archive_prefix_test() {
+        let _ = std::fs::remove_dir_all("tmp/archive_prefix");
+        std::fs::create_dir_all("tmp/archive_prefix/input/dir").unwrap();
+        std::fs::write("tmp/archive_prefix/input/a.txt", "a").unwrap();
+        std::fs::write("tmp/archive_prefix/input/dir/b.txt", "b").unwrap();
+        std::fs::write("tmp/archive_prefix/input/c.log", "c").unwrap();
+        let (output_file_path, _) = CreateArchive::builder()
+            .input("tmp/archive_prefix/input")
+            .name("prefix")
+            .version("1.2.3")
+            .exclude("*.log")
+            .archive_prefix("myapp-1.2.3/")
+            .build()
+            .unwrap()
+            .create("tmp/archive_prefix", NoProgress)
+            .unwrap();
+
+        let extracted = Decoder::new(
+            output_file_path.as_str(),
+            None,
+            "tmp/archive_prefix/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        let mut files: Vec<_> = extracted.files.into_iter().collect();
+        files.sort();
+        assert_eq!(files, vec!["myapp-1.2.3/a.txt", "myapp-1.2.3/dir/b.txt"]);
+    }
+
+    #[test]
+    fn modified_since_test() {
+        let _ = std::fs::remove_dir_all("tmp/modified_since");
+        std::fs::create_dir_all("tmp/modified_since/input").unwrap();
+        let now = std::time::SystemTime::now();
+        let hour = std::time::Duration::from_secs(3600);
+        for (name, modified) in [("old.txt", now - 2 * hour), ("new.txt", now)] {
+            let path = format!("tmp/modified_since/input/{name}");
+            std::fs::write(path.as_str(), name).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(path.as_str())
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let files: Vec<String> = CreateArchive::builder()
+            .input("tmp/modified_since/input")
+            .name("since")
+            .version("1.0")
+            .modified_since(now - hour)
+            .build()
+            .unwrap()
+            .build_file_list()
+            .unwrap()
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect();
+        assert_eq!(files, vec!["new.txt"]);
+    }
+
+    #[test]
+    fn deduplicate_test() {
+        let _ = std::fs::remove_dir_all("tmp/deduplicate");
+        std::fs::create_dir_all("tmp/deduplicate/input/b").unwrap();
+        let runtime = "runtime".repeat(10_000);
+        std::fs::write("tmp/deduplicate/input/a.bin", runtime.as_str()).unwrap();
+        std::fs::write("tmp/deduplicate/input/b/a.bin", runtime.as_str()).unwrap();
+        std::fs::write("tmp/deduplicate/input/c.txt", "c").unwrap();
+        let (output_file_path, _) = CreateArchive::builder()
+            .input("tmp/deduplicate/input")
+            .name("dedup")
+            .version("1.0")
+            .driver(driver::Driver::Gzip)
+            .deduplicate(true)
+            .build()
+            .unwrap()
+            .create("tmp/deduplicate", NoProgress)
+            .unwrap();
+
+        let contents = memory::tar_contents(
+            driver::Driver::Gzip,
+            std::fs::read(output_file_path.as_str()).unwrap().as_slice(),
+        )
+        .unwrap();
+        let links = tar::Archive::new(contents.as_slice())
+            .entries()
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().header().entry_type().is_hard_link())
+            .count();
+        assert_eq!(links, 1);
+
+        Decoder::new(
+            output_file_path.as_str(),
+            None,
+            "tmp/deduplicate/output",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        for path in ["a.bin", "b/a.bin"] {
+            let extracted =
+                std::fs::read_to_string(format!("tmp/deduplicate/output/{path}")).unwrap();
+            assert_eq!(extracted, runtime);
+        }
+    }
+
+    #[test]
+    fn skip_unreadable_test() {
+        let _ = std::fs::remove_dir_all("tmp/skip_unreadable");
+        std::fs::create_dir_all("tmp/skip_unreadable/input").unwrap();
+        std::fs::write("tmp/skip_unreadable/input/a.txt", "a").unwrap();
+        let builder = CreateArchive::builder()
+            .input("tmp/skip_unreadable/input")
+            .name("skip")
+            .version("1.0")
+            .file_list(FileList::Files(vec![
+                "tmp/skip_unreadable/input/a.txt".to_string(),
+                "tmp/skip_unreadable/input/gone.txt".to_string(),
+            ]));
+        assert!(builder
+            .clone()
+            .build()
+            .unwrap()
+            .create("tmp/skip_unreadable", NoProgress)
+            .is_err());
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (output_file_path, _) = builder
+            .skip_unreadable(true)
+            .build()
+            .unwrap()
+            .create("tmp/skip_unreadable", sender)
+            .unwrap();
+        let skipped: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                ProgressEvent::EntrySkipped { archive_path, .. } => Some(archive_path),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(skipped, vec!["gone.txt"]);
+        let digests = Decoder::new(
+            output_file_path.as_str(),
+            None,
+            "tmp/skip_unreadable/unused",
+            NoProgress,
+        )
+        .unwrap()
+        .entry_digests()
+        .unwrap();
+        assert_eq!(digests.into_keys().collect::<Vec<_>>(), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn retry_test() {
+        let retry = RetryPolicy::new(3, std::time::Duration::from_millis(1));
+        let mut attempts = 0;
+        let result = retry.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(std::io::Error::from(std::io::ErrorKind::TimedOut)).context(format_context!(""))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry.run(|| {
+            attempts += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound)).context(format_context!(""))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn empty_input_test() {
+        let _ = std::fs::remove_dir_all("tmp/empty_input");
+        std::fs::create_dir_all("tmp/empty_input/input").unwrap();
+        let mut create_archive = CreateArchive::builder()
+            .input("tmp/empty_input/input")
+            .name("empty")
+            .version("1.0")
+            .build()
+            .unwrap();
+        assert!(create_archive
+            .create("tmp/empty_input", NoProgress)
+            .is_err());
+        create_archive.allow_empty = true;
+        create_archive
+            .create("tmp/empty_input", NoProgress)
+            .unwrap();
+
+        create_archive.input = "tmp/empty_input/missing".to_string();
+        let error = create_archive
+            .create("tmp/empty_input", NoProgress)
+            .unwrap_err();
+        assert!(matches!(Error::find(&error), Some(Error::Io { .. })));
+    }
+
+    #[test]
+    fn free_space_test() {
+        let _ = std::fs::remove_dir_all("tmp/free_space");
+        std::fs::create_dir_all("tmp/free_space").unwrap();
+        decoder::check_free_space("tmp/free_space/not/created", 1).unwrap();
+        let error = decoder::check_free_space("tmp/free_space/not/created", u64::MAX).unwrap_err();
+        let Some(Error::InsufficientSpace { needed, .. }) = Error::find(&error) else {
+            panic!("expected insufficient space: {error:?}");
+        };
+        assert_eq!(*needed, u64::MAX);
+        assert!(format!("{error:#}").contains("need 18446744.1 TB, have "));
+    }
+
+    #[test]
+    fn tar_format_test() {
+        let _ = std::fs::remove_dir_all("tmp/tar_format");
+        std::fs::create_dir_all("tmp/tar_format").unwrap();
+        let long_name = format!("{}.txt", "a".repeat(120));
+        for format in [TarFormat::Ustar, TarFormat::Gnu, TarFormat::Pax] {
+            let output_filename = format!("{format:?}.tar.gz");
+            let mut encoder =
+                encoder::Encoder::new("tmp/tar_format", output_filename.as_str(), NoProgress)
+                    .unwrap()
+                    .with_tar_format(format);
+            encoder.add_data(long_name.as_str(), b"long").unwrap();
+            encoder.compress().unwrap();
+
+            let archive_path = format!("tmp/tar_format/{output_filename}");
+            let file = std::fs::File::open(archive_path.as_str()).unwrap();
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            let mut entries = archive.entries().unwrap().raw(true);
+            let first = entries.next().unwrap().unwrap();
+            let expected = match format {
+                TarFormat::Pax => tar::EntryType::XHeader,
+                _ => tar::EntryType::GNULongName,
+            };
+            assert_eq!(first.header().entry_type(), expected);
+            drop(first);
+            let entry = entries.next().unwrap().unwrap();
+            assert_eq!(
+                entry.header().as_ustar().is_some(),
+                format != TarFormat::Gnu
+            );
+
+            let output_directory = format!("tmp/tar_format/{format:?}");
+            Decoder::new(
+                archive_path.as_str(),
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .extract()
+            .unwrap();
+            let extracted = std::path::Path::new(output_directory.as_str()).join(&long_name);
+            assert_eq!(std::fs::read_to_string(extracted).unwrap(), "long");
+        }
+    }
+
+    #[test]
+    fn strict_ustar_test() {
+        let _ = std::fs::remove_dir_all("tmp/strict_ustar");
+        std::fs::create_dir_all("tmp/strict_ustar").unwrap();
+        let mut encoder = encoder::Encoder::new("tmp/strict_ustar", "strict.tar.gz", NoProgress)
+            .unwrap()
+            .with_tar_format(TarFormat::UstarStrict);
+        let nested = format!("{}/{}.txt", "d".repeat(120), "f".repeat(90));
+        encoder.add_data(nested.as_str(), b"fits").unwrap();
+        let error = encoder
+            .add_data(format!("{}.txt", "a".repeat(120)).as_str(), b"long")
+            .unwrap_err();
+        assert!(format!("{error:?}").contains("strict ustar"));
+
+        let result = encoder::Encoder::new("tmp/strict_ustar", "comment.tar.gz", NoProgress)
+            .unwrap()
+            .with_tar_format(TarFormat::UstarStrict)
+            .with_comment("build 42")
+            .add_data("a.txt", b"a");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn list_test() {
+        let _ = std::fs::remove_dir_all("tmp/list_test");
+        std::fs::create_dir_all("tmp/list_test").unwrap();
+        for driver in [
+            driver::Driver::Gzip,
+            driver::Driver::Zip,
+            driver::Driver::SevenZ,
+        ] {
+            let output_filename = format!("list.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/list_test", output_filename.as_str(), NoProgress)
+                    .unwrap()
+                    .with_comment("build 42")
+                    .with_pax_global("BUILD.id", "42");
+            encoder.add_data("a.txt", b"abc").unwrap();
+            encoder.compress().unwrap();
+
+            let listing = Decoder::new(
+                format!("tmp/list_test/{output_filename}").as_str(),
+                None,
+                "tmp/list_test/unused",
+                NoProgress,
+            )
+            .unwrap()
+            .list()
+            .unwrap();
+            assert_eq!(listing.comment.as_deref(), Some("build 42"));
+            assert_eq!(listing.entries.len(), 1);
+            assert_eq!(listing.entries[0].archive_path, "a.txt");
+            assert_eq!(listing.entries[0].size, 3);
+            if driver != driver::Driver::Zip {
+                assert!(listing
+                    .pax_globals
+                    .contains(&("BUILD.id".to_string(), "42".to_string())));
+            }
+
+            let metadata = Decoder::new(
+                format!("tmp/list_test/{output_filename}").as_str(),
+                None,
+                "tmp/list_test/unused",
+                NoProgress,
+            )
+            .unwrap()
+            .metadata()
+            .unwrap();
+            assert_eq!(metadata.entry_count, 1);
+            assert_eq!(metadata.uncompressed_size, 3);
+            assert!(!metadata.is_zip64);
+            assert!(!metadata.is_encrypted);
+            if driver == driver::Driver::Zip {
+                assert_eq!(metadata.compression_methods, vec!["deflated"]);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_metadata_test() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::remove_dir_all("tmp/zip_metadata_test");
+        std::fs::create_dir_all("tmp/zip_metadata_test").unwrap();
+
+        let file = std::fs::File::create("tmp/zip_metadata_test/tool.zip").unwrap();
+        let mut writer = zip::ZipWriter::new(file);
+        let modified = zip::DateTime::from_date_and_time(2020, 1, 2, 3, 4, 6).unwrap();
+        let options = zip::write::SimpleFileOptions::default()
+            .unix_permissions(0o755)
+            .last_modified_time(modified);
+        writer.start_file("bin/tool", options).unwrap();
+        writer.write_all(b"#!/bin/sh").unwrap();
+        writer.finish().unwrap();
+
+        for restore in [true, false] {
+            let output_directory = format!("tmp/zip_metadata_test/{restore}");
+            Decoder::new(
+                "tmp/zip_metadata_test/tool.zip",
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_options(decoder::ExtractOptions {
+                restore_permissions: restore,
+                restore_mtime: restore,
+                ..Default::default()
+            })
+            .extract()
+            .unwrap();
+
+            let metadata = std::fs::metadata(format!("{output_directory}/bin/tool")).unwrap();
+            let mtime = metadata
+                .modified()
+                .unwrap()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            assert_eq!(mtime == 1577934246, restore);
+            assert_eq!(metadata.permissions().mode() & 0o777 == 0o755, restore);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_mtime_test() {
+        let _ = std::fs::remove_dir_all("tmp/directory_mtime_test");
+        std::fs::create_dir_all("tmp/directory_mtime_test").unwrap();
+
+        let mut archiver = tar::Builder::new(Vec::new());
+        for (path, mtime) in [("outer/", 1_000_000_000), ("outer/inner/", 1_100_000_000)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            header.set_mtime(mtime);
+            archiver
+                .append_data(&mut header, path, std::io::empty())
+                .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        archiver
+            .append_data(&mut header, "outer/inner/a.txt", b"a".as_slice())
+            .unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, archiver.into_inner().unwrap().as_slice()).unwrap();
+        std::fs::write(
+            "tmp/directory_mtime_test/dirs.tar.gz",
+            encoder.finish().unwrap(),
+        )
+        .unwrap();
+
+        Decoder::new(
+            "tmp/directory_mtime_test/dirs.tar.gz",
+            None,
+            "tmp/directory_mtime_test/out",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            restore_directory_mtimes: true,
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+
+        let mtime = |path: &str| {
+            std::fs::metadata(path)
+                .unwrap()
+                .modified()
+                .unwrap()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        assert_eq!(mtime("tmp/directory_mtime_test/out/outer"), 1_000_000_000);
+        assert_eq!(
+            mtime("tmp/directory_mtime_test/out/outer/inner"),
+            1_100_000_000
+        );
+    }
+
+    /// A tar.gz at `archive_path` with `(path, Some(target))` symlinks and
+    /// `(path, None)` files holding `path`.
+    #[cfg(unix)]
+    fn write_symlink_archive(archive_path: &str, entries: &[(&str, Option<&str>)]) {
+        let mut archiver = tar::Builder::new(Vec::new());
+        for (path, target) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            match target {
+                Some(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    archiver.append_link(&mut header, path, target).unwrap();
+                }
+                None => {
+                    header.set_size(path.len() as u64);
+                    archiver
+                        .append_data(&mut header, path, path.as_bytes())
+                        .unwrap();
+                }
+            }
+        }
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, archiver.into_inner().unwrap().as_slice()).unwrap();
+        std::fs::write(archive_path, encoder.finish().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape_test() {
+        let _ = std::fs::remove_dir_all("tmp/symlink_escape");
+        std::fs::create_dir_all("tmp/symlink_escape/outside").unwrap();
+        std::fs::write("tmp/symlink_escape/outside/x", "original").unwrap();
+        let outside = std::fs::canonicalize("tmp/symlink_escape/outside").unwrap();
+        let outside_file = outside.join("x");
+        write_symlink_archive(
+            "tmp/symlink_escape/escape.tar.gz",
+            &[
+                ("a", Some(outside_file.to_str().unwrap())),
+                ("a", None),
+                ("d", Some(outside.to_str().unwrap())),
+                ("d/new/f", None),
+            ],
+        );
+
+        // `d/new/f` is skipped with a warning or fails depending on the
+        // unpack path; either way nothing is written through the symlinks
+        let _ = Decoder::new(
+            "tmp/symlink_escape/escape.tar.gz",
+            None,
+            "tmp/symlink_escape/out",
+            NoProgress,
+        )
+        .unwrap()
+        .extract();
+        assert_eq!(
+            std::fs::read_to_string("tmp/symlink_escape/outside/x").unwrap(),
+            "original"
+        );
+        assert_eq!(
+            std::fs::read_to_string("tmp/symlink_escape/out/a").unwrap(),
+            "a"
+        );
+        assert!(!std::path::Path::new("tmp/symlink_escape/out/a").is_symlink());
+        assert!(!outside.join("new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn update_symlink_escape_test() {
+        let _ = std::fs::remove_dir_all("tmp/update_symlink_escape");
+        std::fs::create_dir_all("tmp/update_symlink_escape/outside").unwrap();
+        let outside = std::fs::canonicalize("tmp/update_symlink_escape/outside").unwrap();
+        write_symlink_archive(
+            "tmp/update_symlink_escape/escape.tar.gz",
+            &[("d", Some(outside.to_str().unwrap())), ("d/passwd", None)],
+        );
+
+        for update in [decoder::UpdatePolicy::Changed, decoder::UpdatePolicy::Newer] {
+            // same size as the entry so `Changed` would rewrite it in place
+            std::fs::write(outside.join("passwd"), "ORIGINAL").unwrap();
+            let output_directory = format!("tmp/update_symlink_escape/{update:?}");
+            let extracted = Decoder::new(
+                "tmp/update_symlink_escape/escape.tar.gz",
+                None,
+                output_directory.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_options(decoder::ExtractOptions {
+                update,
+                ..Default::default()
+            })
+            .extract()
+            .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(outside.join("passwd")).unwrap(),
+                "ORIGINAL"
+            );
+            assert!(extracted
+                .warnings
+                .iter()
+                .any(|warning| warning.path == "d/passwd"
+                    && warning.kind == decoder::EntryIssueKind::UnsafePath));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn merge_symlink_escape_test() {
+        let _ = std::fs::remove_dir_all("tmp/merge_symlink_escape");
+        std::fs::create_dir_all("tmp/merge_symlink_escape/outside").unwrap();
+        std::fs::write("tmp/merge_symlink_escape/outside/secret", "secret").unwrap();
+        let outside = std::fs::canonicalize("tmp/merge_symlink_escape/outside").unwrap();
+        write_symlink_archive(
+            "tmp/merge_symlink_escape/escape.tar.gz",
+            &[("d", Some(outside.to_str().unwrap())), ("d/secret", None)],
+        );
+
+        let extracted = Decoder::new(
+            "tmp/merge_symlink_escape/escape.tar.gz",
+            None,
+            "tmp/merge_symlink_escape/output",
+            NoProgress,
+        )
+        .unwrap()
+        .with_options(decoder::ExtractOptions {
+            merge: true,
+            report_conflicts: true,
+            ..Default::default()
+        })
+        .extract()
+        .unwrap();
+        // the file outside the output is neither compared nor reported
+        assert!(extracted.conflicts.is_empty());
+        assert!(extracted
+            .warnings
+            .iter()
+            .any(|warning| warning.path == "d/secret"
+                && warning.kind == decoder::EntryIssueKind::UnsafePath));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_policy_test() {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::remove_dir_all("tmp/mode_policy_test");
+        std::fs::create_dir_all("tmp/mode_policy_test").unwrap();
+        for driver in [driver::Driver::Gzip, driver::Driver::Zip] {
+            let output_filename = format!("modes.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/mode_policy_test", output_filename.as_str(), NoProgress)
+                    .unwrap();
+            encoder.add_data("dir/a.txt", b"a").unwrap();
+            encoder.compress().unwrap();
+
+            for (mode_policy, expected) in [
+                (
+                    decoder::ModePolicy::Fixed {
+                        file: 0o600,
+                        directory: 0o700,
+                    },
+                    0o600,
+                ),
+                (decoder::ModePolicy::Umask(0o077), 0o600),
+            ] {
+                let output_directory = format!("tmp/mode_policy_test/{}", driver.extension());
+                let _ = std::fs::remove_dir_all(output_directory.as_str());
+                Decoder::new(
+                    format!("tmp/mode_policy_test/{output_filename}").as_str(),
+                    None,
+                    output_directory.as_str(),
+                    NoProgress,
+                )
+                .unwrap()
+                .with_options(decoder::ExtractOptions {
+                    mode_policy,
+                    ..Default::default()
+                })
+                .extract()
+                .unwrap();
+                let mode = std::fs::metadata(format!("{output_directory}/dir/a.txt"))
+                    .unwrap()
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o777, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn paths_test() {
+        assert_eq!(
+            paths::to_archive_path(std::path::Path::new("./dir/a.txt")),
+            "dir/a.txt"
+        );
+        let joined = paths::join("out", "/dir/a.txt");
+        assert!(std::path::Path::new(joined.as_str()).starts_with("out"));
+        assert!(joined.ends_with("a.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_name_test() {
+        use std::os::unix::ffi::OsStrExt;
+        let _ = std::fs::remove_dir_all("tmp/non_utf8");
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        let input = std::path::Path::new("tmp/non_utf8/input");
+        std::fs::create_dir_all(input).unwrap();
+        std::fs::write(input.join(name), "latin-1").unwrap();
+
+        let entries = vec![encoder::Entry::new(name, input.join(name))];
+        let mut encoder =
+            encoder::Encoder::new("tmp/non_utf8", "non_utf8.tar.gz", NoProgress).unwrap();
+        encoder.add_entries(&entries).unwrap();
+        encoder.compress().unwrap();
+
+        decoder::Decoder::new(
+            "tmp/non_utf8/non_utf8.tar.gz",
+            None,
+            "tmp/non_utf8/extracted",
+            NoProgress,
+        )
+        .unwrap()
+        .extract()
+        .unwrap();
+        let extracted = std::path::Path::new("tmp/non_utf8/extracted").join(name);
+        assert_eq!(std::fs::read_to_string(extracted).unwrap(), "latin-1");
+    }
+
+    #[test]
+    fn memory_test() {
+        let entries = vec![
+            memory::MemoryEntry {
+                archive_path: "a.txt".to_string(),
+                contents: b"a".repeat(1000),
+            },
+            memory::MemoryEntry {
+                archive_path: "dir/b.txt".to_string(),
+                contents: Vec::new(),
+            },
+        ];
+        for driver in [
+            driver::Driver::Gzip,
+            driver::Driver::Bzip2,
+            driver::Driver::Zip,
+            driver::Driver::SevenZ,
+            driver::Driver::Xz,
+        ] {
+            let archive = memory::encode(driver, &entries).unwrap();
+            assert_eq!(memory::decode(driver, archive.as_slice()).unwrap(), entries);
+        }
+    }
+
+    #[test]
+    fn small_archive_test() {
+        std::fs::create_dir_all("tmp/small").unwrap();
+        std::fs::write("tmp/small/small.txt", "small").unwrap();
+        let entries = vec![encoder::Entry::new("small.txt", "tmp/small/small.txt")];
+
+        let mut encoder = encoder::Encoder::new("tmp/small", "small.tar.gz", NoProgress)
+            .unwrap()
+            .with_buffer_size(16);
+        encoder.add_entries(&entries).unwrap();
+        encoder.compress().unwrap();
+
+        let extracted = decoder::Decoder::new(
+            "tmp/small/small.tar.gz",
+            None,
+            "tmp/small/extracted",
+            NoProgress,
+        )
+        .unwrap()
+        .with_buffer_size(16)
+        .extract()
+        .unwrap();
+        assert_eq!(extracted.stats.files, 1);
+        assert_eq!(
+            std::fs::read_to_string("tmp/small/extracted/small.txt").unwrap(),
+            "small"
+        );
+
+        std::fs::write("tmp/small/image.png", "not really a png").unwrap();
+        let entries = vec![
+            encoder::Entry::new("small.txt", "tmp/small/small.txt"),
+            encoder::Entry::new("image.png", "tmp/small/image.png"),
+        ];
+        let mut encoder = encoder::Encoder::new("tmp/small", "small.zip", NoProgress).unwrap();
+        encoder.add_entries(&entries).unwrap();
+        encoder.compress().unwrap();
+        let mut zip_archive =
+            zip::ZipArchive::new(std::fs::File::open("tmp/small/small.zip").unwrap()).unwrap();
+        assert_eq!(
+            zip_archive.by_name("image.png").unwrap().compression(),
+            zip::CompressionMethod::Stored
+        );
+        assert_eq!(
+            zip_archive.by_name("small.txt").unwrap().compression(),
+            zip::CompressionMethod::Deflated
+        );
+    }
+
+    #[test]
+    fn compress_test() {
+        let entries = generate_tmp_files();
+
+        let mut printer = printer::Printer::new_stdout();
+
+        const DRIVERS: &[driver::Driver] = &[
+            driver::Driver::Gzip,
+            driver::Driver::Bzip2,
+            driver::Driver::Zip,
+            driver::Driver::SevenZ,
+            driver::Driver::Xz,
+        ];
+
+        let mut multi_progress = printer::MultiProgress::new(&mut printer);
+
+        for driver in DRIVERS {
+            let output_directory = "./tmp";
+            let output_filename = format!("test.{}", driver.extension());
+
+            let progress_bar = multi_progress.add_progress(&driver.extension(), Some(100), None);
+
+            let mut encoder =
+                encoder::Encoder::new(output_directory, &output_filename, progress_bar).unwrap();
+
+            encoder.add_entries(&entries).unwrap();
+
+            let _digest = encoder.compress().unwrap().digest().unwrap();
+        }
+
+        for driver in DRIVERS {
+            let output_dir = format!("tmp/extract_test.{}", driver.extension());
+            std::fs::create_dir_all(output_dir.as_str()).unwrap();
+
+            let archive_path_string = format!("tmp/test.{}", driver.extension());
+
+            let digest = {
+                let contents = std::fs::read(archive_path_string.as_str()).unwrap();
+                driver::sha256_reader(contents.as_slice(), &driver::CancellationToken::default())
+                    .unwrap()
+            };
+
+            let progress_bar = multi_progress.add_progress(&driver.extension(), Some(100), None);
 
             let decoder = decoder::Decoder::new(
                 archive_path_string.as_str(),
@@ -302,7 +4757,45 @@ mod tests {
                 progress_bar,
             )
             .unwrap();
-            decoder.extract().unwrap();
+            let extracted = decoder.extract().unwrap();
+            assert_eq!(extracted.stats.files, FILE_COUNT as u64);
+
+            verify_generated_files(output_dir.as_str());
+        }
+    }
+
+    #[test]
+    fn threaded_extract_test() {
+        let _ = std::fs::remove_dir_all("tmp/threaded_extract");
+        std::fs::create_dir_all("tmp/threaded_extract").unwrap();
+        let contents: String = (0..LINE_COUNT)
+            .map(|line| format!("This is line #{line}\n"))
+            .collect();
+
+        for driver in [driver::Driver::Gzip, driver::Driver::Xz] {
+            let output_filename = format!("test.{}", driver.extension());
+            let mut encoder =
+                encoder::Encoder::new("tmp/threaded_extract", &output_filename, NoProgress)
+                    .unwrap();
+            for i in 0..FILE_COUNT {
+                encoder
+                    .add_data(format!("file_{i}.txt").as_str(), contents.as_bytes())
+                    .unwrap();
+            }
+            encoder.compress().unwrap();
+
+            let output_dir = format!("tmp/threaded_extract/output.{}", driver.extension());
+            let extracted = decoder::Decoder::new(
+                format!("tmp/threaded_extract/{output_filename}").as_str(),
+                None,
+                output_dir.as_str(),
+                NoProgress,
+            )
+            .unwrap()
+            .with_threads(2)
+            .extract()
+            .unwrap();
+            assert_eq!(extracted.stats.files, FILE_COUNT as u64);
 
             verify_generated_files(output_dir.as_str());
         }
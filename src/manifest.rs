@@ -0,0 +1,48 @@
+use anyhow::Context;
+use anyhow_source_location::format_context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Name of the manifest entry written at the root of the archive.
+pub const MANIFEST_FILE_NAME: &str = "artifact.json";
+
+pub const DIGEST_ALGORITHM: &str = "sha256";
+
+/// Identifies an archive created by `CreateArchive` without extracting it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactManifest {
+    pub name: String,
+    pub version: String,
+    pub platform: Option<String>,
+    /// Algorithm used for the archive digest returned by `create`.
+    pub digest_algorithm: String,
+    /// Archive paths of the other entries, sorted.
+    pub files: Vec<String>,
+    /// sha256 of each regular file in `files`, keyed by archive path. Empty in
+    /// manifests written before checksums were added.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl ArtifactManifest {
+    pub fn new(name: &str, version: &str, platform: Option<&str>, files: &[String]) -> Self {
+        let mut files = files.to_vec();
+        files.sort();
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            platform: platform.map(|platform| platform.to_string()),
+            digest_algorithm: DIGEST_ALGORITHM.to_string(),
+            files,
+            checksums: BTreeMap::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).context(format_context!("{MANIFEST_FILE_NAME}"))
+    }
+
+    pub fn from_json(contents: &[u8]) -> anyhow::Result<Self> {
+        serde_json::from_slice(contents).context(format_context!("{MANIFEST_FILE_NAME}"))
+    }
+}
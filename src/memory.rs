@@ -0,0 +1,256 @@
+#[cfg(feature = "sevenz")]
+use crate::driver::SEVEN_Z_TAR_FILENAME;
+use crate::driver::{Driver, MAX_ENTRY_PREALLOCATION};
+use crate::Error;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use std::io::{Read, Write};
+
+/// A file held in memory rather than on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryEntry {
+    pub archive_path: String,
+    pub contents: Vec<u8>,
+}
+
+fn encode_tar(entries: &[MemoryEntry]) -> anyhow::Result<Vec<u8>> {
+    let mut archiver = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.contents.len() as u64);
+        header.set_mode(0o644);
+        archiver
+            .append_data(
+                &mut header,
+                entry.archive_path.as_str(),
+                entry.contents.as_slice(),
+            )
+            .context(format_context!("appending {}", entry.archive_path))?;
+    }
+    archiver.into_inner().context(format_context!(""))
+}
+
+#[cfg(feature = "zip")]
+fn encode_zip(entries: &[MemoryEntry]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o644);
+    for entry in entries {
+        encoder
+            .start_file(entry.archive_path.as_str(), options)
+            .context(format_context!("{}", entry.archive_path))?;
+        encoder
+            .write_all(entry.contents.as_slice())
+            .context(format_context!("{}", entry.archive_path))?;
+    }
+    let output = encoder.finish().context(format_context!(""))?;
+    Ok(output.into_inner())
+}
+
+/// Builds an archive of `entries` without touching the filesystem. The result
+/// has the same layout as an archive written by `Encoder`.
+pub fn encode(driver: Driver, entries: &[MemoryEntry]) -> anyhow::Result<Vec<u8>> {
+    driver.check_supported()?;
+    if driver.is_package() {
+        return Err(format_error!(
+            "{} packages can only be written to a file",
+            driver.extension()
+        ));
+    }
+    #[cfg(feature = "zip")]
+    if driver == Driver::Zip {
+        return encode_zip(entries).context(format_context!("zip"));
+    }
+
+    let contents = encode_tar(entries).context(format_context!("{driver:?}"))?;
+    match driver {
+        Driver::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(contents.as_slice())
+                .context(format_context!("{driver:?}"))?;
+            encoder.finish().context(format_context!("{driver:?}"))
+        }
+        #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+        Driver::Bzip2 => {
+            let mut encoder =
+                bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder
+                .write_all(contents.as_slice())
+                .context(format_context!("{driver:?}"))?;
+            encoder.finish().context(format_context!("{driver:?}"))
+        }
+        #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+        Driver::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 9);
+            encoder
+                .write_all(contents.as_slice())
+                .context(format_context!("{driver:?}"))?;
+            encoder.finish().context(format_context!("{driver:?}"))
+        }
+        #[cfg(not(all(feature = "bzip2", not(target_family = "wasm"))))]
+        Driver::Bzip2 => unreachable!("rejected by check_supported"),
+        #[cfg(not(all(feature = "xz", not(target_family = "wasm"))))]
+        Driver::Xz => unreachable!("rejected by check_supported"),
+        #[cfg(feature = "sevenz")]
+        Driver::SevenZ => {
+            let mut writer = sevenz_rust::SevenZWriter::new(std::io::Cursor::new(Vec::new()))
+                .context(format_context!("{driver:?}"))?;
+            let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+            entry.name = SEVEN_Z_TAR_FILENAME.to_string();
+            entry.has_stream = true;
+            entry.size = contents.len() as u64;
+            writer
+                .push_archive_entry(entry, Some(contents.as_slice()))
+                .context(format_context!("{driver:?}"))?;
+            let output = writer.finish().context(format_context!("{driver:?}"))?;
+            Ok(output.into_inner())
+        }
+        #[cfg(not(feature = "sevenz"))]
+        Driver::SevenZ => unreachable!("rejected by check_supported"),
+        Driver::Zip | Driver::Deb | Driver::Rpm => unreachable!("handled above"),
+    }
+}
+
+fn decode_tar(contents: &[u8]) -> anyhow::Result<Vec<MemoryEntry>> {
+    let mut archive = tar::Archive::new(contents);
+    let mut result = Vec::new();
+    for entry in archive.entries().context(format_context!(""))? {
+        let mut entry = entry.context(format_context!(""))?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let archive_path = entry
+            .path()
+            .context(format_context!(""))?
+            .to_string_lossy()
+            .to_string();
+        let mut contents = Vec::with_capacity(entry.size().min(MAX_ENTRY_PREALLOCATION) as usize);
+        entry
+            .read_to_end(&mut contents)
+            .context(format_context!("{archive_path}"))?;
+        result.push(MemoryEntry {
+            archive_path,
+            contents,
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(feature = "zip")]
+fn decode_zip(archive: &[u8]) -> anyhow::Result<Vec<MemoryEntry>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive))
+        .context(format_context!("open zip failed"))?;
+    let mut result = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        let mut zip_file = archive.by_index(index).context(format_context!(""))?;
+        if !zip_file.is_file() {
+            continue;
+        }
+        let archive_path = zip_file.name().to_string();
+        let mut contents =
+            Vec::with_capacity(zip_file.size().min(MAX_ENTRY_PREALLOCATION) as usize);
+        zip_file
+            .read_to_end(&mut contents)
+            .context(format_context!("{archive_path}"))?;
+        result.push(MemoryEntry {
+            archive_path,
+            contents,
+        });
+    }
+    Ok(result)
+}
+
+/// Reads the tar stream that `Encoder` stores inside `tar.7z` archives.
+#[cfg(feature = "sevenz")]
+pub(crate) fn seven_z_tar_contents<Reader: Read + std::io::Seek>(
+    mut source: Reader,
+) -> anyhow::Result<Vec<u8>> {
+    let size = source
+        .seek(std::io::SeekFrom::End(0))
+        .context(format_context!(""))?;
+    source.rewind().context(format_context!(""))?;
+    let mut reader = sevenz_rust::SevenZReader::new(source, size, sevenz_rust::Password::empty())
+        .context(format_context!("open 7z failed"))?;
+
+    let mut contents = None;
+    reader
+        .for_each_entries(|entry, reader| {
+            if entry.name() == SEVEN_Z_TAR_FILENAME {
+                let mut buffer =
+                    Vec::with_capacity(entry.size().min(MAX_ENTRY_PREALLOCATION) as usize);
+                reader.read_to_end(&mut buffer)?;
+                contents = Some(buffer);
+            } else {
+                std::io::copy(reader, &mut std::io::sink())?;
+            }
+            Ok(true)
+        })
+        .context(format_context!(""))?;
+    contents.ok_or(format_error!(
+        "{SEVEN_Z_TAR_FILENAME} not found in 7z archive"
+    ))
+}
+
+#[cfg(not(feature = "sevenz"))]
+pub(crate) fn seven_z_tar_contents<Reader: Read + std::io::Seek>(
+    _source: Reader,
+) -> anyhow::Result<Vec<u8>> {
+    Driver::SevenZ.check_supported()?;
+    unreachable!("rejected by check_supported")
+}
+
+/// Reads the regular files in `archive` without touching the filesystem.
+pub fn decode(driver: Driver, archive: &[u8]) -> anyhow::Result<Vec<MemoryEntry>> {
+    #[cfg(feature = "zip")]
+    if driver == Driver::Zip {
+        driver.check_extractable()?;
+        return decode_zip(archive).context(format_context!("zip"));
+    }
+    let contents = tar_contents(driver, archive)?;
+    decode_tar(contents.as_slice()).context(format_context!("{driver:?}"))
+}
+
+/// The decompressed tar stream of a tar based or 7z archive.
+pub(crate) fn tar_contents(driver: Driver, archive: &[u8]) -> anyhow::Result<Vec<u8>> {
+    driver.check_extractable()?;
+    let mut contents = Vec::new();
+    match driver {
+        Driver::Gzip => {
+            flate2::read::MultiGzDecoder::new(archive)
+                .read_to_end(&mut contents)
+                .context(format_context!("{driver:?}"))?;
+        }
+        #[cfg(all(feature = "bzip2", not(target_family = "wasm")))]
+        Driver::Bzip2 => {
+            bzip2::read::BzDecoder::new(archive)
+                .read_to_end(&mut contents)
+                .context(format_context!("{driver:?}"))?;
+        }
+        #[cfg(all(feature = "xz", not(target_family = "wasm")))]
+        Driver::Xz => {
+            xz2::read::XzDecoder::new(archive)
+                .read_to_end(&mut contents)
+                .context(format_context!("{driver:?}"))?;
+        }
+        #[cfg(not(all(feature = "bzip2", not(target_family = "wasm"))))]
+        Driver::Bzip2 => unreachable!("rejected by check_supported"),
+        #[cfg(not(all(feature = "xz", not(target_family = "wasm"))))]
+        Driver::Xz => unreachable!("rejected by check_supported"),
+        Driver::Zip => {
+            return Err(Error::UnsupportedFormat {
+                driver,
+                reason: "zip archives don't contain a tar stream".to_string(),
+            }
+            .into())
+        }
+        Driver::Deb | Driver::Rpm => unreachable!("rejected by check_extractable"),
+        Driver::SevenZ => {
+            contents = seven_z_tar_contents(std::io::Cursor::new(archive))
+                .context(format_context!("{driver:?}"))?;
+        }
+    }
+    Ok(contents)
+}
@@ -0,0 +1,211 @@
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use futures::StreamExt;
+use object_store::{ObjectStore, PutPayload};
+use sha2::Digest;
+use std::io::Read;
+use std::sync::mpsc;
+
+/// S3 requires every part except the last to be at least 5 MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// An object addressed by a URI such as `s3://bucket/key`, `gs://bucket/key`
+/// or `file:///path`. Credentials and regions are read from the environment
+/// (`AWS_ACCESS_KEY_ID`, `GOOGLE_SERVICE_ACCOUNT`, ...).
+///
+/// The object store API is async, so each location runs its own
+/// single-threaded tokio runtime. Don't use it from inside another runtime.
+pub(crate) struct ObjectLocation {
+    uri: String,
+    runtime: tokio::runtime::Runtime,
+    store: Box<dyn ObjectStore>,
+    path: object_store::path::Path,
+}
+
+impl ObjectLocation {
+    pub(crate) fn parse(uri: &str) -> anyhow::Result<Self> {
+        let url =
+            url::Url::parse(uri).context(format_context!("invalid object store URI {uri}"))?;
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, path) = object_store::parse_url_opts(&url, options)
+            .context(format_context!("unsupported object store URI {uri}"))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context(format_context!("failed to start a runtime for {uri}"))?;
+        Ok(Self {
+            uri: uri.to_string(),
+            runtime,
+            store,
+            path,
+        })
+    }
+
+    /// Streams the object's contents.
+    pub(crate) fn reader(self) -> anyhow::Result<ObjectReader> {
+        let result = self
+            .runtime
+            .block_on(self.store.get(&self.path))
+            .context(format_context!("failed to get {}", self.uri))?;
+        let size = result.meta.size;
+        Ok(ObjectReader {
+            stream: result.into_stream(),
+            chunk: bytes::Bytes::new(),
+            position: 0,
+            size,
+            location: self,
+        })
+    }
+}
+
+pub(crate) struct ObjectReader {
+    location: ObjectLocation,
+    stream: futures::stream::BoxStream<'static, object_store::Result<bytes::Bytes>>,
+    chunk: bytes::Bytes,
+    position: usize,
+    size: u64,
+}
+
+impl ObjectReader {
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.chunk.len() {
+            match self.location.runtime.block_on(self.stream.next()) {
+                Some(chunk) => {
+                    self.chunk = chunk.map_err(std::io::Error::other)?;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let count = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Uploads a file with a multipart upload while another thread is still
+/// appending to it. Call `finish` once the file is complete, or `abort`.
+pub(crate) struct Upload {
+    /// Sends the sha256 of the complete file; dropping it aborts the upload.
+    finished: mpsc::Sender<String>,
+    handle: std::thread::JoinHandle<anyhow::Result<()>>,
+}
+
+impl Upload {
+    pub(crate) fn start(local_path: &str, location: ObjectLocation) -> Self {
+        let (finished, thread_finished) = mpsc::channel();
+        let local_path = local_path.to_string();
+        let handle = std::thread::spawn(move || {
+            upload_growing_file(&local_path, location, &thread_finished)
+        });
+        Self { finished, handle }
+    }
+
+    /// Uploads the rest of the file and completes the upload if what was
+    /// uploaded hashes to `sha256`. Otherwise the upload is aborted.
+    pub(crate) fn finish(self, sha256: &str) -> anyhow::Result<()> {
+        // a closed channel means the thread already failed and reports why
+        let _ = self.finished.send(sha256.to_string());
+        self.handle
+            .join()
+            .map_err(|err| format_error!("failed to join upload thread: {:?}", err))?
+    }
+
+    /// Stops uploading and discards the parts uploaded so far.
+    pub(crate) fn abort(self) {
+        drop(self.finished);
+        let _ = self.handle.join();
+    }
+}
+
+fn upload_growing_file(
+    local_path: &str,
+    location: ObjectLocation,
+    finished: &mpsc::Receiver<String>,
+) -> anyhow::Result<()> {
+    let mut upload = location
+        .runtime
+        .block_on(location.store.put_multipart(&location.path))
+        .context(format_context!(
+            "failed to start upload to {}",
+            location.uri
+        ))?;
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut file = None;
+        let mut part = Vec::with_capacity(PART_SIZE);
+        let mut buffer = vec![0; 64 * 1024];
+        let mut hasher = sha2::Sha256::new();
+        let expected = loop {
+            // check before reading so the final pass sees everything written
+            let expected = match finished.try_recv() {
+                Ok(expected) => Some(expected),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    return Err(format_error!("upload of {local_path} was aborted"));
+                }
+            };
+            if file.is_none() {
+                file = std::fs::File::open(local_path).ok();
+            }
+            if let Some(file) = file.as_mut() {
+                loop {
+                    let bytes_read = file
+                        .read(buffer.as_mut_slice())
+                        .context(format_context!("{local_path}"))?;
+                    if bytes_read == 0 {
+                        break;
+                    }
+                    hasher.update(&buffer[..bytes_read]);
+                    part.extend_from_slice(&buffer[..bytes_read]);
+                    if part.len() >= PART_SIZE {
+                        let payload = PutPayload::from(std::mem::take(&mut part));
+                        location
+                            .runtime
+                            .block_on(upload.put_part(payload))
+                            .context(format_context!("failed to upload part of {local_path}"))?;
+                    }
+                }
+            }
+            if let Some(expected) = expected {
+                break expected;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        };
+        if file.is_none() {
+            return Err(format_error!("{local_path} was never created"));
+        }
+        let actual = format!("{:x}", hasher.finalize());
+        if actual != expected {
+            return Err(crate::Error::DigestMismatch {
+                name: format!("{local_path} as uploaded"),
+                expected,
+                actual,
+            }
+            .into());
+        }
+        if !part.is_empty() {
+            location
+                .runtime
+                .block_on(upload.put_part(PutPayload::from(part)))
+                .context(format_context!("failed to upload part of {local_path}"))?;
+        }
+        location
+            .runtime
+            .block_on(upload.complete())
+            .context(format_context!("failed to complete upload of {local_path}"))?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = location.runtime.block_on(upload.abort());
+    }
+    result.context(format_context!("{}", location.uri))
+}
@@ -0,0 +1,259 @@
+use anyhow::Context;
+use anyhow_source_location::format_context;
+#[cfg(feature = "rpm")]
+use anyhow_source_location::format_error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::Write;
+
+/// Package metadata for the `deb` and `rpm` drivers. `CreateArchive` fills in
+/// the name, version and platform.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    /// Mapped to a package architecture (`x86_64` becomes `amd64` for deb).
+    /// Packages without a recognized platform are architecture independent.
+    pub platform: Option<String>,
+    pub maintainer: Option<String>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+}
+
+impl PackageInfo {
+    pub(crate) fn from_file_name(file_name: &str) -> Self {
+        let name = file_name
+            .trim_end_matches(".deb")
+            .trim_end_matches(".rpm")
+            .to_string();
+        Self {
+            name,
+            version: "0".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn description(&self) -> String {
+        self.description.clone().unwrap_or(self.name.clone())
+    }
+
+    /// `-` separates the revision in both formats, so a pre-release such as
+    /// `1.0-rc1` becomes `1.0~rc1`, which also sorts before `1.0`.
+    fn package_version(&self) -> String {
+        self.version.replace('-', "~").replace('_', ".")
+    }
+
+    fn architecture(&self, is_deb: bool) -> &'static str {
+        let platform = self.platform.clone().unwrap_or_default().to_lowercase();
+        let matches = |names: &[&str]| names.iter().any(|name| platform.contains(name));
+        let (deb, rpm) = if matches(&["x86_64", "amd64", "x64"]) {
+            ("amd64", "x86_64")
+        } else if matches(&["aarch64", "arm64"]) {
+            ("arm64", "aarch64")
+        } else if matches(&["armv7", "armhf"]) {
+            ("armhf", "armv7hl")
+        } else if matches(&["riscv64"]) {
+            ("riscv64", "riscv64")
+        } else {
+            ("all", "noarch")
+        };
+        if is_deb {
+            deb
+        } else {
+            rpm
+        }
+    }
+}
+
+pub(crate) enum PackageSource {
+    File(std::path::PathBuf),
+    Data(Vec<u8>),
+}
+
+/// A file to install at `/{archive_path}`.
+pub(crate) struct PackageEntry {
+    pub(crate) archive_path: String,
+    pub(crate) source: PackageSource,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn gzip_tar(archiver: tar::Builder<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    let contents = archiver.into_inner().context(format_context!(""))?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(contents.as_slice())
+        .context(format_context!(""))?;
+    encoder.finish().context(format_context!(""))
+}
+
+fn append_data(
+    archiver: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    mode: u32,
+    contents: &[u8],
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(if path.ends_with('/') {
+        tar::EntryType::Directory
+    } else {
+        tar::EntryType::Regular
+    });
+    header.set_size(contents.len() as u64);
+    header.set_mode(mode);
+    header.set_mtime(unix_now());
+    archiver
+        .append_data(&mut header, path, contents)
+        .context(format_context!("appending {path}"))
+}
+
+fn append_ar_member(output: &mut Vec<u8>, name: &str, contents: &[u8]) {
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        name,
+        unix_now(),
+        0,
+        0,
+        "100644",
+        contents.len()
+    );
+    output.extend_from_slice(header.as_bytes());
+    output.extend_from_slice(contents);
+    if contents.len() % 2 == 1 {
+        output.push(b'\n');
+    }
+}
+
+/// Writes a Debian package: an `ar` archive holding `debian-binary`,
+/// `control.tar.gz` and `data.tar.gz`.
+pub(crate) fn write_deb(
+    entries: &[PackageEntry],
+    info: &PackageInfo,
+    output_path: &str,
+) -> anyhow::Result<()> {
+    let mut data = tar::Builder::new(Vec::new());
+    let directories: BTreeSet<String> = entries
+        .iter()
+        .flat_map(|entry| {
+            std::path::Path::new(entry.archive_path.as_str())
+                .ancestors()
+                .skip(1)
+                .map(|ancestor| ancestor.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    // "" sorts first and becomes the "./" root entry
+    for directory in directories {
+        let path = if directory.is_empty() {
+            "./".to_string()
+        } else {
+            format!("./{directory}/")
+        };
+        append_data(&mut data, path.as_str(), 0o755, &[])?;
+    }
+
+    let mut installed_bytes = 0;
+    for entry in entries {
+        let path = format!("./{}", entry.archive_path);
+        match &entry.source {
+            PackageSource::File(file_path) => {
+                installed_bytes += std::fs::metadata(file_path)
+                    .context(format_context!("{file_path:?}"))?
+                    .len();
+                data.append_path_with_name(file_path, path.as_str())
+                    .context(format_context!("appending {file_path:?}"))?;
+            }
+            PackageSource::Data(contents) => {
+                installed_bytes += contents.len() as u64;
+                append_data(&mut data, path.as_str(), 0o644, contents.as_slice())?;
+            }
+        }
+    }
+
+    let package_name = info.name.to_lowercase().replace('_', "-");
+    let control = format!(
+        "Package: {package_name}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: {}\nDescription: {}\n",
+        info.package_version(),
+        info.architecture(true),
+        info.maintainer.as_deref().unwrap_or("unknown"),
+        installed_bytes.div_ceil(1024),
+        info.description()
+    );
+    let mut control_tar = tar::Builder::new(Vec::new());
+    append_data(&mut control_tar, "./", 0o755, &[])?;
+    append_data(&mut control_tar, "./control", 0o644, control.as_bytes())?;
+
+    let mut output = b"!<arch>\n".to_vec();
+    append_ar_member(&mut output, "debian-binary", b"2.0\n");
+    append_ar_member(
+        &mut output,
+        "control.tar.gz",
+        gzip_tar(control_tar)
+            .context(format_context!("control.tar.gz"))?
+            .as_slice(),
+    );
+    append_ar_member(
+        &mut output,
+        "data.tar.gz",
+        gzip_tar(data)
+            .context(format_context!("data.tar.gz"))?
+            .as_slice(),
+    );
+    std::fs::write(output_path, output).context(format_context!("{output_path}"))
+}
+
+#[cfg(feature = "rpm")]
+pub(crate) fn write_rpm(
+    entries: &[PackageEntry],
+    info: &PackageInfo,
+    output_path: &str,
+) -> anyhow::Result<()> {
+    let mut builder = rpm::PackageBuilder::new(
+        info.name.as_str(),
+        info.package_version().as_str(),
+        info.license.as_deref().unwrap_or("Unspecified"),
+        info.architecture(false),
+        info.description().as_str(),
+    )
+    .compression(rpm::CompressionType::Gzip);
+    if let Some(maintainer) = info.maintainer.as_ref() {
+        builder = builder.packager(maintainer.as_str());
+    }
+
+    // the rpm builder only reads files from disk, so data entries are staged
+    let staging_path = format!("{output_path}.staging");
+    let mut staged = Vec::new();
+    let result = (|| -> anyhow::Result<()> {
+        for (index, entry) in entries.iter().enumerate() {
+            let file_path = match &entry.source {
+                PackageSource::File(file_path) => file_path.clone(),
+                PackageSource::Data(contents) => {
+                    let file_path = std::path::PathBuf::from(format!("{staging_path}.{index}"));
+                    std::fs::write(file_path.as_path(), contents)
+                        .context(format_context!("{file_path:?}"))?;
+                    staged.push(file_path.clone());
+                    file_path
+                }
+            };
+            let options = rpm::FileOptions::new(format!("/{}", entry.archive_path));
+            builder = builder
+                .with_file(file_path.as_path(), options)
+                .map_err(|err| format_error!("failed to add {file_path:?}: {err}"))?;
+        }
+        builder
+            .build()
+            .map_err(|err| format_error!("failed to build {output_path}: {err}"))?
+            .write_file(output_path)
+            .map_err(|err| format_error!("failed to write {output_path}: {err}"))
+    })();
+
+    for file_path in staged {
+        let _ = std::fs::remove_file(file_path);
+    }
+    result
+}
@@ -0,0 +1,80 @@
+use std::path::{Component, Path, PathBuf};
+
+/// `/` separated archive path for a relative filesystem path.
+pub(crate) fn to_archive_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Raw bytes of an archive path, as stored in tar headers.
+pub(crate) fn to_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        to_archive_path(path).into_bytes()
+    }
+}
+
+/// The normal components of `path`, without `.`, `..` or a root.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Joins the normal components of `path` onto `directory`, so the result
+/// stays inside `directory` and keeps names that aren't valid UTF-8.
+pub(crate) fn join_path(directory: &str, path: &Path) -> PathBuf {
+    let mut joined = PathBuf::from(directory);
+    joined.push(normalize(path));
+    joined
+}
+
+/// `join_path` for UTF-8 paths, using the platform separator.
+pub(crate) fn join(directory: &str, path: &str) -> String {
+    let joined = join_path(directory, Path::new(path))
+        .to_string_lossy()
+        .to_string();
+    if cfg!(windows) {
+        joined.replace('/', "\\")
+    } else {
+        joined
+    }
+}
+
+/// Windows APIs reject paths longer than `MAX_PATH` unless they use the
+/// `\\?\` verbatim prefix, which also turns off `/` normalization.
+#[cfg(windows)]
+pub(crate) fn long_path(path: impl AsRef<Path>) -> PathBuf {
+    const MAX_PATH: usize = 260;
+    let path = path.as_ref().to_string_lossy();
+    let path: &str = &path;
+    if path.starts_with(r"\\?\") {
+        return PathBuf::from(path.replace('/', "\\"));
+    }
+    if path.len() < MAX_PATH {
+        return PathBuf::from(path);
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return PathBuf::from(path);
+    };
+    let absolute = absolute.to_string_lossy().replace('/', "\\");
+    match absolute.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{share}")),
+        None => PathBuf::from(format!(r"\\?\{absolute}")),
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn long_path(path: impl AsRef<Path>) -> PathBuf {
+    path.as_ref().to_path_buf()
+}
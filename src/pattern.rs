@@ -0,0 +1,91 @@
+use anyhow::Context;
+use anyhow_source_location::format_context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobOptions {
+    /// Match patterns without regard to case (useful for Windows inputs).
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+/// An ordered list of globs where a leading `!` negates a pattern. The last
+/// pattern that matches a path decides the outcome.
+#[derive(Debug, Clone)]
+pub struct PatternList {
+    patterns: Vec<(globset::GlobMatcher, bool)>,
+}
+
+impl PatternList {
+    pub fn new(patterns: &[String], options: GlobOptions) -> anyhow::Result<Self> {
+        let mut result = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            let (glob, is_negated) = match pattern.strip_prefix('!') {
+                Some(glob) => (glob, true),
+                None => (pattern.as_str(), false),
+            };
+            let matcher = globset::GlobBuilder::new(glob)
+                .literal_separator(true)
+                .case_insensitive(options.case_insensitive)
+                .build()
+                .context(format_context!("invalid pattern {pattern}"))?
+                .compile_matcher();
+            result.push((matcher, is_negated));
+        }
+        Ok(Self { patterns: result })
+    }
+
+    /// Returns `Some(true)` if the last matching pattern is positive,
+    /// `Some(false)` if it is negated and `None` if nothing matched.
+    pub fn matches(&self, path: &str) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map(|(_, is_negated)| !is_negated)
+    }
+}
+
+/// File names that are never useful in an archive.
+pub const JUNK_FILE_NAMES: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini"];
+
+/// Returns true if any component of `path` is a dotfile or the file name is
+/// junk left behind by an OS or editor.
+pub fn is_hidden_or_junk(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    path.split('/')
+        .any(|component| component.starts_with('.') && component != "." && component != "..")
+        || JUNK_FILE_NAMES.contains(&file_name)
+        || file_name.ends_with(".swp")
+        || file_name.ends_with('~')
+}
+
+pub(crate) fn build_gitignore(
+    patterns: &[String],
+    options: GlobOptions,
+) -> anyhow::Result<ignore::gitignore::Gitignore> {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new("");
+    builder.case_insensitive(options.case_insensitive)?;
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .context(format_context!("invalid exclude pattern {pattern}"))?;
+    }
+    builder
+        .build()
+        .context(format_context!("failed to build excludes"))
+}
+
+/// The `(child, ancestor)` of a symlink loop found by an `ignore` walker.
+pub(crate) fn symlink_loop(
+    error: &ignore::Error,
+) -> Option<(std::path::PathBuf, std::path::PathBuf)> {
+    match error {
+        ignore::Error::Loop { ancestor, child } => Some((child.clone(), ancestor.clone())),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => symlink_loop(err),
+        _ => None,
+    }
+}
@@ -0,0 +1,166 @@
+use crate::Error;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use std::io::{Read, Seek};
+
+const READ_AHEAD_SIZE: u64 = 256 * 1024;
+
+/// Seekable reader over a URL that fetches byte ranges on demand.
+struct RangeReader {
+    url: String,
+    length: u64,
+    position: u64,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl RangeReader {
+    fn new(url: &str) -> anyhow::Result<Self> {
+        let response = ureq::head(url)
+            .call()
+            .context(format_context!("HEAD {url} failed"))?;
+        if response.header("Accept-Ranges") != Some("bytes") {
+            return Err(format_error!("{url} doesn't support range requests"));
+        }
+        let length = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .context(format_context!("{url} didn't report a Content-Length"))?;
+        Ok(Self {
+            url: url.to_string(),
+            length,
+            position: 0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+        })
+    }
+
+    fn fetch(&mut self, start: u64, size: u64) -> std::io::Result<()> {
+        let end = (start + size).min(self.length) - 1;
+        let response = ureq::get(self.url.as_str())
+            .set("Range", format!("bytes={start}-{end}").as_str())
+            .call()
+            .map_err(std::io::Error::other)?;
+        if response.status() != 206 {
+            return Err(std::io::Error::other(format!(
+                "expected a partial response for {} but got {}",
+                self.url,
+                response.status()
+            )));
+        }
+        self.buffer.clear();
+        response.into_reader().read_to_end(&mut self.buffer)?;
+        self.buffer_start = start;
+        Ok(())
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.length || buffer.is_empty() {
+            return Ok(0);
+        }
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            self.fetch(self.position, READ_AHEAD_SIZE.max(buffer.len() as u64))?;
+        }
+        let offset = (self.position - self.buffer_start) as usize;
+        let count = buffer.len().min(self.buffer.len() - offset);
+        buffer[..count].copy_from_slice(&self.buffer[offset..offset + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match position {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        Ok(self.position)
+    }
+}
+
+/// A zip archive on a server that supports range requests. Only the central
+/// directory and the requested entries are downloaded.
+pub struct RemoteZip {
+    url: String,
+    archive: zip::ZipArchive<RangeReader>,
+}
+
+impl RemoteZip {
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        let reader = RangeReader::new(url).context(format_context!("{url}"))?;
+        let archive =
+            zip::ZipArchive::new(reader).context(format_context!("open zip failed: {url}"))?;
+        Ok(Self {
+            url: url.to_string(),
+            archive,
+        })
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        self.archive
+            .file_names()
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// Extracts the entries named in `names` into `destination_directory` and
+    /// returns their paths relative to it.
+    pub fn extract(
+        &mut self,
+        names: &[&str],
+        destination_directory: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut extracted = Vec::with_capacity(names.len());
+        for name in names {
+            let mut zip_file = self
+                .archive
+                .by_name(name)
+                .context(format_context!("{name} not found in {}", self.url))?;
+            let enclosed_name = zip_file
+                .enclosed_name()
+                .ok_or_else(|| Error::PathEscape {
+                    name: name.to_string(),
+                })
+                .context(format_context!("{}", self.url))?;
+            let destination_path = std::path::Path::new(destination_directory).join(enclosed_name);
+
+            if zip_file.is_dir() {
+                std::fs::create_dir_all(destination_path.as_path())
+                    .context(format_context!("failed to create {destination_path:?}"))?;
+                continue;
+            }
+            if let Some(parent) = destination_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .context(format_context!("failed to create {parent:?}"))?;
+            }
+
+            let mut output_file = std::fs::File::create(destination_path.as_path())
+                .context(format_context!("failed to create {destination_path:?}"))?;
+            std::io::copy(&mut zip_file, &mut output_file).context(format_context!(
+                "failed to extract {name} from {}",
+                self.url
+            ))?;
+
+            #[cfg(unix)]
+            if let Some(mode) = zip_file.unix_mode() {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(
+                    destination_path.as_path(),
+                    std::fs::Permissions::from_mode(mode),
+                )
+                .context(format_context!(
+                    "failed to set permissions {destination_path:?}"
+                ))?;
+            }
+
+            extracted.push(name.to_string());
+        }
+        Ok(extracted)
+    }
+}
@@ -0,0 +1,197 @@
+use crate::driver::{self, CancellationToken};
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Appended to the archive path to name the sidecar written by
+/// `SegmentDigests::write_sidecar`.
+pub const SIDECAR_EXTENSION: &str = "segments";
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// sha256 of each `segment_size` bytes of an archive, so a file that is
+/// still downloading can be checked up to the last complete segment. The
+/// last segment is shorter when the size isn't a multiple of `segment_size`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentDigests {
+    pub segment_size: u64,
+    /// Size of the complete archive.
+    pub size: u64,
+    /// Lowercase hex, in file order.
+    pub segments: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartialVerification {
+    /// Length of the prefix covered by matching segments. A download can be
+    /// truncated to this length and resumed.
+    pub verified: u64,
+    /// The first segment whose digest doesn't match.
+    pub mismatch: Option<usize>,
+    /// Every segment is present and matches.
+    pub is_complete: bool,
+}
+
+fn segment_count(size: u64, segment_size: u64) -> u64 {
+    size.div_ceil(segment_size)
+}
+
+impl SegmentDigests {
+    /// Hashes the file at `file_path` in segments of `segment_size` bytes.
+    pub fn compute(
+        file_path: &str,
+        segment_size: u64,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<Self> {
+        if segment_size == 0 {
+            return Err(format_error!("segment size must be greater than zero"));
+        }
+        let mut file = std::fs::File::open(file_path).context(format_context!("{file_path}"))?;
+        let size = file
+            .metadata()
+            .context(format_context!("{file_path}"))?
+            .len();
+        let mut segments = Vec::new();
+        for _ in 0..segment_count(size, segment_size) {
+            let digest = driver::sha256_reader((&mut file).take(segment_size), cancellation)
+                .context(format_context!("{file_path}"))?;
+            segments.push(digest);
+        }
+        Ok(Self {
+            segment_size,
+            size,
+            segments,
+        })
+    }
+
+    /// sha256 of the segment digests, one per line. Publishing this one value
+    /// is enough to authenticate a sidecar fetched from a mirror.
+    pub fn root(&self) -> String {
+        let mut lines = String::new();
+        for segment in self.segments.iter() {
+            lines.push_str(segment);
+            lines.push('\n');
+        }
+        driver::sha256_reader(lines.as_bytes(), &CancellationToken::default()).unwrap_or_default()
+    }
+
+    /// Offset and length of segment `index` in the archive.
+    pub fn segment_range(&self, index: usize) -> Option<(u64, u64)> {
+        if index >= self.segments.len() {
+            return None;
+        }
+        let offset = index as u64 * self.segment_size;
+        Some((offset, self.segment_size.min(self.size - offset)))
+    }
+
+    /// Whether `contents` is segment `index`, for checking a download as each
+    /// segment arrives.
+    pub fn verify_segment(&self, index: usize, contents: &[u8]) -> bool {
+        let (Some((_, length)), Some(expected)) =
+            (self.segment_range(index), self.segments.get(index))
+        else {
+            return false;
+        };
+        contents.len() as u64 == length
+            && driver::sha256_reader(contents, &CancellationToken::default())
+                .is_ok_and(|digest| digest == *expected)
+    }
+
+    /// Checks the complete segments at the start of the file at `file_path`,
+    /// which may be a partial download, stopping at the first mismatch.
+    pub fn verify_partial(
+        &self,
+        file_path: &str,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<PartialVerification> {
+        let mut file = std::fs::File::open(file_path).context(format_context!("{file_path}"))?;
+        let file_size = file
+            .metadata()
+            .context(format_context!("{file_path}"))?
+            .len();
+
+        let mut verification = PartialVerification::default();
+        for (index, expected) in self.segments.iter().enumerate() {
+            let Some((offset, length)) = self.segment_range(index) else {
+                break;
+            };
+            if offset + length > file_size {
+                break;
+            }
+            let digest = driver::sha256_reader((&mut file).take(length), cancellation)
+                .context(format_context!("{file_path}"))?;
+            if digest != *expected {
+                verification.mismatch = Some(index);
+                break;
+            }
+            verification.verified = offset + length;
+        }
+        verification.is_complete = verification.mismatch.is_none()
+            && verification.verified == self.size
+            && file_size == self.size;
+        Ok(verification)
+    }
+
+    /// `segment-size` and `size` lines followed by one digest per line.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("segment-size {}\nsize {}\n", self.segment_size, self.size);
+        for segment in self.segments.iter() {
+            text.push_str(segment);
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(contents: &str) -> anyhow::Result<Self> {
+        let mut lines = contents.lines();
+        let mut header = |name: &str| -> anyhow::Result<u64> {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or(format_error!("expected a `{name} <bytes>` line"))
+        };
+        let segment_size = header("segment-size ")?;
+        let size = header("size ")?;
+        if segment_size == 0 {
+            return Err(format_error!("segment size must be greater than zero"));
+        }
+
+        let mut segments = Vec::new();
+        for (index, line) in lines.enumerate() {
+            let line = line.trim();
+            if line.len() != 64 || !line.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format_error!("line {}: expected a sha256", index + 3));
+            }
+            segments.push(line.to_ascii_lowercase());
+        }
+        if segments.len() as u64 != segment_count(size, segment_size) {
+            return Err(format_error!(
+                "{} segments listed for {size} bytes in segments of {segment_size}",
+                segments.len()
+            ));
+        }
+        Ok(Self {
+            segment_size,
+            size,
+            segments,
+        })
+    }
+
+    /// Writes `<archive_path>.segments` and returns its path.
+    pub fn write_sidecar(&self, archive_path: &str) -> anyhow::Result<String> {
+        let sidecar_path = format!("{archive_path}.{SIDECAR_EXTENSION}");
+        std::fs::write(sidecar_path.as_str(), self.to_text())
+            .context(format_context!("{sidecar_path}"))?;
+        Ok(sidecar_path)
+    }
+
+    /// Reads the sidecar written for `archive_path`.
+    pub fn read_sidecar(archive_path: &str) -> anyhow::Result<Self> {
+        let sidecar_path = format!("{archive_path}.{SIDECAR_EXTENSION}");
+        let contents = std::fs::read_to_string(sidecar_path.as_str())
+            .context(format_context!("{sidecar_path}"))?;
+        Self::from_text(contents.as_str()).context(format_context!("{sidecar_path}"))
+    }
+}
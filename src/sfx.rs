@@ -0,0 +1,177 @@
+use crate::driver::Driver;
+use crate::Error;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+
+/// Ends a self-extracting file written with `SfxStub::Executable`, followed by
+/// the archive offset as a little endian `u64`.
+pub const TRAILER_MAGIC: &[u8; 8] = b"EASYSFX1";
+
+const TRAILER_SIZE: u64 = 16;
+
+/// Width the archive offset is padded to in the shell stub, so the length of
+/// the script doesn't depend on the offset it contains.
+const SHELL_OFFSET_WIDTH: usize = 20;
+
+const SHELL_OFFSET_PREFIX: &str = "offset=";
+
+/// The program placed in front of the archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SfxStub {
+    /// A POSIX shell script that extracts the archive with `tar` or `unzip`:
+    /// `sh <file> [destination]`.
+    #[serde(rename = "shell")]
+    Shell,
+    /// A prebuilt extractor, such as one for Windows. The archive is appended
+    /// to it followed by a trailer (`TRAILER_MAGIC` and the archive offset)
+    /// that the extractor reads to find the archive.
+    #[serde(rename = "executable")]
+    Executable(PathBuf),
+}
+
+impl SfxStub {
+    /// Appended to the archive file name.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SfxStub::Shell => "sh",
+            SfxStub::Executable(_) => "exe",
+        }
+    }
+}
+
+fn shell_script(driver: Driver, offset: &str) -> anyhow::Result<String> {
+    let extract = match driver {
+        Driver::Gzip => "tail -c +$((offset + 1)) \"$0\" | tar -xzf - -C \"$destination\"",
+        Driver::Bzip2 => "tail -c +$((offset + 1)) \"$0\" | tar -xjf - -C \"$destination\"",
+        Driver::Xz => "tail -c +$((offset + 1)) \"$0\" | tar -xJf - -C \"$destination\"",
+        Driver::Zip => {
+            "archive=\"$(mktemp)\"\n\
+            trap 'rm -f \"$archive\"' EXIT\n\
+            tail -c +$((offset + 1)) \"$0\" > \"$archive\"\n\
+            unzip -o -q \"$archive\" -d \"$destination\""
+        }
+        _ => {
+            return Err(Error::UnsupportedFormat {
+                driver,
+                reason: format!(
+                    "{} archives can't be extracted by the shell stub",
+                    driver.extension()
+                ),
+            }
+            .into())
+        }
+    };
+    Ok(format!(
+        "#!/bin/sh\n\
+        # Self-extracting archive. Usage: sh \"$0\" [destination]\n\
+        set -e\n\
+        {SHELL_OFFSET_PREFIX}{offset:<SHELL_OFFSET_WIDTH$}\n\
+        destination=\"${{1:-.}}\"\n\
+        mkdir -p \"$destination\"\n\
+        {extract}\n\
+        exit 0\n"
+    ))
+}
+
+/// Writes `stub` followed by the archive at `archive_path` to
+/// `output_file_path` and marks it executable. Returns the offset of the
+/// archive in the output.
+pub fn write_self_extracting(
+    archive_path: &str,
+    stub: &SfxStub,
+    output_file_path: &str,
+) -> anyhow::Result<u64> {
+    let driver = Driver::from_filename(archive_path)
+        .ok_or_else(|| Error::unknown_format(archive_path))
+        .context(format_context!("{archive_path}"))?;
+    let header = match stub {
+        SfxStub::Shell => {
+            let length = shell_script(driver, "")?.len();
+            shell_script(driver, length.to_string().as_str())?.into_bytes()
+        }
+        SfxStub::Executable(stub_path) => {
+            std::fs::read(stub_path).context(format_context!("{stub_path:?}"))?
+        }
+    };
+    let offset = header.len() as u64;
+
+    let mut archive =
+        std::fs::File::open(archive_path).context(format_context!("{archive_path}"))?;
+    let mut output = std::fs::File::create(output_file_path)
+        .context(format_context!("cannot create {output_file_path}"))?;
+    output
+        .write_all(header.as_slice())
+        .context(format_context!("{output_file_path}"))?;
+    std::io::copy(&mut archive, &mut output).context(format_context!("{output_file_path}"))?;
+    if matches!(stub, SfxStub::Executable(_)) {
+        output
+            .write_all(TRAILER_MAGIC)
+            .and_then(|_| output.write_all(&offset.to_le_bytes()))
+            .context(format_context!("{output_file_path}"))?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(output_file_path, std::fs::Permissions::from_mode(0o755))
+            .context(format_context!("{output_file_path}"))?;
+    }
+
+    Ok(offset)
+}
+
+/// The offset and length of the archive inside a file written by
+/// `write_self_extracting`.
+pub fn payload_range(sfx_path: &str) -> anyhow::Result<(u64, u64)> {
+    let mut file = std::fs::File::open(sfx_path).context(format_context!("{sfx_path}"))?;
+    let size = file
+        .metadata()
+        .context(format_context!("{sfx_path}"))?
+        .len();
+
+    if size >= TRAILER_SIZE {
+        let mut trailer = [0; TRAILER_SIZE as usize];
+        file.seek(std::io::SeekFrom::End(-(TRAILER_SIZE as i64)))
+            .and_then(|_| file.read_exact(&mut trailer))
+            .context(format_context!("{sfx_path}"))?;
+        if trailer[..8] == TRAILER_MAGIC[..] {
+            let mut offset = [0; 8];
+            offset.copy_from_slice(&trailer[8..]);
+            let offset = u64::from_le_bytes(offset);
+            if offset > size - TRAILER_SIZE {
+                return Err(format_error!(
+                    "{sfx_path}: archive offset {offset} is out of range"
+                ));
+            }
+            return Ok((offset, size - TRAILER_SIZE - offset));
+        }
+    }
+
+    let mut header = Vec::new();
+    file.rewind()
+        .and_then(|_| (&mut file).take(4096).read_to_end(&mut header))
+        .context(format_context!("{sfx_path}"))?;
+    let offset = String::from_utf8_lossy(header.as_slice())
+        .lines()
+        .find_map(|line| line.strip_prefix(SHELL_OFFSET_PREFIX))
+        .and_then(|offset| offset.trim().parse::<u64>().ok())
+        .filter(|offset| *offset <= size)
+        .ok_or_else(|| format_error!("{sfx_path} is not a self-extracting archive"))?;
+    Ok((offset, size - offset))
+}
+
+/// Copies the archive inside a self-extracting file to `archive_path`.
+pub fn extract_payload(sfx_path: &str, archive_path: &str) -> anyhow::Result<()> {
+    let (offset, length) = payload_range(sfx_path).context(format_context!("{sfx_path}"))?;
+    let mut file = std::fs::File::open(sfx_path).context(format_context!("{sfx_path}"))?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .context(format_context!("{sfx_path}"))?;
+    let mut output = std::fs::File::create(archive_path)
+        .context(format_context!("cannot create {archive_path}"))?;
+    std::io::copy(&mut file.take(length), &mut output)
+        .context(format_context!("{archive_path}"))?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use crate::driver::ProgressReporter;
+use crate::encoder::{Entry, SkippedFile};
+use crate::{paths, CreateArchive};
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Space reserved for the headers and padding of each entry when files are
+/// assigned to parts.
+const ENTRY_OVERHEAD: u64 = 1024;
+
+/// Space reserved for the end of archive marker of each part.
+const PART_OVERHEAD: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplitPart {
+    pub file_name: String,
+    pub sha256: String,
+    pub size: u64,
+    /// Archive paths stored in this part, in archive order.
+    pub files: Vec<String>,
+}
+
+/// Written next to the parts of a split archive so a file can be restored
+/// from its part without extracting the others.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplitIndex {
+    pub name: String,
+    pub version: String,
+    pub platform: Option<String>,
+    pub max_part_size: u64,
+    pub parts: Vec<SplitPart>,
+}
+
+impl SplitIndex {
+    /// The part that stores `archive_path`.
+    pub fn find(&self, archive_path: &str) -> Option<&SplitPart> {
+        self.parts
+            .iter()
+            .find(|part| part.files.iter().any(|file| file == archive_path))
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).context(format_context!(""))
+    }
+
+    pub fn from_json(contents: &[u8]) -> anyhow::Result<Self> {
+        serde_json::from_slice(contents).context(format_context!(""))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitArchive {
+    pub index_file_path: String,
+    pub index: SplitIndex,
+}
+
+impl CreateArchive {
+    /// Like `create` but writes the files to as many complete archives as
+    /// needed to keep each one at or below `max_part_size` bytes, plus a
+    /// `<name>.index.json` listing the files in each part. Parts are named
+    /// `<name>.part001.<extension>` and can be extracted on their own. Files
+    /// are assigned in order using their uncompressed size; a part that still
+    /// comes out too large is split again. A single file that doesn't fit in
+    /// a part is an error. `embed_manifest` adds a manifest of its own files
+    /// to each part.
+    pub fn create_split<Progress: ProgressReporter>(
+        &self,
+        output_directory: &str,
+        max_part_size: u64,
+        progress: Progress,
+    ) -> anyhow::Result<SplitArchive> {
+        if self.driver.is_package() {
+            return Err(format_error!(
+                "{:?} packages can't be split into parts",
+                self.driver
+            ));
+        }
+
+        let (entries, mut skipped) = self
+            .collect_entries()
+            .context(format_error!("Failed to build file list"))?;
+        let output_file_name = self.get_output_file();
+        if entries.is_empty() && !self.allow_empty {
+            return Err(format_error!(
+                "no files matched the inputs of {output_file_name}; set allow_empty to create an empty archive"
+            ));
+        }
+
+        let mut groups = VecDeque::new();
+        let mut group = Vec::new();
+        let mut group_size = PART_OVERHEAD;
+        for entry in entries {
+            let size = std::fs::symlink_metadata(entry.file_path.as_path())
+                .map(|metadata| metadata.len())
+                .unwrap_or_default()
+                + ENTRY_OVERHEAD;
+            if size + PART_OVERHEAD > max_part_size {
+                return Err(format_error!(
+                    "{} doesn't fit in a part of {max_part_size} bytes",
+                    entry.archive_path_lossy()
+                ));
+            }
+            if group_size + size > max_part_size {
+                groups.push_back(std::mem::take(&mut group));
+                group_size = PART_OVERHEAD;
+            }
+            group.push(entry);
+            group_size += size;
+        }
+        groups.push_back(group);
+
+        std::fs::create_dir_all(output_directory)
+            .context(format_context!("failed to create {output_directory}"))?;
+        let extension = format!(".{}", self.driver.extension());
+        let stem = output_file_name
+            .strip_suffix(extension.as_str())
+            .unwrap_or(output_file_name.as_str());
+
+        let mut parts = Vec::new();
+        let mut progress = progress;
+        while let Some(group) = groups.pop_front() {
+            let file_name = format!("{stem}.part{:03}{extension}", parts.len() + 1);
+            let file_path = paths::join(output_directory, file_name.as_str());
+            let mut encoder = self
+                .new_encoder(output_directory, file_name.as_str(), progress)
+                .context(format_context!("{file_path}"))?;
+            encoder.record_skipped(std::mem::take(&mut skipped));
+            if self.embed_manifest {
+                self.add_manifest(&mut encoder, &group)
+                    .context(format_context!("{file_path}"))?;
+            }
+            encoder
+                .add_entries(&group)
+                .context(format_context!("{file_path}"))?;
+            let files = part_files(&group, encoder.skipped());
+            let digested = encoder
+                .compress()
+                .context(format_context!("{file_path}"))?
+                .digest()
+                .context(format_context!("{file_path}"))?;
+            progress = digested.progress_bar;
+
+            let size = std::fs::metadata(file_path.as_str())
+                .context(format_context!("{file_path}"))?
+                .len();
+            if size > max_part_size {
+                if group.len() < 2 {
+                    return Err(format_error!(
+                        "{file_path} is {size} bytes after compression, more than {max_part_size}"
+                    ));
+                }
+                std::fs::remove_file(file_path.as_str()).context(format_context!("{file_path}"))?;
+                let mut first = group;
+                let second = first.split_off(first.len() / 2);
+                groups.push_front(second);
+                groups.push_front(first);
+                continue;
+            }
+
+            parts.push(SplitPart {
+                file_name,
+                sha256: digested.sha256,
+                size,
+                files,
+            });
+        }
+
+        let index = SplitIndex {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            platform: self.platform.clone(),
+            max_part_size,
+            parts,
+        };
+        let index_file_path = paths::join(output_directory, &format!("{stem}.index.json"));
+        std::fs::write(index_file_path.as_str(), index.to_json()?)
+            .context(format_context!("{index_file_path}"))?;
+
+        Ok(SplitArchive {
+            index_file_path,
+            index,
+        })
+    }
+}
+
+/// Archive paths of `group` that weren't left out by `skip_unreadable`.
+fn part_files(group: &[Entry], skipped: &[SkippedFile]) -> Vec<String> {
+    group
+        .iter()
+        .map(|entry| entry.archive_path_lossy())
+        .filter(|archive_path| {
+            !skipped
+                .iter()
+                .any(|skipped| skipped.archive_path == *archive_path)
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+use crate::decoder::{Decoder, VisitedEntryKind};
+use crate::driver::{self, Driver, NoProgress, TarFormat};
+use crate::encoder::Encoder;
+use crate::{paths, Error};
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    /// Where to write the output archive. Defaults to the directory of the input.
+    pub output_directory: Option<String>,
+    /// Sorts entries by path, zeroes modification times and normalizes modes
+    /// to `0o644`/`0o755` so the same contents always produce the same archive.
+    pub deterministic: bool,
+    pub tar_format: TarFormat,
+    /// Threads used to compress the output; see `Encoder::with_threads`.
+    pub threads: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transcoded {
+    pub output_file_path: String,
+    pub sha256: String,
+    /// Number of files copied.
+    pub entries: usize,
+    /// Symlinks and special files, which aren't copied.
+    pub skipped: Vec<String>,
+}
+
+/// `name.tar.gz` or `name.tgz` -> `name`
+fn archive_stem(file_name: &str, driver: Driver) -> &str {
+    driver
+        .aliases()
+        .iter()
+        .find_map(|alias| file_name.strip_suffix(alias)?.strip_suffix('.'))
+        .unwrap_or(file_name)
+}
+
+/// Converts `input_archive` to `output_driver` by streaming each file from
+/// the decoder into the encoder, so nothing is extracted to disk. The output
+/// is named after the input with the new extension. Directory entries are
+/// implied by the file paths and aren't copied.
+pub fn transcode(
+    input_archive: &str,
+    output_driver: Driver,
+    options: &TranscodeOptions,
+) -> anyhow::Result<Transcoded> {
+    let input_driver =
+        Driver::from_filename(input_archive).ok_or_else(|| Error::unknown_format(input_archive))?;
+    input_driver
+        .check_extractable()
+        .context(format_context!("{input_archive}"))?;
+
+    let input_path = std::path::Path::new(input_archive);
+    let input_file_name = input_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let output_file_name = format!(
+        "{}.{}",
+        archive_stem(input_file_name.as_str(), input_driver),
+        output_driver.extension()
+    );
+    let output_directory = match options.output_directory.as_ref() {
+        Some(output_directory) => output_directory.clone(),
+        None => input_path
+            .parent()
+            .map(|parent| parent.to_string_lossy().to_string())
+            .filter(|parent| !parent.is_empty())
+            .unwrap_or(".".to_string()),
+    };
+    let output_file_path = paths::join(output_directory.as_str(), output_file_name.as_str());
+    if std::path::Path::new(output_file_path.as_str()) == input_path {
+        return Err(format_error!(
+            "{input_archive} is already a {} archive",
+            output_driver.extension()
+        ));
+    }
+
+    let decoder = Decoder::new(input_archive, None, output_directory.as_str(), NoProgress)
+        .context(format_context!("{input_archive}"))?;
+    std::fs::create_dir_all(output_directory.as_str())
+        .context(format_context!("failed to create {output_directory}"))?;
+    let mut encoder = Encoder::new(
+        output_directory.as_str(),
+        output_file_name.as_str(),
+        NoProgress,
+    )
+    .context(format_context!("{output_file_path}"))?
+    .with_tar_format(options.tar_format)
+    .with_threads(options.threads.unwrap_or_else(driver::default_threads));
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    let mut entries = 0;
+    decoder
+        .visit_entries(|entry, reader| {
+            match entry.kind {
+                VisitedEntryKind::File => {}
+                VisitedEntryKind::Directory => return Ok(()),
+                VisitedEntryKind::Other => {
+                    skipped.push(entry.archive_path);
+                    return Ok(());
+                }
+            }
+            let mut contents = Vec::new();
+            reader
+                .read_to_end(&mut contents)
+                .context(format_context!("{}", entry.archive_path))?;
+            let mode = entry.mode.unwrap_or(0o644) & 0o7777;
+            if options.deterministic {
+                let mode = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+                files.push((entry.archive_path, mode, contents));
+                return Ok(());
+            }
+            entries += 1;
+            encoder.add_data_with_metadata(
+                entry.archive_path.as_str(),
+                contents.as_slice(),
+                mode,
+                entry.mtime.unwrap_or_default(),
+            )
+        })
+        .context(format_context!("{input_archive}"))?;
+
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    for (archive_path, mode, contents) in files {
+        entries += 1;
+        encoder
+            .add_data_with_metadata(archive_path.as_str(), contents.as_slice(), mode, 0)
+            .context(format_context!("{output_file_path}"))?;
+    }
+
+    let sha256 = encoder
+        .compress()
+        .context(format_context!("{output_file_path}"))?
+        .digest()
+        .context(format_context!("{output_file_path}"))?
+        .sha256;
+
+    Ok(Transcoded {
+        output_file_path,
+        sha256,
+        entries,
+        skipped,
+    })
+}
@@ -0,0 +1,160 @@
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const QUEUE_DEPTH: u32 = 256;
+const MAX_PENDING_BYTES: usize = 64 * 1024 * 1024;
+
+struct PendingWrite {
+    path: PathBuf,
+    file: std::fs::File,
+    contents: Vec<u8>,
+    written: usize,
+    mtime: Option<u64>,
+}
+
+/// Queues the contents of extracted files and writes them in batches through
+/// io_uring, so unpacking thousands of small files isn't bound by one `write`
+/// syscall per file.
+pub(crate) struct BatchWriter {
+    ring: io_uring::IoUring,
+    pending: Vec<PendingWrite>,
+    pending_bytes: usize,
+}
+
+impl BatchWriter {
+    /// Returns `None` if the kernel (or a seccomp sandbox) doesn't allow io_uring.
+    pub(crate) fn new() -> Option<Self> {
+        let ring = io_uring::IoUring::new(QUEUE_DEPTH).ok()?;
+        Some(Self {
+            ring,
+            pending: Vec::new(),
+            pending_bytes: 0,
+        })
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        mtime: Option<u64>,
+        contents: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+        // a repeated path must not race the earlier queued write
+        if self.pending.iter().any(|pending| pending.path == path) {
+            self.flush().context(format_context!(""))?;
+        }
+
+        // replace whatever is at `path` rather than writing through a symlink
+        let open = || {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode)
+                .open(path)
+        };
+        let file = match open() {
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                std::fs::remove_file(path)
+                    .context(format_context!("failed to replace {path:?}"))?;
+                open()
+            }
+            result => result,
+        }
+        .context(format_context!("failed to create {path:?}"))?;
+
+        self.pending_bytes += contents.len();
+        self.pending.push(PendingWrite {
+            path: path.to_path_buf(),
+            file,
+            contents,
+            written: 0,
+            mtime,
+        });
+
+        if self.pending.len() >= QUEUE_DEPTH as usize || self.pending_bytes >= MAX_PENDING_BYTES {
+            self.flush().context(format_context!(""))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> anyhow::Result<()> {
+        let mut remaining: Vec<usize> = (0..self.pending.len())
+            .filter(|index| !self.pending[*index].contents.is_empty())
+            .collect();
+
+        let mut first_error = None;
+        while !remaining.is_empty() && first_error.is_none() {
+            {
+                let mut submission = self.ring.submission();
+                for index in remaining.iter() {
+                    let pending = &self.pending[*index];
+                    let data = &pending.contents[pending.written..];
+                    let write = io_uring::opcode::Write::new(
+                        io_uring::types::Fd(pending.file.as_raw_fd()),
+                        data.as_ptr(),
+                        data.len() as u32,
+                    )
+                    .offset(pending.written as u64)
+                    .build()
+                    .user_data(*index as u64);
+                    // SAFETY: the buffer and file are owned by `self.pending`, which
+                    // isn't touched until every submitted write has completed below.
+                    unsafe { submission.push(&write) }
+                        .map_err(|_| format_error!("io_uring submission queue is full"))?;
+                }
+            }
+
+            let mut next = Vec::new();
+            let mut completed = 0;
+            while completed < remaining.len() {
+                self.ring
+                    .submit_and_wait(1)
+                    .context(format_context!("io_uring submit failed"))?;
+                for completion in self.ring.completion() {
+                    completed += 1;
+                    let index = completion.user_data() as usize;
+                    let pending = &mut self.pending[index];
+                    let result = completion.result();
+                    if result <= 0 {
+                        let err = if result < 0 {
+                            std::io::Error::from_raw_os_error(-result)
+                        } else {
+                            std::io::Error::from(std::io::ErrorKind::WriteZero)
+                        };
+                        first_error.get_or_insert(format_error!(
+                            "failed to write {:?}: {err}",
+                            pending.path
+                        ));
+                        continue;
+                    }
+                    pending.written += result as usize;
+                    if pending.written < pending.contents.len() {
+                        next.push(index);
+                    }
+                }
+            }
+            remaining = next;
+        }
+
+        if let Some(err) = first_error {
+            self.pending.clear();
+            self.pending_bytes = 0;
+            return Err(err);
+        }
+
+        for pending in self.pending.drain(..) {
+            if let Some(mtime) = pending.mtime {
+                let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                pending
+                    .file
+                    .set_modified(modified)
+                    .context(format_context!("failed to set mtime {:?}", pending.path))?;
+            }
+        }
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
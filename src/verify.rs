@@ -0,0 +1,248 @@
+use crate::decoder::Decoder;
+use crate::driver::{self, CancellationToken, NoProgress};
+use crate::paths;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum VerifyMismatch {
+    /// The digest of the archive file itself.
+    #[serde(rename = "archive")]
+    Archive { expected: String, actual: String },
+    /// The digest of a file inside the archive.
+    #[serde(rename = "entry")]
+    Entry {
+        archive_path: String,
+        expected: String,
+        actual: String,
+    },
+    /// A file listed in the checksums file that isn't in the archive.
+    #[serde(rename = "missing")]
+    Missing { archive_path: String },
+    /// A file in the directory that the manifest doesn't list. Only reported
+    /// by `verify_tree`.
+    #[serde(rename = "extra")]
+    Extra { archive_path: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// sha256 of the archive file.
+    pub sha256: String,
+    /// Number of digests that were compared.
+    pub checked: usize,
+    pub mismatches: Vec<VerifyMismatch>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeReport {
+    /// Number of files listed in the manifest.
+    pub checked: usize,
+    /// `Missing` and `Entry` mismatches for listed files sorted by archive
+    /// path, followed by `Extra` files sorted by archive path.
+    pub mismatches: Vec<VerifyMismatch>,
+}
+
+impl TreeReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parses `sha256sum` output: `<digest>  <name>` (or `<digest> *<name>` for
+/// binary mode) per line. Blank lines and `#` comments are skipped.
+fn parse_checksums(contents: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut checksums = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (digest, name) = line
+            .split_once(' ')
+            .filter(|(digest, _)| is_sha256(digest))
+            .ok_or(format_error!(
+                "line {}: expected `<sha256>  <name>`",
+                index + 1
+            ))?;
+        let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+        let name = name.strip_prefix("./").unwrap_or(name);
+        checksums.push((digest.to_ascii_lowercase(), name.to_string()));
+    }
+    Ok(checksums)
+}
+
+/// Checks `archive_path` against `expected`, which is either a sha256 digest
+/// of the archive or the path of a `sha256sum` style checksums file. Lines
+/// naming the archive file check the archive itself; every other line names
+/// a file inside the archive, which is hashed without extracting it.
+/// Mismatches are collected in the report rather than returned as errors.
+pub fn verify(archive_path: &str, expected: &str) -> anyhow::Result<VerifyReport> {
+    let sha256 = driver::digest_file(archive_path, &mut NoProgress, &CancellationToken::default())
+        .context(format_context!("{archive_path}"))?;
+    let mut report = VerifyReport {
+        sha256,
+        ..Default::default()
+    };
+
+    let archive_file_name = archive_path.rsplit(['/', '\\']).next().unwrap_or_default();
+    let checksums = if is_sha256(expected) {
+        vec![(expected.to_ascii_lowercase(), archive_file_name.to_string())]
+    } else {
+        let contents = std::fs::read_to_string(expected).context(format_context!("{expected}"))?;
+        parse_checksums(contents.as_str()).context(format_context!("{expected}"))?
+    };
+
+    let (archive_checksums, entry_checksums): (Vec<_>, Vec<_>) = checksums
+        .into_iter()
+        .partition(|(_, name)| name == archive_file_name || name == archive_path);
+
+    for (expected, _) in archive_checksums {
+        report.checked += 1;
+        if expected != report.sha256 {
+            report.mismatches.push(VerifyMismatch::Archive {
+                expected,
+                actual: report.sha256.clone(),
+            });
+        }
+    }
+
+    if entry_checksums.is_empty() {
+        return Ok(report);
+    }
+    let digests = Decoder::new(archive_path, None, ".", NoProgress)
+        .context(format_context!("{archive_path}"))?
+        .entry_digests()
+        .context(format_context!("{archive_path}"))?;
+    for (expected, archive_path) in entry_checksums {
+        report.checked += 1;
+        match digests.get(archive_path.as_str()) {
+            Some(actual) if *actual == expected => {}
+            Some(actual) => report.mismatches.push(VerifyMismatch::Entry {
+                archive_path,
+                expected,
+                actual: actual.clone(),
+            }),
+            None => report
+                .mismatches
+                .push(VerifyMismatch::Missing { archive_path }),
+        }
+    }
+    Ok(report)
+}
+
+/// The files `manifest` lists, with the sha256 expected for each one when
+/// the manifest has it.
+fn load_manifest(manifest: &str) -> anyhow::Result<BTreeMap<String, Option<String>>> {
+    let contents = std::fs::read_to_string(manifest).context(format_context!("{manifest}"))?;
+    if !contents.trim_start().starts_with('{') {
+        return Ok(parse_checksums(contents.as_str())
+            .context(format_context!("{manifest}"))?
+            .into_iter()
+            .map(|(digest, name)| (name, Some(digest)))
+            .collect());
+    }
+
+    #[cfg(feature = "manifest")]
+    {
+        let artifact = crate::manifest::ArtifactManifest::from_json(contents.as_bytes())
+            .context(format_context!("{manifest}"))?;
+        Ok(artifact
+            .files
+            .into_iter()
+            .map(|file| {
+                let digest = artifact.checksums.get(file.as_str()).cloned();
+                (file, digest)
+            })
+            .collect())
+    }
+    #[cfg(not(feature = "manifest"))]
+    Err(format_error!(
+        "{manifest} is an artifact manifest, which requires the manifest feature"
+    ))
+}
+
+/// Checks the files under `directory` against `manifest`, the path of either
+/// an `artifact.json` written by `embed_manifest` or a `sha256sum` style
+/// checksums file. Listed files that are absent are `Missing`, files whose
+/// sha256 differs are `Entry` mismatches and unlisted files are `Extra`. The
+/// manifest itself is never reported as extra. Manifests without checksums
+/// only have their file list compared.
+pub fn verify_tree(directory: &str, manifest: &str) -> anyhow::Result<TreeReport> {
+    if !std::path::Path::new(directory).is_dir() {
+        return Err(format_error!("{directory} is not a directory"));
+    }
+    let expected = load_manifest(manifest).context(format_context!("{manifest}"))?;
+    let manifest_path = std::fs::canonicalize(manifest).context(format_context!("{manifest}"))?;
+    let directory_path =
+        std::fs::canonicalize(directory).context(format_context!("{directory}"))?;
+    let manifest_archive_path = manifest_path
+        .strip_prefix(directory_path.as_path())
+        .ok()
+        .map(paths::to_archive_path);
+
+    let mut present = BTreeSet::new();
+    for item in walkdir::WalkDir::new(directory) {
+        let item = item.context(format_context!("{directory}"))?;
+        if item.file_type().is_dir() {
+            continue;
+        }
+        let relative_path = item
+            .path()
+            .strip_prefix(directory)
+            .context(format_context!("{:?}", item.path()))?;
+        present.insert(paths::to_archive_path(relative_path));
+    }
+
+    let cancellation = CancellationToken::default();
+    let mut report = TreeReport {
+        checked: expected.len(),
+        ..Default::default()
+    };
+    for (archive_path, digest) in expected.iter() {
+        if !present.contains(archive_path) {
+            report.mismatches.push(VerifyMismatch::Missing {
+                archive_path: archive_path.clone(),
+            });
+            continue;
+        }
+        let Some(expected) = digest else {
+            continue;
+        };
+        let file_path = paths::join(directory, archive_path);
+        let file = std::fs::File::open(paths::long_path(file_path.as_str()))
+            .context(format_context!("{file_path}"))?;
+        let actual =
+            driver::sha256_reader(file, &cancellation).context(format_context!("{file_path}"))?;
+        if actual != *expected {
+            report.mismatches.push(VerifyMismatch::Entry {
+                archive_path: archive_path.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    for archive_path in present {
+        if !expected.contains_key(archive_path.as_str())
+            && manifest_archive_path.as_deref() != Some(archive_path.as_str())
+        {
+            report
+                .mismatches
+                .push(VerifyMismatch::Extra { archive_path });
+        }
+    }
+    Ok(report)
+}
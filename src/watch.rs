@@ -0,0 +1,131 @@
+use crate::driver::ProgressReporter;
+use crate::CreateArchive;
+use anyhow::Context;
+use anyhow_source_location::{format_context, format_error};
+use notify::{RecursiveMode, Watcher};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Quiet period to wait for after the last change before re-archiving.
+    pub debounce: Duration,
+    /// Create the archive once before waiting for the first change.
+    pub initial_create: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(500),
+            initial_create: true,
+        }
+    }
+}
+
+impl WatchOptions {
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn with_initial_create(mut self, initial_create: bool) -> Self {
+        self.initial_create = initial_create;
+        self
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Re-runs `CreateArchive::create` whenever something under the archive
+/// inputs changes. Changes inside `output_directory` are ignored so the output
+/// may live in an input. `on_created` receives the result of each run (the
+/// output path and sha256) and stops watching by returning `ControlFlow::Break`.
+pub fn watch<Progress, OnCreated>(
+    create_archive: &CreateArchive,
+    output_directory: &str,
+    options: &WatchOptions,
+    mut make_progress: impl FnMut() -> Progress,
+    mut on_created: OnCreated,
+) -> anyhow::Result<()>
+where
+    Progress: ProgressReporter,
+    OnCreated: FnMut(anyhow::Result<(String, String)>) -> ControlFlow<()>,
+{
+    let mut inputs: Vec<PathBuf> = create_archive
+        .inputs
+        .iter()
+        .map(|(input, _)| PathBuf::from(input))
+        .collect();
+    if !create_archive.input.is_empty() {
+        inputs.push(PathBuf::from(&create_archive.input));
+    }
+    if inputs.is_empty() {
+        return Err(format_error!(
+            "{} has no inputs to watch",
+            create_archive.name
+        ));
+    }
+
+    std::fs::create_dir_all(output_directory).context(format_context!("{output_directory}"))?;
+    let output_directory_path = canonical(Path::new(output_directory));
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context(format_context!("{output_directory}"))?;
+    for input in inputs.iter() {
+        watcher
+            .watch(input, RecursiveMode::Recursive)
+            .context(format_context!("{input:?}"))?;
+    }
+
+    let is_relevant = |event: &notify::Event| {
+        !event.kind.is_access()
+            && event
+                .paths
+                .iter()
+                .any(|path| !canonical(path).starts_with(&output_directory_path))
+    };
+
+    if options.initial_create {
+        let result = create_archive.create(output_directory, make_progress());
+        if on_created(result).is_break() {
+            return Ok(());
+        }
+    }
+
+    loop {
+        // block until a relevant change arrives
+        loop {
+            let event = receiver
+                .recv()
+                .context(format_context!("watcher stopped"))?
+                .context(format_context!("{output_directory}"))?;
+            if is_relevant(&event) {
+                break;
+            }
+        }
+
+        // then wait for the inputs to be quiet for the debounce period
+        loop {
+            match receiver.recv_timeout(options.debounce) {
+                Ok(event) => {
+                    event.context(format_context!("{output_directory}"))?;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(format_error!("watcher stopped"));
+                }
+            }
+        }
+
+        let result = create_archive.create(output_directory, make_progress());
+        if on_created(result).is_break() {
+            return Ok(());
+        }
+    }
+}